@@ -90,6 +90,7 @@ pub fn create_benchmark_extrinsic(
         frame_system::CheckNonce::<runtime::Runtime>::from(nonce),
         frame_system::CheckWeight::<runtime::Runtime>::new(),
         pallet_transaction_payment::ChargeTransactionPayment::<runtime::Runtime>::from(0),
+        pallet_cfmm::check_deadline::CheckSwapDeadline::<runtime::Runtime>::new(None),
     );
 
     let raw_payload = runtime::SignedPayload::from_raw(
@@ -104,6 +105,7 @@ pub fn create_benchmark_extrinsic(
             (),
             (),
             (),
+            (),
         ),
     );
     let signature = raw_payload.using_encoded(|e| sender.sign(e));
@@ -0,0 +1,51 @@
+//! Runtime API for querying the CFMM pallet.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::Codec;
+use pallet_cfmm::{ExchangeSimulation, PoolInfo};
+use sp_runtime::DispatchError;
+use sp_std::vec::Vec;
+
+sp_api::decl_runtime_apis! {
+    /// Queries of the CFMM pallet's liquidity pools.
+    ///
+    /// Version 2 added `pool_info`, and version 3 added `price_history`.
+    #[api_version(3)]
+    pub trait CfmmApi<AccountId, AssetId, AssetBalance, Balance, BlockNumber>
+    where
+        AccountId: Codec,
+        AssetId: Codec,
+        AssetBalance: Codec,
+        Balance: Codec,
+        BlockNumber: Codec,
+    {
+        /// Returns the largest amount of liquidity `who` can remove for the asset pair without
+        /// closing their position. See `Pallet::max_removable_liquidity`.
+        fn max_removable_liquidity(who: AccountId, asset_a: AssetId, asset_b: AssetId)
+            -> AssetBalance;
+
+        /// Returns a page of the liquidity positions held by `who`, along with the key to pass as
+        /// `start_key` to get the next page. See `Pallet::account_liquidity_paged`.
+        fn account_liquidity_paged(who: AccountId, start_key: Option<Vec<u8>>, limit: u32)
+            -> (Vec<((AssetId, AssetId), AssetBalance)>, Option<Vec<u8>>);
+
+        /// Determines the outcome of exchanging `source_amount` of `source_asset` for
+        /// `dest_asset`, without performing the exchange. See `Pallet::simulate_exchange`.
+        fn simulate_exchange(
+            source_asset: AssetId,
+            source_amount: AssetBalance,
+            dest_asset: AssetId,
+        ) -> Result<ExchangeSimulation<AssetBalance>, DispatchError>;
+
+        /// Returns the settings for the liquidity pool for an asset pair, including who created it
+        /// and when. See `Pallet::pool_info`.
+        fn pool_info(asset_a: AssetId, asset_b: AssetId)
+            -> Option<PoolInfo<AccountId, Balance, BlockNumber>>;
+
+        /// Returns the recent price points for an asset pair, oldest first, as `(block, amount_a,
+        /// amount_b)`. See `Pallet::price_history`.
+        fn price_history(asset_a: AssetId, asset_b: AssetId)
+            -> Vec<(BlockNumber, AssetBalance, AssetBalance)>;
+    }
+}
@@ -0,0 +1,130 @@
+//! A chain extension allowing smart contracts (eg ink! contracts on `pallet_contracts`) to use the
+//! exchange.
+//!
+//! Functions are identified by their `func_id`. Inputs and outputs are SCALE encoded:
+//!
+//! - 1, `exchange`: input `(source_asset, source_amount, dest_asset, min_dest_amount)`, output
+//!   `dest_amount`.
+//! - 2, `get_amount_out`: input `(source_asset, source_amount, dest_asset)`, output `dest_amount`.
+//! - 3, `get_reserves`: input `(asset_a, asset_b)`, output `(reserve_a, reserve_b)`.
+//!
+//! `exchange` behaves like the `exchange` call, with the calling contract as the sender; the
+//! contract's account must be accepted by `SwapOrigin`. `get_amount_out` returns the amount of
+//! `dest_asset` such an exchange would currently give, and `get_reserves` the amount of each asset
+//! in the liquidity pool (zero if there is no liquidity pool). Any error aborts the contract call.
+//!
+//! `CfmmExtension`, which plugs `dispatch` into `pallet_contracts`, requires the `contracts`
+//! feature.
+
+use crate::{Config, Pallet};
+use codec::{Decode, Encode};
+use frame_support::{
+    traits::{EnsureOrigin, Get},
+    weights::Weight,
+};
+#[cfg(feature = "contracts")]
+use pallet_contracts::chain_extension::{
+    ChainExtension, Environment, Ext, InitState, RetVal, SysConfig, UncheckedFrom,
+};
+use sp_runtime::DispatchError;
+use sp_std::vec::Vec;
+
+/// `func_id` of `exchange`.
+pub const EXCHANGE: u32 = 1;
+/// `func_id` of `get_amount_out`.
+pub const GET_AMOUNT_OUT: u32 = 2;
+/// `func_id` of `get_reserves`.
+pub const GET_RESERVES: u32 = 3;
+
+/// The chain extension. Use this as (or as part of) `pallet_contracts::Config::ChainExtension`.
+#[cfg(feature = "contracts")]
+#[derive(Default)]
+pub struct CfmmExtension;
+
+#[cfg(feature = "contracts")]
+impl<T> ChainExtension<T> for CfmmExtension
+where
+    T: pallet_contracts::Config + Config,
+{
+    fn call<E>(func_id: u32, env: Environment<E, InitState>) -> Result<RetVal, DispatchError>
+    where
+        E: Ext<T = T>,
+        <E::T as SysConfig>::AccountId: UncheckedFrom<<E::T as SysConfig>::Hash> + AsRef<[u8]>,
+    {
+        let mut env = env.buf_in_buf_out();
+        env.charge_weight(weight::<T>(func_id)?)?;
+        let input = env.read(env.in_len())?;
+        let contract = env.ext().address().clone();
+        let output = dispatch::<T>(func_id, &contract, &input)?;
+        env.write(&output, false, None)?;
+        Ok(RetVal::Converging(0))
+    }
+}
+
+fn unknown_function() -> DispatchError {
+    DispatchError::Other("Unknown CFMM chain extension function")
+}
+
+/// Returns the weight of the chain extension function with the given `func_id`.
+pub fn weight<T: Config>(func_id: u32) -> Result<Weight, DispatchError> {
+    match func_id {
+        // Matches the `exchange` call
+        EXCHANGE => Ok(Pallet::<T>::exchange_weight()),
+        // Pause switch, pool state, blacklist, and the pool reserves
+        GET_AMOUNT_OUT => Ok(T::DbWeight::get().reads(6)),
+        GET_RESERVES => Ok(T::DbWeight::get().reads(1)),
+        _ => Err(unknown_function()),
+    }
+}
+
+/// Executes the chain extension function with the given `func_id` on behalf of `contract`,
+/// returning the encoded output. See the module documentation for the input and output formats.
+pub fn dispatch<T: Config>(
+    func_id: u32,
+    contract: &T::AccountId,
+    mut input: &[u8],
+) -> Result<Vec<u8>, DispatchError> {
+    fn decode<I: Decode>(input: &mut &[u8]) -> Result<I, DispatchError> {
+        I::decode(input).map_err(|_| DispatchError::Other("Invalid CFMM chain extension input"))
+    }
+
+    match func_id {
+        EXCHANGE => {
+            let (source_asset, source_amount, dest_asset, min_dest_amount): (
+                T::AssetId,
+                T::AssetBalance,
+                T::AssetId,
+                T::AssetBalance,
+            ) = decode(&mut input)?;
+            let who = T::SwapOrigin::ensure_origin(
+                frame_system::RawOrigin::Signed(contract.clone()).into(),
+            )?;
+            let dest_amount = Pallet::<T>::do_exchange(
+                &who,
+                source_asset,
+                source_amount,
+                dest_asset,
+                min_dest_amount,
+                false,
+            )?;
+            Ok(dest_amount.encode())
+        },
+        GET_AMOUNT_OUT => {
+            let (source_asset, source_amount, dest_asset): (
+                T::AssetId,
+                T::AssetBalance,
+                T::AssetId,
+            ) = decode(&mut input)?;
+            let simulation =
+                Pallet::<T>::simulate_exchange(source_asset, source_amount, dest_asset)?;
+            Ok(simulation.dest_amount.encode())
+        },
+        GET_RESERVES => {
+            let (asset_a, asset_b): (T::AssetId, T::AssetId) = decode(&mut input)?;
+            // Contracts expect (0, 0) if there is no liquidity pool
+            let reserves = Pallet::<T>::get_exchange_rate(asset_a, asset_b).unwrap_or_default();
+            Ok(reserves.encode())
+        },
+        _ => Err(unknown_function()),
+    }
+}
@@ -0,0 +1,94 @@
+//! A `SignedExtension` which rejects exchange transactions submitted after a deadline.
+//!
+//! Wallets can use this to protect users against transactions that sit in the transaction pool
+//! (or are deliberately withheld) until the exchange rate has moved against them, without relying
+//! on each call to carry its own deadline.
+
+use crate::{Call, Config};
+use codec::{Decode, Encode};
+use frame_support::traits::IsSubType;
+use scale_info::TypeInfo;
+use sp_runtime::{
+    traits::{DispatchInfoOf, SaturatedConversion, Saturating, SignedExtension},
+    transaction_validity::{
+        InvalidTransaction, TransactionLongevity, TransactionValidity, TransactionValidityError,
+        ValidTransaction,
+    },
+};
+
+/// Rejects calls to the CFMM pallet if the current block number is greater than the given
+/// deadline. Transactions with no deadline, and transactions calling other pallets, are not
+/// affected.
+///
+/// Note that only the outer call is checked; CFMM calls nested inside eg a `utility` batch are not
+/// subject to the deadline.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+pub struct CheckSwapDeadline<T: Config>(Option<T::BlockNumber>);
+
+impl<T: Config> CheckSwapDeadline<T> {
+    /// Create a new extension with the given deadline (the last block in which a CFMM call may be
+    /// included).
+    pub fn new(deadline: Option<T::BlockNumber>) -> Self {
+        Self(deadline)
+    }
+}
+
+impl<T: Config> sp_std::fmt::Debug for CheckSwapDeadline<T> {
+    #[cfg(feature = "std")]
+    fn fmt(&self, f: &mut sp_std::fmt::Formatter) -> sp_std::fmt::Result {
+        write!(f, "CheckSwapDeadline({:?})", self.0)
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn fmt(&self, _: &mut sp_std::fmt::Formatter) -> sp_std::fmt::Result {
+        Ok(())
+    }
+}
+
+impl<T: Config + Send + Sync> SignedExtension for CheckSwapDeadline<T>
+where
+    T::Call: IsSubType<Call<T>>,
+{
+    const IDENTIFIER: &'static str = "CheckSwapDeadline";
+    type AccountId = T::AccountId;
+    type Call = T::Call;
+    type AdditionalSigned = ();
+    type Pre = ();
+
+    fn additional_signed(&self) -> Result<(), TransactionValidityError> {
+        Ok(())
+    }
+
+    fn validate(
+        &self,
+        _who: &Self::AccountId,
+        call: &Self::Call,
+        _info: &DispatchInfoOf<Self::Call>,
+        _len: usize,
+    ) -> TransactionValidity {
+        let deadline = match self.0 {
+            Some(deadline) if call.is_sub_type().is_some() => deadline,
+            _ => return Ok(ValidTransaction::default()),
+        };
+
+        let now = frame_system::Pallet::<T>::block_number();
+        if now > deadline {
+            return Err(InvalidTransaction::Stale.into())
+        }
+
+        // Drop the transaction from the pool once the deadline has passed
+        let longevity: TransactionLongevity = deadline.saturating_sub(now).saturated_into();
+        Ok(ValidTransaction { longevity: longevity.saturating_add(1), ..Default::default() })
+    }
+
+    fn pre_dispatch(
+        self,
+        who: &Self::AccountId,
+        call: &Self::Call,
+        info: &DispatchInfoOf<Self::Call>,
+        len: usize,
+    ) -> Result<Self::Pre, TransactionValidityError> {
+        self.validate(who, call, info, len).map(|_| ())
+    }
+}
@@ -8,26 +8,51 @@ mod mock;
 #[cfg(test)]
 mod tests;
 
+#[cfg(test)]
+mod proptests;
+
 #[cfg(feature = "runtime-benchmarks")]
 mod benchmarking;
 
+pub mod chain_extension;
+pub mod check_deadline;
+pub mod migrations;
+pub mod native;
+pub mod oracle;
+pub mod payment;
+pub mod traits;
+pub mod voting;
+mod weighted_math;
+
 #[frame_support::pallet]
 pub mod pallet {
+    use crate::traits::{AssetDecimals, OnLiquidityChanged, OnSwap};
     use frame_support::{
         pallet_prelude::*,
         traits::{
-            fungibles::{Inspect, Transfer},
-            tokens,
+            fungibles::{approvals, Inspect, Transfer},
+            tokens, Currency, ExistenceRequirement, ReservableCurrency,
         },
         transactional, PalletId,
     };
-    use frame_system::pallet_prelude::*;
-    use sp_core::U256;
+    use frame_system::{
+        offchain::{SendTransactionTypes, SubmitTransaction},
+        pallet_prelude::*,
+    };
+    use sp_core::{hashing::blake2_256, U256};
     use sp_runtime::{
-        traits::{AccountIdConversion, CheckedAdd, CheckedMul, CheckedSub, Saturating, Zero},
-        ArithmeticError, Permill,
+        traits::{
+            AccountIdConversion, CheckedAdd, CheckedMul, CheckedSub, MaybeSerializeDeserialize,
+            SaturatedConversion, Saturating, TrailingZeroInput, Zero,
+        },
+        ArithmeticError, FixedPointNumber, FixedU128, PerThing, Perbill, Permill, TypeId,
+    };
+    #[cfg(any(feature = "try-runtime", test))]
+    use sp_std::collections::btree_map::BTreeMap;
+    use sp_std::{
+        cmp::{max, min},
+        vec::Vec,
     };
-    use sp_std::cmp::{max, min};
 
     /// Type for result of multiplying two `AssetBalance`s together. Just fixed as `U256` for now.
     /// Could probably be smarter and use something like `overflow_prune_mul` from `per_things` to
@@ -35,7 +60,7 @@ pub mod pallet {
     type BalanceMulResult = U256;
 
     #[pallet::config]
-    pub trait Config: frame_system::Config {
+    pub trait Config: frame_system::Config + SendTransactionTypes<Call<Self>> {
         /// Because this pallet emits events, it depends on the runtime's definition of an event.
         type Event: From<Event<Self>> + IsType<<Self as frame_system::Config>::Event>;
 
@@ -43,16 +68,58 @@ pub mod pallet {
         #[pallet::constant]
         type PalletId: Get<PalletId>;
 
-        type AssetId: tokens::AssetId + MaxEncodedLen;
+        /// Asset pairs are canonically ordered by `Ord`; this determines eg the pool account for
+        /// each pair.
+        type AssetId: tokens::AssetId + MaxEncodedLen + MaybeSerializeDeserialize + Ord;
         type AssetBalance: tokens::Balance
             + MaxEncodedLen
+            + MaybeSerializeDeserialize
             + Into<BalanceMulResult>
             + TryFrom<BalanceMulResult>;
-        type Fungibles: Transfer<
-            Self::AccountId,
-            AssetId = Self::AssetId,
-            Balance = Self::AssetBalance,
-        >;
+        type Fungibles: approvals::Mutate<Self::AccountId>
+            + Transfer<Self::AccountId, AssetId = Self::AssetId, Balance = Self::AssetBalance>;
+
+        /// The currency in which pool creation and position deposits are paid (see `PoolDeposit`
+        /// and `PositionDeposit`).
+        type Currency: ReservableCurrency<Self::AccountId>;
+
+        /// The deposit reserved from the account which creates a liquidity pool, ie adds
+        /// liquidity to an empty pool, to discourage spamming storage with pools. It is released
+        /// when the pool is cleaned up with `cleanup_pool` after being drained, or when the
+        /// drained pool is recreated. Zero disables the deposit.
+        #[pallet::constant]
+        type PoolDeposit: Get<BalanceOf<Self>>;
+
+        /// The deposit reserved from an account for each liquidity position (`Liquidity` or
+        /// `WeightedLiquidity` entry) it holds, to make bloating storage with many tiny positions
+        /// costly. It is reserved when
+        /// the position is created, by adding liquidity or having liquidity transferred in, and
+        /// released when the position is removed, by removing or transferring out all of its
+        /// liquidity. The amount released is always the amount reserved, even if this has since
+        /// changed. Zero disables the deposit.
+        #[pallet::constant]
+        type PositionDeposit: Get<BalanceOf<Self>>;
+
+        /// The maximum number of liquidity pools with non-zero liquidity, including weighted
+        /// liquidity pools. Creating a pool beyond this fails with `TooManyPools`; draining a pool
+        /// frees up its slot.
+        #[pallet::constant]
+        type MaxPools: Get<u32>;
+
+        /// The maximum number of assets in a weighted liquidity pool (see `create_weighted_pool`).
+        #[pallet::constant]
+        type MaxWeightedPoolAssets: Get<u32>;
+
+        /// If set, liquidity can only be added to an empty liquidity pool by `PoolCreationOrigin`,
+        /// or by anyone if the asset pair has been registered with `register_pool`. Otherwise, any
+        /// signed account may create liquidity pools.
+        #[pallet::constant]
+        type RestrictPoolCreation: Get<bool>;
+
+        /// The maximum number of accounts which may provide liquidity to a liquidity pool during
+        /// its bootstrap period (see `register_pool`).
+        #[pallet::constant]
+        type MaxBootstrapProviders: Get<u32>;
 
         /// When adding or removing liquidity, we require that the final amount of each asset in
         /// the liquidity pool effectively owned by the sender be at least a certain multiple of
@@ -67,20 +134,206 @@ pub mod pallet {
         type PoolMinAmountMultiple: Get<Self::AssetBalance>;
 
         /// The amount of liquidity tokens given to the first liquidity provider for an asset pair
-        /// is determined by the largest of either asset amount multiplied by this. This number is
-        /// somewhat arbitrary, but determines how accurately the liquidity pool can be divided up
-        /// amongst liquidity providers.
+        /// is determined by combining the asset amounts according to `InitialLiquidityFormula`,
+        /// and multiplying the result by this. This number is somewhat arbitrary, but determines
+        /// how accurately the liquidity pool can be divided up amongst liquidity providers.
         ///
         /// Note that the overall amount of assets in the liquidity pool will rise over time due to
         /// fees, whereas the amount of liquidity tokens will not (unless new liquidity is added).
         /// Also, the balance of assets in the pool may change as exchanges are performed.
+        ///
+        /// This can be overridden for individual asset pairs with
+        /// `set_initial_liquidity_per_asset_unit`.
         #[pallet::constant]
         type InitialLiquidityPerAssetUnit: Get<LiquidityBalanceOf<Self>>;
 
+        /// How the asset amounts provided by the first liquidity provider for an asset pair are
+        /// combined to determine the liquidity tokens they are given.
+        #[pallet::constant]
+        type InitialLiquidityFormula: Get<InitialLiquidityFormula>;
+
+        /// Which way the amounts of exchanges are rounded. `RoundingPolicy::FavorPool` preserves
+        /// the historical behaviour and is the safe choice.
+        #[pallet::constant]
+        type RoundingPolicy: Get<RoundingPolicy>;
+
+        /// The minimum number of liquidity tokens the first liquidity provider for an asset pair
+        /// must be given. Tiny initial positions are rejected, as later operations on them round
+        /// redeemable amounts down to nothing.
+        #[pallet::constant]
+        type MinimumLiquidity: Get<LiquidityBalanceOf<Self>>;
+
         /// This portion of the source amount for each exchange will be added to the pool as a fee;
-        /// the remainder will be exchanged.
+        /// the remainder will be exchanged. This can be overridden for individual pools with
+        /// `set_pool_fee`.
         #[pallet::constant]
         type ExchangeFee: Get<Permill>;
+
+        /// This portion of the fee for each exchange made with `exchange_with_referral` is paid to
+        /// the referrer, rather than being added to the pool.
+        #[pallet::constant]
+        type ReferralShare: Get<Permill>;
+
+        /// Liquidity cannot be removed until this many blocks have passed since liquidity was
+        /// last added to the position. Note that adding liquidity to an existing position locks
+        /// the _whole_ position again, not just the added liquidity. Zero disables the lock.
+        #[pallet::constant]
+        type MinLockPeriod: Get<Self::BlockNumber>;
+
+        /// Liquidity cannot be added to or removed from a position until this many blocks have
+        /// passed since it was last changed. Unlike `MinLockPeriod`, this applies to additions as
+        /// well as removals, discouraging flash add/remove cycles. Zero disables the cooldown.
+        #[pallet::constant]
+        type LiquidityCooldown: Get<Self::BlockNumber>;
+
+        /// If set, exchanges with an effective exchange rate (including the fee) which differs
+        /// from the current exchange rate by more than this are rejected, regardless of the
+        /// minimum amount the sender is willing to receive. This protects the pool from being
+        /// whipsawed by oversized trades.
+        #[pallet::constant]
+        type MaxPriceImpact: Get<Option<Permill>>;
+
+        /// If set, exchanges with a source amount greater than this fraction of the amount of the
+        /// source asset in the liquidity pool are rejected. Unlike `MaxPriceImpact`, this is a
+        /// hard cap on the size of a single trade, independent of the resulting exchange rate.
+        #[pallet::constant]
+        type MaxTradeFraction: Get<Option<Permill>>;
+
+        /// If set, the liquidity pool for an asset pair is automatically paused (as by
+        /// `pause_pool`) when an exchange would move the exchange rate by more than this, relative
+        /// to the exchange rate at the start of the block, so that moves by several exchanges in
+        /// the same block accumulate. The exchange which trips the circuit breaker fails with
+        /// `CircuitBreakerTripped`. If it was made directly with `exchange` (or one of its
+        /// variants), the pool is paused and the call succeeds without exchanging anything, so that
+        /// the pause sticks; further exchanges fail until the pool is resumed. Other callers (eg
+        /// routes, fee payment, and contracts) just fail.
+        #[pallet::constant]
+        type CircuitBreakerThreshold: Get<Option<Permill>>;
+
+        /// The maximum number of liquidity removals that can be scheduled for the same block (see
+        /// `schedule_remove_liquidity`). This bounds the work done in `on_initialize`.
+        #[pallet::constant]
+        type MaxScheduledRemovalsPerBlock: Get<u32>;
+
+        /// The maximum number of unexpired locks an account can have on its liquidity for each
+        /// asset pair (see `lock_liquidity`).
+        #[pallet::constant]
+        type MaxLiquidityLocks: Get<u32>;
+
+        /// The maximum number of asset pairs and weighted liquidity pools an account can hold
+        /// liquidity for. Adding liquidity for, or being transferred liquidity for, a further asset
+        /// pair or weighted pool fails with `TooManyPositions`.
+        #[pallet::constant]
+        type MaxPositionsPerAccount: Get<u32>;
+
+        /// The maximum number of liquidity pools whose cumulative prices are advanced in
+        /// `on_initialize` each block (see `PriceCumulative`). Pools are visited in turn, so each
+        /// pool is updated at least once every `pool_count / MaxOracleUpdatesPerBlock` blocks.
+        /// Zero disables these updates; cumulative prices are then only advanced when the amounts
+        /// in a pool change.
+        #[pallet::constant]
+        type MaxOracleUpdatesPerBlock: Get<u32>;
+
+        /// The maximum number of observations of the cumulative prices kept for each asset pair
+        /// (see `Observations`). This determines how far back `consult` can look; the oldest
+        /// observation is dropped when a new one is recorded.
+        #[pallet::constant]
+        type ObservationCardinality: Get<u32>;
+
+        /// The maximum number of price points kept for each asset pair in `PriceHistory`, for
+        /// charting. Zero disables the price history, so that it costs nothing.
+        #[pallet::constant]
+        type MaxHistoryLen: Get<u32>;
+
+        /// The expected time between blocks, in milliseconds. Only used to annualise yields in
+        /// `estimate_apr`.
+        #[pallet::constant]
+        type ExpectedBlockTime: Get<u64>;
+
+        /// Whether the off-chain worker reports the spot prices in the liquidity pools, with
+        /// unsigned `report_prices` transactions, for recording in `OraclePrices`.
+        #[pallet::constant]
+        type OffchainPriceReporting: Get<bool>;
+
+        /// The maximum number of liquidity pools whose spot prices are reported in a single
+        /// `report_prices` transaction. Pools are reported in a round-robin fashion.
+        #[pallet::constant]
+        type MaxPriceReports: Get<u32>;
+
+        /// The priority of unsigned `report_prices` transactions.
+        #[pallet::constant]
+        type UnsignedPriority: Get<TransactionPriority>;
+
+        /// The maximum number of assets in a route through multiple liquidity pools (see
+        /// `get_amounts_out`). Must be at least 2.
+        #[pallet::constant]
+        type MaxRouteLength: Get<u32>;
+
+        /// The origin which may call `exchange`, and the account the exchange is performed on
+        /// behalf of. Typically `EnsureSigned`.
+        type SwapOrigin: EnsureOrigin<Self::Origin, Success = Self::AccountId>;
+
+        /// The origin which may pause and resume trading in liquidity pools.
+        type PauseOrigin: EnsureOrigin<Self::Origin>;
+
+        /// The origin which may pause and unpause all trading and additions of liquidity, across
+        /// all liquidity pools, with `set_paused`.
+        type EmergencyOrigin: EnsureOrigin<Self::Origin>;
+
+        /// The origin which may override `ExchangeFee` for individual liquidity pools.
+        type FeeAdminOrigin: EnsureOrigin<Self::Origin>;
+
+        /// The origin which may restrict the exchange rates of individual liquidity pools with
+        /// `set_price_band`.
+        type PriceBandOrigin: EnsureOrigin<Self::Origin>;
+
+        /// The origin which may blacklist and unblacklist assets.
+        type BlacklistOrigin: EnsureOrigin<Self::Origin>;
+
+        /// The origin which may whitelist and unwhitelist assets, and enable and disable
+        /// whitelist-only pool creation.
+        type WhitelistOrigin: EnsureOrigin<Self::Origin>;
+
+        /// The origin which may create liquidity pools and register asset pairs for anyone to
+        /// create pools for, if `RestrictPoolCreation` is set. To create a pool, the origin must
+        /// also be signed, as for `add_liquidity`. This origin may also set the minimum addition
+        /// of liquidity to a pool with `set_min_add_amount`.
+        type PoolCreationOrigin: EnsureOrigin<Self::Origin>;
+
+        /// The origin which may add and remove liquidity on behalf of any account, using that
+        /// account's assets, with `force_add_liquidity` and `force_remove_liquidity`. This is
+        /// intended for eg deploying treasury assets.
+        type LiquidityProvisionOrigin: EnsureOrigin<Self::Origin>;
+
+        /// The origin which may fund liquidity mining rewards for liquidity pools with
+        /// `fund_rewards`.
+        type RewardOrigin: EnsureOrigin<Self::Origin>;
+
+        /// The origin which may rescue assets transferred directly to the account of a liquidity
+        /// pool which are not part of the pool's asset pair.
+        type RescueOrigin: EnsureOrigin<Self::Origin>;
+
+        /// The origin which may forcibly drain a liquidity pool with `admin_drain_pool`.
+        type AdminOrigin: EnsureOrigin<Self::Origin>;
+
+        /// Receives any assets left in the account of an empty liquidity pool when it is cleaned up
+        /// with `cleanup_pool`.
+        type DustSink: Get<Self::AccountId>;
+
+        /// Provides the number of decimal places of each asset, for `get_normalized_price`. See
+        /// `MetadataDecimals`.
+        type AssetDecimals: AssetDecimals<Self::AssetId>;
+
+        /// Called after every exchange. Use `()` if nothing needs to react to exchanges.
+        type OnSwap: OnSwap<Self::AccountId, Self::AssetId, Self::AssetBalance>;
+
+        /// Called after liquidity is added or removed. Use `()` if nothing needs to react to
+        /// changes in liquidity.
+        type OnLiquidityChanged: OnLiquidityChanged<
+            Self::AccountId,
+            Self::AssetId,
+            Self::AssetBalance,
+        >;
     }
 
     type AssetIdOf<T> =
@@ -89,9 +342,322 @@ pub mod pallet {
     type AssetBalanceOf<T> =
         <<T as Config>::Fungibles as Inspect<<T as frame_system::Config>::AccountId>>::Balance;
     type LiquidityBalanceOf<T> = AssetBalanceOf<T>;
+    type BalanceOf<T> =
+        <<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
+    type PoolInfoOf<T> = PoolInfo<
+        <T as frame_system::Config>::AccountId,
+        BalanceOf<T>,
+        <T as frame_system::Config>::BlockNumber,
+    >;
+    type LiquidityPositionOf<T> = LiquidityPosition<LiquidityBalanceOf<T>>;
+    type RewardPotOf<T> =
+        RewardPot<AssetIdOf<T>, AssetBalanceOf<T>, <T as frame_system::Config>::BlockNumber>;
+    type RewardPositionOf<T> = RewardPosition<AssetBalanceOf<T>>;
+    type ExchangeSimulationOf<T> = ExchangeSimulation<AssetBalanceOf<T>>;
+    type LiquidityLockOf<T> =
+        LiquidityLock<LiquidityBalanceOf<T>, <T as frame_system::Config>::BlockNumber>;
+    type CumulativePricesOf<T> = CumulativePrices<<T as frame_system::Config>::BlockNumber>;
+    type PricePointOf<T> =
+        (<T as frame_system::Config>::BlockNumber, AssetBalanceOf<T>, AssetBalanceOf<T>);
+    type VolumePointOf<T> =
+        VolumePoint<AssetBalanceOf<T>, <T as frame_system::Config>::BlockNumber>;
+    type OraclePriceOf<T> = OraclePrice<<T as frame_system::Config>::BlockNumber>;
+    type PriceReportsOf<T> =
+        BoundedVec<(AssetIdPairOf<T>, FixedU128, FixedU128), <T as Config>::MaxPriceReports>;
+    type ScheduledRemovalOf<T> = ScheduledRemoval<
+        <T as frame_system::Config>::AccountId,
+        AssetIdPairOf<T>,
+        LiquidityBalanceOf<T>,
+    >;
+    type BootstrapOf<T> = Bootstrap<
+        <T as frame_system::Config>::BlockNumber,
+        BoundedVec<<T as frame_system::Config>::AccountId, <T as Config>::MaxBootstrapProviders>,
+    >;
+    type MinAddAmountOf<T> = MinAddAmount<AssetBalanceOf<T>>;
+    type WeightedAssetOf<T> = WeightedAsset<AssetIdOf<T>, AssetBalanceOf<T>>;
+    type WeightScheduleOf<T> = WeightSchedule<
+        <T as frame_system::Config>::BlockNumber,
+        BoundedVec<Perbill, <T as Config>::MaxWeightedPoolAssets>,
+    >;
+    type WeightedPoolOf<T> = WeightedPool<
+        <T as frame_system::Config>::AccountId,
+        BalanceOf<T>,
+        BoundedVec<WeightedAssetOf<T>, <T as Config>::MaxWeightedPoolAssets>,
+    >;
+
+    /// Identifies a weighted liquidity pool. IDs are assigned sequentially by
+    /// `create_weighted_pool`.
+    pub type WeightedPoolId = u32;
+
+    /// How the liquidity given to the first liquidity provider for an asset pair is determined from
+    /// the amounts of each asset they provide.
+    #[derive(Clone, Copy, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub enum InitialLiquidityFormula {
+        /// The larger of the two amounts. This makes the liquidity depend on which asset has the
+        /// most units (eg due to decimals), so liquidity amounts for different asset pairs are not
+        /// comparable.
+        Max,
+        /// The geometric mean of the two amounts, `sqrt(amount_a * amount_b)`. This is
+        /// independent of the value of a unit of either asset.
+        GeometricMean,
+    }
+
+    /// Which way exchange amounts are rounded when they cannot be computed exactly.
+    ///
+    /// This only affects exchanges. Amounts of assets and liquidity tokens when adding and removing
+    /// liquidity always round in favour of the pool, as the counterparties there are the other
+    /// liquidity providers.
+    #[derive(Clone, Copy, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub enum RoundingPolicy {
+        /// Round in favour of the liquidity pool: the trader receives the amount rounded down, or
+        /// pays the amount rounded up.
+        FavorPool,
+        /// Round in favour of the trader, but only where the fee retained by the pool covers the
+        /// difference, so that the product of the amounts in the pool never decreases. Otherwise,
+        /// round as with `FavorPool`. Without this restriction, a trader could extract value from
+        /// the pool with many tiny exchanges, each gaining a fraction of a unit.
+        FavorTrader,
+    }
+
+    /// A liquidity provider's position in the liquidity pool for an asset pair.
+    #[derive(
+        Clone, Copy, Encode, Decode, Default, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen,
+    )]
+    pub struct LiquidityPosition<LiquidityBalance> {
+        /// The number of liquidity tokens held.
+        pub amount: LiquidityBalance,
+        /// The amount of the first asset of the (ordered) asset pair in the pool per liquidity
+        /// token, as of the last time liquidity was added to the position. If liquidity has been
+        /// added multiple times, this is the average, weighted by the liquidity added each time.
+        pub reserve_a_per_share: FixedU128,
+        /// As `reserve_a_per_share`, but for the second asset of the asset pair.
+        pub reserve_b_per_share: FixedU128,
+    }
+
+    /// Liquidity mining rewards for the liquidity pool for an asset pair. See `fund_rewards`.
+    #[derive(Clone, Copy, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct RewardPot<AssetId, AssetBalance, BlockNumber> {
+        /// The asset the rewards are paid in.
+        pub reward_asset: AssetId,
+        /// The amount of `reward_asset` distributed evenly over the blocks from `start` to `end`.
+        pub amount: AssetBalance,
+        pub start: BlockNumber,
+        pub end: BlockNumber,
+        /// The block up to which rewards have been accrued.
+        pub last_updated: BlockNumber,
+        /// The rewards accrued per liquidity token since the pot was first funded. Rewards accrue
+        /// to liquidity providers in proportion to their share of the liquidity in each block.
+        pub reward_per_share: FixedU128,
+    }
+
+    /// A liquidity provider's share of the liquidity mining rewards for an asset pair.
+    #[derive(
+        Clone, Copy, Encode, Decode, Default, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen,
+    )]
+    pub struct RewardPosition<AssetBalance> {
+        /// The rewards the position's current liquidity would have accrued had it been held since
+        /// the pot was first funded. These are excluded from the rewards owed.
+        pub debt: AssetBalance,
+        /// Rewards accrued but not yet claimed, as of the last change to the position.
+        pub pending: AssetBalance,
+    }
+
+    /// The outcome of an exchange, as determined by `simulate_exchange`.
+    #[derive(Clone, Copy, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo)]
+    pub struct ExchangeSimulation<AssetBalance> {
+        /// The amount of the destination asset that would be received.
+        pub dest_amount: AssetBalance,
+        /// The portion of the source amount that would be added to the pool as a fee.
+        pub fee_amount: AssetBalance,
+        /// The amount of the source asset that would be in the pool after the exchange.
+        pub new_reserve_source: AssetBalance,
+        /// The amount of the destination asset that would be in the pool after the exchange.
+        pub new_reserve_dest: AssetBalance,
+        /// The effective exchange rate, including the fee: the destination amount received per
+        /// unit of the source asset. Zero if the source amount is zero.
+        pub price: FixedU128,
+    }
+
+    /// Which operations the liquidity pool for an asset pair accepts. See `set_pool_status`.
+    ///
+    /// `Active` and `TradingDisabled` encode identically to `false` and `true`, so that this could
+    /// replace the `paused` flag of `PoolInfo` without a storage migration.
+    #[derive(Clone, Copy, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub enum PoolStatus {
+        /// All operations are allowed.
+        Active,
+        /// Exchanges and additions of liquidity are rejected; liquidity can be removed. This is
+        /// set by `pause_pool` and the circuit breaker (see `CircuitBreakerThreshold`),
+        /// and cleared by `resume_pool`.
+        TradingDisabled,
+        /// As `TradingDisabled`, but for winding down the pool: liquidity providers are expected
+        /// to withdraw, and the pool is not expected to be resumed.
+        WithdrawOnly,
+        /// All operations are rejected, including removals of liquidity, eg briefly during a
+        /// migration.
+        Frozen,
+    }
+
+    /// Settings for the liquidity pool for an asset pair.
+    #[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct PoolInfo<AccountId, Balance, BlockNumber> {
+        /// Which operations the pool accepts.
+        pub status: PoolStatus,
+        /// If set, this overrides `ExchangeFee` for exchanges using the pool.
+        pub fee: Option<Permill>,
+        /// The account which created the pool and the deposit reserved from it, until the deposit
+        /// is released (see `PoolDeposit`).
+        pub deposit: Option<(AccountId, Balance)>,
+        /// The account which created the pool, ie added liquidity to it while it was empty. This
+        /// is updated if the pool is drained and created again.
+        pub creator: Option<AccountId>,
+        /// The block in which the pool was created by `creator`.
+        pub created_at: Option<BlockNumber>,
+        /// If set, exchanges which would leave the price of the first asset of the asset pair, in
+        /// units of the second, below this are refused. See `set_price_band`.
+        pub min_rate: Option<FixedU128>,
+        /// As `min_rate`, but the upper bound.
+        pub max_rate: Option<FixedU128>,
+    }
+
+    impl<AccountId, Balance, BlockNumber> Default for PoolInfo<AccountId, Balance, BlockNumber> {
+        fn default() -> Self {
+            Self {
+                status: PoolStatus::Active,
+                fee: None,
+                deposit: None,
+                creator: None,
+                created_at: None,
+                min_rate: None,
+                max_rate: None,
+            }
+        }
+    }
+
+    /// The time-weighted sum of the prices in the liquidity pool for an asset pair, from which
+    /// time-weighted average prices can be derived: the average price between two blocks is the
+    /// difference in the cumulative price divided by the number of blocks. The sums wrap on
+    /// overflow, so differences should be taken with wrapping subtraction of the inner values.
+    #[derive(
+        Clone, Copy, Encode, Decode, Default, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen,
+    )]
+    pub struct CumulativePrices<BlockNumber> {
+        /// The sum over blocks of the price of the first asset of the (ordered) asset pair, in
+        /// units of the second asset.
+        pub price_a: FixedU128,
+        /// As `price_a`, but the price of the second asset in units of the first.
+        pub price_b: FixedU128,
+        /// The block the sums were last advanced to.
+        pub last_updated: BlockNumber,
+    }
+
+    /// The cumulative volume for an asset pair (see `CumulativeVolume`) at the start of a block,
+    /// as recorded in `VolumeHistory`.
+    #[derive(Clone, Copy, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct VolumePoint<AssetBalance, BlockNumber> {
+        pub block: BlockNumber,
+        /// The total amount of the first asset of the (ordered) asset pair exchanged before the
+        /// block.
+        pub volume_a: AssetBalance,
+        /// As `volume_a`, but for the second asset.
+        pub volume_b: AssetBalance,
+    }
+
+    /// The spot prices in the liquidity pool for an asset pair, as reported by the off-chain
+    /// worker.
+    #[derive(Clone, Copy, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct OraclePrice<BlockNumber> {
+        /// The price of the first asset of the (ordered) asset pair, in units of the second asset.
+        pub price_a: FixedU128,
+        /// The price of the second asset in units of the first.
+        pub price_b: FixedU128,
+        /// The block the prices were observed at.
+        pub reported_at: BlockNumber,
+    }
+
+    /// A lock on an amount of an account's liquidity, created with `lock_liquidity`.
+    #[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct LiquidityLock<LiquidityBalance, BlockNumber> {
+        /// The number of liquidity tokens locked.
+        pub amount: LiquidityBalance,
+        /// The lock expires at the start of this block.
+        pub until: BlockNumber,
+    }
+
+    /// A liquidity removal scheduled with `schedule_remove_liquidity`.
+    #[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct ScheduledRemoval<AccountId, AssetIdPair, LiquidityBalance> {
+        /// The account the liquidity is removed for.
+        pub who: AccountId,
+        /// The (ordered) asset pair.
+        pub asset_pair: AssetIdPair,
+        /// The number of liquidity tokens to redeem.
+        pub liquidity: LiquidityBalance,
+    }
+
+    /// An asset in a weighted liquidity pool.
+    #[derive(Clone, Copy, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct WeightedAsset<AssetId, AssetBalance> {
+        pub asset: AssetId,
+        /// The weight of the asset in the pool's invariant. The weights of the assets in a pool
+        /// sum to 100%.
+        pub weight: Perbill,
+        /// The amount of the asset in the pool.
+        pub reserve: AssetBalance,
+    }
+
+    /// A weighted liquidity pool, created with `create_weighted_pool`.
+    #[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct WeightedPool<AccountId, Balance, Assets> {
+        /// The assets in the pool, in the order given when the pool was created.
+        pub assets: Assets,
+        /// The account which created the pool and the deposit reserved from it (see
+        /// `PoolDeposit`). The deposit is released when the pool is drained.
+        pub deposit: (AccountId, Balance),
+    }
+
+    /// The weights of a liquidity bootstrapping pool over time, set by `create_lbp`. Weights are
+    /// in the order of the pool's assets.
+    #[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct WeightSchedule<BlockNumber, Weights> {
+        /// The block from which the weights start shifting.
+        pub start_block: BlockNumber,
+        /// The block from which the end weights apply.
+        pub end_block: BlockNumber,
+        pub start_weights: Weights,
+        pub end_weights: Weights,
+    }
+
+    /// Restrictions on a liquidity pool until the start of block `until`, set by `register_pool`.
+    /// These allow a project launching an asset to set a fair initial price before the pool opens
+    /// to everyone.
+    #[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct Bootstrap<BlockNumber, Providers> {
+        pub until: BlockNumber,
+        /// The only accounts which may add liquidity to the pool.
+        pub providers: Providers,
+        /// If set, only `providers` may exchange using the pool too.
+        pub restrict_exchanges: bool,
+    }
+
+    /// The smallest addition of liquidity accepted by a non-empty liquidity pool, set by
+    /// `set_min_add_amount`. This stops a pool being spammed with near-worthless positions.
+    #[derive(Clone, Copy, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub enum MinAddAmount<AssetBalance> {
+        /// The amount of each asset added must be at least this fraction of the pool's reserve of
+        /// the asset.
+        Fraction(Permill),
+        /// The amounts of the first and second assets of the (ordered) asset pair added must be at
+        /// least these amounts.
+        Absolute(AssetBalance, AssetBalance),
+    }
+
+    /// The in-code storage version.
+    const STORAGE_VERSION: StorageVersion = StorageVersion::new(11);
 
     #[pallet::pallet]
     #[pallet::generate_store(pub(super) trait Store)]
+    #[pallet::storage_version(STORAGE_VERSION)]
     pub struct Pallet<T>(_);
 
     /// Track the total liquidity of each asset pair. Note that this means the number of liquidity
@@ -101,6 +667,24 @@ pub mod pallet {
     pub type TotalLiquidity<T> =
         StorageMap<_, Blake2_128Concat, AssetIdPairOf<T>, LiquidityBalanceOf<T>, ValueQuery>;
 
+    /// The number of asset pairs with non-zero total liquidity, plus the number of weighted
+    /// liquidity pools (see `MaxPools`).
+    #[pallet::storage]
+    pub type PoolCount<T> = StorageValue<_, u32, ValueQuery>;
+
+    /// Track the amount of each asset in the liquidity pool for each asset pair, in asset pair
+    /// order. This is normally the balance of the pool account, but assets transferred directly to
+    /// the pool account are never counted (see `donate`), and a balance which has fallen below its
+    /// reserve is only counted once `sync` is called.
+    #[pallet::storage]
+    pub type Reserves<T: Config> = StorageMap<
+        _,
+        Blake2_128Concat,
+        AssetIdPairOf<T>,
+        (AssetBalanceOf<T>, AssetBalanceOf<T>),
+        ValueQuery,
+    >;
+
     /// Track the liquidity provided for each asset pair by each account.
     ///
     /// Guess that it's probably more useful to be able to efficiently iterate over all liquidity
@@ -113,404 +697,6144 @@ pub mod pallet {
         T::AccountId,
         Blake2_128Concat,
         AssetIdPairOf<T>,
-        LiquidityBalanceOf<T>,
+        LiquidityPositionOf<T>,
         ValueQuery,
     >;
 
-    #[pallet::event]
-    #[pallet::generate_deposit(pub(super) fn deposit_event)]
-    pub enum Event<T: Config> {
-        LiquidityAdded {
-            who: T::AccountId,
-            asset_a: AssetIdOf<T>,
-            amount_a: AssetBalanceOf<T>,
-            asset_b: AssetIdOf<T>,
-            amount_b: AssetBalanceOf<T>,
-            liquidity: LiquidityBalanceOf<T>,
-        },
-        LiquidityRemoved {
-            who: T::AccountId,
-            asset_a: AssetIdOf<T>,
-            amount_a: AssetBalanceOf<T>,
-            asset_b: AssetIdOf<T>,
-            amount_b: AssetBalanceOf<T>,
-            liquidity: LiquidityBalanceOf<T>,
-        },
-        Exchanged {
-            who: T::AccountId,
-            source_asset: AssetIdOf<T>,
-            source_amount: AssetBalanceOf<T>,
-            dest_asset: AssetIdOf<T>,
-            dest_amount: AssetBalanceOf<T>,
-        },
-    }
+    /// The number of asset pairs and weighted liquidity pools each account holds liquidity for, ie
+    /// the number of `Liquidity` and `WeightedLiquidity` entries for the account (see
+    /// `MaxPositionsPerAccount`).
+    #[pallet::storage]
+    pub type PositionCount<T: Config> =
+        StorageMap<_, Blake2_128Concat, T::AccountId, u32, ValueQuery>;
 
-    #[pallet::error]
-    pub enum Error<T> {
-        /// The two assets provided were identical; it does not make sense to exchange between
-        /// them.
-        AssetsIdentical,
-        /// The sender did not leave enough of each asset in the liquidity pool for the asset pair.
-        InsufficientPoolAmount,
-        /// The liquidity pool for the asset pair is empty.
-        NoLiquidity,
-        /// The transaction was aborted as the effective exchange rate was too far from that
-        /// expected by the sender.
-        UnexpectedExchangeRate,
-    }
+    /// The deposit reserved for each liquidity position (see `PositionDeposit`). Positions created
+    /// while the deposit was zero have no entry.
+    #[pallet::storage]
+    pub type PositionDeposits<T: Config> = StorageDoubleMap<
+        _,
+        Blake2_128Concat,
+        T::AccountId,
+        Blake2_128Concat,
+        AssetIdPairOf<T>,
+        BalanceOf<T>,
+        OptionQuery,
+    >;
 
-    fn make_asset_pair<T: Config>(
-        a: AssetIdOf<T>,
-        b: AssetIdOf<T>,
-    ) -> Result<AssetIdPairOf<T>, DispatchError> {
-        ensure!(a != b, Error::<T>::AssetsIdentical);
-        Ok(if a.encode() < b.encode() { (a, b) } else { (b, a) })
-    }
+    /// Track the block in which liquidity was last added for each account and asset pair. This is
+    /// only populated if `MinLockPeriod` is non-zero.
+    #[pallet::storage]
+    pub type LiquidityAddedAt<T: Config> = StorageDoubleMap<
+        _,
+        Blake2_128Concat,
+        T::AccountId,
+        Blake2_128Concat,
+        AssetIdPairOf<T>,
+        T::BlockNumber,
+        OptionQuery,
+    >;
 
-    fn get_pool_account<T: Config>(asset_pair: AssetIdPairOf<T>) -> T::AccountId {
-        T::PalletId::get().into_sub_account_truncating(asset_pair)
-    }
+    /// Track the block in which liquidity was last added or removed for each account and asset
+    /// pair. This is only populated if `LiquidityCooldown` is non-zero, and is kept after a full
+    /// exit so that liquidity cannot immediately be added back.
+    #[pallet::storage]
+    pub type LastLiquidityChange<T: Config> = StorageDoubleMap<
+        _,
+        Blake2_128Concat,
+        T::AccountId,
+        Blake2_128Concat,
+        AssetIdPairOf<T>,
+        T::BlockNumber,
+        OptionQuery,
+    >;
 
-    fn add<T: CheckedAdd>(a: T, b: T) -> Result<T, ArithmeticError> {
-        a.checked_add(&b).ok_or(ArithmeticError::Overflow)
-    }
+    /// Settings for the liquidity pool for each asset pair. Asset pairs without an entry use the
+    /// default settings. Entries are removed by `cleanup_pool`.
+    #[pallet::storage]
+    pub type Pools<T: Config> =
+        StorageMap<_, Blake2_128Concat, AssetIdPairOf<T>, PoolInfoOf<T>, ValueQuery>;
 
-    fn sub<T: CheckedSub>(a: T, b: T) -> Result<T, ArithmeticError> {
-        a.checked_sub(&b).ok_or(ArithmeticError::Underflow)
-    }
+    /// Assets which cannot be added to liquidity pools or exchanged. Liquidity can still be
+    /// removed from existing pools containing them.
+    #[pallet::storage]
+    pub type BlacklistedAssets<T> = StorageMap<_, Blake2_128Concat, AssetIdOf<T>, (), OptionQuery>;
 
-    fn mul<T: Into<BalanceMulResult>>(a: T, b: T) -> Result<BalanceMulResult, ArithmeticError> {
-        <T as Into<BalanceMulResult>>::into(a)
-            .checked_mul(b.into())
-            .ok_or(ArithmeticError::Overflow)
-    }
+    /// Assets which can be used to create liquidity pools while `WhitelistOnly` is set.
+    #[pallet::storage]
+    pub type WhitelistedAssets<T> = StorageMap<_, Blake2_128Concat, AssetIdOf<T>, (), OptionQuery>;
 
-    /// `floor((a * b) / c)`
-    fn mul_div_floor<T: Into<BalanceMulResult> + TryFrom<BalanceMulResult>>(
-        a: T,
-        b: T,
-        c: T,
-    ) -> Result<T, ArithmeticError> {
-        let res: BalanceMulResult =
-            mul(a, b)?.checked_div(c.into()).ok_or(ArithmeticError::DivisionByZero)?;
-        <T as TryFrom<BalanceMulResult>>::try_from(res).map_err(|_| ArithmeticError::Overflow)
-    }
+    /// If set, liquidity pools can only be created (ie liquidity can only be added to empty
+    /// pools) for asset pairs where both assets are in `WhitelistedAssets`. Existing pools are
+    /// unaffected.
+    #[pallet::storage]
+    pub type WhitelistOnly<T> = StorageValue<_, bool, ValueQuery>;
 
-    /// `ceil((a * b) / c)`
-    fn mul_div_ceil<T: Into<BalanceMulResult> + TryFrom<BalanceMulResult> + Copy>(
-        a: T,
-        b: T,
-        c: T,
-    ) -> Result<T, ArithmeticError> {
-        let c_minus_one = <T as Into<BalanceMulResult>>::into(c)
-            .checked_sub(1u32.into())
-            .ok_or(ArithmeticError::Underflow)?;
-        let biased = mul(a, b)?.checked_add(c_minus_one).ok_or(ArithmeticError::Overflow)?;
-        let res: BalanceMulResult =
-            biased.checked_div(c.into()).ok_or(ArithmeticError::DivisionByZero)?;
-        <T as TryFrom<BalanceMulResult>>::try_from(res).map_err(|_| ArithmeticError::Overflow)
-    }
+    /// Asset pairs which anyone may create liquidity pools for, even if `RestrictPoolCreation`
+    /// is set. See `register_pool`.
+    #[pallet::storage]
+    pub type RegisteredPools<T> =
+        StorageMap<_, Blake2_128Concat, AssetIdPairOf<T>, (), OptionQuery>;
 
-    #[pallet::call]
-    impl<T: Config> Pallet<T> {
-        /// Add liquidity for an asset pair.
+    /// The asset pair for each liquidity token asset ID (see `lp_asset_id`), recorded when the
+    /// liquidity pool for the pair is created. Entries are left in place when the pool is removed
+    /// or migrated, so that liquidity tokens can always be resolved.
+    #[pallet::storage]
+    pub type LpAssetLookup<T: Config> =
+        StorageMap<_, Blake2_128Concat, AssetIdOf<T>, AssetIdPairOf<T>, OptionQuery>;
+
+    /// The bootstrap period of each registered asset pair, if any. Entries are removed once the
+    /// period has ended, by `end_bootstrap` or the next addition of liquidity to, or exchange
+    /// using, the liquidity pool.
+    #[pallet::storage]
+    pub type Bootstraps<T: Config> =
+        StorageMap<_, Blake2_128Concat, AssetIdPairOf<T>, BootstrapOf<T>, OptionQuery>;
+
+    /// The minimum addition of liquidity for each asset pair, if any. See `set_min_add_amount`.
+    #[pallet::storage]
+    pub type MinAddAmounts<T: Config> =
+        StorageMap<_, Blake2_128Concat, AssetIdPairOf<T>, MinAddAmountOf<T>, OptionQuery>;
+
+    /// Overrides of `InitialLiquidityPerAssetUnit` for individual asset pairs. See
+    /// `set_initial_liquidity_per_asset_unit`.
+    #[pallet::storage]
+    pub type InitialLiquidityPerAssetUnitOverrides<T: Config> =
+        StorageMap<_, Blake2_128Concat, AssetIdPairOf<T>, LiquidityBalanceOf<T>, OptionQuery>;
+
+    /// If set, exchanges and additions of liquidity are rejected for all liquidity pools. See
+    /// `set_paused`.
+    #[pallet::storage]
+    pub type Paused<T> = StorageValue<_, bool, ValueQuery>;
+
+    /// Track the cumulative prices in the liquidity pool for each asset pair. These are advanced
+    /// before the amounts in a pool change, and periodically in `on_initialize` (see
+    /// `MaxOracleUpdatesPerBlock`). Blocks in which the pool is empty are not counted.
+    #[pallet::storage]
+    pub type PriceCumulative<T> =
+        StorageMap<_, Blake2_128Concat, AssetIdPairOf<T>, CumulativePricesOf<T>, ValueQuery>;
+
+    /// The spot price of the first asset of each asset pair, in units of the second, before the
+    /// first exchange in the given block. Only tracked if `CircuitBreakerThreshold` is set.
+    #[pallet::storage]
+    pub type BlockStartPrices<T: Config> =
+        StorageMap<_, Blake2_128Concat, AssetIdPairOf<T>, (T::BlockNumber, FixedU128), OptionQuery>;
+
+    /// The amounts of each asset in the liquidity pool for each asset pair before they were first
+    /// changed in the given block. See `opening_exchange_rate`.
+    #[pallet::storage]
+    pub type BlockOpeningReserves<T: Config> = StorageMap<
+        _,
+        Blake2_128Concat,
+        AssetIdPairOf<T>,
+        (AssetBalanceOf<T>, AssetBalanceOf<T>, T::BlockNumber),
+        OptionQuery,
+    >;
+
+    /// Observations of the cumulative prices for each asset pair, oldest first. An observation is
+    /// recorded in each block in which the amounts in the liquidity pool change, before the first
+    /// change, so the price is constant between consecutive observations.
+    #[pallet::storage]
+    pub type Observations<T: Config> = StorageMap<
+        _,
+        Blake2_128Concat,
+        AssetIdPairOf<T>,
+        BoundedVec<CumulativePricesOf<T>, T::ObservationCardinality>,
+        ValueQuery,
+    >;
+
+    /// The amounts of each asset in the liquidity pool for each asset pair at the start of each of
+    /// the most recent blocks with exchanges, ie before the block's first exchange. At most
+    /// `MaxHistoryLen` entries are kept, in a ring buffer starting from the oldest entry at
+    /// `PriceHistoryHead`; once it is full, each new block overwrites the oldest entry. See
+    /// `price_history`.
+    #[pallet::storage]
+    pub type PriceHistory<T: Config> = StorageMap<
+        _,
+        Blake2_128Concat,
+        AssetIdPairOf<T>,
+        BoundedVec<PricePointOf<T>, T::MaxHistoryLen>,
+        ValueQuery,
+    >;
+
+    /// The index of the oldest entry in `PriceHistory` for each asset pair.
+    #[pallet::storage]
+    pub type PriceHistoryHead<T: Config> =
+        StorageMap<_, Blake2_128Concat, AssetIdPairOf<T>, u32, ValueQuery>;
+
+    /// The total amount of each asset exchanged into or out of the liquidity pool for each asset
+    /// pair, in the order of the asset pair. These saturate rather than overflow. See `get_volume`.
+    #[pallet::storage]
+    pub type CumulativeVolume<T: Config> = StorageMap<
+        _,
+        Blake2_128Concat,
+        AssetIdPairOf<T>,
+        (AssetBalanceOf<T>, AssetBalanceOf<T>),
+        ValueQuery,
+    >;
+
+    /// The cumulative volume for each asset pair (see `CumulativeVolume`) at the start of each of
+    /// the most recent blocks with exchanges. At most `MaxHistoryLen` entries are kept, in a ring
+    /// buffer starting from the oldest entry at `VolumeHistoryHead`, as for `PriceHistory`. See
+    /// `estimate_apr`.
+    #[pallet::storage]
+    pub type VolumeHistory<T: Config> = StorageMap<
+        _,
+        Blake2_128Concat,
+        AssetIdPairOf<T>,
+        BoundedVec<VolumePointOf<T>, T::MaxHistoryLen>,
+        ValueQuery,
+    >;
+
+    /// The index of the oldest entry in `VolumeHistory` for each asset pair.
+    #[pallet::storage]
+    pub type VolumeHistoryHead<T: Config> =
+        StorageMap<_, Blake2_128Concat, AssetIdPairOf<T>, u32, ValueQuery>;
+
+    /// The asset pair whose cumulative prices were last advanced in `on_initialize`. The next
+    /// block continues from the following pool in `TotalLiquidity`.
+    #[pallet::storage]
+    pub type OracleCursor<T> = StorageValue<_, AssetIdPairOf<T>, OptionQuery>;
+
+    /// The spot prices in the liquidity pool for each asset pair, as last reported by the
+    /// off-chain worker (see `OffchainPriceReporting`).
+    #[pallet::storage]
+    pub type OraclePrices<T: Config> =
+        StorageMap<_, Blake2_128Concat, AssetIdPairOf<T>, OraclePriceOf<T>, OptionQuery>;
+
+    /// The asset pair whose spot prices were last reported. The off-chain worker continues from
+    /// the following pool in `TotalLiquidity`.
+    #[pallet::storage]
+    pub type PriceReportCursor<T> = StorageValue<_, AssetIdPairOf<T>, OptionQuery>;
+
+    /// The block the last accepted price report was made at. Reports for this or earlier blocks
+    /// are rejected.
+    #[pallet::storage]
+    pub type LastPriceReport<T: Config> = StorageValue<_, T::BlockNumber, ValueQuery>;
+
+    /// Track the locks on the liquidity provided for each asset pair by each account. Expired locks
+    /// are ignored, and removed by `unlock_liquidity` or when another lock is added.
+    #[pallet::storage]
+    pub type LiquidityLocks<T: Config> = StorageDoubleMap<
+        _,
+        Blake2_128Concat,
+        T::AccountId,
+        Blake2_128Concat,
+        AssetIdPairOf<T>,
+        BoundedVec<LiquidityLockOf<T>, T::MaxLiquidityLocks>,
+        ValueQuery,
+    >;
+
+    /// Liquidity mining rewards for each asset pair. See `fund_rewards`.
+    #[pallet::storage]
+    pub type RewardPots<T: Config> =
+        StorageMap<_, Blake2_128Concat, AssetIdPairOf<T>, RewardPotOf<T>, OptionQuery>;
+
+    /// Liquidity mining rewards for each asset pair which were not distributed to liquidity
+    /// providers, either because the pool was empty or because of rounding. These are held in the
+    /// rewards account until swept with `sweep_rewards`.
+    #[pallet::storage]
+    pub type UndistributedRewards<T: Config> =
+        StorageMap<_, Blake2_128Concat, AssetIdPairOf<T>, AssetBalanceOf<T>, ValueQuery>;
+
+    /// Track the liquidity mining rewards owed to each account for each asset pair. Entries are
+    /// only kept for accounts with liquidity in, or rewards still to claim from, a pool with a
+    /// reward pot.
+    #[pallet::storage]
+    pub type RewardPositions<T: Config> = StorageDoubleMap<
+        _,
+        Blake2_128Concat,
+        T::AccountId,
+        Blake2_128Concat,
+        AssetIdPairOf<T>,
+        RewardPositionOf<T>,
+        ValueQuery,
+    >;
+
+    /// Liquidity removals scheduled for each block. These are executed, in order, in
+    /// `on_initialize` of the block.
+    #[pallet::storage]
+    pub type ScheduledRemovals<T: Config> = StorageMap<
+        _,
+        Twox64Concat,
+        T::BlockNumber,
+        BoundedVec<ScheduledRemovalOf<T>, T::MaxScheduledRemovalsPerBlock>,
+        ValueQuery,
+    >;
+
+    /// The ID the next weighted liquidity pool will be created with.
+    #[pallet::storage]
+    pub type NextWeightedPoolId<T> = StorageValue<_, WeightedPoolId, ValueQuery>;
+
+    /// The weighted liquidity pools. Pools are removed when their last liquidity tokens are
+    /// redeemed.
+    #[pallet::storage]
+    pub type WeightedPools<T: Config> =
+        StorageMap<_, Twox64Concat, WeightedPoolId, WeightedPoolOf<T>, OptionQuery>;
+
+    /// The weight schedules of liquidity bootstrapping pools (see `create_lbp`). The weights in
+    /// `WeightedPools` are those last used by an exchange; a pool's schedule is removed, freezing
+    /// its weights, by the first exchange after the schedule ends.
+    #[pallet::storage]
+    pub type WeightSchedules<T: Config> =
+        StorageMap<_, Twox64Concat, WeightedPoolId, WeightScheduleOf<T>, OptionQuery>;
+
+    /// Track the total liquidity of each weighted liquidity pool, as `TotalLiquidity` does for
+    /// asset pairs.
+    #[pallet::storage]
+    pub type WeightedTotalLiquidity<T> =
+        StorageMap<_, Twox64Concat, WeightedPoolId, LiquidityBalanceOf<T>, ValueQuery>;
+
+    /// Track the liquidity provided to each weighted liquidity pool by each account.
+    #[pallet::storage]
+    pub type WeightedLiquidity<T: Config> = StorageDoubleMap<
+        _,
+        Blake2_128Concat,
+        T::AccountId,
+        Twox64Concat,
+        WeightedPoolId,
+        LiquidityBalanceOf<T>,
+        ValueQuery,
+    >;
+
+    /// The deposit reserved for each position in a weighted liquidity pool, as for
+    /// `PositionDeposits`.
+    #[pallet::storage]
+    pub type WeightedPositionDeposits<T: Config> = StorageDoubleMap<
+        _,
+        Blake2_128Concat,
+        T::AccountId,
+        Twox64Concat,
+        WeightedPoolId,
+        BalanceOf<T>,
+        OptionQuery,
+    >;
+
+    #[pallet::genesis_config]
+    pub struct GenesisConfig<T: Config> {
+        /// Liquidity to add at genesis, as `(account, asset_a, amount_a, asset_b, amount_b)`. The
+        /// given amounts are transferred from the accounts, which must be funded by the genesis
+        /// config of the `Fungibles` implementation. Entries are applied in order, as if by
+        /// `add_liquidity`, so an entry for an existing pool must match its exchange rate.
+        pub pools: Vec<(T::AccountId, T::AssetId, T::AssetBalance, T::AssetId, T::AssetBalance)>,
+    }
+
+    #[cfg(feature = "std")]
+    impl<T: Config> Default for GenesisConfig<T> {
+        fn default() -> Self {
+            Self { pools: Vec::new() }
+        }
+    }
+
+    #[pallet::genesis_build]
+    impl<T: Config> GenesisBuild<T> for GenesisConfig<T> {
+        fn build(&self) {
+            for (who, asset_a, amount_a, asset_b, amount_b) in &self.pools {
+                Pallet::<T>::do_add_liquidity(
+                    who,
+                    *asset_a,
+                    *amount_a,
+                    *amount_a,
+                    *asset_b,
+                    *amount_b,
+                    *amount_b,
+                    Zero::zero(),
+                    false,
+                )
+                .expect("Genesis liquidity could not be added");
+            }
+        }
+    }
+
+    #[pallet::event]
+    #[pallet::generate_deposit(pub(super) fn deposit_event)]
+    pub enum Event<T: Config> {
+        LiquidityAdded {
+            who: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            amount_a: AssetBalanceOf<T>,
+            asset_b: AssetIdOf<T>,
+            amount_b: AssetBalanceOf<T>,
+            liquidity: LiquidityBalanceOf<T>,
+            new_reserve_a: AssetBalanceOf<T>,
+            new_reserve_b: AssetBalanceOf<T>,
+        },
+        LiquidityRemoved {
+            who: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            amount_a: AssetBalanceOf<T>,
+            asset_b: AssetIdOf<T>,
+            amount_b: AssetBalanceOf<T>,
+            liquidity: LiquidityBalanceOf<T>,
+            new_reserve_a: AssetBalanceOf<T>,
+            new_reserve_b: AssetBalanceOf<T>,
+        },
+        LiquidityTransferred {
+            from: T::AccountId,
+            to: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            liquidity: LiquidityBalanceOf<T>,
+        },
+        Exchanged {
+            who: T::AccountId,
+            source_asset: AssetIdOf<T>,
+            source_amount: AssetBalanceOf<T>,
+            dest_asset: AssetIdOf<T>,
+            dest_amount: AssetBalanceOf<T>,
+            fee_amount: AssetBalanceOf<T>,
+            new_reserve_source: AssetBalanceOf<T>,
+            new_reserve_dest: AssetBalanceOf<T>,
+        },
+        /// Part of the fee for an exchange was paid to `referrer`. This follows the `Exchanged`
+        /// event, whose `fee_amount` includes `amount`.
+        ReferralFeePaid {
+            referrer: T::AccountId,
+            asset: AssetIdOf<T>,
+            amount: AssetBalanceOf<T>,
+        },
+        FeesClaimed {
+            who: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            amount_a: AssetBalanceOf<T>,
+            asset_b: AssetIdOf<T>,
+            amount_b: AssetBalanceOf<T>,
+        },
+        RewardsFunded {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            reward_asset: AssetIdOf<T>,
+            amount: AssetBalanceOf<T>,
+            start: T::BlockNumber,
+            end: T::BlockNumber,
+        },
+        RewardsClaimed {
+            who: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            reward_asset: AssetIdOf<T>,
+            amount: AssetBalanceOf<T>,
+        },
+        Donated {
+            who: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            amount_a: AssetBalanceOf<T>,
+            asset_b: AssetIdOf<T>,
+            amount_b: AssetBalanceOf<T>,
+        },
+        /// The reserves of a liquidity pool were lowered to the balances of the pool account where
+        /// these had fallen below them.
+        Synced {
+            asset_a: AssetIdOf<T>,
+            reserve_a: AssetBalanceOf<T>,
+            asset_b: AssetIdOf<T>,
+            reserve_b: AssetBalanceOf<T>,
+        },
+        /// Liquidity was added to an empty liquidity pool. This precedes the `LiquidityAdded`
+        /// event.
+        PoolCreated {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            pool_account: T::AccountId,
+        },
+        /// All liquidity was removed from a liquidity pool. This follows the `LiquidityRemoved`
+        /// event. Any assets left in the pool account can be swept with `cleanup_pool`.
+        PoolDestroyed {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            pool_account: T::AccountId,
+        },
+        PoolPaused {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        },
+        PoolResumed {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        },
+        PoolStatusChanged {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            status: PoolStatus,
+        },
+        /// An exchange would have moved the exchange rate in the liquidity pool for an asset pair
+        /// by more than `CircuitBreakerThreshold` within a single block. The exchange was not
+        /// executed, and the pool has been paused.
+        CircuitBreakerTripped {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        },
+        PalletPaused,
+        PalletUnpaused,
+        PoolFeeChanged {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            fee: Option<Permill>,
+        },
+        PriceBandChanged {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            min_rate: Option<FixedU128>,
+            max_rate: Option<FixedU128>,
+        },
+        AssetBlacklisted {
+            asset: AssetIdOf<T>,
+        },
+        AssetUnblacklisted {
+            asset: AssetIdOf<T>,
+        },
+        AssetWhitelisted {
+            asset: AssetIdOf<T>,
+        },
+        AssetUnwhitelisted {
+            asset: AssetIdOf<T>,
+        },
+        WhitelistOnlyChanged {
+            enabled: bool,
+        },
+        PoolRegistered {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            bootstrap_until: Option<T::BlockNumber>,
+        },
+        MinAddAmountChanged {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            min_add_amount: Option<MinAddAmountOf<T>>,
+        },
+        InitialLiquidityPerAssetUnitChanged {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            per_asset_unit: Option<LiquidityBalanceOf<T>>,
+        },
+        LiquidityLocked {
+            who: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            amount: LiquidityBalanceOf<T>,
+            until: T::BlockNumber,
+        },
+        /// Expired locks were removed; `amount` is the total liquidity they locked.
+        LiquidityUnlocked {
+            who: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            amount: LiquidityBalanceOf<T>,
+        },
+        PoolFundsRescued {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            stray_asset: AssetIdOf<T>,
+            amount: AssetBalanceOf<T>,
+            beneficiary: T::AccountId,
+        },
+        /// The liquidity pool for an asset pair was drained to `recipient` by `admin_drain_pool`,
+        /// and all liquidity positions in it were removed. This is followed by the `PoolRemoved`
+        /// event.
+        PoolDrainedByAdmin {
+            asset_a: AssetIdOf<T>,
+            amount_a: AssetBalanceOf<T>,
+            asset_b: AssetIdOf<T>,
+            amount_b: AssetBalanceOf<T>,
+            recipient: T::AccountId,
+        },
+        /// The liquidity pool for `old_asset` and `counterpart_asset` was replaced by a pool for
+        /// `new_asset` and `counterpart_asset`, by `force_migrate_pool`.
+        PoolMigrated {
+            old_asset: AssetIdOf<T>,
+            new_asset: AssetIdOf<T>,
+            counterpart_asset: AssetIdOf<T>,
+            old_reserve: AssetBalanceOf<T>,
+            new_reserve: AssetBalanceOf<T>,
+        },
+        /// The deposit reserved from `who` when they created the liquidity pool was released.
+        PoolDepositReleased {
+            who: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            amount: BalanceOf<T>,
+        },
+        PoolCleanedUp {
+            asset_a: AssetIdOf<T>,
+            amount_a: AssetBalanceOf<T>,
+            asset_b: AssetIdOf<T>,
+            amount_b: AssetBalanceOf<T>,
+        },
+        /// All storage associated with a drained liquidity pool was removed by `cleanup_pool` or
+        /// `admin_drain_pool`. This follows the `PoolCleanedUp` or `PoolDrainedByAdmin` event.
+        PoolRemoved {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        },
+        RemovalScheduled {
+            who: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            liquidity: LiquidityBalanceOf<T>,
+            at_block: T::BlockNumber,
+        },
+        ScheduledRemovalCancelled {
+            who: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            at_block: T::BlockNumber,
+        },
+        /// A scheduled removal was executed. This follows the usual `LiquidityRemoved` event.
+        ScheduledRemovalExecuted {
+            who: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            liquidity: LiquidityBalanceOf<T>,
+        },
+        /// A scheduled removal could not be executed, eg because the position shrank. The
+        /// position is left untouched.
+        ScheduledRemovalFailed {
+            who: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            liquidity: LiquidityBalanceOf<T>,
+            error: DispatchError,
+        },
+        /// A weighted liquidity pool was created. This precedes the `WeightedLiquidityAdded`
+        /// event.
+        WeightedPoolCreated {
+            pool_id: WeightedPoolId,
+            who: T::AccountId,
+            pool_account: T::AccountId,
+        },
+        /// Liquidity was added to a weighted liquidity pool. `amounts` are in the order of the
+        /// pool's assets.
+        WeightedLiquidityAdded {
+            who: T::AccountId,
+            pool_id: WeightedPoolId,
+            amounts: Vec<AssetBalanceOf<T>>,
+            liquidity: LiquidityBalanceOf<T>,
+        },
+        /// Liquidity was removed from a weighted liquidity pool. `amounts` are in the order of
+        /// the pool's assets.
+        WeightedLiquidityRemoved {
+            who: T::AccountId,
+            pool_id: WeightedPoolId,
+            amounts: Vec<AssetBalanceOf<T>>,
+            liquidity: LiquidityBalanceOf<T>,
+        },
+        WeightedExchanged {
+            who: T::AccountId,
+            pool_id: WeightedPoolId,
+            source_asset: AssetIdOf<T>,
+            source_amount: AssetBalanceOf<T>,
+            dest_asset: AssetIdOf<T>,
+            dest_amount: AssetBalanceOf<T>,
+            fee_amount: AssetBalanceOf<T>,
+        },
+        /// A weighted liquidity pool was created as a liquidity bootstrapping pool. This follows
+        /// the `WeightedLiquidityAdded` event.
+        LbpCreated {
+            pool_id: WeightedPoolId,
+            start_block: T::BlockNumber,
+            end_block: T::BlockNumber,
+        },
+        /// All liquidity was removed from a weighted liquidity pool and the pool was removed. This
+        /// follows the `WeightedLiquidityRemoved` event.
+        WeightedPoolDestroyed {
+            pool_id: WeightedPoolId,
+        },
+        /// The bootstrap period of an asset pair ended and was removed.
+        BootstrapEnded {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        },
+        /// Liquidity mining rewards for an asset pair which were not distributed to liquidity
+        /// providers were transferred to `recipient`.
+        RewardsReturned {
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            reward_asset: AssetIdOf<T>,
+            amount: AssetBalanceOf<T>,
+            recipient: T::AccountId,
+        },
+    }
+
+    #[pallet::hooks]
+    impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+        fn on_initialize(n: T::BlockNumber) -> Weight {
+            let removals = ScheduledRemovals::<T>::take(n);
+            let count = removals.len() as u64;
+            for removal in removals {
+                let ScheduledRemoval { who, asset_pair, liquidity } = removal;
+                match Self::do_remove_liquidity(&who, asset_pair.0, asset_pair.1, liquidity) {
+                    Ok(_) => Self::deposit_event(Event::ScheduledRemovalExecuted {
+                        who,
+                        asset_a: asset_pair.0,
+                        asset_b: asset_pair.1,
+                        liquidity,
+                    }),
+                    Err(error) => Self::deposit_event(Event::ScheduledRemovalFailed {
+                        who,
+                        asset_a: asset_pair.0,
+                        asset_b: asset_pair.1,
+                        liquidity,
+                        error,
+                    }),
+                }
+            }
+
+            let updates = Self::advance_oracle() as u64;
+
+            // TODO: benchmark. For now, per removal count the position, lock, total liquidity,
+            // and the pool and sender balances of each asset, plus taking the schedule. Per
+            // oracle update count the total liquidity, cumulative prices, and pool balances, plus
+            // the cursor.
+            T::DbWeight::get()
+                .reads_writes(1 + count * 9, 1 + count * 7)
+                .saturating_add(T::DbWeight::get().reads_writes(2 + updates * 5, 1 + updates))
+        }
+
+        fn offchain_worker(n: T::BlockNumber) {
+            if T::OffchainPriceReporting::get() {
+                // There is nothing useful to do if submission fails; the next block's worker will
+                // try again
+                let _ = Self::submit_price_report(n);
+            }
+        }
+
+        fn on_runtime_upgrade() -> Weight {
+            crate::migrations::migrate::<T>()
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn pre_upgrade() -> Result<(), &'static str> {
+            crate::migrations::pre_upgrade::<T>()
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn post_upgrade() -> Result<(), &'static str> {
+            crate::migrations::post_upgrade::<T>()
+        }
+
+        fn integrity_test() {
+            assert!(T::ExchangeFee::get() < Permill::one(), "ExchangeFee must be less than 100%");
+            assert!(
+                !T::PoolMinAmountMultiple::get().is_zero(),
+                "PoolMinAmountMultiple must be non-zero"
+            );
+            assert!(
+                !T::InitialLiquidityPerAssetUnit::get().is_zero(),
+                "InitialLiquidityPerAssetUnit must be non-zero"
+            );
+            assert!(T::MaxRouteLength::get() >= 2, "MaxRouteLength must be at least 2");
+            assert!(
+                T::MaxWeightedPoolAssets::get() >= 2,
+                "MaxWeightedPoolAssets must be at least 2"
+            );
+            assert!(
+                is_account_id_wide_enough::<T::AccountId>(get_pool_sub_account_len::<T>()),
+                "AccountId is too narrow to derive distinct liquidity pool accounts"
+            );
+        }
+    }
+
+    #[pallet::error]
+    pub enum Error<T> {
+        /// The two assets provided were identical; it does not make sense to exchange between
+        /// them.
+        AssetsIdentical,
+        /// The sender did not leave enough of each asset in the liquidity pool for the asset pair.
+        /// The amounts required to add liquidity can be determined with `min_add_amounts`.
+        InsufficientPoolAmount,
+        /// The liquidity pool for the asset pair is empty.
+        NoLiquidity,
+        /// The transaction was aborted as the effective exchange rate was too far from that
+        /// expected by the sender.
+        UnexpectedExchangeRate,
+        /// The sender has not earned any fees to claim for the asset pair.
+        NoFeesEarned,
+        /// No liquidity pool has been created for the asset pair.
+        PoolNotFound,
+        /// The reward period must end after it starts, and must not start in the past.
+        InvalidRewardPeriod,
+        /// The rewards for the asset pair are still being distributed.
+        RewardsActive,
+        /// Rewards for an asset pair must always be paid in the same asset.
+        RewardAssetMismatch,
+        /// The sender has no liquidity mining rewards to claim for the asset pair.
+        NoRewards,
+        /// The sender added liquidity for the asset pair too recently to remove any.
+        LiquidityLocked,
+        /// The exchange would move the exchange rate too far.
+        ExcessivePriceImpact,
+        /// The liquidity pool for the asset pair does not contain enough of the destination asset.
+        InsufficientLiquidity,
+        /// The transaction was aborted as the sender would have received fewer liquidity tokens
+        /// than they required.
+        TooLittleLiquidityMinted,
+        /// The asset to receive is not one of the assets of the asset pair.
+        AssetNotInPair,
+        /// The source amount of the exchange is too large relative to the liquidity pool.
+        TradeTooLarge,
+        /// The sender does not hold that many liquidity tokens for the asset pair (eg they tried
+        /// to remove more liquidity than they own).
+        LiquidityExceedsPosition,
+        /// Liquidity removals can only be scheduled for future blocks.
+        ScheduleNotInFuture,
+        /// The maximum number of liquidity removals are already scheduled for the block.
+        TooManyScheduledRemovals,
+        /// The sender already has a liquidity removal scheduled for the asset pair and block.
+        RemovalAlreadyScheduled,
+        /// The sender has no liquidity removal scheduled for the asset pair and block.
+        ScheduledRemovalNotFound,
+        /// The route has fewer than 2 or more than `MaxRouteLength` assets.
+        InvalidRoute,
+        /// The liquidity pool for the asset pair still has liquidity tokens outstanding.
+        PoolNotEmpty,
+        /// There are no assets left in the account of the liquidity pool, and no storage
+        /// associated with it, to clean up.
+        NothingToCleanUp,
+        /// The removal would leave the sender with less liquidity than they have locked for the
+        /// asset pair.
+        LiquidityTimelocked,
+        /// Liquidity can only be locked until a future block.
+        LockNotInFuture,
+        /// The sender already has the maximum number of unexpired locks for the asset pair.
+        TooManyLocks,
+        /// The sender has no expired locks for the asset pair.
+        NoExpiredLocks,
+        /// Trading in the liquidity pool for the asset pair is paused: its status is not `Active`.
+        PoolPaused,
+        /// Trading in the liquidity pool for the asset pair is not paused: its status is `Active`.
+        PoolNotPaused,
+        /// The liquidity pool for the asset pair is `Frozen`.
+        PoolFrozen,
+        /// Exchange fees must be less than 100%.
+        InvalidFee,
+        /// The sender changed their liquidity for the asset pair too recently to change it again.
+        CooldownActive,
+        /// One of the assets is blacklisted.
+        AssetBlacklisted,
+        /// The asset is not blacklisted.
+        AssetNotBlacklisted,
+        /// Liquidity pools can currently only be created for whitelisted assets, and one of the
+        /// assets is not whitelisted. Or, the asset to unwhitelist is not whitelisted.
+        AssetNotWhitelisted,
+        /// The asset is already whitelisted.
+        AssetAlreadyWhitelisted,
+        /// There are not enough observations of the cumulative prices for the asset pair to cover
+        /// the requested window.
+        InsufficientHistory,
+        /// The assets of a liquidity pool's asset pair cannot be rescued from its account.
+        CannotRescuePairAsset,
+        /// The account of the liquidity pool holds none of the asset.
+        NothingToRescue,
+        /// The first liquidity provider for an asset pair would have received fewer than
+        /// `MinimumLiquidity` liquidity tokens.
+        InsufficientLiquidityMinted,
+        /// All trading and additions of liquidity are paused.
+        PalletPaused,
+        /// There are already `MaxPools` liquidity pools.
+        TooManyPools,
+        /// The account already holds liquidity for `MaxPositionsPerAccount` asset pairs.
+        TooManyPositions,
+        /// A weighted liquidity pool must have between 2 and `MaxWeightedPoolAssets` distinct
+        /// assets, with weights between `MIN_WEIGHT` and `MAX_WEIGHT` summing to 100%. Or, the
+        /// number of amounts given does not match the number of assets in the pool.
+        InvalidWeightedPool,
+        /// There is no weighted liquidity pool with the given ID.
+        WeightedPoolNotFound,
+        /// The asset is not in the weighted liquidity pool.
+        AssetNotInPool,
+        /// A weight schedule must end after it starts.
+        InvalidWeightSchedule,
+        /// Liquidity pools can only be created by `PoolCreationOrigin`, unless the asset pair has
+        /// been registered with `register_pool`. Weighted liquidity pools cannot be registered.
+        PoolNotRegistered,
+        /// The asset pair is already registered.
+        PoolAlreadyRegistered,
+        /// The exchange would move the exchange rate outside the band set by `set_price_band`.
+        PriceOutOfBand,
+        /// Price band rates must be non-zero, and the minimum rate must not exceed the maximum.
+        InvalidPriceBand,
+        /// Only the designated providers may use the liquidity pool during its bootstrap period.
+        BootstrapRestricted,
+        /// A bootstrap period must end after the current block.
+        InvalidBootstrap,
+        /// The amounts added to the liquidity pool are below the minimum set by
+        /// `set_min_add_amount`.
+        AddTooSmall,
+        /// The initial liquidity per asset unit must be non-zero.
+        InvalidInitialLiquidityPerAssetUnit,
+        /// An exchange would have decreased the product of the amounts in a liquidity pool. This
+        /// indicates a bug, and is only checked in tests, benchmarks, and with `try-runtime`.
+        InvariantViolation,
+        /// There is already a liquidity pool, or storage for a drained pool that has not been
+        /// cleaned up, for the asset pair a pool is being migrated to.
+        MigrationTargetExists,
+        /// The migration rate would leave none of the new asset in the liquidity pool.
+        InvalidMigrationRate,
+        /// The account of the liquidity pool being migrated to does not hold enough of the new
+        /// asset.
+        MigrationNotFunded,
+        /// The exchange would have moved the exchange rate by more than `CircuitBreakerThreshold`
+        /// within a single block.
+        CircuitBreakerTripped,
+        /// Liquidity can only be transferred to another account, and the amount must be non-zero.
+        InvalidLiquidityTransfer,
+        /// The asset pair has no bootstrap period.
+        NoBootstrap,
+        /// The bootstrap period of the asset pair has not ended yet.
+        BootstrapActive,
+        /// `force_migrate_pool` or `admin_drain_pool` would visit more than `max_items` storage
+        /// items.
+        MigrationTooLarge,
+        /// An account cannot refer its own exchanges.
+        SelfReferral,
+        /// The asset ID for the liquidity tokens of the asset pair (see `lp_asset_id`) is already
+        /// used by another asset pair or by an existing asset.
+        LpAssetIdTaken,
+    }
+
+    /// Returns the canonical asset pair for `a` and `b`: the two assets ordered by `Ord`.
+    fn make_asset_pair<T: Config>(
+        a: AssetIdOf<T>,
+        b: AssetIdOf<T>,
+    ) -> Result<AssetIdPairOf<T>, DispatchError> {
+        ensure!(a != b, Error::<T>::AssetsIdentical);
+        Ok(if a < b { (a, b) } else { (b, a) })
+    }
+
+    /// Swap `a` and `b`, which correspond to `asset_a` and the other asset of `asset_pair`, if
+    /// necessary so that they are in the same order as the assets in `asset_pair`. This is its
+    /// own inverse.
+    fn order_for_pair<T: Config, V>(
+        asset_a: AssetIdOf<T>,
+        asset_pair: AssetIdPairOf<T>,
+        a: V,
+        b: V,
+    ) -> (V, V) {
+        if asset_a == asset_pair.0 {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    /// Returns the reserves of `asset_a` and the other asset of `asset_pair`, in that order.
+    fn get_reserves<T: Config>(
+        asset_a: AssetIdOf<T>,
+        asset_pair: AssetIdPairOf<T>,
+    ) -> (AssetBalanceOf<T>, AssetBalanceOf<T>) {
+        let (reserve_0, reserve_1) = Reserves::<T>::get(asset_pair);
+        order_for_pair::<T, _>(asset_a, asset_pair, reserve_0, reserve_1)
+    }
+
+    /// Set the reserves of `asset_a` and the other asset of `asset_pair`.
+    fn set_reserves<T: Config>(
+        asset_a: AssetIdOf<T>,
+        asset_pair: AssetIdPairOf<T>,
+        reserve_a: AssetBalanceOf<T>,
+        reserve_b: AssetBalanceOf<T>,
+    ) {
+        Reserves::<T>::insert(
+            asset_pair,
+            order_for_pair::<T, _>(asset_a, asset_pair, reserve_a, reserve_b),
+        );
+    }
+
+    /// The minimum encoded length of `AccountId` if pool accounts must be derived by hashing (see
+    /// `get_pool_account`). With narrower account IDs, finding two asset pairs which share a pool
+    /// account becomes feasible.
+    const MIN_HASHED_ACCOUNT_ID_LEN: usize = 16;
+
+    /// Returns the encoded length of the sub-account of `PalletId` for an asset pair.
+    fn get_pool_sub_account_len<T: Config>() -> usize {
+        PalletId::TYPE_ID
+            .len()
+            .saturating_add(T::PalletId::get().encoded_size())
+            .saturating_add(AssetIdPairOf::<T>::max_encoded_len())
+    }
+
+    /// Returns true if `AccountId` is wide enough for `get_pool_account` to derive distinct
+    /// accounts for distinct asset pairs, given the encoded length of pool sub-accounts: either
+    /// the sub-accounts fit without truncation, or the hashed accounts are wide enough to make
+    /// collisions infeasible.
+    pub(crate) fn is_account_id_wide_enough<AccountId: MaxEncodedLen>(
+        sub_account_len: usize,
+    ) -> bool {
+        let account_id_len = AccountId::max_encoded_len();
+        sub_account_len <= account_id_len || account_id_len >= MIN_HASHED_ACCOUNT_ID_LEN
+    }
+
+    /// Returns the account holding the liquidity pool for the asset pair.
+    ///
+    /// If the asset pair fits in a sub-account of `PalletId` without truncation, that is used.
+    /// Otherwise, distinct asset pairs could end up sharing a sub-account, so the account is
+    /// instead derived from a hash of the pallet ID and the asset pair.
+    pub(crate) fn get_pool_account<T: Config>(asset_pair: AssetIdPairOf<T>) -> T::AccountId {
+        let pallet_id = T::PalletId::get();
+        if get_pool_sub_account_len::<T>() <= T::AccountId::max_encoded_len() {
+            pallet_id.into_sub_account_truncating(asset_pair)
+        } else {
+            let hash = (PalletId::TYPE_ID, pallet_id, asset_pair).using_encoded(blake2_256);
+            T::AccountId::decode(&mut TrailingZeroInput::new(&hash))
+                .expect("infinite length input; no invalid inputs for type; qed")
+        }
+    }
+
+    /// Returns the account which held the liquidity pool for the asset pair before storage version
+    /// 8: the sub-account of `PalletId` for the asset pair, truncated if necessary. This is the
+    /// same as `get_pool_account` if the asset pair fits without truncation. Only for use by the
+    /// migrations; see `migrations::v8`.
+    pub(crate) fn get_legacy_pool_account<T: Config>(asset_pair: AssetIdPairOf<T>) -> T::AccountId {
+        T::PalletId::get().into_sub_account_truncating(asset_pair)
+    }
+
+    /// Returns the asset ID for the liquidity tokens of an asset pair's liquidity pool. This is
+    /// derived from a hash of the pallet ID and the asset pair. Creating a pool fails if this
+    /// coincides with a real asset's ID or that of another asset pair, so the runtime should use a
+    /// large enough `AssetId` to make this unlikely.
+    pub(crate) fn get_lp_asset_id<T: Config>(asset_pair: AssetIdPairOf<T>) -> AssetIdOf<T> {
+        let hash =
+            (PalletId::TYPE_ID, T::PalletId::get(), b"lp", asset_pair).using_encoded(blake2_256);
+        AssetIdOf::<T>::decode(&mut TrailingZeroInput::new(&hash))
+            .expect("infinite length input; no invalid inputs for type; qed")
+    }
+
+    /// Returns the account holding the liquidity mining rewards for all asset pairs (see
+    /// `fund_rewards`).
+    pub(crate) fn get_rewards_account<T: Config>() -> T::AccountId {
+        let hash = (PalletId::TYPE_ID, T::PalletId::get(), b"rewards").using_encoded(blake2_256);
+        T::AccountId::decode(&mut TrailingZeroInput::new(&hash))
+            .expect("infinite length input; no invalid inputs for type; qed")
+    }
+
+    /// Returns the account holding the weighted liquidity pool with the given ID. This is always
+    /// derived from a hash of the pallet ID and the pool ID, so that it cannot coincide with the
+    /// account of an asset pair's liquidity pool.
+    pub(crate) fn get_weighted_pool_account<T: Config>(pool_id: WeightedPoolId) -> T::AccountId {
+        let hash =
+            (PalletId::TYPE_ID, T::PalletId::get(), b"weighted", pool_id).using_encoded(blake2_256);
+        T::AccountId::decode(&mut TrailingZeroInput::new(&hash))
+            .expect("infinite length input; no invalid inputs for type; qed")
+    }
+
+    fn add<T: CheckedAdd>(a: T, b: T) -> Result<T, ArithmeticError> {
+        a.checked_add(&b).ok_or(ArithmeticError::Overflow)
+    }
+
+    fn sub<T: CheckedSub>(a: T, b: T) -> Result<T, ArithmeticError> {
+        a.checked_sub(&b).ok_or(ArithmeticError::Underflow)
+    }
+
+    fn mul<T: Into<BalanceMulResult>>(a: T, b: T) -> Result<BalanceMulResult, ArithmeticError> {
+        <T as Into<BalanceMulResult>>::into(a)
+            .checked_mul(b.into())
+            .ok_or(ArithmeticError::Overflow)
+    }
+
+    /// `floor((a * b) / c)`
+    fn mul_div_floor<T: Into<BalanceMulResult> + TryFrom<BalanceMulResult>>(
+        a: T,
+        b: T,
+        c: T,
+    ) -> Result<T, ArithmeticError> {
+        let res: BalanceMulResult =
+            mul(a, b)?.checked_div(c.into()).ok_or(ArithmeticError::DivisionByZero)?;
+        <T as TryFrom<BalanceMulResult>>::try_from(res).map_err(|_| ArithmeticError::Overflow)
+    }
+
+    /// `ceil((a * b) / c)`
+    fn mul_div_ceil<T: Into<BalanceMulResult> + TryFrom<BalanceMulResult> + Copy>(
+        a: T,
+        b: T,
+        c: T,
+    ) -> Result<T, ArithmeticError> {
+        let c_minus_one = <T as Into<BalanceMulResult>>::into(c)
+            .checked_sub(1u32.into())
+            .ok_or(ArithmeticError::Underflow)?;
+        let biased = mul(a, b)?.checked_add(c_minus_one).ok_or(ArithmeticError::Overflow)?;
+        let res: BalanceMulResult =
+            biased.checked_div(c.into()).ok_or(ArithmeticError::DivisionByZero)?;
+        <T as TryFrom<BalanceMulResult>>::try_from(res).map_err(|_| ArithmeticError::Overflow)
+    }
+
+    /// Returns the amount of each asset that must be added to a non-empty liquidity pool in
+    /// exchange for `liquidity` liquidity tokens. Rounds up, in favour of the existing liquidity
+    /// providers.
+    fn get_amounts_for_liquidity<T: Config>(
+        liquidity: LiquidityBalanceOf<T>,
+        pool_amount_a: AssetBalanceOf<T>,
+        pool_amount_b: AssetBalanceOf<T>,
+        total_liquidity: LiquidityBalanceOf<T>,
+    ) -> Result<(AssetBalanceOf<T>, AssetBalanceOf<T>), ArithmeticError> {
+        Ok((
+            mul_div_ceil(liquidity, pool_amount_a, total_liquidity)?,
+            mul_div_ceil(liquidity, pool_amount_b, total_liquidity)?,
+        ))
+    }
+
+    /// Returns the number of liquidity tokens to mint in exchange for adding the given amounts of
+    /// each asset to a non-empty liquidity pool. Rounds down, in favour of the existing liquidity
+    /// providers.
+    fn get_liquidity_for_amounts<T: Config>(
+        amount_a: AssetBalanceOf<T>,
+        amount_b: AssetBalanceOf<T>,
+        pool_amount_a: AssetBalanceOf<T>,
+        pool_amount_b: AssetBalanceOf<T>,
+        total_liquidity: LiquidityBalanceOf<T>,
+    ) -> Result<LiquidityBalanceOf<T>, ArithmeticError> {
+        Ok(min(
+            mul_div_floor(amount_a, total_liquidity, pool_amount_a)?,
+            mul_div_floor(amount_b, total_liquidity, pool_amount_b)?,
+        ))
+    }
+
+    /// Collect `iter`, failing with `MigrationTooLarge` if it yields more than `remaining` items.
+    /// `remaining` is reduced by the number of items collected.
+    fn collect_bounded<T: Config, I: Iterator>(
+        iter: I,
+        remaining: &mut u32,
+    ) -> Result<Vec<I::Item>, DispatchError> {
+        let items: Vec<_> = iter.take((*remaining as usize).saturating_add(1)).collect();
+        *remaining = u32::try_from(items.len())
+            .ok()
+            .and_then(|len| remaining.checked_sub(len))
+            .ok_or(Error::<T>::MigrationTooLarge)?;
+        Ok(items)
+    }
+
+    /// Add `point` to the ring buffer `history`, whose oldest entry is at index `head`. Once the
+    /// buffer is full, the oldest entry is overwritten. Does nothing if the bound is zero.
+    fn push_ring<P, S: Get<u32>>(history: &mut BoundedVec<P, S>, head: &mut u32, point: P) {
+        let index = *head as usize;
+        if history.len() < S::get() as usize {
+            if index == 0 {
+                // Cannot fail as there is room
+                let _ = history.try_push(point);
+            } else {
+                // The bound has been raised since the buffer filled up: put the entries back in
+                // order so that the new entry can simply be appended
+                let mut points = sp_std::mem::take(history).into_inner();
+                points.rotate_left(index.min(points.len()));
+                points.push(point);
+                *history = points.try_into().unwrap_or_default();
+                *head = 0;
+            }
+        } else if let Some(oldest) = history.iter_mut().nth(index) {
+            *oldest = point;
+            *head = ((index + 1) % history.len()) as u32;
+        }
+    }
+
+    /// Iterate over the ring buffer `history`, whose oldest entry is at index `head`, oldest
+    /// first.
+    fn ring_iter<P>(history: &[P], head: u32) -> impl Iterator<Item = &P> {
+        let (newer, older) = history.split_at((head as usize).min(history.len()));
+        older.iter().chain(newer)
+    }
+
+    /// Add the point returned by `point` for the current block to the history ring buffer
+    /// `history`, whose oldest entry is at index `head`, unless the newest entry, whose block is
+    /// given by `block_of`, is already for the current block.
+    fn record_history<T: Config, P>(
+        history: &mut BoundedVec<P, T::MaxHistoryLen>,
+        head: &mut u32,
+        block_of: impl Fn(&P) -> T::BlockNumber,
+        point: impl FnOnce(T::BlockNumber) -> P,
+    ) {
+        let now = frame_system::Pallet::<T>::block_number();
+        if ring_iter(history, *head).last().map_or(true, |newest| block_of(newest) != now) {
+            push_ring(history, head, point(now));
+        }
+    }
+
+    /// `floor(sqrt(a * b))`
+    fn geometric_mean<T: Config>(
+        a: AssetBalanceOf<T>,
+        b: AssetBalanceOf<T>,
+    ) -> Result<AssetBalanceOf<T>, ArithmeticError> {
+        // The result never exceeds max(a, b), so the conversion can't actually fail
+        <AssetBalanceOf<T> as TryFrom<BalanceMulResult>>::try_from(mul(a, b)?.integer_sqrt())
+            .map_err(|_| ArithmeticError::Overflow)
+    }
+
+    /// Returns the amount of an asset in the liquidity pool that a position would effectively own
+    /// after removing `liquidity` from it, without closing it. This mirrors the calculation in
+    /// `do_remove_liquidity`.
+    fn remaining_position_amount<T: Config>(
+        pool_amount: AssetBalanceOf<T>,
+        reducible_pool_amount: AssetBalanceOf<T>,
+        total_liquidity: LiquidityBalanceOf<T>,
+        position_liquidity: LiquidityBalanceOf<T>,
+        liquidity: LiquidityBalanceOf<T>,
+    ) -> Result<AssetBalanceOf<T>, ArithmeticError> {
+        let amount =
+            min(mul_div_floor(liquidity, pool_amount, total_liquidity)?, reducible_pool_amount);
+        mul_div_floor(
+            sub(pool_amount, amount)?,
+            sub(position_liquidity, liquidity)?,
+            sub(total_liquidity, liquidity)?,
+        )
+    }
+
+    /// `pool_amount / total_liquidity` as a fixed-point number.
+    pub(crate) fn get_amount_per_share<T: Config>(
+        pool_amount: AssetBalanceOf<T>,
+        total_liquidity: LiquidityBalanceOf<T>,
+    ) -> Result<FixedU128, ArithmeticError> {
+        ensure!(!total_liquidity.is_zero(), ArithmeticError::DivisionByZero);
+        FixedU128::checked_from_rational(
+            pool_amount.saturated_into::<u128>(),
+            total_liquidity.saturated_into::<u128>(),
+        )
+        .ok_or(ArithmeticError::Overflow)
+    }
+
+    /// `(a * prev_liquidity + b * added_liquidity) / (prev_liquidity + added_liquidity)`
+    fn blend_amount_per_share<T: Config>(
+        a: FixedU128,
+        prev_liquidity: LiquidityBalanceOf<T>,
+        b: FixedU128,
+        added_liquidity: LiquidityBalanceOf<T>,
+    ) -> Result<FixedU128, ArithmeticError> {
+        let weighted_a = mul(BalanceMulResult::from(a.into_inner()), prev_liquidity.into())?;
+        let weighted_b = mul(BalanceMulResult::from(b.into_inner()), added_liquidity.into())?;
+        let sum = weighted_a.checked_add(weighted_b).ok_or(ArithmeticError::Overflow)?;
+        let res = sum
+            .checked_div(add(prev_liquidity, added_liquidity)?.into())
+            .ok_or(ArithmeticError::DivisionByZero)?;
+        u128::try_from(res)
+            .map(FixedU128::from_inner)
+            .map_err(|_| ArithmeticError::Overflow)
+    }
+
+    /// Returns the amount of each asset in the liquidity pool for the asset pair per liquidity
+    /// token, in asset pair order.
+    pub(crate) fn get_amounts_per_share<T: Config>(
+        asset_pair: AssetIdPairOf<T>,
+    ) -> Result<(FixedU128, FixedU128), ArithmeticError> {
+        let total_liquidity = TotalLiquidity::<T>::get(asset_pair);
+        let (pool_amount_0, pool_amount_1) = Reserves::<T>::get(asset_pair);
+        Ok((
+            get_amount_per_share::<T>(pool_amount_0, total_liquidity)?,
+            get_amount_per_share::<T>(pool_amount_1, total_liquidity)?,
+        ))
+    }
+
+    /// `sqrt(a * b)`, with the same fixed-point scaling as `a` and `b`.
+    fn root_product(a: FixedU128, b: FixedU128) -> Result<BalanceMulResult, ArithmeticError> {
+        Ok(mul(a.into_inner(), b.into_inner())?.integer_sqrt())
+    }
+
+    /// Returns the square root of the product of the amounts of each asset in the liquidity pool
+    /// per liquidity token, as of the position's snapshot and now, respectively.
+    fn get_position_roots<T: Config>(
+        position: &LiquidityPositionOf<T>,
+        asset_pair: AssetIdPairOf<T>,
+    ) -> Result<(BalanceMulResult, BalanceMulResult), ArithmeticError> {
+        let (reserve_a_per_share, reserve_b_per_share) = get_amounts_per_share::<T>(asset_pair)?;
+        Ok((
+            root_product(position.reserve_a_per_share, position.reserve_b_per_share)?,
+            root_product(reserve_a_per_share, reserve_b_per_share)?,
+        ))
+    }
+
+    /// Returns `true` if the effective exchange rate of an exchange of `source_amount` for
+    /// `dest_amount` is worse than the current exchange rate by more than `max_impact`.
+    fn exceeds_price_impact<T: Config>(
+        pool_source_amount: AssetBalanceOf<T>,
+        pool_dest_amount: AssetBalanceOf<T>,
+        source_amount: AssetBalanceOf<T>,
+        dest_amount: AssetBalanceOf<T>,
+        max_impact: Permill,
+    ) -> Result<bool, ArithmeticError> {
+        // The price impact is 1 - (dest_amount / source_amount) / (pool_dest_amount /
+        // pool_source_amount). Cross-multiply to compare against the maximum.
+        let accuracy = BalanceMulResult::from(Permill::ACCURACY);
+        let max_retained =
+            BalanceMulResult::from(Permill::one().saturating_sub(max_impact).deconstruct());
+        let effective = mul(dest_amount, pool_source_amount)?
+            .checked_mul(accuracy)
+            .ok_or(ArithmeticError::Overflow)?;
+        let bound = mul(source_amount, pool_dest_amount)?
+            .checked_mul(max_retained)
+            .ok_or(ArithmeticError::Overflow)?;
+        Ok(effective < bound)
+    }
+
+    /// Returns `true` if the price moved from `from` to `to` by more than `threshold`, in either
+    /// direction. The move is measured relative to the lower of the two prices, so that a move
+    /// counts the same whichever asset the price is expressed in.
+    fn exceeds_price_move(from: FixedU128, to: FixedU128, threshold: Permill) -> bool {
+        let (low, high) = if from <= to { (from, to) } else { (to, from) };
+        high > low.saturating_add(low.saturating_mul(threshold.into()))
+    }
+
+    /// Returns `true` if the price of the first asset of an asset pair, in units of the second,
+    /// is within the (inclusive) bounds `min_rate` and `max_rate` when the pool contains
+    /// `reserve_0` and `reserve_1` of the assets.
+    fn within_price_band<T: Config>(
+        reserve_0: AssetBalanceOf<T>,
+        reserve_1: AssetBalanceOf<T>,
+        min_rate: Option<FixedU128>,
+        max_rate: Option<FixedU128>,
+    ) -> Result<bool, ArithmeticError> {
+        // reserve_1 / reserve_0 against rate.into_inner() / DIV, cross-multiplied
+        let scaled_reserve_1 = mul(reserve_1.saturated_into::<u128>(), FixedU128::DIV)?;
+        let reserve_0 = reserve_0.saturated_into::<u128>();
+        if let Some(min_rate) = min_rate {
+            if scaled_reserve_1 < mul(reserve_0, min_rate.into_inner())? {
+                return Ok(false)
+            }
+        }
+        if let Some(max_rate) = max_rate {
+            if scaled_reserve_1 > mul(reserve_0, max_rate.into_inner())? {
+                return Ok(false)
+            }
+        }
+        Ok(true)
+    }
+
+    /// Linearly interpolates between the weights `from` and `to`, `elapsed` blocks into a span of
+    /// `span` blocks. The result is between `from` and `to`, so is non-zero if they are.
+    fn interpolate_weight(
+        from: Perbill,
+        to: Perbill,
+        elapsed: u128,
+        span: u128,
+    ) -> Result<Perbill, ArithmeticError> {
+        let (from, to) = (u128::from(from.deconstruct()), u128::from(to.deconstruct()));
+        let weight = if from <= to {
+            add(from, mul_div_floor(to - from, elapsed, span)?)?
+        } else {
+            sub(from, mul_div_floor(from - to, elapsed, span)?)?
+        };
+        Ok(Perbill::from_parts(weight.saturated_into()))
+    }
+
+    /// Returns the amount of the destination asset that must remain in a liquidity pool
+    /// containing `pool_source_amount` and `pool_dest_amount` after an exchange which adds
+    /// `source_amount_less_fee` to it for pricing, and `source_amount` (including the fee) in
+    /// total, to preserve the product of the amounts. Rounded according to `RoundingPolicy`.
+    fn get_new_pool_dest_amount<T: Config>(
+        pool_source_amount: AssetBalanceOf<T>,
+        pool_dest_amount: AssetBalanceOf<T>,
+        source_amount_less_fee: AssetBalanceOf<T>,
+        source_amount: AssetBalanceOf<T>,
+    ) -> Result<AssetBalanceOf<T>, ArithmeticError> {
+        let new_pool_source_amount_less_fee = add(pool_source_amount, source_amount_less_fee)?;
+        if T::RoundingPolicy::get() == RoundingPolicy::FavorTrader {
+            let rounded_down = mul_div_floor(
+                pool_source_amount,
+                pool_dest_amount,
+                new_pool_source_amount_less_fee,
+            )?;
+            if mul(add(pool_source_amount, source_amount)?, rounded_down)? >=
+                mul(pool_source_amount, pool_dest_amount)?
+            {
+                return Ok(rounded_down)
+            }
+        }
+        mul_div_ceil(pool_source_amount, pool_dest_amount, new_pool_source_amount_less_fee)
+    }
+
+    /// Returns the prices of the first and second assets of the asset pair, each in units of the
+    /// other, from the amounts in the liquidity pool. Zero if either amount is zero.
+    fn get_spot_prices<T: Config>(asset_pair: AssetIdPairOf<T>) -> (FixedU128, FixedU128) {
+        let (pool_amount_0, pool_amount_1) = Reserves::<T>::get(asset_pair);
+        let pool_amount_0 = pool_amount_0.saturated_into::<u128>();
+        let pool_amount_1 = pool_amount_1.saturated_into::<u128>();
+        (
+            FixedU128::checked_from_rational(pool_amount_1, pool_amount_0).unwrap_or_default(),
+            FixedU128::checked_from_rational(pool_amount_0, pool_amount_1).unwrap_or_default(),
+        )
+    }
+
+    /// Returns `cumulative` advanced to block `now` at the current prices in the liquidity pool
+    /// for the asset pair. Blocks in which the pool is empty are not counted.
+    fn advance_cumulative_prices<T: Config>(
+        asset_pair: AssetIdPairOf<T>,
+        cumulative: CumulativePricesOf<T>,
+        now: T::BlockNumber,
+    ) -> CumulativePricesOf<T> {
+        let elapsed = now.saturating_sub(cumulative.last_updated);
+        if elapsed.is_zero() || TotalLiquidity::<T>::get(asset_pair).is_zero() {
+            return CumulativePrices { last_updated: now, ..cumulative }
+        }
+        let (price_a, price_b) = get_spot_prices::<T>(asset_pair);
+        let elapsed = FixedU128::saturating_from_integer(elapsed.saturated_into::<u128>());
+        let accumulate = |sum: FixedU128, price: FixedU128| {
+            FixedU128::from_inner(
+                sum.into_inner().wrapping_add(price.saturating_mul(elapsed).into_inner()),
+            )
+        };
+        CumulativePrices {
+            price_a: accumulate(cumulative.price_a, price_a),
+            price_b: accumulate(cumulative.price_b, price_b),
+            last_updated: now,
+        }
+    }
+
+    /// Linearly interpolates between the cumulative prices `prev` and `next` to find the
+    /// cumulative prices as of block `at`, which must be between them.
+    fn interpolate_cumulative_prices<T: Config>(
+        prev: CumulativePricesOf<T>,
+        next: CumulativePricesOf<T>,
+        at: T::BlockNumber,
+    ) -> Result<CumulativePricesOf<T>, ArithmeticError> {
+        let span = next.last_updated.saturating_sub(prev.last_updated).saturated_into::<u128>();
+        if span == 0 {
+            return Ok(next)
+        }
+        let elapsed = at.saturating_sub(prev.last_updated).saturated_into::<u128>();
+        let interpolate = |prev: FixedU128, next: FixedU128| -> Result<_, ArithmeticError> {
+            let delta = next.into_inner().wrapping_sub(prev.into_inner());
+            Ok(FixedU128::from_inner(
+                prev.into_inner().wrapping_add(mul_div_floor(delta, elapsed, span)?),
+            ))
+        };
+        Ok(CumulativePrices {
+            price_a: interpolate(prev.price_a, next.price_a)?,
+            price_b: interpolate(prev.price_b, next.price_b)?,
+            last_updated: at,
+        })
+    }
+
+    /// Returns the exchange fee for the liquidity pool for the asset pair: the pool's override if
+    /// set, otherwise `ExchangeFee`.
+    fn get_exchange_fee<T: Config>(asset_pair: AssetIdPairOf<T>) -> Permill {
+        Pools::<T>::get(asset_pair).fee.unwrap_or_else(T::ExchangeFee::get)
+    }
+
+    /// Returns how much of `amount` should be exchanged for the other asset of a liquidity pool
+    /// containing `pool_amount` of the same asset and charging `fee`, such that the remainder and
+    /// the proceeds of the exchange are in the pool's ratio after the exchange (ignoring
+    /// rounding).
+    fn get_swap_amount_for_add<T: Config>(
+        pool_amount: AssetBalanceOf<T>,
+        amount: AssetBalanceOf<T>,
+        fee: Permill,
+    ) -> Result<AssetBalanceOf<T>, ArithmeticError> {
+        // With pool amount R, fee fraction f, and r = 1 - f, the swap amount s satisfies
+        // r s^2 + R (1 + r) s - R amount = 0. Solve with the quadratic formula, scaling by
+        // Permill::ACCURACY to keep everything integral.
+        let accuracy = BalanceMulResult::from(Permill::ACCURACY);
+        let retained = BalanceMulResult::from(Permill::one().saturating_sub(fee).deconstruct());
+        let b = mul(pool_amount.into(), accuracy.saturating_add(retained))?;
+        let four_a_c = mul(mul(pool_amount, amount)?, retained.saturating_mul(accuracy))?
+            .checked_mul(4u32.into())
+            .ok_or(ArithmeticError::Overflow)?;
+        let discriminant = mul(b, b)?.checked_add(four_a_c).ok_or(ArithmeticError::Overflow)?;
+        let res = discriminant
+            .integer_sqrt()
+            .checked_sub(b)
+            .ok_or(ArithmeticError::Underflow)?
+            .checked_div(retained.saturating_mul(2u32.into()))
+            .ok_or(ArithmeticError::DivisionByZero)?;
+        // The result never exceeds amount, so the conversion can't actually fail
+        <AssetBalanceOf<T> as TryFrom<BalanceMulResult>>::try_from(res)
+            .map_err(|_| ArithmeticError::Overflow)
+    }
+
+    #[pallet::call]
+    impl<T: Config> Pallet<T> {
+        /// Add liquidity for an asset pair.
+        ///
+        /// If the sender is the first liquidity provider for the given asset pair, the full
+        /// amounts will be added to the liquidity pool. Otherwise, an equivalent value of each
+        /// asset will be transferred, according to the current exchange rate.
+        ///
+        /// If the actual amount of either asset added would be less than the provided minimum, the
+        /// transaction is aborted. The purpose of this is to protect the sender against
+        /// unfavourable movements in the exchange rate.
+        ///
+        /// The sender will be provided with liquidity tokens representing their share of the
+        /// liquidity pool for the given asset pair; the number of tokens provided can be
+        /// determined by looking at the raised `LiquidityAdded` event. These tokens can be
+        /// redeemed for the underlying assets in the pool by calling `remove_liquidity`.
+        ///
+        /// If the number of liquidity tokens provided would be less than `min_liquidity`, the
+        /// transaction is aborted. Note that the number of tokens is rounded down, in favour of the
+        /// existing liquidity providers.
+        ///
+        /// If `keep_alive` is true, the transaction is aborted if either transfer would take the
+        /// sender's balance below the minimum. Otherwise, any remaining balance below the minimum
+        /// is swept into the pool along with the transferred amount.
+        ///
+        /// If `RestrictPoolCreation` is set, only `PoolCreationOrigin` may add liquidity to an
+        /// empty pool, unless the asset pair has been registered with `register_pool`.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn add_liquidity(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            min_amount_a: AssetBalanceOf<T>,
+            max_amount_a: AssetBalanceOf<T>,
+            asset_b: AssetIdOf<T>,
+            min_amount_b: AssetBalanceOf<T>,
+            max_amount_b: AssetBalanceOf<T>,
+            min_liquidity: LiquidityBalanceOf<T>,
+            keep_alive: bool,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin.clone())?;
+            Self::ensure_pool_creation_allowed(origin, asset_a, asset_b)?;
+            Self::do_add_liquidity(
+                &sender,
+                asset_a,
+                min_amount_a,
+                max_amount_a,
+                asset_b,
+                min_amount_b,
+                max_amount_b,
+                min_liquidity,
+                keep_alive,
+            )?;
+
+            Ok(())
+        }
+
+        /// Add liquidity for an asset pair on behalf of `owner`, using assets `owner` has approved
+        /// the sender to transfer (see `pallet_assets::approve_transfer`). The liquidity tokens
+        /// are credited to `owner`, as are any deposits (see `PoolDeposit` and
+        /// `PositionDeposit`). Otherwise, this is as `add_liquidity` with `keep_alive` unset;
+        /// `RestrictPoolCreation` applies to the sender.
+        ///
+        /// This allows eg a manager or smart wallet to provide liquidity with its users' funds,
+        /// without holding them.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn add_liquidity_from(
+            origin: OriginFor<T>,
+            owner: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            min_amount_a: AssetBalanceOf<T>,
+            max_amount_a: AssetBalanceOf<T>,
+            asset_b: AssetIdOf<T>,
+            min_amount_b: AssetBalanceOf<T>,
+            max_amount_b: AssetBalanceOf<T>,
+            min_liquidity: LiquidityBalanceOf<T>,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin.clone())?;
+            Self::ensure_pool_creation_allowed(origin, asset_a, asset_b)?;
+            Self::do_add_liquidity_from(
+                &owner,
+                Some(&sender),
+                asset_a,
+                min_amount_a,
+                max_amount_a,
+                asset_b,
+                min_amount_b,
+                max_amount_b,
+                min_liquidity,
+                false,
+            )?;
+
+            Ok(())
+        }
+
+        /// Add liquidity for an asset pair on behalf of `provider`, drawing the assets from
+        /// `provider` and crediting the liquidity tokens to them, as if `provider` had called
+        /// `add_liquidity` with `keep_alive` set. This allows governance to deploy eg treasury
+        /// assets into liquidity pools without a signed key. `RestrictPoolCreation` does not
+        /// apply. The liquidity can be removed again with `force_remove_liquidity`.
+        ///
+        /// The dispatch origin for this call must be `LiquidityProvisionOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn force_add_liquidity(
+            origin: OriginFor<T>,
+            provider: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            min_amount_a: AssetBalanceOf<T>,
+            max_amount_a: AssetBalanceOf<T>,
+            asset_b: AssetIdOf<T>,
+            min_amount_b: AssetBalanceOf<T>,
+            max_amount_b: AssetBalanceOf<T>,
+            min_liquidity: LiquidityBalanceOf<T>,
+        ) -> DispatchResult {
+            T::LiquidityProvisionOrigin::ensure_origin(origin)?;
+
+            Self::do_add_liquidity(
+                &provider,
+                asset_a,
+                min_amount_a,
+                max_amount_a,
+                asset_b,
+                min_amount_b,
+                max_amount_b,
+                min_liquidity,
+                true,
+            )?;
+
+            Ok(())
+        }
+
+        /// Redeem liquidity tokens for an asset pair on behalf of `provider`, transferring the
+        /// assets to `provider`, as if `provider` had called `remove_liquidity`. This is the
+        /// counterpart of `force_add_liquidity`.
+        ///
+        /// The dispatch origin for this call must be `LiquidityProvisionOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn force_remove_liquidity(
+            origin: OriginFor<T>,
+            provider: T::AccountId,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            liquidity: LiquidityBalanceOf<T>,
+        ) -> DispatchResult {
+            T::LiquidityProvisionOrigin::ensure_origin(origin)?;
+            Self::do_remove_liquidity(&provider, asset_a, asset_b, liquidity)?;
+            Ok(())
+        }
+
+        /// Redeem liquidity tokens for an asset pair. The share of the liquidity pool represented
+        /// by the tokens will be transferred back to the sender.
+        ///
+        /// Note this transaction does not perform any exchange rate checks as the sender always
+        /// benefits from any deviations from the true rate.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn remove_liquidity(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            liquidity: LiquidityBalanceOf<T>,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin)?;
+            Self::do_remove_liquidity(&sender, asset_a, asset_b, liquidity)?;
+            Ok(())
+        }
+
+        /// Transfer `liquidity` of the sender's liquidity tokens for an asset pair to another
+        /// account. Any fees earned by the transferred liquidity tokens are transferred with them.
+        ///
+        /// The same restrictions apply to the sender as for `remove_liquidity`: the pool must not
+        /// be `Frozen`, their liquidity must not be locked, and unless they transfer all of it,
+        /// they must leave a sufficient amount of each asset in the liquidity pool. The recipient's
+        /// resulting liquidity must also be worth a sufficient amount of each asset, and the
+        /// transferred liquidity must be worth at least the minimum set by `set_min_add_amount`.
+        /// The sender is subject to `LiquidityCooldown` afterwards; the recipient is not, so that
+        /// transfers cannot be used to stop them removing their liquidity. If the recipient has no
+        /// liquidity for the asset pair, the sender pays the `PositionDeposit` for their new
+        /// position.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn transfer_liquidity(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            to: T::AccountId,
+            liquidity: LiquidityBalanceOf<T>,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin)?;
+            ensure!(to != sender && !liquidity.is_zero(), Error::<T>::InvalidLiquidityTransfer);
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            let total_liquidity = TotalLiquidity::<T>::get(asset_pair);
+            ensure!(!total_liquidity.is_zero(), Error::<T>::NoLiquidity);
+            ensure!(
+                Pools::<T>::get(asset_pair).status != PoolStatus::Frozen,
+                Error::<T>::PoolFrozen
+            );
+            Self::ensure_liquidity_unlocked(&sender, asset_pair)?;
+            Self::ensure_cooldown_elapsed(&sender, asset_pair)?;
+
+            let (pool_amount_a, pool_amount_b) = get_reserves::<T>(asset_a, asset_pair);
+            // The transferred liquidity is an addition for the recipient
+            Self::ensure_min_add_amount(
+                asset_a,
+                asset_pair,
+                (pool_amount_a, pool_amount_b),
+                mul_div_floor(pool_amount_a, liquidity, total_liquidity)?,
+                mul_div_floor(pool_amount_b, liquidity, total_liquidity)?,
+            )?;
+            // Check a position of the given size is worth a sufficient amount of each asset
+            let ensure_sufficient = |position_liquidity| -> DispatchResult {
+                ensure!(
+                    mul_div_floor(pool_amount_a, position_liquidity, total_liquidity)? >=
+                        Self::get_min_pool_amount(asset_a)?,
+                    Error::<T>::InsufficientPoolAmount
+                );
+                ensure!(
+                    mul_div_floor(pool_amount_b, position_liquidity, total_liquidity)? >=
+                        Self::get_min_pool_amount(asset_b)?,
+                    Error::<T>::InsufficientPoolAmount
+                );
+                Ok(())
+            };
+
+            // The total liquidity is unchanged, so the reward pot is the same for both accounts
+            Self::settle_rewards(&sender, asset_pair)?;
+            let reward_pot = Self::settle_rewards(&to, asset_pair)?;
+
+            // Debit the sender
+            let sender_position = Liquidity::<T>::get(&sender, asset_pair);
+            ensure!(liquidity <= sender_position.amount, Error::<T>::LiquidityExceedsPosition);
+            let sender_liquidity = sub(sender_position.amount, liquidity)?;
+            ensure!(
+                sender_liquidity >= Self::get_locked_liquidity(&sender, asset_pair)?,
+                Error::<T>::LiquidityTimelocked
+            );
+            if sender_liquidity.is_zero() {
+                Liquidity::<T>::remove(&sender, asset_pair);
+                Self::close_position(&sender, asset_pair);
+                LiquidityAddedAt::<T>::remove(&sender, asset_pair);
+                // Any locks must have expired
+                LiquidityLocks::<T>::remove(&sender, asset_pair);
+            } else {
+                ensure_sufficient(sender_liquidity)?;
+                Liquidity::<T>::insert(
+                    &sender,
+                    asset_pair,
+                    LiquidityPosition { amount: sender_liquidity, ..sender_position },
+                );
+            }
+            Self::note_liquidity_change(&sender, asset_pair);
+
+            // Credit the recipient, blending the sender's snapshot into theirs so that fees earned
+            // before the transfer stay with the transferred liquidity
+            let to_position = Liquidity::<T>::get(&to, asset_pair);
+            if to_position.amount.is_zero() {
+                Self::open_position(&to, asset_pair, &sender)?;
+            }
+            let to_liquidity = add(to_position.amount, liquidity)?;
+            ensure_sufficient(to_liquidity)?;
+            let reserve_a_per_share = blend_amount_per_share::<T>(
+                to_position.reserve_a_per_share,
+                to_position.amount,
+                sender_position.reserve_a_per_share,
+                liquidity,
+            )?;
+            let reserve_b_per_share = blend_amount_per_share::<T>(
+                to_position.reserve_b_per_share,
+                to_position.amount,
+                sender_position.reserve_b_per_share,
+                liquidity,
+            )?;
+            Liquidity::<T>::insert(
+                &to,
+                asset_pair,
+                LiquidityPosition {
+                    amount: to_liquidity,
+                    reserve_a_per_share,
+                    reserve_b_per_share,
+                },
+            );
+            if let Some(pot) = reward_pot {
+                Self::update_reward_debt(&sender, asset_pair, &pot);
+                Self::update_reward_debt(&to, asset_pair, &pot);
+            }
+
+            Self::deposit_event(Event::LiquidityTransferred {
+                from: sender,
+                to,
+                asset_a,
+                asset_b,
+                liquidity,
+            });
+
+            Ok(())
+        }
+
+        /// Claim the exchange fees earned by the sender's liquidity for an asset pair, without
+        /// withdrawing the principal.
+        ///
+        /// The fees are claimed by redeeming the portion of the sender's liquidity tokens which
+        /// is attributable to fees (see `position_earnings`). The remaining liquidity tokens are
+        /// worth the same as when the sender last added liquidity, and are treated as if they
+        /// were added now for the purposes of future fee calculations. As with
+        /// `remove_liquidity`, the sender must leave a sufficient amount of each asset in the
+        /// liquidity pool.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn claim_fees(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            let position = Liquidity::<T>::get(&sender, asset_pair);
+            ensure!(!position.amount.is_zero(), Error::<T>::NoFeesEarned);
+
+            // The portion of the position attributable to fees is 1 - (root_then / root_now).
+            // Round the liquidity to redeem down to favour the pool.
+            let (root_then, root_now) = get_position_roots::<T>(&position, asset_pair)?;
+            ensure!(root_then < root_now, Error::<T>::NoFeesEarned);
+            let liquidity = mul_div_floor(
+                position.amount.into(),
+                root_now.saturating_sub(root_then),
+                root_now,
+            )?;
+            let liquidity =
+                <LiquidityBalanceOf<T> as TryFrom<BalanceMulResult>>::try_from(liquidity)
+                    .map_err(|_| ArithmeticError::Overflow)?;
+            ensure!(!liquidity.is_zero(), Error::<T>::NoFeesEarned);
+
+            let (amount_a, amount_b) =
+                Self::do_remove_liquidity(&sender, asset_a, asset_b, liquidity)?;
+
+            // Reset the snapshot for the remaining liquidity
+            if Liquidity::<T>::contains_key(&sender, asset_pair) {
+                let (reserve_a_per_share, reserve_b_per_share) =
+                    get_amounts_per_share::<T>(asset_pair)?;
+                Liquidity::<T>::mutate(&sender, asset_pair, |position| {
+                    position.reserve_a_per_share = reserve_a_per_share;
+                    position.reserve_b_per_share = reserve_b_per_share;
+                });
+            }
+
+            Self::deposit_event(Event::FeesClaimed {
+                who: sender,
+                asset_a,
+                amount_a,
+                asset_b,
+                amount_b,
+            });
+
+            Ok(())
+        }
+
+        /// Fund liquidity mining rewards for the liquidity pool for an asset pair: `amount` of
+        /// `reward_asset` is transferred from `source` and distributed evenly over the blocks from
+        /// `start` to `end`. The rewards for each block are shared between the liquidity providers
+        /// in proportion to their liquidity, and can be claimed with `claim_rewards`. Rewards for
+        /// blocks in which the pool is empty are not distributed, and can be recovered with
+        /// `sweep_rewards`.
+        ///
+        /// Each asset pair has at most one reward pot. Once its reward period has ended, it can be
+        /// funded again, but only with the same reward asset.
+        ///
+        /// The dispatch origin for this call must be `RewardOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn fund_rewards(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            source: T::AccountId,
+            reward_asset: AssetIdOf<T>,
+            amount: AssetBalanceOf<T>,
+            start: T::BlockNumber,
+            end: T::BlockNumber,
+        ) -> DispatchResult {
+            T::RewardOrigin::ensure_origin(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            let now = frame_system::Pallet::<T>::block_number();
+            ensure!(now <= start && start < end, Error::<T>::InvalidRewardPeriod);
+            let reward_per_share = match Self::update_reward_pot(asset_pair)? {
+                Some(pot) => {
+                    ensure!(pot.end <= now, Error::<T>::RewardsActive);
+                    ensure!(pot.reward_asset == reward_asset, Error::<T>::RewardAssetMismatch);
+                    pot.reward_per_share
+                },
+                None => Zero::zero(),
+            };
+
+            T::Fungibles::transfer(
+                reward_asset,
+                &source,
+                &get_rewards_account::<T>(),
+                amount,
+                true,
+            )?;
+            RewardPots::<T>::insert(
+                asset_pair,
+                RewardPot { reward_asset, amount, start, end, last_updated: now, reward_per_share },
+            );
+
+            Self::deposit_event(Event::RewardsFunded {
+                asset_a,
+                asset_b,
+                reward_asset,
+                amount,
+                start,
+                end,
+            });
+
+            Ok(())
+        }
+
+        /// Claim the liquidity mining rewards accrued by the sender's liquidity in the pool for an
+        /// asset pair (see `fund_rewards`). Rewards remain claimable after the liquidity has been
+        /// removed.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn claim_rewards(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            let pot = Self::settle_rewards(&sender, asset_pair)?.ok_or(Error::<T>::NoRewards)?;
+            let amount = RewardPositions::<T>::mutate(&sender, asset_pair, |position| {
+                sp_std::mem::take(&mut position.pending)
+            });
+            ensure!(!amount.is_zero(), Error::<T>::NoRewards);
+            Self::update_reward_debt(&sender, asset_pair, &pot);
+            let amount = T::Fungibles::transfer(
+                pot.reward_asset,
+                &get_rewards_account::<T>(),
+                &sender,
+                amount,
+                false,
+            )?;
+
+            Self::deposit_event(Event::RewardsClaimed {
+                who: sender,
+                asset_a,
+                asset_b,
+                reward_asset: pot.reward_asset,
+                amount,
+            });
+
+            Ok(())
+        }
+
+        /// Transfer the liquidity mining rewards for an asset pair which have not been distributed
+        /// to liquidity providers (see `UndistributedRewards`) to `recipient`, typically the
+        /// account that funded them.
+        ///
+        /// The dispatch origin for this call must be `AdminOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn sweep_rewards(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            recipient: T::AccountId,
+        ) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            let pot = Self::update_reward_pot(asset_pair)?.ok_or(Error::<T>::NoRewards)?;
+            let amount = UndistributedRewards::<T>::take(asset_pair);
+            ensure!(!amount.is_zero(), Error::<T>::NoRewards);
+            let amount = T::Fungibles::transfer(
+                pot.reward_asset,
+                &get_rewards_account::<T>(),
+                &recipient,
+                amount,
+                false,
+            )?;
+
+            Self::deposit_event(Event::RewardsReturned {
+                asset_a,
+                asset_b,
+                reward_asset: pot.reward_asset,
+                amount,
+                recipient,
+            });
+
+            Ok(())
+        }
+
+        /// Donate assets to the liquidity pool for an asset pair, without receiving any liquidity
+        /// tokens in return. This raises the value of every existing liquidity token; the
+        /// donation is counted towards the earnings of each liquidity provider (see
+        /// `position_earnings`) just like exchange fees.
+        ///
+        /// The liquidity pool must not be empty, as otherwise the donation would simply go to
+        /// the next liquidity provider.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn donate(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            amount_a: AssetBalanceOf<T>,
+            asset_b: AssetIdOf<T>,
+            amount_b: AssetBalanceOf<T>,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            ensure!(!TotalLiquidity::<T>::get(asset_pair).is_zero(), Error::<T>::NoLiquidity);
+            Self::update_price_cumulative(asset_pair);
+            let pool_account = get_pool_account::<T>(asset_pair);
+
+            // As with add_liquidity, we might transfer a bit more than requested if the sender
+            // would otherwise be left with a balance between 0 and the minimum
+            let amount_a =
+                T::Fungibles::transfer(asset_a, &sender, &pool_account, amount_a, false)?;
+            let amount_b =
+                T::Fungibles::transfer(asset_b, &sender, &pool_account, amount_b, false)?;
+            let (reserve_a, reserve_b) = get_reserves::<T>(asset_a, asset_pair);
+            set_reserves::<T>(
+                asset_a,
+                asset_pair,
+                add(reserve_a, amount_a)?,
+                add(reserve_b, amount_b)?,
+            );
+
+            Self::deposit_event(Event::Donated {
+                who: sender,
+                asset_a,
+                amount_a,
+                asset_b,
+                amount_b,
+            });
+
+            Ok(())
+        }
+
+        /// Lower each reserve of the liquidity pool for an asset pair to the corresponding balance
+        /// of the pool account, if the balance has somehow fallen below it (eg through a transfer
+        /// out of the pool account by another pallet), so that the pool never promises more than
+        /// it holds. Until then, exchanges continue to be priced against the reserves rather than
+        /// the balances, so the shortfall cannot be exploited to trade at a distorted rate.
+        ///
+        /// Reserves are never raised: assets transferred directly to the pool account are not
+        /// added to the pool. Use `donate` for that.
+        ///
+        /// The liquidity pool must not be empty.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn sync(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> DispatchResult {
+            ensure_signed(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            ensure!(!TotalLiquidity::<T>::get(asset_pair).is_zero(), Error::<T>::NoLiquidity);
+            Self::update_price_cumulative(asset_pair);
+            let pool_account = get_pool_account::<T>(asset_pair);
+
+            let (reserve_a, reserve_b) = get_reserves::<T>(asset_a, asset_pair);
+            let reserve_a = min(reserve_a, T::Fungibles::balance(asset_a, &pool_account));
+            let reserve_b = min(reserve_b, T::Fungibles::balance(asset_b, &pool_account));
+            set_reserves::<T>(asset_a, asset_pair, reserve_a, reserve_b);
+
+            Self::deposit_event(Event::Synced { asset_a, reserve_a, asset_b, reserve_b });
+
+            Ok(())
+        }
+
+        /// Exchange a given amount of one asset for an equivalent value of another asset, using
+        /// the current exchange rate.
+        ///
+        /// To protect the sender against unfavourable movements in the exchange rate, if the
+        /// equivalent value is less than `min_dest_amount`, the transaction is aborted.
+        ///
+        /// A fixed percentage fee is charged and added to the liquidity pool for the asset pair.
+        ///
+        /// If the exchange would trip the circuit breaker (see `CircuitBreakerThreshold`), the
+        /// pool is paused instead and the exchange is not executed.
+        ///
+        /// The dispatch origin for this call must be `SwapOrigin`.
+        #[pallet::weight(Pallet::<T>::exchange_weight())]
+        #[transactional]
+        pub fn exchange(
+            origin: OriginFor<T>,
+            source_asset: AssetIdOf<T>,
+            source_amount: AssetBalanceOf<T>,
+            dest_asset: AssetIdOf<T>,
+            min_dest_amount: AssetBalanceOf<T>,
+        ) -> DispatchResult {
+            let sender = T::SwapOrigin::ensure_origin(origin)?;
+            let result = Self::do_exchange(
+                &sender,
+                source_asset,
+                source_amount,
+                dest_asset,
+                min_dest_amount,
+                false,
+            );
+            Self::pause_if_tripped(source_asset, dest_asset, result)
+        }
+
+        /// Same as `exchange`, but the transaction is aborted if the transfer of `source_asset`
+        /// would take the sender's balance below the minimum, rather than sweeping any remaining
+        /// balance below the minimum into the pool and reaping the account.
+        ///
+        /// The dispatch origin for this call must be `SwapOrigin`.
+        #[pallet::weight(Pallet::<T>::exchange_weight())]
+        #[transactional]
+        pub fn exchange_keep_alive(
+            origin: OriginFor<T>,
+            source_asset: AssetIdOf<T>,
+            source_amount: AssetBalanceOf<T>,
+            dest_asset: AssetIdOf<T>,
+            min_dest_amount: AssetBalanceOf<T>,
+        ) -> DispatchResult {
+            let sender = T::SwapOrigin::ensure_origin(origin)?;
+            let result = Self::do_exchange(
+                &sender,
+                source_asset,
+                source_amount,
+                dest_asset,
+                min_dest_amount,
+                true,
+            );
+            Self::pause_if_tripped(source_asset, dest_asset, result)
+        }
+
+        /// Same as `exchange`, but `ReferralShare` of the fee is paid to `referrer`, if given (eg
+        /// the wallet or aggregator which routed the exchange), instead of being added to the
+        /// pool. The referrer must be able to receive the payment, ie must already hold the
+        /// minimum balance of `source_asset` or receive at least that much. Fails with
+        /// `SelfReferral` if the referrer is the sender.
+        ///
+        /// The dispatch origin for this call must be `SwapOrigin`.
+        #[pallet::weight(Pallet::<T>::exchange_weight())]
+        #[transactional]
+        pub fn exchange_with_referral(
+            origin: OriginFor<T>,
+            source_asset: AssetIdOf<T>,
+            source_amount: AssetBalanceOf<T>,
+            dest_asset: AssetIdOf<T>,
+            min_dest_amount: AssetBalanceOf<T>,
+            referrer: Option<T::AccountId>,
+        ) -> DispatchResult {
+            let sender = T::SwapOrigin::ensure_origin(origin)?;
+            let result = Self::do_exchange_with_referral(
+                &sender,
+                source_asset,
+                source_amount,
+                dest_asset,
+                min_dest_amount,
+                false,
+                referrer.as_ref(),
+            );
+            Self::pause_if_tripped(source_asset, dest_asset, result)
+        }
+
+        /// Exchange the sender's entire balance of one asset for another asset. This is like
+        /// `exchange` with a `source_amount` of the sender's reducible balance of `source_asset`,
+        /// read at execution time; the sender's account may be reaped as a result.
+        ///
+        /// The dispatch origin for this call must be `SwapOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn exchange_all(
+            origin: OriginFor<T>,
+            source_asset: AssetIdOf<T>,
+            dest_asset: AssetIdOf<T>,
+            min_dest_amount: AssetBalanceOf<T>,
+        ) -> DispatchResult {
+            let sender = T::SwapOrigin::ensure_origin(origin)?;
+            let source_amount = T::Fungibles::reducible_balance(source_asset, &sender, false);
+            let result = Self::do_exchange(
+                &sender,
+                source_asset,
+                source_amount,
+                dest_asset,
+                min_dest_amount,
+                false,
+            );
+            Self::pause_if_tripped(source_asset, dest_asset, result)
+        }
+
+        /// Create a weighted liquidity pool for two or more assets, given as `(asset, weight,
+        /// amount)`. Whereas the liquidity pool for an asset pair holds an equal value of each of
+        /// its assets, a weighted pool holds a value of each asset proportional to its weight:
+        /// exchanges preserve the product of the amount of each asset raised to the power of its
+        /// weight. Each weight must be between 1% and 99% (`MIN_WEIGHT` and `MAX_WEIGHT`), which
+        /// bounds the error of the exchange calculation, and the weights must sum to 100%.
+        ///
+        /// The given amounts, which determine the initial prices, are transferred from the sender
+        /// and must each be at least the minimum pool amount of the asset (see
+        /// `PoolMinAmountMultiple`). The sender is given liquidity tokens as if they were the first
+        /// liquidity provider for an asset pair, with the `Max` formula applied to all of the
+        /// amounts. `PoolDeposit` is reserved from the sender until the pool is drained. As for an
+        /// asset pair, positions in weighted pools count towards `MaxPositionsPerAccount` and
+        /// require `PositionDeposit`.
+        ///
+        /// Weighted pools are identified by the ID in the `WeightedPoolCreated` event rather than
+        /// by their assets, so there may be several pools for the same assets. They count towards
+        /// `MaxPools`.
+        ///
+        /// Weighted pools cannot be registered with `register_pool`, so if `RestrictPoolCreation`
+        /// is set, only `PoolCreationOrigin` may create them.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn create_weighted_pool(
+            origin: OriginFor<T>,
+            assets: Vec<(AssetIdOf<T>, Perbill, AssetBalanceOf<T>)>,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin.clone())?;
+            Self::ensure_weighted_pool_creation_allowed(origin)?;
+            Self::do_create_weighted_pool(&sender, assets)?;
+            Ok(())
+        }
+
+        /// Create a liquidity bootstrapping pool: a weighted liquidity pool whose weights shift
+        /// linearly over time. The assets are given as `(asset, start_weight, end_weight,
+        /// amount)`. The pool is created as by `create_weighted_pool` with the start weights,
+        /// which apply until `start_block`. From then until `end_block`, the weights used for
+        /// exchanges and prices are interpolated between the start and end weights; after
+        /// `end_block` the end weights apply, and are frozen by the next exchange.
+        ///
+        /// Shifting weight away from an asset lowers its price if the amounts in the pool are left
+        /// unchanged. Launching a token with a high initial weight thus gives a descending price,
+        /// which discourages buying early.
+        ///
+        /// As for `create_weighted_pool`, `RestrictPoolCreation` applies to the sender.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn create_lbp(
+            origin: OriginFor<T>,
+            assets: Vec<(AssetIdOf<T>, Perbill, Perbill, AssetBalanceOf<T>)>,
+            start_block: T::BlockNumber,
+            end_block: T::BlockNumber,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin.clone())?;
+            Self::ensure_weighted_pool_creation_allowed(origin)?;
+            ensure!(start_block < end_block, Error::<T>::InvalidWeightSchedule);
+            let (start_weights, end_weights): (Vec<_>, Vec<_>) = assets
+                .iter()
+                .map(|(_, start_weight, end_weight, _)| (*start_weight, *end_weight))
+                .unzip();
+            Self::ensure_valid_weights(&end_weights)?;
+            let pool_id = Self::do_create_weighted_pool(
+                &sender,
+                assets
+                    .into_iter()
+                    .map(|(asset, start_weight, _, amount)| (asset, start_weight, amount))
+                    .collect(),
+            )?;
+
+            // do_create_weighted_pool checked the number of assets, so these conversions succeed
+            let schedule = WeightSchedule {
+                start_block,
+                end_block,
+                start_weights: start_weights
+                    .try_into()
+                    .map_err(|_| Error::<T>::InvalidWeightedPool)?,
+                end_weights: end_weights.try_into().map_err(|_| Error::<T>::InvalidWeightedPool)?,
+            };
+            WeightSchedules::<T>::insert(pool_id, schedule);
+
+            Self::deposit_event(Event::LbpCreated { pool_id, start_block, end_block });
+
+            Ok(())
+        }
+
+        /// Add liquidity to a weighted liquidity pool in exchange for `liquidity` liquidity tokens.
+        /// The same share of each asset in the pool is transferred from the sender, rounded up in
+        /// favour of the existing liquidity providers. If more than the corresponding amount in
+        /// `max_amounts`, which must be in the order of the pool's assets, would be transferred,
+        /// the transaction is aborted.
+        ///
+        /// As for an asset pair, the sender's share of each asset in the pool must be at least the
+        /// minimum pool amount.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn join_weighted_pool(
+            origin: OriginFor<T>,
+            pool_id: WeightedPoolId,
+            liquidity: LiquidityBalanceOf<T>,
+            max_amounts: Vec<AssetBalanceOf<T>>,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin)?;
+            ensure!(!Paused::<T>::get(), Error::<T>::PalletPaused);
+            let mut pool =
+                WeightedPools::<T>::get(pool_id).ok_or(Error::<T>::WeightedPoolNotFound)?;
+            ensure!(max_amounts.len() == pool.assets.len(), Error::<T>::InvalidWeightedPool);
+            let total_liquidity = WeightedTotalLiquidity::<T>::get(pool_id);
+            let pool_account = get_weighted_pool_account::<T>(pool_id);
+
+            let mut amounts = Vec::with_capacity(max_amounts.len());
+            for (pool_asset, max_amount) in pool.assets.iter_mut().zip(max_amounts) {
+                ensure!(
+                    !BlacklistedAssets::<T>::contains_key(pool_asset.asset),
+                    Error::<T>::AssetBlacklisted
+                );
+                let amount = mul_div_ceil(liquidity, pool_asset.reserve, total_liquidity)?;
+                ensure!(amount <= max_amount, Error::<T>::UnexpectedExchangeRate);
+                let amount = T::Fungibles::transfer(
+                    pool_asset.asset,
+                    &sender,
+                    &pool_account,
+                    amount,
+                    false,
+                )?;
+                pool_asset.reserve = add(pool_asset.reserve, amount)?;
+                amounts.push(amount);
+            }
+
+            let total_liquidity = add(total_liquidity, liquidity)?;
+            WeightedTotalLiquidity::<T>::insert(pool_id, total_liquidity);
+            let sender_liquidity = WeightedLiquidity::<T>::get(&sender, pool_id);
+            if sender_liquidity.is_zero() {
+                Self::open_weighted_position(&sender, pool_id)?;
+            }
+            let sender_liquidity = add(sender_liquidity, liquidity)?;
+            WeightedLiquidity::<T>::insert(&sender, pool_id, sender_liquidity);
+            Self::ensure_sufficient_weighted_position(
+                &pool.assets,
+                sender_liquidity,
+                total_liquidity,
+            )?;
+            WeightedPools::<T>::insert(pool_id, pool);
+
+            Self::deposit_event(Event::WeightedLiquidityAdded {
+                who: sender,
+                pool_id,
+                amounts,
+                liquidity,
+            });
+
+            Ok(())
+        }
+
+        /// Redeem liquidity tokens for a weighted liquidity pool. The share of each asset in the
+        /// pool represented by the tokens is transferred to the sender, rounded down. Unless the
+        /// sender redeems all of their liquidity tokens, they must leave a sufficient amount of
+        /// each asset in the pool.
+        ///
+        /// When the last liquidity tokens are redeemed, the pool is removed and its deposit is
+        /// released.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn exit_weighted_pool(
+            origin: OriginFor<T>,
+            pool_id: WeightedPoolId,
+            liquidity: LiquidityBalanceOf<T>,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin)?;
+            let mut pool =
+                WeightedPools::<T>::get(pool_id).ok_or(Error::<T>::WeightedPoolNotFound)?;
+            let sender_liquidity = WeightedLiquidity::<T>::get(&sender, pool_id);
+            ensure!(liquidity <= sender_liquidity, Error::<T>::LiquidityExceedsPosition);
+            let sender_liquidity = sub(sender_liquidity, liquidity)?;
+            let total_liquidity = WeightedTotalLiquidity::<T>::get(pool_id);
+            let new_total_liquidity = sub(total_liquidity, liquidity)?;
+            let pool_account = get_weighted_pool_account::<T>(pool_id);
+
+            // As in do_remove_liquidity, keep the pool account alive unless the pool is drained
+            let keep_alive = !new_total_liquidity.is_zero();
+            let mut amounts = Vec::with_capacity(pool.assets.len());
+            for pool_asset in pool.assets.iter_mut() {
+                let amount = min(
+                    mul_div_floor(liquidity, pool_asset.reserve, total_liquidity)?,
+                    T::Fungibles::reducible_balance(pool_asset.asset, &pool_account, keep_alive),
+                );
+                let amount = T::Fungibles::transfer(
+                    pool_asset.asset,
+                    &pool_account,
+                    &sender,
+                    amount,
+                    keep_alive,
+                )?;
+                pool_asset.reserve = sub(pool_asset.reserve, amount)?;
+                amounts.push(amount);
+            }
+
+            if sender_liquidity.is_zero() {
+                WeightedLiquidity::<T>::remove(&sender, pool_id);
+                Self::close_weighted_position(&sender, pool_id);
+            } else {
+                WeightedLiquidity::<T>::insert(&sender, pool_id, sender_liquidity);
+                Self::ensure_sufficient_weighted_position(
+                    &pool.assets,
+                    sender_liquidity,
+                    new_total_liquidity,
+                )?;
+            }
+
+            Self::deposit_event(Event::WeightedLiquidityRemoved {
+                who: sender,
+                pool_id,
+                amounts,
+                liquidity,
+            });
+            if new_total_liquidity.is_zero() {
+                WeightedTotalLiquidity::<T>::remove(pool_id);
+                WeightedPools::<T>::remove(pool_id);
+                WeightSchedules::<T>::remove(pool_id);
+                let (depositor, deposit) = pool.deposit;
+                T::Currency::unreserve(&depositor, deposit);
+                // See do_remove_liquidity
+                let _ = frame_system::Pallet::<T>::dec_providers(&pool_account);
+                PoolCount::<T>::mutate(|count| *count = count.saturating_sub(1));
+                Self::deposit_event(Event::WeightedPoolDestroyed { pool_id });
+            } else {
+                WeightedTotalLiquidity::<T>::insert(pool_id, new_total_liquidity);
+                WeightedPools::<T>::insert(pool_id, pool);
+            }
+
+            Ok(())
+        }
+
+        /// Exchange a given amount of one asset in a weighted liquidity pool for another asset in
+        /// the same pool, preserving the pool's invariant. If the two assets have equal weights,
+        /// this gives exactly the same result as `exchange` with a liquidity pool of the same
+        /// amounts.
+        ///
+        /// As for `exchange`, `ExchangeFee` is charged and added to the pool, and the transaction
+        /// is aborted if the sender would receive less than `min_dest_amount`. `MaxTradeFraction`
+        /// also applies.
+        ///
+        /// The dispatch origin for this call must be `SwapOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn exchange_weighted(
+            origin: OriginFor<T>,
+            pool_id: WeightedPoolId,
+            source_asset: AssetIdOf<T>,
+            source_amount: AssetBalanceOf<T>,
+            dest_asset: AssetIdOf<T>,
+            min_dest_amount: AssetBalanceOf<T>,
+        ) -> DispatchResult {
+            let sender = T::SwapOrigin::ensure_origin(origin)?;
+            ensure!(!Paused::<T>::get(), Error::<T>::PalletPaused);
+            ensure!(source_asset != dest_asset, Error::<T>::AssetsIdentical);
+            ensure!(
+                !BlacklistedAssets::<T>::contains_key(source_asset) &&
+                    !BlacklistedAssets::<T>::contains_key(dest_asset),
+                Error::<T>::AssetBlacklisted
+            );
+            let mut pool =
+                WeightedPools::<T>::get(pool_id).ok_or(Error::<T>::WeightedPoolNotFound)?;
+            if Self::apply_weight_schedule(pool_id, &mut pool)? {
+                WeightSchedules::<T>::remove(pool_id);
+            }
+            let find = |asset: AssetIdOf<T>| {
+                pool.assets
+                    .iter()
+                    .position(|pool_asset| pool_asset.asset == asset)
+                    .ok_or(Error::<T>::AssetNotInPool)
+            };
+            let source_index = find(source_asset)?;
+            let dest_index = find(dest_asset)?;
+            let source = pool.assets[source_index];
+            let dest = pool.assets[dest_index];
+            let pool_account = get_weighted_pool_account::<T>(pool_id);
+
+            if let Some(max_fraction) = T::MaxTradeFraction::get() {
+                ensure!(
+                    source_amount <= max_fraction.mul_floor(source.reserve),
+                    Error::<T>::TradeTooLarge
+                );
+            }
+
+            let fee_amount = T::ExchangeFee::get().mul_ceil(source_amount);
+            let new_dest_reserve = crate::weighted_math::get_new_balance_out(
+                source.reserve.saturated_into(),
+                source.weight,
+                dest.reserve.saturated_into(),
+                dest.weight,
+                sub(source_amount, fee_amount)?.saturated_into(),
+            )?;
+            let dest_amount = sub(dest.reserve, new_dest_reserve.saturated_into())?;
+
+            // Possibly reduce dest_amount to avoid leaving the pool with less than the minimum
+            // balance of the destination asset
+            let dest_amount =
+                min(dest_amount, T::Fungibles::reducible_balance(dest_asset, &pool_account, true));
+
+            // Abort the transaction if the sender would not receive enough
+            ensure!(dest_amount >= min_dest_amount, Error::<T>::UnexpectedExchangeRate);
+
+            let source_amount =
+                T::Fungibles::transfer(source_asset, &sender, &pool_account, source_amount, false)?;
+            let dest_amount =
+                T::Fungibles::transfer(dest_asset, &pool_account, &sender, dest_amount, true)?;
+            pool.assets[source_index].reserve = add(source.reserve, source_amount)?;
+            pool.assets[dest_index].reserve = sub(dest.reserve, dest_amount)?;
+            WeightedPools::<T>::insert(pool_id, pool);
+
+            Self::deposit_event(Event::WeightedExchanged {
+                who: sender.clone(),
+                pool_id,
+                source_asset,
+                source_amount,
+                dest_asset,
+                dest_amount,
+                fee_amount,
+            });
+            T::OnSwap::on_swap(&sender, source_asset, source_amount, dest_asset, dest_amount);
+
+            Ok(())
+        }
+
+        /// Pause trading in the liquidity pool for an asset pair: until `resume_pool` is called,
+        /// exchanges and additions of liquidity will fail with `PoolPaused`. Liquidity can still
+        /// be removed, so that liquidity providers can always exit. Other pools are unaffected.
+        ///
+        /// This sets the pool's status from `Active` to `TradingDisabled`; see `set_pool_status`
+        /// for the other statuses.
+        ///
+        /// The dispatch origin for this call must be `PauseOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn pause_pool(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> DispatchResult {
+            T::PauseOrigin::ensure_origin(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            Pools::<T>::try_mutate(asset_pair, |info| -> DispatchResult {
+                ensure!(info.status == PoolStatus::Active, Error::<T>::PoolPaused);
+                info.status = PoolStatus::TradingDisabled;
+                Ok(())
+            })?;
+
+            Self::deposit_event(Event::PoolPaused { asset_a, asset_b });
+
+            Ok(())
+        }
+
+        /// Resume trading in the liquidity pool for an asset pair, after `pause_pool`. This sets
+        /// the pool's status to `Active`, whatever it was.
+        ///
+        /// The dispatch origin for this call must be `PauseOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn resume_pool(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> DispatchResult {
+            T::PauseOrigin::ensure_origin(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            Pools::<T>::try_mutate(asset_pair, |info| -> DispatchResult {
+                ensure!(info.status != PoolStatus::Active, Error::<T>::PoolNotPaused);
+                info.status = PoolStatus::Active;
+                Ok(())
+            })?;
+            // Measure further price moves from the current price
+            BlockStartPrices::<T>::remove(asset_pair);
+
+            Self::deposit_event(Event::PoolResumed { asset_a, asset_b });
+
+            Ok(())
+        }
+
+        /// Set the status of the liquidity pool for an asset pair, restricting which operations it
+        /// accepts. Exchanges and additions of liquidity require `Active`, failing with
+        /// `PoolPaused` otherwise. Removals and transfers of liquidity fail with `PoolFrozen` if
+        /// `Frozen`. Fails with `PoolNotFound` if the pool has never been created (or has since
+        /// been cleaned up with `cleanup_pool`).
+        ///
+        /// The dispatch origin for this call must be `PauseOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn set_pool_status(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            status: PoolStatus,
+        ) -> DispatchResult {
+            T::PauseOrigin::ensure_origin(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            let old_status =
+                Pools::<T>::try_mutate(asset_pair, |info| -> Result<_, DispatchError> {
+                    ensure!(info.created_at.is_some(), Error::<T>::PoolNotFound);
+                    Ok(sp_std::mem::replace(&mut info.status, status))
+                })?;
+            if old_status != PoolStatus::Active && status == PoolStatus::Active {
+                // As for resume_pool
+                BlockStartPrices::<T>::remove(asset_pair);
+            }
+
+            Self::deposit_event(Event::PoolStatusChanged { asset_a, asset_b, status });
+
+            Ok(())
+        }
+
+        /// Pause or unpause all trading and additions of liquidity, across all liquidity pools.
+        /// This is an emergency switch, independent of `pause_pool`: while paused, exchanges and
+        /// additions of liquidity fail with `PalletPaused`. Liquidity can still be removed.
+        ///
+        /// The dispatch origin for this call must be `EmergencyOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn set_paused(origin: OriginFor<T>, paused: bool) -> DispatchResult {
+            T::EmergencyOrigin::ensure_origin(origin)?;
+
+            Paused::<T>::put(paused);
+
+            if paused {
+                Self::deposit_event(Event::PalletPaused);
+            } else {
+                Self::deposit_event(Event::PalletUnpaused);
+            }
+
+            Ok(())
+        }
+
+        /// Override `ExchangeFee` for the liquidity pool for an asset pair, or remove the override
+        /// if `fee` is `None`. This only affects future exchanges; the value of existing
+        /// liquidity is unchanged.
+        ///
+        /// The dispatch origin for this call must be `FeeAdminOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn set_pool_fee(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            fee: Option<Permill>,
+        ) -> DispatchResult {
+            T::FeeAdminOrigin::ensure_origin(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            ensure!(fee.map_or(true, |fee| fee < Permill::one()), Error::<T>::InvalidFee);
+            Pools::<T>::mutate(asset_pair, |info| info.fee = fee);
+
+            Self::deposit_event(Event::PoolFeeChanged { asset_a, asset_b, fee });
+
+            Ok(())
+        }
+
+        /// Restrict the exchange rate of the liquidity pool for an asset pair: exchanges which
+        /// would leave the price of `asset_a`, in units of `asset_b`, below `min_rate` or above
+        /// `max_rate` will fail with `PriceOutOfBand`. Either bound may be `None`, and setting
+        /// both to `None` removes the band. Adding and removing liquidity are unaffected.
+        ///
+        /// The dispatch origin for this call must be `PriceBandOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn set_price_band(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            min_rate: Option<FixedU128>,
+            max_rate: Option<FixedU128>,
+        ) -> DispatchResult {
+            T::PriceBandOrigin::ensure_origin(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            ensure!(
+                min_rate.map_or(true, |rate| !rate.is_zero()) &&
+                    max_rate.map_or(true, |rate| !rate.is_zero()),
+                Error::<T>::InvalidPriceBand
+            );
+            if let (Some(min_rate), Some(max_rate)) = (min_rate, max_rate) {
+                ensure!(min_rate <= max_rate, Error::<T>::InvalidPriceBand);
+            }
+
+            // The band is stored in terms of the price of the first asset of the asset pair, so
+            // the bounds swap places and are inverted if asset_a is the second
+            let (pool_min_rate, pool_max_rate) = if asset_a == asset_pair.0 {
+                (min_rate, max_rate)
+            } else {
+                let invert = |rate: Option<FixedU128>| rate.and_then(|rate| rate.reciprocal());
+                (invert(max_rate), invert(min_rate))
+            };
+            Pools::<T>::mutate(asset_pair, |info| {
+                info.min_rate = pool_min_rate;
+                info.max_rate = pool_max_rate;
+            });
+
+            Self::deposit_event(Event::PriceBandChanged { asset_a, asset_b, min_rate, max_rate });
+
+            Ok(())
+        }
+
+        /// Blacklist an asset: until `unblacklist_asset` is called, adding liquidity to any pool
+        /// containing it (including creating a new pool) and exchanging it will fail with
+        /// `AssetBlacklisted`. Liquidity can still be removed, so that liquidity providers can
+        /// always exit.
+        ///
+        /// The dispatch origin for this call must be `BlacklistOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn blacklist_asset(origin: OriginFor<T>, asset: AssetIdOf<T>) -> DispatchResult {
+            T::BlacklistOrigin::ensure_origin(origin)?;
+
+            ensure!(!BlacklistedAssets::<T>::contains_key(asset), Error::<T>::AssetBlacklisted);
+            BlacklistedAssets::<T>::insert(asset, ());
+
+            Self::deposit_event(Event::AssetBlacklisted { asset });
+
+            Ok(())
+        }
+
+        /// Remove an asset from the blacklist, after `blacklist_asset`.
+        ///
+        /// The dispatch origin for this call must be `BlacklistOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn unblacklist_asset(origin: OriginFor<T>, asset: AssetIdOf<T>) -> DispatchResult {
+            T::BlacklistOrigin::ensure_origin(origin)?;
+
+            ensure!(BlacklistedAssets::<T>::contains_key(asset), Error::<T>::AssetNotBlacklisted);
+            BlacklistedAssets::<T>::remove(asset);
+
+            Self::deposit_event(Event::AssetUnblacklisted { asset });
+
+            Ok(())
+        }
+
+        /// Whitelist an asset, allowing liquidity pools containing it to be created while
+        /// `WhitelistOnly` is set.
+        ///
+        /// The dispatch origin for this call must be `WhitelistOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn whitelist_asset(origin: OriginFor<T>, asset: AssetIdOf<T>) -> DispatchResult {
+            T::WhitelistOrigin::ensure_origin(origin)?;
+
+            ensure!(
+                !WhitelistedAssets::<T>::contains_key(asset),
+                Error::<T>::AssetAlreadyWhitelisted
+            );
+            WhitelistedAssets::<T>::insert(asset, ());
+
+            Self::deposit_event(Event::AssetWhitelisted { asset });
+
+            Ok(())
+        }
+
+        /// Remove an asset from the whitelist, after `whitelist_asset`. Existing liquidity pools
+        /// containing the asset are unaffected, but while `WhitelistOnly` is set no new pools
+        /// containing it can be created.
+        ///
+        /// The dispatch origin for this call must be `WhitelistOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn unwhitelist_asset(origin: OriginFor<T>, asset: AssetIdOf<T>) -> DispatchResult {
+            T::WhitelistOrigin::ensure_origin(origin)?;
+
+            ensure!(WhitelistedAssets::<T>::contains_key(asset), Error::<T>::AssetNotWhitelisted);
+            WhitelistedAssets::<T>::remove(asset);
+
+            Self::deposit_event(Event::AssetUnwhitelisted { asset });
+
+            Ok(())
+        }
+
+        /// Enable or disable whitelist-only pool creation. While enabled, liquidity can only be
+        /// added to an empty liquidity pool if both assets have been whitelisted with
+        /// `whitelist_asset`.
+        ///
+        /// The dispatch origin for this call must be `WhitelistOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn set_whitelist_only(origin: OriginFor<T>, enabled: bool) -> DispatchResult {
+            T::WhitelistOrigin::ensure_origin(origin)?;
+
+            WhitelistOnly::<T>::put(enabled);
+
+            Self::deposit_event(Event::WhitelistOnlyChanged { enabled });
+
+            Ok(())
+        }
+
+        /// Register an asset pair, so that any signed account may create its liquidity pool even
+        /// if `RestrictPoolCreation` is set.
+        ///
+        /// If `bootstrap` is provided, then until the start of block `bootstrap.until`, only
+        /// `bootstrap.providers` may add liquidity to the pool (including creating it), and, if
+        /// `bootstrap.restrict_exchanges` is set, exchange using it. Other accounts fail with
+        /// `BootstrapRestricted`. The restrictions lapse automatically.
+        ///
+        /// The dispatch origin for this call must be `PoolCreationOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn register_pool(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            bootstrap: Option<BootstrapOf<T>>,
+        ) -> DispatchResult {
+            T::PoolCreationOrigin::ensure_origin(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            ensure!(
+                !RegisteredPools::<T>::contains_key(asset_pair),
+                Error::<T>::PoolAlreadyRegistered
+            );
+            let bootstrap_until = bootstrap.as_ref().map(|bootstrap| bootstrap.until);
+            if let Some(bootstrap) = bootstrap {
+                ensure!(
+                    bootstrap.until > frame_system::Pallet::<T>::block_number(),
+                    Error::<T>::InvalidBootstrap
+                );
+                Bootstraps::<T>::insert(asset_pair, bootstrap);
+            }
+            RegisteredPools::<T>::insert(asset_pair, ());
+
+            Self::deposit_event(Event::PoolRegistered { asset_a, asset_b, bootstrap_until });
+
+            Ok(())
+        }
+
+        /// Remove the bootstrap period of an asset pair once it has ended. This can be called by
+        /// anyone. Ended bootstrap periods are also removed by the next addition of liquidity to,
+        /// or exchange using, the liquidity pool.
+        #[pallet::weight(10_000)] // TODO
+        pub fn end_bootstrap(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> DispatchResult {
+            ensure_signed(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            let bootstrap = Bootstraps::<T>::get(asset_pair).ok_or(Error::<T>::NoBootstrap)?;
+            ensure!(
+                frame_system::Pallet::<T>::block_number() >= bootstrap.until,
+                Error::<T>::BootstrapActive
+            );
+            Bootstraps::<T>::remove(asset_pair);
+
+            Self::deposit_event(Event::BootstrapEnded { asset_a, asset_b });
+
+            Ok(())
+        }
+
+        /// Set the minimum addition of liquidity to the liquidity pool for an asset pair, or remove
+        /// it with `None`. Additions to the pool below the minimum fail with `AddTooSmall`, except
+        /// when the pool is empty. For `MinAddAmount::Absolute`, the amounts are of `asset_a` and
+        /// `asset_b` respectively.
+        ///
+        /// The dispatch origin for this call must be `PoolCreationOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn set_min_add_amount(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            min_add_amount: Option<MinAddAmountOf<T>>,
+        ) -> DispatchResult {
+            T::PoolCreationOrigin::ensure_origin(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            // Absolute amounts are stored in asset pair order
+            let pool_min_add_amount = min_add_amount.map(|min_add_amount| match min_add_amount {
+                MinAddAmount::Absolute(amount_a, amount_b) => {
+                    let (amount_0, amount_1) =
+                        order_for_pair::<T, _>(asset_a, asset_pair, amount_a, amount_b);
+                    MinAddAmount::Absolute(amount_0, amount_1)
+                },
+                fraction => fraction,
+            });
+            MinAddAmounts::<T>::set(asset_pair, pool_min_add_amount);
+
+            Self::deposit_event(Event::MinAddAmountChanged { asset_a, asset_b, min_add_amount });
+
+            Ok(())
+        }
+
+        /// Override `InitialLiquidityPerAssetUnit` for the liquidity pool for an asset pair, or
+        /// remove the override with `None`. This only takes effect when the pool is created, ie
+        /// when liquidity is added to it while it is empty, so it should be set beforehand. It
+        /// allows eg pools of assets with very small units to mint fewer liquidity tokens.
+        ///
+        /// The dispatch origin for this call must be `PoolCreationOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn set_initial_liquidity_per_asset_unit(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            per_asset_unit: Option<LiquidityBalanceOf<T>>,
+        ) -> DispatchResult {
+            T::PoolCreationOrigin::ensure_origin(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            ensure!(
+                per_asset_unit.map_or(true, |per_asset_unit| !per_asset_unit.is_zero()),
+                Error::<T>::InvalidInitialLiquidityPerAssetUnit
+            );
+            InitialLiquidityPerAssetUnitOverrides::<T>::set(asset_pair, per_asset_unit);
+
+            Self::deposit_event(Event::InitialLiquidityPerAssetUnitChanged {
+                asset_a,
+                asset_b,
+                per_asset_unit,
+            });
+
+            Ok(())
+        }
+
+        /// Lock `amount` of the sender's liquidity tokens for an asset pair until the start of
+        /// block `until`. Until then, the sender cannot remove liquidity if doing so would leave
+        /// them with less than the total of their unexpired locks for the asset pair. This allows
+        /// eg a project to prove that its liquidity will stay in the pool.
+        ///
+        /// The total of the sender's unexpired locks for the asset pair cannot exceed their
+        /// liquidity. Expired locks are ignored, and can be removed with `unlock_liquidity`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn lock_liquidity(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            amount: LiquidityBalanceOf<T>,
+            until: T::BlockNumber,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            let now = frame_system::Pallet::<T>::block_number();
+            ensure!(until > now, Error::<T>::LockNotInFuture);
+
+            LiquidityLocks::<T>::try_mutate(&sender, asset_pair, |locks| -> DispatchResult {
+                locks.retain(|lock| lock.until > now);
+                let locked = locks.iter().try_fold(amount, |sum, lock| add(sum, lock.amount))?;
+                ensure!(
+                    locked <= Liquidity::<T>::get(&sender, asset_pair).amount,
+                    Error::<T>::LiquidityExceedsPosition
+                );
+                locks
+                    .try_push(LiquidityLock { amount, until })
+                    .map_err(|_| Error::<T>::TooManyLocks)?;
+                Ok(())
+            })?;
+
+            Self::deposit_event(Event::LiquidityLocked {
+                who: sender,
+                asset_a,
+                asset_b,
+                amount,
+                until,
+            });
+
+            Ok(())
+        }
+
+        /// Remove the sender's expired locks for an asset pair. Note that expired locks are
+        /// ignored anyway; this just tidies up.
+        #[pallet::weight(10_000)] // TODO
+        pub fn unlock_liquidity(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            let now = frame_system::Pallet::<T>::block_number();
+
+            let amount = LiquidityLocks::<T>::try_mutate_exists(
+                &sender,
+                asset_pair,
+                |maybe_locks| -> Result<LiquidityBalanceOf<T>, DispatchError> {
+                    let locks = maybe_locks.as_mut().ok_or(Error::<T>::NoExpiredLocks)?;
+                    let mut amount: LiquidityBalanceOf<T> = Zero::zero();
+                    let prev_len = locks.len();
+                    locks.retain(|lock| {
+                        let expired = lock.until <= now;
+                        if expired {
+                            amount = amount.saturating_add(lock.amount);
+                        }
+                        !expired
+                    });
+                    ensure!(locks.len() < prev_len, Error::<T>::NoExpiredLocks);
+                    if locks.is_empty() {
+                        *maybe_locks = None;
+                    }
+                    Ok(amount)
+                },
+            )?;
+
+            Self::deposit_event(Event::LiquidityUnlocked { who: sender, asset_a, asset_b, amount });
+
+            Ok(())
+        }
+
+        /// Clean up after a drained liquidity pool: transfer any assets left in the pool account to
+        /// `DustSink`, release the pool creation deposit, and remove all storage associated with
+        /// the pool. This can be called by anyone.
+        ///
+        /// Removing the last liquidity from a pool transfers everything in the pool account, but
+        /// assets may subsequently be transferred to the account directly. Sweeping them allows
+        /// the pool account's asset accounts, and thus the pool account itself, to be reaped. The
+        /// pool's creation record (see `PoolInfo`) and price history are removed, so that dead
+        /// pools do not accumulate in storage. The pool can be recreated by adding
+        /// liquidity as usual.
+        ///
+        /// The settings made by governance (the status, fee, and price band) are kept, so that
+        /// anyone draining a pool cannot use this to reset eg a paused or frozen pool.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn cleanup_pool(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> DispatchResult {
+            ensure_signed(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            ensure!(TotalLiquidity::<T>::get(asset_pair).is_zero(), Error::<T>::PoolNotEmpty);
+            let pool_account = get_pool_account::<T>(asset_pair);
+            let dust_sink = T::DustSink::get();
+
+            let sweep = |asset| -> Result<AssetBalanceOf<T>, DispatchError> {
+                let balance = T::Fungibles::balance(asset, &pool_account);
+                if balance.is_zero() {
+                    return Ok(balance)
+                }
+                // Don't keep the pool account alive, so that it can be reaped
+                T::Fungibles::transfer(asset, &pool_account, &dust_sink, balance, false)
+            };
+            let amount_a = sweep(asset_a)?;
+            let amount_b = sweep(asset_b)?;
+
+            let mut info = Pools::<T>::get(asset_pair);
+            let kept_info = PoolInfo {
+                status: info.status,
+                fee: info.fee,
+                min_rate: info.min_rate,
+                max_rate: info.max_rate,
+                ..Default::default()
+            };
+            let has_storage = info != kept_info || PriceCumulative::<T>::contains_key(asset_pair);
+            ensure!(
+                !amount_a.is_zero() || !amount_b.is_zero() || has_storage,
+                Error::<T>::NothingToCleanUp
+            );
+            Self::deposit_event(Event::PoolCleanedUp { asset_a, amount_a, asset_b, amount_b });
+
+            Self::release_pool_deposit(&mut info, asset_a, asset_b);
+            if kept_info == PoolInfo::default() {
+                Pools::<T>::remove(asset_pair);
+            } else {
+                Pools::<T>::insert(asset_pair, kept_info);
+            }
+            PriceCumulative::<T>::remove(asset_pair);
+            Observations::<T>::remove(asset_pair);
+            PriceHistory::<T>::remove(asset_pair);
+            PriceHistoryHead::<T>::remove(asset_pair);
+            CumulativeVolume::<T>::remove(asset_pair);
+            VolumeHistory::<T>::remove(asset_pair);
+            VolumeHistoryHead::<T>::remove(asset_pair);
+            BlockStartPrices::<T>::remove(asset_pair);
+            BlockOpeningReserves::<T>::remove(asset_pair);
+            Self::deposit_event(Event::PoolRemoved { asset_a, asset_b });
+
+            Ok(())
+        }
+
+        /// Transfer the full balance of `stray_asset` held by the account of the liquidity pool for
+        /// an asset pair to `beneficiary`. This recovers assets transferred to the pool account
+        /// by mistake, which would otherwise be stranded. The assets of the asset pair itself can
+        /// never be moved this way.
+        ///
+        /// The dispatch origin for this call must be `RescueOrigin`.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn rescue_pool_funds(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            stray_asset: AssetIdOf<T>,
+            beneficiary: T::AccountId,
+        ) -> DispatchResult {
+            T::RescueOrigin::ensure_origin(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            ensure!(
+                stray_asset != asset_pair.0 && stray_asset != asset_pair.1,
+                Error::<T>::CannotRescuePairAsset
+            );
+            let pool_account = get_pool_account::<T>(asset_pair);
+            let balance = T::Fungibles::balance(stray_asset, &pool_account);
+            ensure!(!balance.is_zero(), Error::<T>::NothingToRescue);
+            let amount =
+                T::Fungibles::transfer(stray_asset, &pool_account, &beneficiary, balance, false)?;
+
+            Self::deposit_event(Event::PoolFundsRescued {
+                asset_a,
+                asset_b,
+                stray_asset,
+                amount,
+                beneficiary,
+            });
+
+            Ok(())
+        }
+
+        /// Transfer everything in the account of the liquidity pool for an asset pair to
+        /// `recipient`, and remove the pool along with all liquidity positions in it. This is a
+        /// last resort for recovering the assets of a broken or stuck pool, and deliberately skips
+        /// the checks of `remove_liquidity` (minimum amounts, locks, pauses, and so on). Liquidity
+        /// providers are not compensated; that is left to governance. Position deposits and the
+        /// pool creation deposit are released, but `OnLiquidityChanged` is not notified.
+        ///
+        /// Everything stored for the pool is removed, including its settings and scheduled
+        /// removals. Liquidity mining rewards already accrued are paid out to their owners, and
+        /// those not yet distributed are transferred to `recipient`.
+        ///
+        /// Finding the pool's positions requires visiting every liquidity position (of any pool),
+        /// every record of a recent liquidity change, every scheduled removal block, and, if the
+        /// pool has a reward pot, every reward position. `max_items` bounds the total number of
+        /// these storage items visited, and thus the weight; the call fails with
+        /// `MigrationTooLarge` if there are more.
+        ///
+        /// The dispatch origin for this call must be `AdminOrigin`.
+        #[pallet::weight(Pallet::<T>::admin_drain_pool_weight(*max_items))]
+        #[transactional]
+        pub fn admin_drain_pool(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            recipient: T::AccountId,
+            max_items: u32,
+        ) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            ensure!(!TotalLiquidity::<T>::get(asset_pair).is_zero(), Error::<T>::NoLiquidity);
+            let pool_account = get_pool_account::<T>(asset_pair);
+
+            // Collect the storage items to remove up front, as it isn't safe to modify a map while
+            // iterating over it
+            let mut remaining = max_items;
+            let holders: Vec<T::AccountId> =
+                collect_bounded::<T, _>(Liquidity::<T>::iter_keys(), &mut remaining)?
+                    .into_iter()
+                    .filter(|(_, pair)| *pair == asset_pair)
+                    .map(|(who, _)| who)
+                    .collect();
+            let changes: Vec<T::AccountId> =
+                collect_bounded::<T, _>(LastLiquidityChange::<T>::iter_keys(), &mut remaining)?
+                    .into_iter()
+                    .filter(|(_, pair)| *pair == asset_pair)
+                    .map(|(who, _)| who)
+                    .collect();
+            let removal_blocks =
+                collect_bounded::<T, _>(ScheduledRemovals::<T>::iter_keys(), &mut remaining)?;
+            let reward_accounts: Vec<T::AccountId> = if RewardPots::<T>::contains_key(asset_pair) {
+                collect_bounded::<T, _>(RewardPositions::<T>::iter_keys(), &mut remaining)?
+                    .into_iter()
+                    .filter(|(_, pair)| *pair == asset_pair)
+                    .map(|(who, _)| who)
+                    // Settling the holders' rewards below may add positions for them
+                    .chain(holders.iter().cloned())
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
+            let drain = |asset| -> Result<AssetBalanceOf<T>, DispatchError> {
+                let balance = T::Fungibles::balance(asset, &pool_account);
+                if balance.is_zero() {
+                    return Ok(balance)
+                }
+                T::Fungibles::transfer(asset, &pool_account, &recipient, balance, false)
+            };
+            let amount_a = drain(asset_a)?;
+            let amount_b = drain(asset_b)?;
+
+            for who in holders {
+                Self::settle_rewards(&who, asset_pair)?;
+                Liquidity::<T>::remove(&who, asset_pair);
+                Self::close_position(&who, asset_pair);
+                LiquidityAddedAt::<T>::remove(&who, asset_pair);
+                LiquidityLocks::<T>::remove(&who, asset_pair);
+            }
+            for who in changes {
+                LastLiquidityChange::<T>::remove(&who, asset_pair);
+            }
+            for block in removal_blocks {
+                ScheduledRemovals::<T>::mutate_exists(block, |maybe_removals| {
+                    if let Some(removals) = maybe_removals {
+                        removals.retain(|removal| removal.asset_pair != asset_pair);
+                        if removals.is_empty() {
+                            *maybe_removals = None;
+                        }
+                    }
+                });
+            }
+            if let Some(pot) = RewardPots::<T>::take(asset_pair) {
+                Self::close_reward_pot(asset_pair, pot, reward_accounts, &recipient)?;
+            }
+            TotalLiquidity::<T>::remove(asset_pair);
+            Reserves::<T>::remove(asset_pair);
+            // See do_remove_liquidity
+            let _ = frame_system::Pallet::<T>::dec_providers(&pool_account);
+            PoolCount::<T>::mutate(|count| *count = count.saturating_sub(1));
+            Self::deposit_event(Event::PoolDrainedByAdmin {
+                asset_a,
+                amount_a,
+                asset_b,
+                amount_b,
+                recipient,
+            });
+
+            // As for cleanup_pool, but the settings are removed too
+            let mut info = Pools::<T>::take(asset_pair);
+            Self::release_pool_deposit(&mut info, asset_a, asset_b);
+            MinAddAmounts::<T>::remove(asset_pair);
+            InitialLiquidityPerAssetUnitOverrides::<T>::remove(asset_pair);
+            PriceCumulative::<T>::remove(asset_pair);
+            Observations::<T>::remove(asset_pair);
+            PriceHistory::<T>::remove(asset_pair);
+            PriceHistoryHead::<T>::remove(asset_pair);
+            CumulativeVolume::<T>::remove(asset_pair);
+            VolumeHistory::<T>::remove(asset_pair);
+            VolumeHistoryHead::<T>::remove(asset_pair);
+            BlockStartPrices::<T>::remove(asset_pair);
+            BlockOpeningReserves::<T>::remove(asset_pair);
+            OraclePrices::<T>::remove(asset_pair);
+            Self::deposit_event(Event::PoolRemoved { asset_a, asset_b });
+
+            Ok(())
+        }
+
+        /// Migrate the liquidity pool for `old_asset` and `counterpart_asset` to `new_asset`, for
+        /// when `old_asset` is redenominated or replaced. Each unit of `old_asset` in the pool is
+        /// replaced by `rate_numerator / rate_denominator` units of `new_asset`, rounded down.
+        ///
+        /// The account of the new pool (see `get_pool_account`) must be funded with enough of
+        /// `new_asset` beforehand. The counterpart asset is moved to it from the old pool account,
+        /// and the old asset is transferred to `DustSink` for conversion. Liquidity positions keep
+        /// exactly the same number of liquidity tokens, along with their locks and deposits. The
+        /// pool's settings are kept, except for any price band, minimum addition, and initial
+        /// liquidity per asset unit, which are removed as they are expressed in the old asset.
+        /// Price history is discarded.
+        ///
+        /// Fails with `MigrationTargetExists` if there is already a pool for `new_asset` and
+        /// `counterpart_asset`, or storage for one awaiting `cleanup_pool`.
+        ///
+        /// Finding the pool's positions requires visiting every liquidity position (of any pool),
+        /// every scheduled removal block, and, if the pool has a reward pot, every reward
+        /// position. `max_items` bounds the total number of these storage items visited, and thus
+        /// the weight; the call fails with `MigrationTooLarge` if there are more.
+        ///
+        /// The dispatch origin for this call must be root.
+        #[pallet::weight(Pallet::<T>::force_migrate_pool_weight(*max_items))]
+        #[transactional]
+        pub fn force_migrate_pool(
+            origin: OriginFor<T>,
+            old_asset: AssetIdOf<T>,
+            new_asset: AssetIdOf<T>,
+            rate_numerator: AssetBalanceOf<T>,
+            rate_denominator: AssetBalanceOf<T>,
+            counterpart_asset: AssetIdOf<T>,
+            max_items: u32,
+        ) -> DispatchResult {
+            ensure_root(origin)?;
+
+            ensure!(old_asset != new_asset, Error::<T>::AssetsIdentical);
+            let old_pair = make_asset_pair::<T>(old_asset, counterpart_asset)?;
+            let new_pair = make_asset_pair::<T>(new_asset, counterpart_asset)?;
+            let total_liquidity = TotalLiquidity::<T>::get(old_pair);
+            ensure!(!total_liquidity.is_zero(), Error::<T>::NoLiquidity);
+            ensure!(
+                TotalLiquidity::<T>::get(new_pair).is_zero() && !Pools::<T>::contains_key(new_pair),
+                Error::<T>::MigrationTargetExists
+            );
+
+            // Collect the storage items to re-key up front, as it isn't safe to modify a map while
+            // iterating over it
+            let mut remaining = max_items;
+            let positions = collect_bounded::<T, _>(Liquidity::<T>::iter(), &mut remaining)?;
+            let removal_blocks =
+                collect_bounded::<T, _>(ScheduledRemovals::<T>::iter_keys(), &mut remaining)?;
+            let reward_positions = if RewardPots::<T>::contains_key(old_pair) {
+                collect_bounded::<T, _>(RewardPositions::<T>::iter(), &mut remaining)?
+            } else {
+                Vec::new()
+            };
+
+            let (old_reserve, counterpart_reserve) = get_reserves::<T>(old_asset, old_pair);
+            let new_reserve = mul_div_floor(old_reserve, rate_numerator, rate_denominator)?;
+            ensure!(!new_reserve.is_zero(), Error::<T>::InvalidMigrationRate);
+            let migrate_per_share = |per_share: FixedU128| -> Result<FixedU128, ArithmeticError> {
+                Ok(FixedU128::from_inner(mul_div_floor(
+                    per_share.into_inner(),
+                    rate_numerator.saturated_into(),
+                    rate_denominator.saturated_into(),
+                )?))
+            };
+
+            // Move the assets. The new pool account takes over the provider reference from the
+            // old one (see do_add_liquidity).
+            let old_account = get_pool_account::<T>(old_pair);
+            let new_account = get_pool_account::<T>(new_pair);
+            ensure!(
+                T::Fungibles::balance(new_asset, &new_account) >= new_reserve,
+                Error::<T>::MigrationNotFunded
+            );
+            frame_system::Pallet::<T>::inc_providers(&new_account);
+            let counterpart_balance = T::Fungibles::balance(counterpart_asset, &old_account);
+            T::Fungibles::transfer(
+                counterpart_asset,
+                &old_account,
+                &new_account,
+                counterpart_balance,
+                false,
+            )?;
+            let old_balance = T::Fungibles::balance(old_asset, &old_account);
+            T::Fungibles::transfer(
+                old_asset,
+                &old_account,
+                &T::DustSink::get(),
+                old_balance,
+                false,
+            )?;
+            let _ = frame_system::Pallet::<T>::dec_providers(&old_account);
+
+            // Re-key the pool
+            TotalLiquidity::<T>::remove(old_pair);
+            TotalLiquidity::<T>::insert(new_pair, total_liquidity);
+            Reserves::<T>::remove(old_pair);
+            set_reserves::<T>(new_asset, new_pair, new_reserve, counterpart_reserve);
+            let info = Pools::<T>::take(old_pair);
+            Pools::<T>::insert(new_pair, PoolInfo { min_rate: None, max_rate: None, ..info });
+            Self::record_lp_asset_id(new_pair)?;
+            if RegisteredPools::<T>::take(old_pair).is_some() {
+                RegisteredPools::<T>::insert(new_pair, ());
+            }
+            if let Some(bootstrap) = Bootstraps::<T>::take(old_pair) {
+                Bootstraps::<T>::insert(new_pair, bootstrap);
+            }
+            // The liquidity positions are unchanged, so the reward accounting carries over as is
+            if let Some(pot) = RewardPots::<T>::take(old_pair) {
+                RewardPots::<T>::insert(new_pair, pot);
+                UndistributedRewards::<T>::insert(
+                    new_pair,
+                    UndistributedRewards::<T>::take(old_pair),
+                );
+                for (who, _, position) in reward_positions
+                    .into_iter()
+                    .filter(|(_, asset_pair, _)| *asset_pair == old_pair)
+                {
+                    RewardPositions::<T>::remove(&who, old_pair);
+                    RewardPositions::<T>::insert(&who, new_pair, position);
+                }
+            }
+            MinAddAmounts::<T>::remove(old_pair);
+            InitialLiquidityPerAssetUnitOverrides::<T>::remove(old_pair);
+            PriceCumulative::<T>::remove(old_pair);
+            Observations::<T>::remove(old_pair);
+            PriceHistory::<T>::remove(old_pair);
+            PriceHistoryHead::<T>::remove(old_pair);
+            CumulativeVolume::<T>::remove(old_pair);
+            VolumeHistory::<T>::remove(old_pair);
+            VolumeHistoryHead::<T>::remove(old_pair);
+            BlockStartPrices::<T>::remove(old_pair);
+            BlockOpeningReserves::<T>::remove(old_pair);
+            OraclePrices::<T>::remove(old_pair);
+
+            // Re-key the positions
+            for (who, _, position) in
+                positions.into_iter().filter(|(_, asset_pair, _)| *asset_pair == old_pair)
+            {
+                Liquidity::<T>::remove(&who, old_pair);
+                let (old_per_share, counterpart_per_share) = order_for_pair::<T, _>(
+                    old_asset,
+                    old_pair,
+                    position.reserve_a_per_share,
+                    position.reserve_b_per_share,
+                );
+                let (reserve_a_per_share, reserve_b_per_share) = order_for_pair::<T, _>(
+                    new_asset,
+                    new_pair,
+                    migrate_per_share(old_per_share)?,
+                    counterpart_per_share,
+                );
+                Liquidity::<T>::insert(
+                    &who,
+                    new_pair,
+                    LiquidityPosition {
+                        amount: position.amount,
+                        reserve_a_per_share,
+                        reserve_b_per_share,
+                    },
+                );
+                if let Some(deposit) = PositionDeposits::<T>::take(&who, old_pair) {
+                    PositionDeposits::<T>::insert(&who, new_pair, deposit);
+                }
+                if let Some(added_at) = LiquidityAddedAt::<T>::take(&who, old_pair) {
+                    LiquidityAddedAt::<T>::insert(&who, new_pair, added_at);
+                }
+                if let Some(changed_at) = LastLiquidityChange::<T>::take(&who, old_pair) {
+                    LastLiquidityChange::<T>::insert(&who, new_pair, changed_at);
+                }
+                let locks = LiquidityLocks::<T>::take(&who, old_pair);
+                if !locks.is_empty() {
+                    LiquidityLocks::<T>::insert(&who, new_pair, locks);
+                }
+            }
+            for block in removal_blocks {
+                ScheduledRemovals::<T>::mutate(block, |removals| {
+                    for removal in
+                        removals.iter_mut().filter(|removal| removal.asset_pair == old_pair)
+                    {
+                        removal.asset_pair = new_pair;
+                    }
+                });
+            }
+
+            Self::deposit_event(Event::PoolMigrated {
+                old_asset,
+                new_asset,
+                counterpart_asset,
+                old_reserve,
+                new_reserve,
+            });
+
+            Ok(())
+        }
+
+        /// Schedule the redemption of liquidity tokens for an asset pair at the start of a future
+        /// block, as with `remove_liquidity`. This allows a liquidity provider to commit to
+        /// exiting at a known block without having to submit a transaction at that time.
         ///
-        /// If the sender is the first liquidity provider for the given asset pair, the full
-        /// amounts will be added to the liquidity pool. Otherwise, an equivalent value of each
-        /// asset will be transferred, according to the current exchange rate.
+        /// Other than checking that the sender currently holds enough liquidity tokens, whether
+        /// the removal can succeed is only checked when it is executed; if it fails (eg because
+        /// the sender's position has shrunk), a `ScheduledRemovalFailed` event is raised
+        /// and the position is left untouched. At most `MaxScheduledRemovalsPerBlock` removals
+        /// can be scheduled for each block, and at most one per sender and asset pair.
+        #[pallet::weight(10_000)] // TODO
+        pub fn schedule_remove_liquidity(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            liquidity: LiquidityBalanceOf<T>,
+            at_block: T::BlockNumber,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            ensure!(
+                liquidity <= Liquidity::<T>::get(&sender, asset_pair).amount,
+                Error::<T>::LiquidityExceedsPosition
+            );
+            ensure!(
+                at_block > frame_system::Pallet::<T>::block_number(),
+                Error::<T>::ScheduleNotInFuture
+            );
+
+            ScheduledRemovals::<T>::try_mutate(at_block, |removals| -> DispatchResult {
+                ensure!(
+                    !removals
+                        .iter()
+                        .any(|removal| removal.who == sender && removal.asset_pair == asset_pair),
+                    Error::<T>::RemovalAlreadyScheduled
+                );
+                removals
+                    .try_push(ScheduledRemoval { who: sender.clone(), asset_pair, liquidity })
+                    .map_err(|_| Error::<T>::TooManyScheduledRemovals)?;
+                Ok(())
+            })?;
+
+            Self::deposit_event(Event::RemovalScheduled {
+                who: sender,
+                asset_a,
+                asset_b,
+                liquidity,
+                at_block,
+            });
+
+            Ok(())
+        }
+
+        /// Cancel a liquidity removal previously scheduled with `schedule_remove_liquidity`.
+        #[pallet::weight(10_000)] // TODO
+        pub fn cancel_scheduled_removal(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            at_block: T::BlockNumber,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            ScheduledRemovals::<T>::try_mutate_exists(at_block, |maybe_removals| {
+                let removals =
+                    maybe_removals.as_mut().ok_or(Error::<T>::ScheduledRemovalNotFound)?;
+                let index = removals
+                    .iter()
+                    .position(|removal| removal.who == sender && removal.asset_pair == asset_pair)
+                    .ok_or(Error::<T>::ScheduledRemovalNotFound)?;
+                removals.remove(index);
+                if removals.is_empty() {
+                    *maybe_removals = None;
+                }
+                Ok::<_, Error<T>>(())
+            })?;
+
+            Self::deposit_event(Event::ScheduledRemovalCancelled {
+                who: sender,
+                asset_a,
+                asset_b,
+                at_block,
+            });
+
+            Ok(())
+        }
+
+        /// Add liquidity for an asset pair using only one of the assets.
+        ///
+        /// Part of `amount_in` of `asset_in` is first exchanged for `asset_other`, as with
+        /// `exchange`. The amount exchanged is chosen, taking the exchange fee into account, so
+        /// that the remainder and the proceeds can be added to the liquidity pool together, as
+        /// with `add_liquidity`. Any dust left over due to rounding stays with the sender.
+        ///
+        /// The liquidity pool must not be empty. The only protection against unfavourable
+        /// movements in the exchange rate is `min_liquidity`: if the number of liquidity tokens
+        /// provided would be less than this, the transaction is aborted.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn swap_and_add_liquidity(
+            origin: OriginFor<T>,
+            asset_in: AssetIdOf<T>,
+            amount_in: AssetBalanceOf<T>,
+            asset_other: AssetIdOf<T>,
+            min_liquidity: LiquidityBalanceOf<T>,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let asset_pair = make_asset_pair::<T>(asset_in, asset_other)?;
+            ensure!(!TotalLiquidity::<T>::get(asset_pair).is_zero(), Error::<T>::NoLiquidity);
+
+            let swap_amount = get_swap_amount_for_add::<T>(
+                get_reserves::<T>(asset_in, asset_pair).0,
+                amount_in,
+                get_exchange_fee::<T>(asset_pair),
+            )?;
+            let amount_other = Self::do_exchange(
+                &sender,
+                asset_in,
+                swap_amount,
+                asset_other,
+                Zero::zero(),
+                false,
+            )?;
+            Self::do_add_liquidity(
+                &sender,
+                asset_in,
+                Zero::zero(),
+                sub(amount_in, swap_amount)?,
+                asset_other,
+                Zero::zero(),
+                amount_other,
+                min_liquidity,
+                false,
+            )?;
+
+            Ok(())
+        }
+
+        /// Redeem liquidity tokens for an asset pair, as with `remove_liquidity`, and exchange the
+        /// redeemed amount of the other asset for `receive_asset`, as with `exchange`, so that the
+        /// sender ends up with only `receive_asset`. `receive_asset` must be one of the two assets
+        /// of the pair.
+        ///
+        /// The exchange uses the same liquidity pool, after the liquidity has been removed, and is
+        /// charged the normal fee. If the combined amount of `receive_asset` transferred to the
+        /// sender is less than `min_total_out`, the transaction is aborted. Note that the exchange
+        /// fails with `NoLiquidity` if removing the liquidity empties the pool.
+        #[pallet::weight(10_000)] // TODO
+        #[transactional]
+        pub fn remove_and_swap(
+            origin: OriginFor<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            liquidity: LiquidityBalanceOf<T>,
+            receive_asset: AssetIdOf<T>,
+            min_total_out: AssetBalanceOf<T>,
+        ) -> DispatchResult {
+            let sender = ensure_signed(origin)?;
+            ensure!(
+                receive_asset == asset_a || receive_asset == asset_b,
+                Error::<T>::AssetNotInPair
+            );
+
+            let (amount_a, amount_b) =
+                Self::do_remove_liquidity(&sender, asset_a, asset_b, liquidity)?;
+            let (removed_amount, swap_asset, swap_amount) = if receive_asset == asset_a {
+                (amount_a, asset_b, amount_b)
+            } else {
+                (amount_b, asset_a, amount_a)
+            };
+
+            let swapped_amount = if swap_amount.is_zero() {
+                Zero::zero()
+            } else {
+                Self::do_exchange(
+                    &sender,
+                    swap_asset,
+                    swap_amount,
+                    receive_asset,
+                    Zero::zero(),
+                    false,
+                )?
+            };
+
+            // Abort the transaction if the sender would not receive enough in total
+            ensure!(
+                add(removed_amount, swapped_amount)? >= min_total_out,
+                Error::<T>::UnexpectedExchangeRate
+            );
+
+            Ok(())
+        }
+
+        /// Record the spot prices in liquidity pools, as observed at `block_number`, in
+        /// `OraclePrices`. This is submitted by the off-chain worker as an unsigned transaction
+        /// when `OffchainPriceReporting` is enabled; the reported prices must match the current
+        /// spot prices for the transaction to be valid.
+        ///
+        /// The dispatch origin for this call must be none.
+        #[pallet::weight(10_000)] // TODO
+        pub fn report_prices(
+            origin: OriginFor<T>,
+            block_number: T::BlockNumber,
+            prices: PriceReportsOf<T>,
+        ) -> DispatchResult {
+            ensure_none(origin)?;
+
+            for (asset_pair, price_a, price_b) in prices.iter() {
+                OraclePrices::<T>::insert(
+                    asset_pair,
+                    OraclePrice { price_a: *price_a, price_b: *price_b, reported_at: block_number },
+                );
+            }
+            if let Some((asset_pair, _, _)) = prices.last() {
+                PriceReportCursor::<T>::put(asset_pair);
+            }
+            LastPriceReport::<T>::put(block_number);
+
+            Ok(())
+        }
+    }
+
+    #[pallet::validate_unsigned]
+    impl<T: Config> ValidateUnsigned for Pallet<T> {
+        type Call = Call<T>;
+
+        fn validate_unsigned(_source: TransactionSource, call: &Self::Call) -> TransactionValidity {
+            match call {
+                Call::report_prices { block_number, prices } => {
+                    Self::validate_price_report(*block_number, prices)?;
+                    ValidTransaction::with_tag_prefix("CfmmPriceReport")
+                        .priority(T::UnsignedPriority::get())
+                        .and_provides(block_number)
+                        // The reported prices quickly become out of date
+                        .longevity(3)
+                        .propagate(true)
+                        .build()
+                },
+                _ => InvalidTransaction::Call.into(),
+            }
+        }
+    }
+
+    impl<T: Config> Pallet<T> {
+        /// Add liquidity for an asset pair on behalf of `who`, returning the amount of each asset
+        /// transferred from them and the number of liquidity tokens minted. See `add_liquidity`.
+        ///
+        /// This performs no origin checks, so that other pallets can add liquidity on behalf of
+        /// accounts.
+        #[allow(clippy::too_many_arguments)]
+        pub fn do_add_liquidity(
+            who: &T::AccountId,
+            asset_a: AssetIdOf<T>,
+            min_amount_a: AssetBalanceOf<T>,
+            max_amount_a: AssetBalanceOf<T>,
+            asset_b: AssetIdOf<T>,
+            min_amount_b: AssetBalanceOf<T>,
+            max_amount_b: AssetBalanceOf<T>,
+            min_liquidity: LiquidityBalanceOf<T>,
+            keep_alive: bool,
+        ) -> Result<(AssetBalanceOf<T>, AssetBalanceOf<T>, LiquidityBalanceOf<T>), DispatchError>
+        {
+            Self::do_add_liquidity_from(
+                who,
+                None,
+                asset_a,
+                min_amount_a,
+                max_amount_a,
+                asset_b,
+                min_amount_b,
+                max_amount_b,
+                min_liquidity,
+                keep_alive,
+            )
+        }
+
+        /// As `do_add_liquidity`, but if `delegate` is provided, the assets are transferred from
+        /// `who` using `delegate`'s approvals, and `keep_alive` is ignored. See
+        /// `add_liquidity_from`.
+        #[allow(clippy::too_many_arguments)]
+        #[transactional]
+        pub fn do_add_liquidity_from(
+            who: &T::AccountId,
+            delegate: Option<&T::AccountId>,
+            asset_a: AssetIdOf<T>,
+            min_amount_a: AssetBalanceOf<T>,
+            max_amount_a: AssetBalanceOf<T>,
+            asset_b: AssetIdOf<T>,
+            min_amount_b: AssetBalanceOf<T>,
+            max_amount_b: AssetBalanceOf<T>,
+            min_liquidity: LiquidityBalanceOf<T>,
+            keep_alive: bool,
+        ) -> Result<(AssetBalanceOf<T>, AssetBalanceOf<T>, LiquidityBalanceOf<T>), DispatchError>
+        {
+            ensure!(!Paused::<T>::get(), Error::<T>::PalletPaused);
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            ensure!(
+                Pools::<T>::get(asset_pair).status == PoolStatus::Active,
+                Error::<T>::PoolPaused
+            );
+            Self::ensure_not_blacklisted(asset_pair)?;
+            Self::ensure_bootstrap_allows(who, asset_pair, false)?;
+            Self::ensure_cooldown_elapsed(who, asset_pair)?;
+            Self::update_price_cumulative(asset_pair);
+            let reward_pot = Self::settle_rewards(who, asset_pair)?;
+            let total_liquidity = TotalLiquidity::<T>::get(asset_pair);
+            let pool_account = get_pool_account::<T>(asset_pair);
+
+            let (pool_amount_a, pool_amount_b) = get_reserves::<T>(asset_a, asset_pair);
+
+            let (amount_a, amount_b) = if total_liquidity.is_zero() {
+                // The sender is the first liquidity provider
+                ensure!(
+                    !WhitelistOnly::<T>::get() ||
+                        (WhitelistedAssets::<T>::contains_key(asset_a) &&
+                            WhitelistedAssets::<T>::contains_key(asset_b)),
+                    Error::<T>::AssetNotWhitelisted
+                );
+                let pool_count = PoolCount::<T>::get();
+                ensure!(pool_count < T::MaxPools::get(), Error::<T>::TooManyPools);
+                PoolCount::<T>::put(pool_count.saturating_add(1));
+                Self::record_pool_creation(who, asset_a, asset_b, asset_pair)?;
+                // Keep the pool account alive for as long as the pool has liquidity, regardless
+                // of the balances it holds. This also permits pools of assets which are not
+                // sufficient. The reference is released when the pool is drained.
+                frame_system::Pallet::<T>::inc_providers(&pool_account);
+                (max_amount_a, max_amount_b)
+            } else {
+                // There is already some liquidity in the pool. An equivalent value of each asset
+                // must be added, using the current exchange rate. Figure out which of max_amount_a
+                // and max_amount_b is the least valuable, and determine the added liquidity from
+                // that.
+                let added_liquidity =
+                    if mul(max_amount_a, pool_amount_b)? < mul(max_amount_b, pool_amount_a)? {
+                        // pool_amount_a=0 would imply the pool is empty despite the total
+                        // liquidity being non-zero
+                        mul_div_floor(max_amount_a, total_liquidity, pool_amount_a)?
+                    } else {
+                        mul_div_floor(max_amount_b, total_liquidity, pool_amount_b)?
+                    };
+
+                // Determine the actual amounts to add to the pool. We round down above and up here
+                // to favour the existing liquidity providers over the sender of this transaction.
+                get_amounts_for_liquidity::<T>(
+                    added_liquidity,
+                    pool_amount_a,
+                    pool_amount_b,
+                    total_liquidity,
+                )?
+            };
+
+            // Abort the transaction if the sender would not add enough of each asset (these checks
+            // exist to protect the sender against adding liquidity to a pool when the exchange
+            // rate has moved too far from what they expect).
+            ensure!(amount_a >= min_amount_a, Error::<T>::UnexpectedExchangeRate);
+            ensure!(amount_b >= min_amount_b, Error::<T>::UnexpectedExchangeRate);
+
+            // Transfer the assets to the pool. Note that unless keep_alive is set we might end up
+            // adding a bit more than we thought if the source account would otherwise end up with
+            // a balance between 0 and the minimum. This is harmless, but we do take care to report
+            // it properly in the LiquidityAdded event, and to credit the sender for it...
+            let transfer = |asset, amount| match delegate {
+                Some(delegate) =>
+                    Self::transfer_approved(asset, who, delegate, &pool_account, amount),
+                None => T::Fungibles::transfer(asset, who, &pool_account, amount, keep_alive),
+            };
+            let amount_a = transfer(asset_a, amount_a)?;
+            let amount_b = transfer(asset_b, amount_b)?;
+
+            // Reject dust additions to existing pools, based on the amounts actually transferred.
+            // The first addition to an empty pool is exempt.
+            if !total_liquidity.is_zero() {
+                Self::ensure_min_add_amount(
+                    asset_a,
+                    asset_pair,
+                    (pool_amount_a, pool_amount_b),
+                    amount_a,
+                    amount_b,
+                )?;
+            }
+
+            // Determine the added liquidity from the amounts actually transferred. As above, round
+            // down to favour the existing liquidity providers.
+            let added_liquidity = if total_liquidity.is_zero() {
+                // The value we choose here is somewhat arbitrary
+                let combined_amount = match T::InitialLiquidityFormula::get() {
+                    InitialLiquidityFormula::Max => max(amount_a, amount_b),
+                    InitialLiquidityFormula::GeometricMean =>
+                        geometric_mean::<T>(amount_a, amount_b)?,
+                };
+                let added_liquidity = combined_amount.saturating_mul(
+                    InitialLiquidityPerAssetUnitOverrides::<T>::get(asset_pair)
+                        .unwrap_or_else(T::InitialLiquidityPerAssetUnit::get),
+                );
+                ensure!(
+                    added_liquidity >= T::MinimumLiquidity::get(),
+                    Error::<T>::InsufficientLiquidityMinted
+                );
+                added_liquidity
+            } else {
+                get_liquidity_for_amounts::<T>(
+                    amount_a,
+                    amount_b,
+                    pool_amount_a,
+                    pool_amount_b,
+                    total_liquidity,
+                )?
+            };
+
+            // Abort the transaction if the sender would not receive enough liquidity tokens
+            ensure!(added_liquidity >= min_liquidity, Error::<T>::TooLittleLiquidityMinted);
+
+            let pool_amount_a = add(pool_amount_a, amount_a)?;
+            let pool_amount_b = add(pool_amount_b, amount_b)?;
+            set_reserves::<T>(asset_a, asset_pair, pool_amount_a, pool_amount_b);
+
+            // Credit the sender with the added liquidity
+            let total_liquidity = add(total_liquidity, added_liquidity)?;
+            TotalLiquidity::<T>::set(asset_pair, total_liquidity);
+            let sender_position = Liquidity::<T>::get(who, asset_pair);
+            let sender_liquidity = add(sender_position.amount, added_liquidity)?;
+
+            // Blend the current amounts per share into the sender's snapshot, so that only growth
+            // from this point on is counted as fee earnings for the added liquidity
+            let (pool_amount_0, pool_amount_1) =
+                order_for_pair::<T, _>(asset_a, asset_pair, pool_amount_a, pool_amount_b);
+            let reserve_a_per_share = blend_amount_per_share::<T>(
+                sender_position.reserve_a_per_share,
+                sender_position.amount,
+                get_amount_per_share::<T>(pool_amount_0, total_liquidity)?,
+                added_liquidity,
+            )?;
+            let reserve_b_per_share = blend_amount_per_share::<T>(
+                sender_position.reserve_b_per_share,
+                sender_position.amount,
+                get_amount_per_share::<T>(pool_amount_1, total_liquidity)?,
+                added_liquidity,
+            )?;
+            if sender_position.amount.is_zero() {
+                Self::open_position(who, asset_pair, who)?;
+            }
+            Liquidity::<T>::insert(
+                who,
+                asset_pair,
+                LiquidityPosition {
+                    amount: sender_liquidity,
+                    reserve_a_per_share,
+                    reserve_b_per_share,
+                },
+            );
+            if let Some(pot) = reward_pot {
+                Self::update_reward_debt(who, asset_pair, &pot);
+            }
+            if !T::MinLockPeriod::get().is_zero() {
+                LiquidityAddedAt::<T>::insert(
+                    who,
+                    asset_pair,
+                    frame_system::Pallet::<T>::block_number(),
+                );
+            }
+            Self::note_liquidity_change(who, asset_pair);
+
+            // Check the sender added a sufficient amount of each asset
+            ensure!(
+                mul_div_floor(pool_amount_a, sender_liquidity, total_liquidity)? >=
+                    Self::get_min_pool_amount(asset_a)?,
+                Error::<T>::InsufficientPoolAmount
+            );
+            ensure!(
+                mul_div_floor(pool_amount_b, sender_liquidity, total_liquidity)? >=
+                    Self::get_min_pool_amount(asset_b)?,
+                Error::<T>::InsufficientPoolAmount
+            );
+
+            if total_liquidity == added_liquidity {
+                // The sender is the first liquidity provider
+                Self::deposit_event(Event::PoolCreated { asset_a, asset_b, pool_account });
+            }
+            Self::deposit_event(Event::LiquidityAdded {
+                who: who.clone(),
+                asset_a,
+                amount_a,
+                asset_b,
+                amount_b,
+                liquidity: added_liquidity,
+                new_reserve_a: pool_amount_a,
+                new_reserve_b: pool_amount_b,
+            });
+            T::OnLiquidityChanged::on_liquidity_added(
+                who,
+                asset_a,
+                amount_a,
+                asset_b,
+                amount_b,
+                added_liquidity,
+            );
+
+            Ok((amount_a, amount_b, added_liquidity))
+        }
+
+        /// Redeem liquidity tokens for an asset pair on behalf of `who`, returning the amount of
+        /// each asset transferred to them. See `remove_liquidity`.
+        ///
+        /// This performs no origin checks, so that other pallets can remove liquidity on behalf of
+        /// accounts.
+        #[transactional]
+        pub fn do_remove_liquidity(
+            who: &T::AccountId,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            liquidity: LiquidityBalanceOf<T>,
+        ) -> Result<(AssetBalanceOf<T>, AssetBalanceOf<T>), DispatchError> {
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            ensure!(
+                Pools::<T>::get(asset_pair).status != PoolStatus::Frozen,
+                Error::<T>::PoolFrozen
+            );
+            Self::ensure_liquidity_unlocked(who, asset_pair)?;
+            Self::ensure_cooldown_elapsed(who, asset_pair)?;
+            let sender_position = Liquidity::<T>::get(who, asset_pair);
+            ensure!(liquidity <= sender_position.amount, Error::<T>::LiquidityExceedsPosition);
+            Self::update_price_cumulative(asset_pair);
+            let reward_pot = Self::settle_rewards(who, asset_pair)?;
+            let total_liquidity = TotalLiquidity::<T>::get(asset_pair);
+            let pool_account = get_pool_account::<T>(asset_pair);
+
+            let (pool_amount_a, pool_amount_b) = get_reserves::<T>(asset_a, asset_pair);
+
+            let amount_a = mul_div_floor(liquidity, pool_amount_a, total_liquidity)?;
+            let amount_b = mul_div_floor(liquidity, pool_amount_b, total_liquidity)?;
+
+            // Debit the removed liquidity from the sender's account
+            let total_liquidity = sub(total_liquidity, liquidity)?;
+            if total_liquidity.is_zero() {
+                TotalLiquidity::<T>::remove(asset_pair);
+            } else {
+                TotalLiquidity::<T>::set(asset_pair, total_liquidity);
+            }
+            let sender_liquidity = sub(sender_position.amount, liquidity)?;
+            ensure!(
+                sender_liquidity >= Self::get_locked_liquidity(who, asset_pair)?,
+                Error::<T>::LiquidityTimelocked
+            );
+            if sender_liquidity.is_zero() {
+                Liquidity::<T>::remove(who, asset_pair);
+                Self::close_position(who, asset_pair);
+                LiquidityAddedAt::<T>::remove(who, asset_pair);
+                // Any locks must have expired
+                LiquidityLocks::<T>::remove(who, asset_pair);
+            } else {
+                Liquidity::<T>::insert(
+                    who,
+                    asset_pair,
+                    LiquidityPosition { amount: sender_liquidity, ..sender_position },
+                );
+            }
+            if let Some(pot) = reward_pot {
+                Self::update_reward_debt(who, asset_pair, &pot);
+            }
+            Self::note_liquidity_change(who, asset_pair);
+
+            // If the total liquidity after the removal is non-zero, we want to keep the pool
+            // accounts alive...
+            let keep_alive = !total_liquidity.is_zero();
+
+            // Possibly reduce the transferred amounts to avoid leaving the pool with less than the
+            // minimum balance of either asset
+            let amount_a =
+                min(amount_a, T::Fungibles::reducible_balance(asset_a, &pool_account, keep_alive));
+            let amount_b =
+                min(amount_b, T::Fungibles::reducible_balance(asset_b, &pool_account, keep_alive));
+
+            // Transfer the assets to the sender
+            let amount_a =
+                T::Fungibles::transfer(asset_a, &pool_account, who, amount_a, keep_alive)?;
+            let pool_amount_a = sub(pool_amount_a, amount_a)?;
+            let amount_b =
+                T::Fungibles::transfer(asset_b, &pool_account, who, amount_b, keep_alive)?;
+            let pool_amount_b = sub(pool_amount_b, amount_b)?;
+            if total_liquidity.is_zero() {
+                // Anything left in the pool account is no longer part of the pool; it can be
+                // swept by cleanup_pool
+                Reserves::<T>::remove(asset_pair);
+            } else {
+                set_reserves::<T>(asset_a, asset_pair, pool_amount_a, pool_amount_b);
+            }
+
+            // Check the sender left a sufficient amount of each asset (note that removing all of
+            // your liquidity is always fine)
+            if !sender_liquidity.is_zero() {
+                ensure!(
+                    mul_div_floor(pool_amount_a, sender_liquidity, total_liquidity)? >=
+                        Self::get_min_pool_amount(asset_a)?,
+                    Error::<T>::InsufficientPoolAmount
+                );
+                ensure!(
+                    mul_div_floor(pool_amount_b, sender_liquidity, total_liquidity)? >=
+                        Self::get_min_pool_amount(asset_b)?,
+                    Error::<T>::InsufficientPoolAmount
+                );
+            }
+
+            Self::deposit_event(Event::LiquidityRemoved {
+                who: who.clone(),
+                asset_a,
+                amount_a,
+                asset_b,
+                amount_b,
+                liquidity,
+                new_reserve_a: pool_amount_a,
+                new_reserve_b: pool_amount_b,
+            });
+            if total_liquidity.is_zero() {
+                // This only fails if the pool account still holds assets which are not sufficient,
+                // in which case the reference is leaked and the account is never reaped. This is
+                // harmless; the account is simply reused if the pool is recreated.
+                let _ = frame_system::Pallet::<T>::dec_providers(&pool_account);
+                PoolCount::<T>::mutate(|count| *count = count.saturating_sub(1));
+                Self::deposit_event(Event::PoolDestroyed { asset_a, asset_b, pool_account });
+            }
+            T::OnLiquidityChanged::on_liquidity_removed(
+                who, asset_a, amount_a, asset_b, amount_b, liquidity,
+            );
+
+            Ok((amount_a, amount_b))
+        }
+
+        /// Exchange `source_amount` of `source_asset` for `dest_asset` on behalf of `who`,
+        /// returning the amount of `dest_asset` transferred to them. See `exchange` and
+        /// `exchange_keep_alive`. This fails with `CircuitBreakerTripped` if the exchange trips the
+        /// circuit breaker, without pausing the pool; see `pause_if_tripped`.
+        pub fn do_exchange(
+            who: &T::AccountId,
+            source_asset: AssetIdOf<T>,
+            source_amount: AssetBalanceOf<T>,
+            dest_asset: AssetIdOf<T>,
+            min_dest_amount: AssetBalanceOf<T>,
+            keep_alive: bool,
+        ) -> Result<AssetBalanceOf<T>, DispatchError> {
+            Self::do_exchange_with_referral(
+                who,
+                source_asset,
+                source_amount,
+                dest_asset,
+                min_dest_amount,
+                keep_alive,
+                None,
+            )
+        }
+
+        /// As for `do_exchange`, but if `referrer` is given, `ReferralShare` of the fee is paid
+        /// to them out of the pool. See `exchange_with_referral`.
+        #[transactional]
+        pub fn do_exchange_with_referral(
+            who: &T::AccountId,
+            source_asset: AssetIdOf<T>,
+            source_amount: AssetBalanceOf<T>,
+            dest_asset: AssetIdOf<T>,
+            min_dest_amount: AssetBalanceOf<T>,
+            keep_alive: bool,
+            referrer: Option<&T::AccountId>,
+        ) -> Result<AssetBalanceOf<T>, DispatchError> {
+            ensure!(referrer != Some(who), Error::<T>::SelfReferral);
+            let asset_pair = make_asset_pair::<T>(source_asset, dest_asset)?;
+            Self::ensure_bootstrap_allows(who, asset_pair, true)?;
+            let pool_account = get_pool_account::<T>(asset_pair);
+
+            let ExchangeSimulation {
+                dest_amount,
+                fee_amount,
+                new_reserve_source,
+                new_reserve_dest,
+                ..
+            } = Self::simulate_exchange(source_asset, source_amount, dest_asset)?;
+
+            // Abort the transaction if the sender would not receive enough
+            ensure!(dest_amount >= min_dest_amount, Error::<T>::UnexpectedExchangeRate);
+
+            Self::update_price_cumulative(asset_pair);
+
+            // Refuse to perform the exchange if it would move the exchange rate too far this block.
+            // The pool cannot be paused here, as the pause would be rolled back with the exchange.
+            if let Some(threshold) = T::CircuitBreakerThreshold::get() {
+                let start_price = Self::note_block_start_price(asset_pair);
+                let (new_reserve_0, new_reserve_1) = order_for_pair::<T, _>(
+                    source_asset,
+                    asset_pair,
+                    new_reserve_source,
+                    new_reserve_dest,
+                );
+                let price = FixedU128::checked_from_rational(
+                    new_reserve_1.saturated_into::<u128>(),
+                    new_reserve_0.saturated_into::<u128>(),
+                )
+                .unwrap_or_default();
+                ensure!(
+                    !exceeds_price_move(start_price, price, threshold),
+                    Error::<T>::CircuitBreakerTripped
+                );
+            }
+
+            let (pool_source_amount, pool_dest_amount) =
+                get_reserves::<T>(source_asset, asset_pair);
+
+            // Transfer the assets to/from the sender. Note that unless keep_alive is set we might
+            // transfer more than expected to the pool if the source account would otherwise end up
+            // with a balance between 0 and the minimum. This is harmless, but we do take care to
+            // report it properly in the Exchanged event. Possibly we should handle this before
+            // calculating dest_amount but it doesn't really matter.
+            let source_amount = T::Fungibles::transfer(
+                source_asset,
+                who,
+                &pool_account,
+                source_amount,
+                keep_alive,
+            )?;
+            let new_pool_source_amount = add(pool_source_amount, source_amount)?;
+            let dest_amount =
+                T::Fungibles::transfer(dest_asset, &pool_account, who, dest_amount, true)?;
+            let new_pool_dest_amount = sub(pool_dest_amount, dest_amount)?;
+
+            // Pay the referrer's share of the fee out of the pool. Only the remainder of the fee
+            // is retained for liquidity providers.
+            let referral_amount = match referrer {
+                Some(referrer) => {
+                    let amount = T::ReferralShare::get().mul_floor(fee_amount);
+                    if amount.is_zero() {
+                        amount
+                    } else {
+                        T::Fungibles::transfer(source_asset, &pool_account, referrer, amount, true)?
+                    }
+                },
+                None => Zero::zero(),
+            };
+            let new_pool_source_amount = sub(new_pool_source_amount, referral_amount)?;
+
+            // A safety net against rounding regressions: the product of the amounts in the pool,
+            // excluding the fee, must never decrease. RoundingPolicy::FavorTrader may dip into the
+            // fee, so only the product including the fee is checked then.
+            #[cfg(any(feature = "try-runtime", feature = "runtime-benchmarks", test))]
+            {
+                let retained_source_amount = match T::RoundingPolicy::get() {
+                    RoundingPolicy::FavorPool =>
+                        sub(new_pool_source_amount, sub(fee_amount, referral_amount)?)?,
+                    RoundingPolicy::FavorTrader => new_pool_source_amount,
+                };
+                ensure!(
+                    mul(retained_source_amount, new_pool_dest_amount)? >=
+                        mul(pool_source_amount, pool_dest_amount)?,
+                    Error::<T>::InvariantViolation
+                );
+            }
+
+            Self::record_price_history(asset_pair);
+            set_reserves::<T>(
+                source_asset,
+                asset_pair,
+                new_pool_source_amount,
+                new_pool_dest_amount,
+            );
+            Self::record_volume_history(asset_pair);
+            CumulativeVolume::<T>::mutate(asset_pair, |volume| {
+                let (volume_source, volume_dest) =
+                    order_for_pair::<T, _>(source_asset, asset_pair, volume.0, volume.1);
+                *volume = order_for_pair::<T, _>(
+                    source_asset,
+                    asset_pair,
+                    volume_source.saturating_add(source_amount),
+                    volume_dest.saturating_add(dest_amount),
+                );
+            });
+
+            Self::deposit_event(Event::Exchanged {
+                who: who.clone(),
+                source_asset,
+                source_amount,
+                dest_asset,
+                dest_amount,
+                fee_amount,
+                new_reserve_source: new_pool_source_amount,
+                new_reserve_dest: new_pool_dest_amount,
+            });
+            if let Some(referrer) = referrer.filter(|_| !referral_amount.is_zero()) {
+                Self::deposit_event(Event::ReferralFeePaid {
+                    referrer: referrer.clone(),
+                    asset: source_asset,
+                    amount: referral_amount,
+                });
+            }
+
+            T::OnSwap::on_swap(who, source_asset, source_amount, dest_asset, dest_amount);
+
+            Ok(dest_amount)
+        }
+
+        /// Handle the result of an exchange between `source_asset` and `dest_asset` made directly
+        /// by an extrinsic. If the exchange tripped the circuit breaker, pause the liquidity pool
+        /// and succeed, so that the pause is not rolled back along with the exchange. Other errors
+        /// are passed through.
+        fn pause_if_tripped(
+            source_asset: AssetIdOf<T>,
+            dest_asset: AssetIdOf<T>,
+            result: Result<AssetBalanceOf<T>, DispatchError>,
+        ) -> DispatchResult {
+            match result {
+                Err(error) if error == Error::<T>::CircuitBreakerTripped.into() => {
+                    let asset_pair = make_asset_pair::<T>(source_asset, dest_asset)?;
+                    Pools::<T>::mutate(asset_pair, |info| {
+                        info.status = PoolStatus::TradingDisabled
+                    });
+                    Self::deposit_event(Event::CircuitBreakerTripped {
+                        asset_a: asset_pair.0,
+                        asset_b: asset_pair.1,
+                    });
+                    Ok(())
+                },
+                result => result.map(|_| ()),
+            }
+        }
+
+        /// The weight of `do_exchange_with_referral`, from the storage it accesses: the reads
+        /// made by `simulate_exchange` and the bootstrap check; the price accumulator,
+        /// observations, price history, and circuit breaker; the three asset transfers (including
+        /// the referral fee); and the reserves and volume. `OnSwap` is not included.
+        pub fn exchange_weight() -> Weight {
+            T::DbWeight::get().reads_writes(7 + 5 + 9 + 3, 4 + 6 + 3)
+        }
+
+        /// The weight of `admin_drain_pool` visiting up to `max_items` storage items.
+        pub fn admin_drain_pool_weight(max_items: u32) -> Weight {
+            // TODO: benchmark. For now, count the pool's own storage, the asset transfers, and
+            // closing the reward pot, plus, per item, removing a liquidity position with its
+            // deposit, timestamps, locks, and reward position, and paying out its rewards.
+            T::DbWeight::get().reads_writes(30, 35).saturating_add(
+                T::DbWeight::get().reads_writes(8, 10).saturating_mul(max_items.into()),
+            )
+        }
+
+        /// The weight of `force_migrate_pool` visiting up to `max_items` storage items.
+        pub fn force_migrate_pool_weight(max_items: u32) -> Weight {
+            // TODO: benchmark. For now, count the pool's own storage and the asset transfers,
+            // plus, per item, re-keying a liquidity position with its deposit, timestamps, and
+            // locks.
+            T::DbWeight::get().reads_writes(30, 40).saturating_add(
+                T::DbWeight::get().reads_writes(5, 10).saturating_mul(max_items.into()),
+            )
+        }
+
+        /// Determine the outcome of exchanging `source_amount` of `source_asset` for `dest_asset`,
+        /// without actually performing the exchange. This uses exactly the same calculation as
+        /// `exchange`, and fails in the same cases, except for those depending on the sender (eg
+        /// insufficient balance, or `min_dest_amount`). Note that `exchange` may transfer slightly
+        /// more than `source_amount` if the sender would otherwise be left with a balance below the
+        /// minimum; this is not accounted for in `new_reserve_source`.
+        pub fn simulate_exchange(
+            source_asset: AssetIdOf<T>,
+            source_amount: AssetBalanceOf<T>,
+            dest_asset: AssetIdOf<T>,
+        ) -> Result<ExchangeSimulationOf<T>, DispatchError> {
+            ensure!(!Paused::<T>::get(), Error::<T>::PalletPaused);
+            let asset_pair = make_asset_pair::<T>(source_asset, dest_asset)?;
+            let info = Pools::<T>::get(asset_pair);
+            ensure!(info.status == PoolStatus::Active, Error::<T>::PoolPaused);
+            Self::ensure_not_blacklisted(asset_pair)?;
+            let pool_account = get_pool_account::<T>(asset_pair);
+
+            let (pool_source_amount, pool_dest_amount) =
+                get_reserves::<T>(source_asset, asset_pair);
+            ensure!(!pool_source_amount.is_zero(), Error::<T>::NoLiquidity);
+            ensure!(!pool_dest_amount.is_zero(), Error::<T>::NoLiquidity);
+
+            if let Some(max_fraction) = T::MaxTradeFraction::get() {
+                ensure!(
+                    source_amount <= max_fraction.mul_floor(pool_source_amount),
+                    Error::<T>::TradeTooLarge
+                );
+            }
+
+            let source_fee = get_exchange_fee::<T>(asset_pair).mul_ceil(source_amount);
+            let new_pool_source_amount = add(pool_source_amount, source_amount)?;
+
+            // We want to preserve the product of pool_source_amount and pool_dest_amount when
+            // performing the exchange, then add the fee to the pool.
+            let new_pool_dest_amount = get_new_pool_dest_amount::<T>(
+                pool_source_amount,
+                pool_dest_amount,
+                sub(source_amount, source_fee)?,
+                source_amount,
+            )?;
+            let dest_amount = sub(pool_dest_amount, new_pool_dest_amount)?;
+
+            // Possibly reduce dest_amount to avoid leaving the pool with less than the minimum
+            // balance of the destination asset
+            let dest_amount =
+                min(dest_amount, T::Fungibles::reducible_balance(dest_asset, &pool_account, true));
+
+            // Fail if the exchange would move the exchange rate too far
+            if let Some(max_impact) = T::MaxPriceImpact::get() {
+                ensure!(
+                    !exceeds_price_impact::<T>(
+                        pool_source_amount,
+                        pool_dest_amount,
+                        source_amount,
+                        dest_amount,
+                        max_impact,
+                    )?,
+                    Error::<T>::ExcessivePriceImpact
+                );
+            }
+
+            // Fail if the exchange would leave the exchange rate outside the pool's price band
+            let new_reserve_dest = sub(pool_dest_amount, dest_amount)?;
+            let (new_reserve_0, new_reserve_1) = order_for_pair::<T, _>(
+                source_asset,
+                asset_pair,
+                new_pool_source_amount,
+                new_reserve_dest,
+            );
+            ensure!(
+                within_price_band::<T>(new_reserve_0, new_reserve_1, info.min_rate, info.max_rate)?,
+                Error::<T>::PriceOutOfBand
+            );
+
+            let price = if source_amount.is_zero() {
+                FixedU128::zero()
+            } else {
+                FixedU128::checked_from_rational(
+                    dest_amount.saturated_into::<u128>(),
+                    source_amount.saturated_into::<u128>(),
+                )
+                .ok_or(ArithmeticError::Overflow)?
+            };
+
+            Ok(ExchangeSimulation {
+                dest_amount,
+                fee_amount: source_fee,
+                new_reserve_source: new_pool_source_amount,
+                new_reserve_dest,
+                price,
+            })
+        }
+
+        /// Returns how much worse the effective exchange rate of exchanging `source_amount` of
+        /// `source_asset` for `dest_asset` would be than the current exchange rate, including the
+        /// fee: `1 - (dest_amount / source_amount) / (pool_dest_amount / pool_source_amount)`. The
+        /// outcome of the exchange is determined by `simulate_exchange`, and this fails in the same
+        /// cases. The result is rounded up, and is zero if `source_amount` is zero.
+        pub fn price_impact(
+            source_asset: AssetIdOf<T>,
+            source_amount: AssetBalanceOf<T>,
+            dest_asset: AssetIdOf<T>,
+        ) -> Result<Permill, DispatchError> {
+            let simulation = Self::simulate_exchange(source_asset, source_amount, dest_asset)?;
+            if source_amount.is_zero() {
+                return Ok(Permill::zero())
+            }
+            let asset_pair = make_asset_pair::<T>(source_asset, dest_asset)?;
+            let (pool_source_amount, pool_dest_amount) =
+                get_reserves::<T>(source_asset, asset_pair);
+
+            // Neither amount can be zero, as simulate_exchange succeeded
+            let effective = mul(simulation.dest_amount, pool_source_amount)?
+                .checked_mul(BalanceMulResult::from(Permill::ACCURACY))
+                .ok_or(ArithmeticError::Overflow)?;
+            let retained = effective / mul(source_amount, pool_dest_amount)?;
+            let retained = retained.min(BalanceMulResult::from(Permill::ACCURACY)).as_u32();
+            Ok(Permill::one().saturating_sub(Permill::from_parts(retained)))
+        }
+
+        /// Exchange `source_asset` for (at least) `dest_amount` of `dest_asset` on behalf of `who`,
+        /// returning the amount of `source_asset` transferred from them. The exchange is aborted if
+        /// the effective exchange rate, including the fee, is worse than the current exchange rate
+        /// by more than `max_impact`.
+        #[transactional]
+        pub fn do_exchange_for_exact(
+            who: &T::AccountId,
+            source_asset: AssetIdOf<T>,
+            dest_asset: AssetIdOf<T>,
+            dest_amount: AssetBalanceOf<T>,
+            max_impact: Permill,
+        ) -> Result<AssetBalanceOf<T>, DispatchError> {
+            let source_amount = Self::get_source_amount(source_asset, dest_asset, dest_amount)?;
+            let (pool_source_amount, pool_dest_amount) =
+                Self::get_exchange_rate(source_asset, dest_asset)?;
+            ensure!(
+                !exceeds_price_impact::<T>(
+                    pool_source_amount,
+                    pool_dest_amount,
+                    source_amount,
+                    dest_amount,
+                    max_impact,
+                )?,
+                Error::<T>::ExcessivePriceImpact
+            );
+            Self::do_exchange(who, source_asset, source_amount, dest_asset, dest_amount, false)?;
+            Ok(source_amount)
+        }
+
+        /// Check the pallet's storage invariants:
         ///
-        /// If the actual amount of either asset added would be less than the provided minimum, the
-        /// transaction is aborted. The purpose of this is to protect the sender against
-        /// unfavourable movements in the exchange rate.
+        /// - The liquidity held by all accounts for each asset pair sums to the total liquidity for
+        ///   the asset pair.
+        /// - The liquidity pool for each asset pair with non-zero total liquidity contains a
+        ///   non-zero amount of both assets.
+        /// - The account of each such liquidity pool holds at least the minimum balance of both
+        ///   assets, and at least the amounts in the pool (`Reserves`).
+        /// - The same holds for each weighted liquidity pool and each of its assets.
+        /// - `PoolCount` is the number of asset pairs with non-zero total liquidity plus the number
+        ///   of weighted liquidity pools.
+        /// - `PositionCount` is the number of asset pairs and weighted pools each account holds
+        ///   liquidity for.
+        /// - Position deposits are only held for positions which exist.
         ///
-        /// The sender will be provided with liquidity tokens representing their share of the
-        /// liquidity pool for the given asset pair; the number of tokens provided can be
-        /// determined by looking at the raised `LiquidityAdded` event. These tokens can be
-        /// redeemed for the underlying assets in the pool by calling `remove_liquidity`.
-        #[pallet::weight(10_000)] // TODO
-        #[transactional]
-        pub fn add_liquidity(
+        /// This is intended for tests and `try-runtime`; it iterates over all of the pallet's
+        /// storage so should never be called on-chain. The version of FRAME this pallet is built
+        /// against has no `try_state` hook, so this is called from `post_upgrade` instead.
+        #[cfg(any(feature = "try-runtime", test))]
+        pub fn do_try_state() -> Result<(), &'static str> {
+            let mut liquidity_sums = BTreeMap::new();
+            let mut position_counts = BTreeMap::new();
+            for (who, asset_pair, position) in Liquidity::<T>::iter() {
+                let sum: &mut LiquidityBalanceOf<T> =
+                    liquidity_sums.entry(asset_pair).or_insert_with(Zero::zero);
+                *sum = add(*sum, position.amount).map_err(|_| "Liquidity sum overflowed")?;
+                *position_counts.entry(who).or_insert(0u32) += 1;
+            }
+
+            for (asset_pair, total_liquidity) in TotalLiquidity::<T>::iter() {
+                ensure!(
+                    liquidity_sums.remove(&asset_pair).unwrap_or_else(Zero::zero) ==
+                        total_liquidity,
+                    "Liquidity does not sum to TotalLiquidity"
+                );
+                if !total_liquidity.is_zero() {
+                    let (reserve_0, reserve_1) = Reserves::<T>::get(asset_pair);
+                    ensure!(
+                        !reserve_0.is_zero() && !reserve_1.is_zero(),
+                        "Liquidity pool is empty despite non-zero TotalLiquidity"
+                    );
+                    let pool_account = get_pool_account::<T>(asset_pair);
+                    let balance_0 = T::Fungibles::balance(asset_pair.0, &pool_account);
+                    let balance_1 = T::Fungibles::balance(asset_pair.1, &pool_account);
+                    ensure!(
+                        balance_0 >= T::Fungibles::minimum_balance(asset_pair.0) &&
+                            balance_1 >= T::Fungibles::minimum_balance(asset_pair.1),
+                        "Pool account balance is below the minimum balance"
+                    );
+                    ensure!(
+                        reserve_0 <= balance_0 && reserve_1 <= balance_1,
+                        "Reserves exceed the balances of the pool account"
+                    );
+                }
+            }
+
+            ensure!(
+                liquidity_sums.values().all(Zero::is_zero),
+                "Liquidity held for asset pair without TotalLiquidity"
+            );
+
+            let mut weighted_sums = BTreeMap::new();
+            for (who, pool_id, liquidity) in WeightedLiquidity::<T>::iter() {
+                let sum: &mut LiquidityBalanceOf<T> =
+                    weighted_sums.entry(pool_id).or_insert_with(Zero::zero);
+                *sum = add(*sum, liquidity).map_err(|_| "Liquidity sum overflowed")?;
+                *position_counts.entry(who).or_insert(0u32) += 1;
+            }
+            let mut weighted_pool_count = 0;
+            for (pool_id, pool) in WeightedPools::<T>::iter() {
+                let total_liquidity = WeightedTotalLiquidity::<T>::get(pool_id);
+                ensure!(
+                    !total_liquidity.is_zero() &&
+                        weighted_sums.remove(&pool_id).unwrap_or_else(Zero::zero) ==
+                            total_liquidity,
+                    "WeightedLiquidity does not sum to WeightedTotalLiquidity"
+                );
+                let pool_account = get_weighted_pool_account::<T>(pool_id);
+                for pool_asset in pool.assets {
+                    let balance = T::Fungibles::balance(pool_asset.asset, &pool_account);
+                    ensure!(
+                        !pool_asset.reserve.is_zero() &&
+                            balance >= T::Fungibles::minimum_balance(pool_asset.asset),
+                        "Weighted pool account balance is below the minimum balance"
+                    );
+                    ensure!(
+                        pool_asset.reserve <= balance,
+                        "Weighted pool reserves exceed the balances of the pool account"
+                    );
+                }
+                weighted_pool_count += 1;
+            }
+            ensure!(
+                weighted_sums.is_empty() &&
+                    WeightedTotalLiquidity::<T>::iter_keys()
+                        .all(|pool_id| WeightedPools::<T>::contains_key(pool_id)),
+                "Liquidity held for weighted pool which does not exist"
+            );
+            ensure!(
+                WeightSchedules::<T>::iter_keys()
+                    .all(|pool_id| WeightedPools::<T>::contains_key(pool_id)),
+                "Weight schedule for weighted pool which does not exist"
+            );
+
+            let pool_count = TotalLiquidity::<T>::iter_values().filter(|l| !l.is_zero()).count() +
+                weighted_pool_count;
+            ensure!(
+                pool_count == PoolCount::<T>::get() as usize,
+                "PoolCount does not match the number of liquidity pools"
+            );
+
+            ensure!(
+                PositionCount::<T>::iter().all(|(who, count)| {
+                    position_counts.remove(&who).map_or(false, |actual| actual == count)
+                }) && position_counts.is_empty(),
+                "PositionCount does not match the number of positions"
+            );
+            ensure!(
+                PositionDeposits::<T>::iter_keys()
+                    .all(|(who, asset_pair)| Liquidity::<T>::contains_key(who, asset_pair)) &&
+                    WeightedPositionDeposits::<T>::iter_keys().all(|(who, pool_id)| {
+                        WeightedLiquidity::<T>::contains_key(who, pool_id)
+                    }),
+                "Position deposit held for a position which does not exist"
+            );
+
+            Ok(())
+        }
+
+        /// Record `who` as the creator of the liquidity pool for the asset pair, and reserve
+        /// `PoolDeposit` from them, first releasing any deposit still held for a previous
+        /// incarnation of the pool.
+        fn record_pool_creation(
+            who: &T::AccountId,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            asset_pair: AssetIdPairOf<T>,
+        ) -> DispatchResult {
+            Self::record_lp_asset_id(asset_pair)?;
+            Pools::<T>::try_mutate(asset_pair, |info| -> DispatchResult {
+                Self::release_pool_deposit(info, asset_a, asset_b);
+                let amount = T::PoolDeposit::get();
+                if !amount.is_zero() {
+                    T::Currency::reserve(who, amount)?;
+                    info.deposit = Some((who.clone(), amount));
+                }
+                info.creator = Some(who.clone());
+                info.created_at = Some(frame_system::Pallet::<T>::block_number());
+                Ok(())
+            })
+        }
+
+        /// Record the asset pair for the asset ID of its liquidity tokens in `LpAssetLookup`,
+        /// failing with `LpAssetIdTaken` if the ID is already used by another asset pair or by an
+        /// existing asset. Existing assets are recognised by their non-zero minimum balance.
+        fn record_lp_asset_id(asset_pair: AssetIdPairOf<T>) -> DispatchResult {
+            let id = get_lp_asset_id::<T>(asset_pair);
+            ensure!(
+                LpAssetLookup::<T>::get(id).map_or(true, |pair| pair == asset_pair) &&
+                    T::Fungibles::minimum_balance(id).is_zero(),
+                Error::<T>::LpAssetIdTaken
+            );
+            LpAssetLookup::<T>::insert(id, asset_pair);
+            Ok(())
+        }
+
+        /// Release the pool creation deposit recorded in `info`, if any.
+        fn release_pool_deposit(
+            info: &mut PoolInfoOf<T>,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) {
+            if let Some((who, amount)) = info.deposit.take() {
+                T::Currency::unreserve(&who, amount);
+                Self::deposit_event(Event::PoolDepositReleased { who, asset_a, asset_b, amount });
+            }
+        }
+
+        /// Advance the cumulative prices for the asset pair to the current block, using the
+        /// amounts currently in the liquidity pool, and record an observation and the opening
+        /// reserves if this is the first change to the amounts in the block. This must be called
+        /// before the amounts change.
+        fn update_price_cumulative(asset_pair: AssetIdPairOf<T>) {
+            let now = frame_system::Pallet::<T>::block_number();
+            if BlockOpeningReserves::<T>::get(asset_pair).map_or(true, |(_, _, at)| at != now) {
+                let (reserve_0, reserve_1) = Reserves::<T>::get(asset_pair);
+                BlockOpeningReserves::<T>::insert(asset_pair, (reserve_0, reserve_1, now));
+            }
+            let prices = Self::advance_price_cumulative(asset_pair);
+            Observations::<T>::mutate(asset_pair, |observations| {
+                if observations
+                    .last()
+                    .map_or(true, |last| last.last_updated != prices.last_updated)
+                {
+                    if observations.len() >= T::ObservationCardinality::get() as usize &&
+                        !observations.is_empty()
+                    {
+                        observations.remove(0);
+                    }
+                    // Can only fail if ObservationCardinality is zero
+                    let _ = observations.try_push(prices);
+                }
+            });
+        }
+
+        /// Record the amounts currently in the liquidity pool for the asset pair in `PriceHistory`,
+        /// unless they have already been recorded in the current block. This must be called
+        /// before the amounts change. Does nothing if `MaxHistoryLen` is zero.
+        fn record_price_history(asset_pair: AssetIdPairOf<T>) {
+            if T::MaxHistoryLen::get() == 0 {
+                return
+            }
+            PriceHistory::<T>::mutate(asset_pair, |history| {
+                PriceHistoryHead::<T>::mutate(asset_pair, |head| {
+                    record_history::<T, _>(
+                        history,
+                        head,
+                        |(at, _, _)| *at,
+                        |now| {
+                            let (reserve_0, reserve_1) = Reserves::<T>::get(asset_pair);
+                            (now, reserve_0, reserve_1)
+                        },
+                    )
+                })
+            });
+        }
+
+        /// Record the cumulative volume for the asset pair in `VolumeHistory`, unless it has
+        /// already been recorded in the current block. This must be called before the volume
+        /// changes. Does nothing if `MaxHistoryLen` is zero.
+        fn record_volume_history(asset_pair: AssetIdPairOf<T>) {
+            if T::MaxHistoryLen::get() == 0 {
+                return
+            }
+            VolumeHistory::<T>::mutate(asset_pair, |history| {
+                VolumeHistoryHead::<T>::mutate(asset_pair, |head| {
+                    record_history::<T, _>(
+                        history,
+                        head,
+                        |point| point.block,
+                        |block| {
+                            let (volume_a, volume_b) = CumulativeVolume::<T>::get(asset_pair);
+                            VolumePoint { block, volume_a, volume_b }
+                        },
+                    )
+                })
+            });
+        }
+
+        /// Returns the spot price of the first asset of the asset pair as of the start of the
+        /// current block. The current spot price is recorded as such if this is the first call for
+        /// the asset pair in the block, so this must be called before the amounts in the pool
+        /// change.
+        fn note_block_start_price(asset_pair: AssetIdPairOf<T>) -> FixedU128 {
+            let now = frame_system::Pallet::<T>::block_number();
+            BlockStartPrices::<T>::mutate(asset_pair, |start| match *start {
+                Some((at, price)) if at == now => price,
+                _ => {
+                    let (price, _) = get_spot_prices::<T>(asset_pair);
+                    *start = Some((now, price));
+                    price
+                },
+            })
+        }
+
+        /// Advance the cumulative prices for the asset pair to the current block, using the
+        /// amounts currently in the liquidity pool, returning the new cumulative prices.
+        fn advance_price_cumulative(asset_pair: AssetIdPairOf<T>) -> CumulativePricesOf<T> {
+            let now = frame_system::Pallet::<T>::block_number();
+            PriceCumulative::<T>::mutate(asset_pair, |cumulative| {
+                *cumulative = advance_cumulative_prices::<T>(asset_pair, *cumulative, now);
+                *cumulative
+            })
+        }
+
+        /// Returns the cumulative prices for the asset pair as of block `at`, which must not be
+        /// before the oldest observation or after the current block.
+        fn get_cumulative_prices_at(
+            asset_pair: AssetIdPairOf<T>,
+            at: T::BlockNumber,
+        ) -> Result<CumulativePricesOf<T>, DispatchError> {
+            // The cumulative prices as of the current block. The amounts in the pool cannot have
+            // changed since the cumulative prices were last advanced, so this is exact.
+            let now = frame_system::Pallet::<T>::block_number();
+            let latest = advance_cumulative_prices::<T>(
+                asset_pair,
+                PriceCumulative::<T>::get(asset_pair),
+                now,
+            );
+
+            // The price is constant between consecutive observations, so the cumulative prices
+            // can be linearly interpolated between them
+            let mut points = Observations::<T>::get(asset_pair).into_iter().chain([latest]);
+            let mut prev = points.next().unwrap_or(latest);
+            ensure!(at >= prev.last_updated, Error::<T>::InsufficientHistory);
+            for next in points {
+                if at <= next.last_updated {
+                    return Ok(interpolate_cumulative_prices::<T>(prev, next, at)?)
+                }
+                prev = next;
+            }
+            ensure!(at == prev.last_updated, Error::<T>::InsufficientHistory);
+            Ok(prev)
+        }
+
+        /// Advance the cumulative prices of up to `MaxOracleUpdatesPerBlock` liquidity pools,
+        /// continuing from `OracleCursor`. Returns the number of pools updated.
+        fn advance_oracle() -> u32 {
+            let max_updates = T::MaxOracleUpdatesPerBlock::get();
+            if max_updates == 0 {
+                return 0
+            }
+            let mut iter = match OracleCursor::<T>::get() {
+                Some(asset_pair) => TotalLiquidity::<T>::iter_keys_from(
+                    TotalLiquidity::<T>::hashed_key_for(asset_pair),
+                ),
+                None => TotalLiquidity::<T>::iter_keys(),
+            };
+            let asset_pairs: Vec<_> = iter.by_ref().take(max_updates.saturated_into()).collect();
+            for asset_pair in &asset_pairs {
+                Self::advance_price_cumulative(*asset_pair);
+            }
+            // Start again from the first pool once we reach the end
+            match asset_pairs.last() {
+                Some(asset_pair) if iter.next().is_some() => OracleCursor::<T>::put(asset_pair),
+                _ => OracleCursor::<T>::kill(),
+            }
+            asset_pairs.len() as u32
+        }
+
+        /// Returns the asset pairs the next price report must cover: up to `MaxPriceReports`
+        /// pools following `PriceReportCursor`, starting again from the first pool once the end
+        /// is reached.
+        fn next_price_report_pairs() -> Vec<AssetIdPairOf<T>> {
+            let max_reports = T::MaxPriceReports::get().saturated_into();
+            let asset_pairs: Vec<_> = match PriceReportCursor::<T>::get() {
+                Some(asset_pair) => TotalLiquidity::<T>::iter_keys_from(
+                    TotalLiquidity::<T>::hashed_key_for(asset_pair),
+                )
+                .take(max_reports)
+                .collect(),
+                None => Vec::new(),
+            };
+            if asset_pairs.is_empty() {
+                return TotalLiquidity::<T>::iter_keys().take(max_reports).collect()
+            }
+            asset_pairs
+        }
+
+        /// Submit an unsigned `report_prices` transaction with the spot prices in the liquidity
+        /// pools following `PriceReportCursor`, as of block `now`.
+        fn submit_price_report(now: T::BlockNumber) -> Result<(), ()> {
+            let asset_pairs = Self::next_price_report_pairs();
+            if asset_pairs.is_empty() {
+                return Ok(())
+            }
+
+            let prices: Vec<_> = asset_pairs
+                .into_iter()
+                .map(|asset_pair| {
+                    let (price_a, price_b) = get_spot_prices::<T>(asset_pair);
+                    (asset_pair, price_a, price_b)
+                })
+                .collect();
+            let call = Call::report_prices {
+                block_number: now,
+                prices: prices.try_into().map_err(|_| ())?,
+            };
+            SubmitTransaction::<T, Call<T>>::submit_unsigned_transaction(call.into())
+        }
+
+        /// Check a price report is for a block after the last accepted report, and that the
+        /// reported prices match the current spot prices in the liquidity pools.
+        fn validate_price_report(
+            block_number: T::BlockNumber,
+            prices: &PriceReportsOf<T>,
+        ) -> Result<(), InvalidTransaction> {
+            ensure!(T::OffchainPriceReporting::get(), InvalidTransaction::Call);
+            ensure!(block_number > LastPriceReport::<T>::get(), InvalidTransaction::Stale);
+            ensure!(
+                block_number <= frame_system::Pallet::<T>::block_number(),
+                InvalidTransaction::Future
+            );
+            ensure!(!prices.is_empty(), InvalidTransaction::Call);
+            // Anyone can submit a report, so it must cover exactly the pools the off-chain worker
+            // would; otherwise the cursor could be steered to keep some pools from being reported
+            let asset_pairs = Self::next_price_report_pairs();
+            ensure!(
+                prices.len() == asset_pairs.len() &&
+                    prices.iter().zip(asset_pairs).all(|((reported_pair, _, _), asset_pair)| {
+                        *reported_pair == asset_pair
+                    }),
+                InvalidTransaction::Stale
+            );
+            for (asset_pair, price_a, price_b) in prices.iter() {
+                ensure!(
+                    get_spot_prices::<T>(*asset_pair) == (*price_a, *price_b),
+                    InvalidTransaction::Stale
+                );
+            }
+            Ok(())
+        }
+
+        /// Returns the total of `who`'s unexpired locks on their liquidity for the asset pair.
+        fn get_locked_liquidity(
+            who: &T::AccountId,
+            asset_pair: AssetIdPairOf<T>,
+        ) -> Result<LiquidityBalanceOf<T>, ArithmeticError> {
+            let now = frame_system::Pallet::<T>::block_number();
+            LiquidityLocks::<T>::get(who, asset_pair)
+                .iter()
+                .filter(|lock| lock.until > now)
+                .try_fold(Zero::zero(), |sum, lock| add(sum, lock.amount))
+        }
+
+        /// Fail with `LiquidityLocked` if `who` added liquidity for the asset pair less than
+        /// `MinLockPeriod` blocks ago.
+        fn ensure_liquidity_unlocked(
+            who: &T::AccountId,
+            asset_pair: AssetIdPairOf<T>,
+        ) -> DispatchResult {
+            if let Some(added_at) = LiquidityAddedAt::<T>::get(who, asset_pair) {
+                ensure!(
+                    frame_system::Pallet::<T>::block_number() >=
+                        added_at.saturating_add(T::MinLockPeriod::get()),
+                    Error::<T>::LiquidityLocked
+                );
+            }
+            Ok(())
+        }
+
+        /// Fail with `AssetBlacklisted` if either asset of the asset pair is blacklisted.
+        fn ensure_not_blacklisted(asset_pair: AssetIdPairOf<T>) -> DispatchResult {
+            ensure!(
+                !BlacklistedAssets::<T>::contains_key(asset_pair.0) &&
+                    !BlacklistedAssets::<T>::contains_key(asset_pair.1),
+                Error::<T>::AssetBlacklisted
+            );
+            Ok(())
+        }
+
+        /// Create a weighted liquidity pool with assets from `who`, returning its ID. See
+        /// `create_weighted_pool`.
+        fn do_create_weighted_pool(
+            who: &T::AccountId,
+            assets: Vec<(AssetIdOf<T>, Perbill, AssetBalanceOf<T>)>,
+        ) -> Result<WeightedPoolId, DispatchError> {
+            ensure!(!Paused::<T>::get(), Error::<T>::PalletPaused);
+
+            let mut pool_assets: BoundedVec<WeightedAssetOf<T>, T::MaxWeightedPoolAssets> = assets
+                .into_iter()
+                .map(|(asset, weight, reserve)| WeightedAsset { asset, weight, reserve })
+                .collect::<Vec<_>>()
+                .try_into()
+                .map_err(|_| Error::<T>::InvalidWeightedPool)?;
+            ensure!(pool_assets.len() >= 2, Error::<T>::InvalidWeightedPool);
+            for (i, pool_asset) in pool_assets.iter().enumerate() {
+                let asset = pool_asset.asset;
+                ensure!(
+                    pool_assets[..i].iter().all(|other| other.asset != asset),
+                    Error::<T>::InvalidWeightedPool
+                );
+                ensure!(!BlacklistedAssets::<T>::contains_key(asset), Error::<T>::AssetBlacklisted);
+                ensure!(
+                    !WhitelistOnly::<T>::get() || WhitelistedAssets::<T>::contains_key(asset),
+                    Error::<T>::AssetNotWhitelisted
+                );
+                ensure!(
+                    pool_asset.reserve >= Self::get_min_pool_amount(asset)?,
+                    Error::<T>::InsufficientPoolAmount
+                );
+            }
+            Self::ensure_valid_weights(
+                &pool_assets.iter().map(|pool_asset| pool_asset.weight).collect::<Vec<_>>(),
+            )?;
+
+            let pool_count = PoolCount::<T>::get();
+            ensure!(pool_count < T::MaxPools::get(), Error::<T>::TooManyPools);
+            PoolCount::<T>::put(pool_count.saturating_add(1));
+            let pool_id = NextWeightedPoolId::<T>::get();
+            NextWeightedPoolId::<T>::put(add(pool_id, 1)?);
+            let deposit = T::PoolDeposit::get();
+            T::Currency::reserve(who, deposit)?;
+            let pool_account = get_weighted_pool_account::<T>(pool_id);
+            // As for asset pairs, keep the pool account alive for as long as the pool exists
+            frame_system::Pallet::<T>::inc_providers(&pool_account);
+
+            for pool_asset in pool_assets.iter_mut() {
+                pool_asset.reserve = T::Fungibles::transfer(
+                    pool_asset.asset,
+                    who,
+                    &pool_account,
+                    pool_asset.reserve,
+                    false,
+                )?;
+            }
+            let amounts: Vec<_> = pool_assets.iter().map(|pool_asset| pool_asset.reserve).collect();
+
+            let max_amount = amounts.iter().copied().max().unwrap_or_else(Zero::zero);
+            let liquidity = max_amount.saturating_mul(T::InitialLiquidityPerAssetUnit::get());
+            ensure!(
+                liquidity >= T::MinimumLiquidity::get(),
+                Error::<T>::InsufficientLiquidityMinted
+            );
+            WeightedTotalLiquidity::<T>::insert(pool_id, liquidity);
+            Self::open_weighted_position(who, pool_id)?;
+            WeightedLiquidity::<T>::insert(who, pool_id, liquidity);
+            WeightedPools::<T>::insert(
+                pool_id,
+                WeightedPool { assets: pool_assets, deposit: (who.clone(), deposit) },
+            );
+
+            Self::deposit_event(Event::WeightedPoolCreated {
+                pool_id,
+                who: who.clone(),
+                pool_account,
+            });
+            Self::deposit_event(Event::WeightedLiquidityAdded {
+                who: who.clone(),
+                pool_id,
+                amounts,
+                liquidity,
+            });
+
+            Ok(pool_id)
+        }
+
+        /// Fail with `InvalidWeightedPool` unless the weights are all between `MIN_WEIGHT` and
+        /// `MAX_WEIGHT` and sum to 100%.
+        fn ensure_valid_weights(weights: &[Perbill]) -> DispatchResult {
+            let mut total_weight = 0u64;
+            for weight in weights {
+                ensure!(
+                    *weight >= crate::weighted_math::MIN_WEIGHT &&
+                        *weight <= crate::weighted_math::MAX_WEIGHT,
+                    Error::<T>::InvalidWeightedPool
+                );
+                total_weight = total_weight.saturating_add(weight.deconstruct().into());
+            }
+            ensure!(total_weight == Perbill::ACCURACY.into(), Error::<T>::InvalidWeightedPool);
+            Ok(())
+        }
+
+        /// Set the weights of the assets of the weighted liquidity pool to their current values
+        /// according to the pool's weight schedule, if it has one (see `create_lbp`). Returns
+        /// `true` if the schedule has ended, in which case the weights are now the end weights.
+        fn apply_weight_schedule(
+            pool_id: WeightedPoolId,
+            pool: &mut WeightedPoolOf<T>,
+        ) -> Result<bool, ArithmeticError> {
+            let schedule = match WeightSchedules::<T>::get(pool_id) {
+                Some(schedule) => schedule,
+                None => return Ok(false),
+            };
+            let now = frame_system::Pallet::<T>::block_number();
+            let span = schedule.end_block.saturating_sub(schedule.start_block);
+            let elapsed = min(now.saturating_sub(schedule.start_block), span);
+            for ((pool_asset, start_weight), end_weight) in
+                pool.assets.iter_mut().zip(schedule.start_weights).zip(schedule.end_weights)
+            {
+                pool_asset.weight = interpolate_weight(
+                    start_weight,
+                    end_weight,
+                    elapsed.saturated_into(),
+                    span.saturated_into(),
+                )?;
+            }
+            Ok(now >= schedule.end_block)
+        }
+
+        /// Fail with `InsufficientPoolAmount` if `liquidity` liquidity tokens out of
+        /// `total_liquidity` for a weighted liquidity pool with the given assets are not worth at
+        /// least the minimum pool amount of each asset.
+        fn ensure_sufficient_weighted_position(
+            pool_assets: &[WeightedAssetOf<T>],
+            liquidity: LiquidityBalanceOf<T>,
+            total_liquidity: LiquidityBalanceOf<T>,
+        ) -> DispatchResult {
+            for pool_asset in pool_assets {
+                ensure!(
+                    mul_div_floor(pool_asset.reserve, liquidity, total_liquidity)? >=
+                        Self::get_min_pool_amount(pool_asset.asset)?,
+                    Error::<T>::InsufficientPoolAmount
+                );
+            }
+            Ok(())
+        }
+
+        /// Fail with `PoolNotRegistered` if `RestrictPoolCreation` is set, the liquidity pool for
+        /// the asset pair is empty and unregistered, and `origin` is not `PoolCreationOrigin`.
+        fn ensure_pool_creation_allowed(
             origin: OriginFor<T>,
             asset_a: AssetIdOf<T>,
-            min_amount_a: AssetBalanceOf<T>,
-            max_amount_a: AssetBalanceOf<T>,
             asset_b: AssetIdOf<T>,
-            min_amount_b: AssetBalanceOf<T>,
-            max_amount_b: AssetBalanceOf<T>,
         ) -> DispatchResult {
-            let sender = ensure_signed(origin)?;
+            if T::RestrictPoolCreation::get() {
+                let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+                if TotalLiquidity::<T>::get(asset_pair).is_zero() &&
+                    !RegisteredPools::<T>::contains_key(asset_pair)
+                {
+                    Self::ensure_weighted_pool_creation_allowed(origin)?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Fail with `PoolNotRegistered` if `RestrictPoolCreation` is set and `origin` is not
+        /// `PoolCreationOrigin`. Weighted liquidity pools cannot be registered, so this applies to
+        /// the creation of every weighted pool.
+        fn ensure_weighted_pool_creation_allowed(origin: OriginFor<T>) -> DispatchResult {
+            if T::RestrictPoolCreation::get() {
+                T::PoolCreationOrigin::try_origin(origin)
+                    .map_err(|_| Error::<T>::PoolNotRegistered)?;
+            }
+            Ok(())
+        }
+
+        /// Transfer `amount` of `asset` from `owner` to `dest` using `delegate`'s approval,
+        /// returning the amount actually received by `dest`. As with `Transfer::transfer` without
+        /// `keep_alive`, this may exceed `amount` if `owner`'s remaining balance is swept along.
+        fn transfer_approved(
+            asset: AssetIdOf<T>,
+            owner: &T::AccountId,
+            delegate: &T::AccountId,
+            dest: &T::AccountId,
+            amount: AssetBalanceOf<T>,
+        ) -> Result<AssetBalanceOf<T>, DispatchError> {
+            let balance = T::Fungibles::balance(asset, dest);
+            <T::Fungibles as approvals::Mutate<_>>::transfer_from(
+                asset, owner, delegate, dest, amount,
+            )?;
+            Ok(sub(T::Fungibles::balance(asset, dest), balance)?)
+        }
+
+        /// Fail with `BootstrapRestricted` if the liquidity pool for the asset pair is in its
+        /// bootstrap period and `who` is not one of its providers. `exchange` should be set when
+        /// checking an exchange rather than an addition of liquidity. A bootstrap period that has
+        /// ended is removed.
+        fn ensure_bootstrap_allows(
+            who: &T::AccountId,
+            asset_pair: AssetIdPairOf<T>,
+            exchange: bool,
+        ) -> DispatchResult {
+            if let Some(bootstrap) = Bootstraps::<T>::get(asset_pair) {
+                if frame_system::Pallet::<T>::block_number() >= bootstrap.until {
+                    Bootstraps::<T>::remove(asset_pair);
+                    let (asset_a, asset_b) = asset_pair;
+                    Self::deposit_event(Event::BootstrapEnded { asset_a, asset_b });
+                    return Ok(())
+                }
+                ensure!(
+                    (exchange && !bootstrap.restrict_exchanges) ||
+                        bootstrap.providers.contains(who),
+                    Error::<T>::BootstrapRestricted
+                );
+            }
+            Ok(())
+        }
+
+        /// Fail with `CooldownActive` if `who` added or removed liquidity for the asset pair less
+        /// than `LiquidityCooldown` blocks ago.
+        fn ensure_cooldown_elapsed(
+            who: &T::AccountId,
+            asset_pair: AssetIdPairOf<T>,
+        ) -> DispatchResult {
+            if let Some(changed_at) = LastLiquidityChange::<T>::get(who, asset_pair) {
+                ensure!(
+                    frame_system::Pallet::<T>::block_number() >=
+                        changed_at.saturating_add(T::LiquidityCooldown::get()),
+                    Error::<T>::CooldownActive
+                );
+            }
+            Ok(())
+        }
+
+        /// Fail with `AddTooSmall` if `amount_a` and `amount_b`, added to a liquidity pool with
+        /// the given reserves (of `asset_a` and the other asset), are below the minimum set by
+        /// `set_min_add_amount`.
+        fn ensure_min_add_amount(
+            asset_a: AssetIdOf<T>,
+            asset_pair: AssetIdPairOf<T>,
+            (pool_amount_a, pool_amount_b): (AssetBalanceOf<T>, AssetBalanceOf<T>),
+            amount_a: AssetBalanceOf<T>,
+            amount_b: AssetBalanceOf<T>,
+        ) -> DispatchResult {
+            if let Some(min_add_amount) = MinAddAmounts::<T>::get(asset_pair) {
+                let (min_amount_a, min_amount_b) = match min_add_amount {
+                    MinAddAmount::Fraction(fraction) =>
+                        (fraction * pool_amount_a, fraction * pool_amount_b),
+                    MinAddAmount::Absolute(min_amount_0, min_amount_1) =>
+                        order_for_pair::<T, _>(asset_a, asset_pair, min_amount_0, min_amount_1),
+                };
+                ensure!(
+                    amount_a >= min_amount_a && amount_b >= min_amount_b,
+                    Error::<T>::AddTooSmall
+                );
+            }
+            Ok(())
+        }
+
+        /// Count a new position (non-zero liquidity for an asset pair) held by `who` and reserve
+        /// `PositionDeposit` from them, failing if they already hold `MaxPositionsPerAccount`
+        /// positions or the deposit cannot be paid. The deposit is paid by `payer`: if this is not
+        /// `who`, it is transferred to `who` first, and released to `who` with the position.
+        fn open_position(
+            who: &T::AccountId,
+            asset_pair: AssetIdPairOf<T>,
+            payer: &T::AccountId,
+        ) -> DispatchResult {
+            if let Some(deposit) = Self::count_position(who, payer)? {
+                PositionDeposits::<T>::insert(who, asset_pair, deposit);
+            }
+            Ok(())
+        }
+
+        /// Stop counting a position held by `who`, which has been removed, and release its
+        /// deposit.
+        fn close_position(who: &T::AccountId, asset_pair: AssetIdPairOf<T>) {
+            Self::uncount_position(who, PositionDeposits::<T>::take(who, asset_pair));
+        }
+
+        /// As `open_position`, but for a position in a weighted liquidity pool, paid for by `who`.
+        fn open_weighted_position(who: &T::AccountId, pool_id: WeightedPoolId) -> DispatchResult {
+            if let Some(deposit) = Self::count_position(who, who)? {
+                WeightedPositionDeposits::<T>::insert(who, pool_id, deposit);
+            }
+            Ok(())
+        }
+
+        /// As `close_position`, but for a position in a weighted liquidity pool.
+        fn close_weighted_position(who: &T::AccountId, pool_id: WeightedPoolId) {
+            Self::uncount_position(who, WeightedPositionDeposits::<T>::take(who, pool_id));
+        }
+
+        /// Count a new position held by `who` and reserve `PositionDeposit` from them, paid by
+        /// `payer`, as described for `open_position`. Returns the deposit, if it is non-zero.
+        fn count_position(
+            who: &T::AccountId,
+            payer: &T::AccountId,
+        ) -> Result<Option<BalanceOf<T>>, DispatchError> {
+            PositionCount::<T>::try_mutate(who, |count| -> DispatchResult {
+                ensure!(*count < T::MaxPositionsPerAccount::get(), Error::<T>::TooManyPositions);
+                *count = count.saturating_add(1);
+                Ok(())
+            })?;
+            let deposit = T::PositionDeposit::get();
+            if deposit.is_zero() {
+                return Ok(None)
+            }
+            if payer != who {
+                T::Currency::transfer(payer, who, deposit, ExistenceRequirement::KeepAlive)?;
+            }
+            T::Currency::reserve(who, deposit)?;
+            Ok(Some(deposit))
+        }
+
+        /// Stop counting a position held by `who`, and release `deposit`, if any.
+        fn uncount_position(who: &T::AccountId, deposit: Option<BalanceOf<T>>) {
+            PositionCount::<T>::mutate_exists(who, |count| {
+                *count = count.and_then(|count| count.checked_sub(1)).filter(|count| *count != 0);
+            });
+            if let Some(deposit) = deposit {
+                T::Currency::unreserve(who, deposit);
+            }
+        }
+
+        /// Accrue the liquidity mining rewards for the asset pair up to the current block,
+        /// returning the updated reward pot, if there is one. This must be called before the total
+        /// liquidity for the asset pair changes.
+        fn update_reward_pot(
+            asset_pair: AssetIdPairOf<T>,
+        ) -> Result<Option<RewardPotOf<T>>, DispatchError> {
+            RewardPots::<T>::try_mutate(asset_pair, |maybe_pot| {
+                let pot = match maybe_pot {
+                    Some(pot) => pot,
+                    None => return Ok(None),
+                };
+                let now = frame_system::Pallet::<T>::block_number();
+                let from = max(pot.last_updated, pot.start);
+                let to = min(now, pot.end);
+                let total_liquidity = TotalLiquidity::<T>::get(asset_pair);
+                if from < to {
+                    // The rewards due by `to`, less those due by `from`, so that the rewards for
+                    // the whole period add up to exactly the pot
+                    let reward = Self::rewards_due(pot, to)?
+                        .checked_sub(Self::rewards_due(pot, from)?)
+                        .ok_or(ArithmeticError::Underflow)?;
+                    let undistributed = if total_liquidity.is_zero() {
+                        reward
+                    } else {
+                        let total_liquidity = total_liquidity.saturated_into::<u128>();
+                        // Round down so that no more than the pot is ever distributed
+                        let reward_per_share =
+                            FixedU128::checked_from_rational(reward, total_liquidity)
+                                .ok_or(ArithmeticError::Overflow)?;
+                        pot.reward_per_share = pot
+                            .reward_per_share
+                            .checked_add(&reward_per_share)
+                            .ok_or(ArithmeticError::Overflow)?;
+                        reward.saturating_sub(reward_per_share.saturating_mul_int(total_liquidity))
+                    };
+                    if undistributed != 0 {
+                        UndistributedRewards::<T>::try_mutate(asset_pair, |total| {
+                            *total = add(*total, undistributed.saturated_into())?;
+                            Ok::<_, DispatchError>(())
+                        })?;
+                    }
+                }
+                pot.last_updated = max(pot.last_updated, now);
+                Ok(Some(*pot))
+            })
+        }
+
+        /// Returns the rewards from the reward pot due over the blocks from the start of its period
+        /// to `at`, rounded down.
+        fn rewards_due(pot: &RewardPotOf<T>, at: T::BlockNumber) -> Result<u128, ArithmeticError> {
+            mul_div_floor(
+                pot.amount.saturated_into::<u128>(),
+                (at - pot.start).saturated_into(),
+                (pot.end - pot.start).saturated_into(),
+            )
+        }
+
+        /// Accrue the liquidity mining rewards for the asset pair, and add those owed to `who` to
+        /// their pending rewards, returning the updated reward pot, if there is one. This must be
+        /// called before the liquidity held by `who` or the total liquidity for the asset pair
+        /// changes, and followed by `update_reward_debt` once it has.
+        fn settle_rewards(
+            who: &T::AccountId,
+            asset_pair: AssetIdPairOf<T>,
+        ) -> Result<Option<RewardPotOf<T>>, DispatchError> {
+            let pot = match Self::update_reward_pot(asset_pair)? {
+                Some(pot) => pot,
+                None => return Ok(None),
+            };
+            let liquidity = Liquidity::<T>::get(who, asset_pair).amount;
+            let accrued: AssetBalanceOf<T> = pot
+                .reward_per_share
+                .saturating_mul_int(liquidity.saturated_into::<u128>())
+                .saturated_into();
+            if !accrued.is_zero() || RewardPositions::<T>::contains_key(who, asset_pair) {
+                RewardPositions::<T>::try_mutate(who, asset_pair, |position| -> DispatchResult {
+                    position.pending =
+                        add(position.pending, accrued.saturating_sub(position.debt))?;
+                    // So that settling again before the liquidity changes adds nothing
+                    position.debt = accrued;
+                    Ok(())
+                })?;
+            }
+            Ok(Some(pot))
+        }
+
+        /// Pay out the rewards owed from a reward pot that has been removed from `RewardPots`, and
+        /// transfer those not yet distributed, including `UndistributedRewards`, to `recipient`.
+        /// The pot must have been updated to the current block, and all liquidity in the pool
+        /// settled with `settle_rewards`. `accounts` must include every account with a reward
+        /// position for the asset pair; accounts may be repeated.
+        fn close_reward_pot(
+            asset_pair: AssetIdPairOf<T>,
+            pot: RewardPotOf<T>,
+            accounts: Vec<T::AccountId>,
+            recipient: &T::AccountId,
+        ) -> DispatchResult {
+            let (asset_a, asset_b) = asset_pair;
+            let rewards_account = get_rewards_account::<T>();
+            for who in accounts {
+                let position = RewardPositions::<T>::take(&who, asset_pair);
+                if !position.pending.is_zero() {
+                    let amount = T::Fungibles::transfer(
+                        pot.reward_asset,
+                        &rewards_account,
+                        &who,
+                        position.pending,
+                        false,
+                    )?;
+                    Self::deposit_event(Event::RewardsClaimed {
+                        who,
+                        asset_a,
+                        asset_b,
+                        reward_asset: pot.reward_asset,
+                        amount,
+                    });
+                }
+            }
+
+            // The rewards for the rest of the period, and those that were not distributed
+            let from = min(max(pot.last_updated, pot.start), pot.end);
+            let remaining =
+                pot.amount.saturating_sub(Self::rewards_due(&pot, from)?.saturated_into());
+            let amount = add(remaining, UndistributedRewards::<T>::take(asset_pair))?;
+            if !amount.is_zero() {
+                let amount = T::Fungibles::transfer(
+                    pot.reward_asset,
+                    &rewards_account,
+                    recipient,
+                    amount,
+                    false,
+                )?;
+                Self::deposit_event(Event::RewardsReturned {
+                    asset_a,
+                    asset_b,
+                    reward_asset: pot.reward_asset,
+                    amount,
+                    recipient: recipient.clone(),
+                });
+            }
+            Ok(())
+        }
+
+        /// Reset the reward debt of `who` for the asset pair to match the liquidity they now hold,
+        /// after a change settled with `settle_rewards`.
+        fn update_reward_debt(
+            who: &T::AccountId,
+            asset_pair: AssetIdPairOf<T>,
+            pot: &RewardPotOf<T>,
+        ) {
+            let liquidity = Liquidity::<T>::get(who, asset_pair).amount;
+            RewardPositions::<T>::mutate_exists(who, asset_pair, |maybe_position| {
+                let mut position = maybe_position.unwrap_or_default();
+                position.debt = pot
+                    .reward_per_share
+                    .saturating_mul_int(liquidity.saturated_into::<u128>())
+                    .saturated_into();
+                *maybe_position = Some(position)
+                    .filter(|position| !liquidity.is_zero() || !position.pending.is_zero());
+            });
+        }
+
+        /// Record that `who` changed their liquidity for the asset pair in the current block, for
+        /// `ensure_cooldown_elapsed`.
+        fn note_liquidity_change(who: &T::AccountId, asset_pair: AssetIdPairOf<T>) {
+            if !T::LiquidityCooldown::get().is_zero() {
+                LastLiquidityChange::<T>::insert(
+                    who,
+                    asset_pair,
+                    frame_system::Pallet::<T>::block_number(),
+                );
+            }
+        }
+
+        pub fn get_min_pool_amount(
+            asset: AssetIdOf<T>,
+        ) -> Result<AssetBalanceOf<T>, ArithmeticError> {
+            let multiple = T::PoolMinAmountMultiple::get();
+            T::Fungibles::minimum_balance(asset)
+                .checked_mul(&multiple)
+                .ok_or(ArithmeticError::Overflow)
+        }
+
+        /// Returns the amount of `asset_b` that will be added to the liquidity pool for the asset
+        /// pair, and the number of liquidity tokens that will be provided, if `add_liquidity` is
+        /// called with `max_amount_a` of `amount_a` (and sufficient `max_amount_b`) at the current
+        /// exchange rate. Note that slightly less than `amount_a` may actually be added.
+        ///
+        /// Fails with `NoLiquidity` if the liquidity pool is empty, in which case the first
+        /// liquidity provider is free to choose the exchange rate.
+        pub fn quote_add_liquidity(
+            asset_a: AssetIdOf<T>,
+            amount_a: AssetBalanceOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> Result<(AssetBalanceOf<T>, LiquidityBalanceOf<T>), DispatchError> {
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            let total_liquidity = TotalLiquidity::<T>::get(asset_pair);
+            ensure!(!total_liquidity.is_zero(), Error::<T>::NoLiquidity);
+            let (pool_amount_a, pool_amount_b) = get_reserves::<T>(asset_a, asset_pair);
 
+            // As in add_liquidity
+            let liquidity = mul_div_floor(amount_a, total_liquidity, pool_amount_a)?;
+            let (amount_a, amount_b) = get_amounts_for_liquidity::<T>(
+                liquidity,
+                pool_amount_a,
+                pool_amount_b,
+                total_liquidity,
+            )?;
+            let liquidity = get_liquidity_for_amounts::<T>(
+                amount_a,
+                amount_b,
+                pool_amount_a,
+                pool_amount_b,
+                total_liquidity,
+            )?;
+            Ok((amount_b, liquidity))
+        }
+
+        /// Returns the smallest `max_amount_a` and `max_amount_b` with which a sender with no
+        /// existing liquidity for the asset pair can add liquidity at the current exchange rate
+        /// without failing with `InsufficientPoolAmount`.
+        ///
+        /// The amounts are rounded up, so may very slightly exceed the strict minimum.
+        pub fn min_add_amounts(
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> Result<(AssetBalanceOf<T>, AssetBalanceOf<T>), DispatchError> {
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            let min_amount_a = Self::get_min_pool_amount(asset_a)?;
+            let min_amount_b = Self::get_min_pool_amount(asset_b)?;
+
+            let total_liquidity = TotalLiquidity::<T>::get(asset_pair);
+            if total_liquidity.is_zero() {
+                // The first liquidity provider owns the entire pool
+                return Ok((min_amount_a, min_amount_b))
+            }
+
+            let (pool_amount_a, pool_amount_b) = get_reserves::<T>(asset_a, asset_pair);
+
+            // The amounts added are rounded up, so each liquidity token is worth at least as much
+            // after adding as before. This much liquidity is thus sufficient for both assets.
+            let liquidity = max(
+                mul_div_ceil(min_amount_a, total_liquidity, pool_amount_a)?,
+                mul_div_ceil(min_amount_b, total_liquidity, pool_amount_b)?,
+            );
+            Ok((
+                mul_div_ceil(liquidity, pool_amount_a, total_liquidity)?,
+                mul_div_ceil(liquidity, pool_amount_b, total_liquidity)?,
+            ))
+        }
+
+        /// Returns the largest amount of liquidity `who` can remove for the asset pair without
+        /// closing their position, ie while leaving enough of each asset in the liquidity pool to
+        /// avoid failing with `InsufficientPoolAmount`. Zero is returned if no amount can be
+        /// removed. Note that removing the entire position is always allowed, and that any
+        /// `MinLockPeriod` is not considered.
+        pub fn max_removable_liquidity(
+            who: &T::AccountId,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> LiquidityBalanceOf<T> {
+            Self::try_max_removable_liquidity(who, asset_a, asset_b)
+                .unwrap_or_else(|_| Zero::zero())
+        }
+
+        fn try_max_removable_liquidity(
+            who: &T::AccountId,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> Result<LiquidityBalanceOf<T>, DispatchError> {
             let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            let position_liquidity = Liquidity::<T>::get(who, asset_pair).amount;
             let total_liquidity = TotalLiquidity::<T>::get(asset_pair);
             let pool_account = get_pool_account::<T>(asset_pair);
 
-            let pool_amount_a = T::Fungibles::balance(asset_a, &pool_account);
-            let pool_amount_b = T::Fungibles::balance(asset_b, &pool_account);
-
-            let (added_liquidity, amount_a, amount_b) = if total_liquidity.is_zero() {
-                // The sender is the first liquidity provider. The value we choose for
-                // added_liquidity here is somewhat arbitrary.
-                (
-                    max(max_amount_a, max_amount_b)
-                        .saturating_mul(T::InitialLiquidityPerAssetUnit::get()),
-                    max_amount_a,
-                    max_amount_b,
-                )
-            } else {
-                // There is already some liquidity in the pool. An equivalent value of each asset
-                // must be added, using the current exchange rate. Figure out which of max_amount_a
-                // and max_amount_b is the least valuable, and determine the added liquidity from
-                // that.
-                let added_liquidity =
-                    if mul(max_amount_a, pool_amount_b)? < mul(max_amount_b, pool_amount_a)? {
-                        // pool_amount_a=0 would imply the pool is empty despite the total
-                        // liquidity being non-zero
-                        mul_div_floor(max_amount_a, total_liquidity, pool_amount_a)?
-                    } else {
-                        mul_div_floor(max_amount_b, total_liquidity, pool_amount_b)?
-                    };
+            let (pool_amount_a, pool_amount_b) = get_reserves::<T>(asset_a, asset_pair);
+            let reducible_amount_a = T::Fungibles::reducible_balance(asset_a, &pool_account, true);
+            let reducible_amount_b = T::Fungibles::reducible_balance(asset_b, &pool_account, true);
+            let min_amount_a = Self::get_min_pool_amount(asset_a)?;
+            let min_amount_b = Self::get_min_pool_amount(asset_b)?;
+
+            // Mirrors the checks in do_remove_liquidity, for a removal which does not close the
+            // position
+            let can_remove = |liquidity| -> Result<bool, ArithmeticError> {
+                Ok(remaining_position_amount::<T>(
+                    pool_amount_a,
+                    reducible_amount_a,
+                    total_liquidity,
+                    position_liquidity,
+                    liquidity,
+                )? >= min_amount_a &&
+                    remaining_position_amount::<T>(
+                        pool_amount_b,
+                        reducible_amount_b,
+                        total_liquidity,
+                        position_liquidity,
+                        liquidity,
+                    )? >= min_amount_b)
+            };
+
+            // Binary search, maintaining that removing low is possible and removing high is not
+            let mut low: LiquidityBalanceOf<T> = Zero::zero();
+            let mut high = position_liquidity;
+            if high.is_zero() || !can_remove(low)? {
+                return Ok(Zero::zero())
+            }
+            let one: LiquidityBalanceOf<T> = 1u32.into();
+            while add(low, one)? < high {
+                let mid = low + (high - low) / 2u32.into();
+                if can_remove(mid)? {
+                    low = mid;
+                } else {
+                    high = mid;
+                }
+            }
+            Ok(low)
+        }
+
+        /// Returns the share of the liquidity pool for the asset pair owned by `who`. Zero is
+        /// returned if `who` has no position or the pool is empty.
+        pub fn get_pool_share(
+            who: &T::AccountId,
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> Permill {
+            let asset_pair = match make_asset_pair::<T>(asset_a, asset_b) {
+                Ok(asset_pair) => asset_pair,
+                Err(_) => return Permill::zero(),
+            };
+            let total_liquidity = TotalLiquidity::<T>::get(asset_pair);
+            if total_liquidity.is_zero() {
+                return Permill::zero()
+            }
+            Permill::from_rational(Liquidity::<T>::get(who, asset_pair).amount, total_liquidity)
+        }
+
+        /// Returns the amount of each asset in the liquidity pool for the asset pair.
+        ///
+        /// The ratio of these is the current exchange rate (this is specifically a property of the
+        /// constant product CFMM). This can be moved arbitrarily within a block; see
+        /// `opening_exchange_rate`.
+        ///
+        /// Fails with `AssetsIdentical` if `asset_a` and `asset_b` are the same, `PoolNotFound` if
+        /// a liquidity pool has never been created for the asset pair (or has since been cleaned
+        /// up with `cleanup_pool`), or `NoLiquidity` if the pool has been drained. In each case it
+        /// is impossible to exchange `asset_a` for `asset_b` or vice-versa.
+        pub fn get_exchange_rate(
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> Result<(AssetBalanceOf<T>, AssetBalanceOf<T>), DispatchError> {
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            if TotalLiquidity::<T>::get(asset_pair).is_zero() {
+                return Err(if Pools::<T>::get(asset_pair).created_at.is_some() {
+                    Error::<T>::NoLiquidity
+                } else {
+                    Error::<T>::PoolNotFound
+                }
+                .into())
+            }
+            Ok(get_reserves::<T>(asset_a, asset_pair))
+        }
 
-                // Determine the actual amounts to add to the pool. We round down above and up here
-                // to favour the existing liquidity providers over the sender of this transaction.
-                let amount_a = mul_div_ceil(added_liquidity, pool_amount_a, total_liquidity)?;
-                let amount_b = mul_div_ceil(added_liquidity, pool_amount_b, total_liquidity)?;
+        /// As for `get_exchange_rate`, but `(0, 0)` is returned if there is no liquidity pool, for
+        /// whatever reason.
+        #[deprecated(
+            note = "Use `get_exchange_rate`, which reports why there is no exchange rate. This \
+                will be removed in the next release."
+        )]
+        pub fn get_exchange_rate_or_zero(
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> (AssetBalanceOf<T>, AssetBalanceOf<T>) {
+            Self::get_exchange_rate(asset_a, asset_b).unwrap_or_else(|_| Default::default())
+        }
 
-                (added_liquidity, amount_a, amount_b)
+        /// Returns the amount of each asset in the liquidity pool for the asset pair as of the
+        /// start of the current block, ie before any exchanges or changes in liquidity in the
+        /// block. The current amounts are returned if the pool has not been touched in the block.
+        ///
+        /// Consumers needing a price (eg oracles) should prefer this over `get_exchange_rate`, as
+        /// it cannot be moved by a large exchange earlier in the same block. It is still only the
+        /// spot price at one instant though; see `consult` for a time-weighted average.
+        pub fn opening_exchange_rate(
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> (AssetBalanceOf<T>, AssetBalanceOf<T>) {
+            let asset_pair = match make_asset_pair::<T>(asset_a, asset_b) {
+                Ok(asset_pair) => asset_pair,
+                // Invalid asset pair, no liquidity pool
+                Err(_) => return (0u32.into(), 0u32.into()),
             };
+            let now = frame_system::Pallet::<T>::block_number();
+            match BlockOpeningReserves::<T>::get(asset_pair) {
+                Some((reserve_0, reserve_1, at)) if at == now =>
+                    order_for_pair::<T, _>(asset_a, asset_pair, reserve_0, reserve_1),
+                _ => get_reserves::<T>(asset_a, asset_pair),
+            }
+        }
 
-            // Abort the transaction if the sender would not add enough of each asset (these checks
-            // exist to protect the sender against adding liquidity to a pool when the exchange
-            // rate has moved too far from what they expect).
-            ensure!(amount_a >= min_amount_a, Error::<T>::UnexpectedExchangeRate);
-            ensure!(amount_b >= min_amount_b, Error::<T>::UnexpectedExchangeRate);
-
-            // Transfer the assets to the pool. Note we might end up adding a bit more than we
-            // thought if the source account would otherwise end up with a balance between 0 and
-            // the minimum. This is harmless, but we do take care to report it properly in the
-            // LiquidityAdded event...
-            let amount_a =
-                T::Fungibles::transfer(asset_a, &sender, &pool_account, amount_a, false)?;
-            let pool_amount_a = add(pool_amount_a, amount_a)?;
-            let amount_b =
-                T::Fungibles::transfer(asset_b, &sender, &pool_account, amount_b, false)?;
-            let pool_amount_b = add(pool_amount_b, amount_b)?;
-
-            // Credit the sender with the added liquidity
-            let total_liquidity = add(total_liquidity, added_liquidity)?;
-            TotalLiquidity::<T>::set(asset_pair, total_liquidity);
-            let sender_liquidity = Liquidity::<T>::get(&sender, asset_pair);
-            let sender_liquidity = add(sender_liquidity, added_liquidity)?;
-            Liquidity::<T>::set(&sender, asset_pair, sender_liquidity);
+        /// Returns the spot price of `base` in units of `quote` in a weighted liquidity pool,
+        /// excluding the fee: `(reserve_quote / weight_quote) / (reserve_base / weight_base)`. The
+        /// weights of a liquidity bootstrapping pool are interpolated to the current block. `None`
+        /// is returned if there is no such pool or either asset is not in it.
+        pub fn get_weighted_spot_price(
+            pool_id: WeightedPoolId,
+            base: AssetIdOf<T>,
+            quote: AssetIdOf<T>,
+        ) -> Option<FixedU128> {
+            let mut pool = WeightedPools::<T>::get(pool_id)?;
+            Self::apply_weight_schedule(pool_id, &mut pool).ok()?;
+            let find = |asset: AssetIdOf<T>| {
+                pool.assets.iter().find(|pool_asset| pool_asset.asset == asset)
+            };
+            let (base, quote) = (find(base)?, find(quote)?);
+            let numerator = quote
+                .reserve
+                .saturated_into::<u128>()
+                .saturating_mul(base.weight.deconstruct().into());
+            let denominator = base
+                .reserve
+                .saturated_into::<u128>()
+                .saturating_mul(quote.weight.deconstruct().into());
+            FixedU128::checked_from_rational(numerator, denominator)
+        }
 
-            // Check the sender added a sufficient amount of each asset
-            ensure!(
-                mul_div_floor(pool_amount_a, sender_liquidity, total_liquidity)? >=
-                    Self::get_min_pool_amount(asset_a)?,
-                Error::<T>::InsufficientPoolAmount
-            );
-            ensure!(
-                mul_div_floor(pool_amount_b, sender_liquidity, total_liquidity)? >=
-                    Self::get_min_pool_amount(asset_b)?,
-                Error::<T>::InsufficientPoolAmount
-            );
+        /// Returns the settings for the liquidity pool for an asset pair, including who created it
+        /// and when. `None` is returned for an invalid asset pair.
+        pub fn pool_info(asset_a: AssetIdOf<T>, asset_b: AssetIdOf<T>) -> Option<PoolInfoOf<T>> {
+            make_asset_pair::<T>(asset_a, asset_b).ok().map(Pools::<T>::get)
+        }
 
-            Self::deposit_event(Event::LiquidityAdded {
-                who: sender,
-                asset_a,
-                amount_a,
-                asset_b,
-                amount_b,
-                liquidity: added_liquidity,
-            });
+        /// Returns the cumulative prices for an asset pair as of each of the given numbers of
+        /// blocks ago, for computing time-weighted average prices over arbitrary windows: the
+        /// average price between two blocks is the difference in the cumulative prices divided by
+        /// the number of blocks. `price_a` is the cumulative price of `asset_a` in units of
+        /// `asset_b`, and `price_b` the reverse. See `CumulativePrices`.
+        ///
+        /// Fails with `InsufficientHistory` if any of the blocks is before the oldest observation
+        /// for the asset pair (see `ObservationCardinality`).
+        pub fn observe(
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            blocks_ago: &[T::BlockNumber],
+        ) -> Result<Vec<CumulativePricesOf<T>>, DispatchError> {
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            let now = frame_system::Pallet::<T>::block_number();
+            blocks_ago
+                .iter()
+                .map(|ago| {
+                    let at = now.checked_sub(ago).ok_or(Error::<T>::InsufficientHistory)?;
+                    let prices = Self::get_cumulative_prices_at(asset_pair, at)?;
+                    Ok(if asset_a == asset_pair.0 {
+                        prices
+                    } else {
+                        CumulativePrices {
+                            price_a: prices.price_b,
+                            price_b: prices.price_a,
+                            ..prices
+                        }
+                    })
+                })
+                .collect()
+        }
 
-            Ok(())
+        /// Returns the cumulative prices for an asset pair as of the current block, as for
+        /// `observe` with a `blocks_ago` of zero. Consumers can store this as a snapshot, and
+        /// later pass it to `twap` to get the average price since.
+        pub fn price_snapshot(
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> Result<CumulativePricesOf<T>, DispatchError> {
+            let mut prices = Self::observe(asset_a, asset_b, &[Zero::zero()])?;
+            prices.pop().ok_or_else(|| Error::<T>::InsufficientHistory.into())
         }
 
-        /// Redeem liquidity tokens for an asset pair. The share of the liquidity pool represented
-        /// by the tokens will be transferred back to the sender.
+        /// Returns the time-weighted average price of `asset_a`, in units of `asset_b`, from the
+        /// block of `start` (a snapshot from `price_snapshot` or `observe` for the same ordering
+        /// of the assets) to the current block. Unlike `consult`, this does not depend on the
+        /// retained observations, so windows of any length are supported.
         ///
-        /// Note this transaction does not perform any exchange rate checks as the sender always
-        /// benefits from any deviations from the true rate.
-        #[pallet::weight(10_000)] // TODO
-        #[transactional]
-        pub fn remove_liquidity(
-            origin: OriginFor<T>,
+        /// Fails with `InsufficientHistory` if `start` is from the current block, as the average
+        /// over an empty window is undefined.
+        pub fn twap(
             asset_a: AssetIdOf<T>,
             asset_b: AssetIdOf<T>,
-            liquidity: LiquidityBalanceOf<T>,
-        ) -> DispatchResult {
-            let sender = ensure_signed(origin)?;
+            start: CumulativePricesOf<T>,
+        ) -> Result<FixedU128, DispatchError> {
+            let end = Self::price_snapshot(asset_a, asset_b)?;
+            ensure!(start.last_updated < end.last_updated, Error::<T>::InsufficientHistory);
+            let window = (end.last_updated - start.last_updated).saturated_into::<u128>();
+            // The sums wrap on overflow, so the difference is correct even if they have wrapped
+            // since the snapshot
+            Ok(FixedU128::from_inner(
+                end.price_a.into_inner().wrapping_sub(start.price_a.into_inner()) / window,
+            ))
+        }
 
-            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
-            let total_liquidity = TotalLiquidity::<T>::get(asset_pair);
-            let pool_account = get_pool_account::<T>(asset_pair);
+        /// Returns the asset ID for the liquidity tokens of the liquidity pool for `asset_a` and
+        /// `asset_b`. This depends only on the asset pair, not on the order of the assets or on
+        /// whether the pool exists. See `pair_from_lp_asset` for the reverse.
+        pub fn lp_asset_id(asset_a: AssetIdOf<T>, asset_b: AssetIdOf<T>) -> AssetIdOf<T> {
+            let asset_pair =
+                if asset_a < asset_b { (asset_a, asset_b) } else { (asset_b, asset_a) };
+            get_lp_asset_id::<T>(asset_pair)
+        }
 
-            let pool_amount_a = T::Fungibles::balance(asset_a, &pool_account);
-            let pool_amount_b = T::Fungibles::balance(asset_b, &pool_account);
+        /// Returns the asset pair whose liquidity tokens have the asset ID `id` (see
+        /// `lp_asset_id`), if a liquidity pool has ever been created for it. See `LpAssetLookup`.
+        pub fn pair_from_lp_asset(id: AssetIdOf<T>) -> Option<AssetIdPairOf<T>> {
+            LpAssetLookup::<T>::get(id)
+        }
 
-            let amount_a = mul_div_floor(liquidity, pool_amount_a, total_liquidity)?;
-            let amount_b = mul_div_floor(liquidity, pool_amount_b, total_liquidity)?;
+        /// Returns the total amount of `asset_a` and `asset_b`, in that order, exchanged into or
+        /// out of the liquidity pool for the asset pair since it was created (see
+        /// `CumulativeVolume`). `(0, 0)` is returned for an invalid asset pair.
+        pub fn get_volume(
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+        ) -> (AssetBalanceOf<T>, AssetBalanceOf<T>) {
+            match make_asset_pair::<T>(asset_a, asset_b) {
+                Ok(asset_pair) => {
+                    let (volume_0, volume_1) = CumulativeVolume::<T>::get(asset_pair);
+                    order_for_pair::<T, _>(asset_a, asset_pair, volume_0, volume_1)
+                },
+                Err(_) => Default::default(),
+            }
+        }
 
-            // Debit the removed liquidity from the sender's account
-            let total_liquidity = sub(total_liquidity, liquidity)?;
-            if total_liquidity.is_zero() {
-                TotalLiquidity::<T>::remove(asset_pair);
-            } else {
-                TotalLiquidity::<T>::set(asset_pair, total_liquidity);
+        /// Returns a rough estimate of the annual percentage rate earned in fees by liquidity
+        /// providers in the pool for an asset pair, based on the volume over the last `over_blocks`
+        /// blocks (from `VolumeHistory`) and the current amounts in the pool and exchange fee.
+        ///
+        /// This is an estimate only. It assumes that:
+        /// - the volume over the window continues at the same rate for a year, with
+        ///   `ExpectedBlockTime` between blocks;
+        /// - the amounts exchanged in and out are of equal value at the current spot price, so that
+        ///   the fees are charged on half of the volume;
+        /// - the two assets in the pool are of equal value, again at the current spot price;
+        /// - fees are not compounded, and the effects of price movements (impermanent loss) and
+        ///   liquidity mining rewards are ignored.
+        ///
+        /// The result saturates at 100%. `None` is returned if the asset pair is invalid, the pool
+        /// is empty, `over_blocks` is zero or reaches back before the first block, or the window
+        /// starts before the oldest entry kept in `VolumeHistory` (in particular, always if
+        /// `MaxHistoryLen` is zero).
+        pub fn estimate_apr(
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            over_blocks: T::BlockNumber,
+        ) -> Option<Permill> {
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b).ok()?;
+            let (reserve_0, reserve_1) = Reserves::<T>::get(asset_pair);
+            if reserve_0.is_zero() || reserve_1.is_zero() || over_blocks.is_zero() {
+                return None
             }
-            let sender_liquidity = Liquidity::<T>::get(&sender, asset_pair);
-            let sender_liquidity = sub(sender_liquidity, liquidity)?;
-            if sender_liquidity.is_zero() {
-                Liquidity::<T>::remove(&sender, asset_pair);
-            } else {
-                Liquidity::<T>::set(&sender, asset_pair, sender_liquidity);
+            let now = frame_system::Pallet::<T>::block_number();
+            let start = now.checked_sub(&over_blocks)?;
+            if T::MaxHistoryLen::get() == 0 {
+                return None
             }
 
-            // If the total liquidity after the removal is non-zero, we want to keep the pool
-            // accounts alive...
-            let keep_alive = !total_liquidity.is_zero();
+            // The cumulative volume at the end of the block before the window: that at the start
+            // of the first block in the window with exchanges, if any. If this is the oldest entry
+            // and the history is full, entries for earlier blocks in the window may have been
+            // dropped
+            let history = VolumeHistory::<T>::get(asset_pair);
+            let head = VolumeHistoryHead::<T>::get(asset_pair);
+            let (volume_0, volume_1) = CumulativeVolume::<T>::get(asset_pair);
+            let (start_volume_0, start_volume_1) = match ring_iter(&history, head)
+                .enumerate()
+                .find(|(_, point)| point.block > start)
+            {
+                Some((0, _)) if history.len() >= T::MaxHistoryLen::get() as usize => return None,
+                Some((_, point)) => (point.volume_a, point.volume_b),
+                None => (volume_0, volume_1),
+            };
+            let volume_0 = volume_0.saturating_sub(start_volume_0);
+            let volume_1 = volume_1.saturating_sub(start_volume_1);
 
-            // Possibly reduce the transferred amounts to avoid leaving the pool with less than the
-            // minimum balance of either asset
-            let amount_a =
-                min(amount_a, T::Fungibles::reducible_balance(asset_a, &pool_account, keep_alive));
-            let amount_b =
-                min(amount_b, T::Fungibles::reducible_balance(asset_b, &pool_account, keep_alive));
+            // Each asset's volume relative to the amount of it in the pool. Half of the sum is the
+            // value exchanged relative to the value of the pool, as both the volume and the pool
+            // are split evenly between the assets
+            let turnover = FixedU128::saturating_from_rational(
+                volume_0.saturated_into::<u128>(),
+                reserve_0.saturated_into::<u128>(),
+            )
+            .saturating_add(FixedU128::saturating_from_rational(
+                volume_1.saturated_into::<u128>(),
+                reserve_1.saturated_into::<u128>(),
+            ));
+            let fee = get_exchange_fee::<T>(asset_pair);
+            let period_yield = turnover
+                .saturating_mul(FixedU128::saturating_from_rational(
+                    fee.deconstruct(),
+                    Permill::ACCURACY,
+                ))
+                .saturating_mul(FixedU128::saturating_from_rational(1, 4));
 
-            // Transfer the assets to the sender
-            let amount_a =
-                T::Fungibles::transfer(asset_a, &pool_account, &sender, amount_a, keep_alive)?;
-            let pool_amount_a = sub(pool_amount_a, amount_a)?;
-            let amount_b =
-                T::Fungibles::transfer(asset_b, &pool_account, &sender, amount_b, keep_alive)?;
-            let pool_amount_b = sub(pool_amount_b, amount_b)?;
+            const MILLISECS_PER_YEAR: u128 = 365 * 24 * 60 * 60 * 1000;
+            let window = over_blocks
+                .saturated_into::<u128>()
+                .saturating_mul(T::ExpectedBlockTime::get().into());
+            // Fails if `ExpectedBlockTime` is zero
+            let apr = period_yield
+                .saturating_mul(FixedU128::checked_from_rational(MILLISECS_PER_YEAR, window)?);
+            Some(Permill::from_rational(
+                apr.into_inner().min(FixedU128::accuracy()),
+                FixedU128::accuracy(),
+            ))
+        }
 
-            // Check the sender left a sufficient amount of each asset (note that removing all of
-            // your liquidity is always fine)
-            if !sender_liquidity.is_zero() {
-                ensure!(
-                    mul_div_floor(pool_amount_a, sender_liquidity, total_liquidity)? >=
-                        Self::get_min_pool_amount(asset_a)?,
-                    Error::<T>::InsufficientPoolAmount
-                );
-                ensure!(
-                    mul_div_floor(pool_amount_b, sender_liquidity, total_liquidity)? >=
-                        Self::get_min_pool_amount(asset_b)?,
-                    Error::<T>::InsufficientPoolAmount
-                );
+        /// Returns the recent price points for an asset pair, oldest first, as `(block, amount_a,
+        /// amount_b)`: the amounts of each asset in the liquidity pool at the start of the block,
+        /// before its first exchange. Empty if `MaxHistoryLen` is zero. See `PriceHistory`.
+        pub fn price_history(asset_a: AssetIdOf<T>, asset_b: AssetIdOf<T>) -> Vec<PricePointOf<T>> {
+            let asset_pair = match make_asset_pair::<T>(asset_a, asset_b) {
+                Ok(asset_pair) => asset_pair,
+                Err(_) => return Vec::new(),
+            };
+            let history = PriceHistory::<T>::get(asset_pair);
+            ring_iter(&history, PriceHistoryHead::<T>::get(asset_pair))
+                .map(|&(at, reserve_0, reserve_1)| {
+                    let (amount_a, amount_b) =
+                        order_for_pair::<T, _>(asset_a, asset_pair, reserve_0, reserve_1);
+                    (at, amount_a, amount_b)
+                })
+                .collect()
+        }
+
+        /// Returns the amount of `asset_b` equivalent to `amount_in` of `asset_a` at the
+        /// time-weighted average price over the last `window` blocks. No fee is charged. Unlike
+        /// `simulate_exchange`, this is resistant to manipulation of the amounts in the liquidity
+        /// pool within a block, so is suitable for eg pricing collateral.
+        ///
+        /// Fails with `InsufficientHistory` if the window extends back before the oldest
+        /// observation for the asset pair (see `ObservationCardinality`). A zero window gives the
+        /// current price.
+        pub fn consult(
+            asset_a: AssetIdOf<T>,
+            asset_b: AssetIdOf<T>,
+            amount_in: AssetBalanceOf<T>,
+            window: T::BlockNumber,
+        ) -> Result<AssetBalanceOf<T>, DispatchError> {
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            ensure!(!TotalLiquidity::<T>::get(asset_pair).is_zero(), Error::<T>::NoLiquidity);
+
+            let (price_a, price_b) = Self::get_average_prices(asset_pair, window)?;
+            let price = if asset_a == asset_pair.0 { price_a } else { price_b };
+            Ok(price.saturating_mul_int(amount_in.saturated_into::<u128>()).saturated_into())
+        }
+
+        /// Returns the time-weighted average prices for the asset pair over the last `window`
+        /// blocks, as in `CumulativePrices`. A zero window gives the spot prices.
+        fn get_average_prices(
+            asset_pair: AssetIdPairOf<T>,
+            window: T::BlockNumber,
+        ) -> Result<(FixedU128, FixedU128), DispatchError> {
+            if window.is_zero() {
+                return Ok(get_spot_prices::<T>(asset_pair))
             }
+            let now = frame_system::Pallet::<T>::block_number();
+            let start = now.checked_sub(&window).ok_or(Error::<T>::InsufficientHistory)?;
+            let start = Self::get_cumulative_prices_at(asset_pair, start)?;
+            let end = Self::get_cumulative_prices_at(asset_pair, now)?;
+            let window = window.saturated_into::<u128>();
+            let average = |start: FixedU128, end: FixedU128| {
+                FixedU128::from_inner(end.into_inner().wrapping_sub(start.into_inner()) / window)
+            };
+            Ok((average(start.price_a, end.price_a), average(start.price_b, end.price_b)))
+        }
 
-            Self::deposit_event(Event::LiquidityRemoved {
-                who: sender,
-                asset_a,
-                amount_a,
-                asset_b,
-                amount_b,
-                liquidity,
-            });
+        /// Returns the spot price of `base` in units of `quote`: the amount of `quote` in the
+        /// liquidity pool divided by the amount of `base`, ignoring the exchange fee and the
+        /// number of decimal places of each asset (see `get_normalized_price` for the latter).
+        /// Prices too large to represent saturate at the largest `FixedU128`, and prices too
+        /// small round down, to zero at worst. `None` is returned if the assets are identical or
+        /// the pool is empty.
+        pub fn spot_price(base: AssetIdOf<T>, quote: AssetIdOf<T>) -> Option<FixedU128> {
+            let (pool_base_amount, pool_quote_amount) =
+                Self::get_exchange_rate(base, quote).ok()?;
+            if pool_base_amount.is_zero() || pool_quote_amount.is_zero() {
+                return None
+            }
+            // Can only fail on overflow, as the denominator is non-zero
+            Some(
+                FixedU128::checked_from_rational(
+                    pool_quote_amount.saturated_into::<u128>(),
+                    pool_base_amount.saturated_into::<u128>(),
+                )
+                .unwrap_or(FixedU128::from_inner(u128::MAX)),
+            )
+        }
 
-            Ok(())
+        /// Returns the price of one unit of `base` in units of `quote`, taking into account the
+        /// number of decimal places of each asset, ie as a human would see it. This is the ratio of
+        /// the amounts of each asset in the liquidity pool, scaled by
+        /// `10^(base_decimals - quote_decimals)`. `None` is returned if there is no liquidity pool
+        /// or the price is out of range.
+        pub fn get_normalized_price(base: AssetIdOf<T>, quote: AssetIdOf<T>) -> Option<FixedU128> {
+            let (pool_base_amount, pool_quote_amount) =
+                Self::get_exchange_rate(base, quote).ok()?;
+            if pool_base_amount.is_zero() || pool_quote_amount.is_zero() {
+                return None
+            }
+
+            let mut n = pool_quote_amount.saturated_into::<u128>();
+            let mut d = pool_base_amount.saturated_into::<u128>();
+            let base_decimals = T::AssetDecimals::decimals(&base);
+            let quote_decimals = T::AssetDecimals::decimals(&quote);
+            if base_decimals >= quote_decimals {
+                n = n.checked_mul(10u128.checked_pow((base_decimals - quote_decimals).into())?)?;
+            } else {
+                d = d.checked_mul(10u128.checked_pow((quote_decimals - base_decimals).into())?)?;
+            }
+            FixedU128::checked_from_rational(n, d)
         }
 
-        /// Exchange a given amount of one asset for an equivalent value of another asset, using
-        /// the current exchange rate.
-        ///
-        /// To protect the sender against unfavourable movements in the exchange rate, if the
-        /// equivalent value is less than `min_dest_amount`, the transaction is aborted.
-        ///
-        /// A fixed percentage fee is charged and added to the liquidity pool for the asset pair.
-        #[pallet::weight(10_000)] // TODO
-        #[transactional]
-        pub fn exchange(
-            origin: OriginFor<T>,
+        /// Returns the amount of `source_asset` that must be exchanged to receive at least
+        /// `dest_amount` of `dest_asset` at the current exchange rate, including the fee. With
+        /// `RoundingPolicy::FavorTrader`, this may occasionally be one more than strictly
+        /// necessary.
+        pub fn get_source_amount(
             source_asset: AssetIdOf<T>,
-            source_amount: AssetBalanceOf<T>,
             dest_asset: AssetIdOf<T>,
-            min_dest_amount: AssetBalanceOf<T>,
-        ) -> DispatchResult {
-            let sender = ensure_signed(origin)?;
-
+            dest_amount: AssetBalanceOf<T>,
+        ) -> Result<AssetBalanceOf<T>, DispatchError> {
             let asset_pair = make_asset_pair::<T>(source_asset, dest_asset)?;
             let pool_account = get_pool_account::<T>(asset_pair);
 
-            let pool_source_amount = T::Fungibles::balance(source_asset, &pool_account);
-            let pool_dest_amount = T::Fungibles::balance(dest_asset, &pool_account);
+            let (pool_source_amount, pool_dest_amount) =
+                get_reserves::<T>(source_asset, asset_pair);
             ensure!(!pool_source_amount.is_zero(), Error::<T>::NoLiquidity);
             ensure!(!pool_dest_amount.is_zero(), Error::<T>::NoLiquidity);
+            ensure!(
+                dest_amount <= T::Fungibles::reducible_balance(dest_asset, &pool_account, true),
+                Error::<T>::InsufficientLiquidity
+            );
 
-            let source_fee = T::ExchangeFee::get().mul_ceil(source_amount);
-            let new_pool_source_amount = add(pool_source_amount, source_amount)?;
-            let new_pool_source_amount_less_fee = sub(new_pool_source_amount, source_fee)?;
-
-            // We want to preserve the product of pool_source_amount and pool_dest_amount when
-            // performing the exchange, then add the fee to the pool.
-            let new_pool_dest_amount = mul_div_ceil(
-                pool_source_amount,
-                pool_dest_amount,
-                new_pool_source_amount_less_fee,
-            )?;
-            let dest_amount = sub(pool_dest_amount, new_pool_dest_amount)?;
-
-            // Possibly reduce dest_amount to avoid leaving the pool with less than the minimum
-            // balance of the destination asset
-            let dest_amount =
-                min(dest_amount, T::Fungibles::reducible_balance(dest_asset, &pool_account, true));
+            // This is the inverse of the calculation in simulate_exchange, rounding up where it
+            // rounds down and vice-versa. First determine the amount that must be added to the
+            // pool to preserve the product of the pool amounts, then account for the fee.
+            let new_pool_dest_amount = sub(pool_dest_amount, dest_amount)?;
+            let accuracy: AssetBalanceOf<T> = Permill::ACCURACY.into();
+            let retained: AssetBalanceOf<T> = Permill::one()
+                .saturating_sub(get_exchange_fee::<T>(asset_pair))
+                .deconstruct()
+                .into();
+            let source_amount_less_fee =
+                mul_div_ceil(pool_source_amount, dest_amount, new_pool_dest_amount)?;
+            let source_amount = mul_div_ceil(source_amount_less_fee, accuracy, retained)?;
 
-            // Abort the transaction if the sender would not receive enough
-            ensure!(dest_amount >= min_dest_amount, Error::<T>::UnexpectedExchangeRate);
+            // Rounding in favour of the trader in simulate_exchange may yield an extra unit of the
+            // destination asset, in which case one less unit of the source asset may suffice
+            if T::RoundingPolicy::get() == RoundingPolicy::FavorTrader && !source_amount.is_zero() {
+                let fewer = source_amount - 1u32.into();
+                let fee = get_exchange_fee::<T>(asset_pair).mul_ceil(fewer);
+                if get_new_pool_dest_amount::<T>(
+                    pool_source_amount,
+                    pool_dest_amount,
+                    sub(fewer, fee)?,
+                    fewer,
+                )? <= new_pool_dest_amount
+                {
+                    return Ok(fewer)
+                }
+            }
+            Ok(source_amount)
+        }
 
-            // Transfer the assets to/from the sender. Note we might transfer more than expected to
-            // the pool if the source account would otherwise end up with a balance between 0 and
-            // the minimum. This is harmless, but we do take care to report it properly in the
-            // Exchanged event. Possibly we should handle this before calculating dest_amount but
-            // it doesn't really matter.
-            let source_amount =
-                T::Fungibles::transfer(source_asset, &sender, &pool_account, source_amount, false)?;
-            let dest_amount =
-                T::Fungibles::transfer(dest_asset, &pool_account, &sender, dest_amount, true)?;
+        /// Returns the amount of each asset along `route` when exchanging `source_amount` of the
+        /// first asset for the last, one liquidity pool at a time. The first amount is
+        /// `source_amount` and the last is the amount of the final asset received. Each hop is
+        /// calculated exactly as with `exchange`.
+        ///
+        /// Note that each hop is calculated against the current state of its liquidity pool, so
+        /// the result is inaccurate for routes that go through the same pool more than once.
+        pub fn get_amounts_out(
+            route: &[AssetIdOf<T>],
+            source_amount: AssetBalanceOf<T>,
+        ) -> Result<Vec<AssetBalanceOf<T>>, DispatchError> {
+            Self::ensure_valid_route(route)?;
+            let mut amounts = Vec::with_capacity(route.len());
+            let mut amount = source_amount;
+            amounts.push(amount);
+            for hop in route.windows(2) {
+                amount = Self::simulate_exchange(hop[0], amount, hop[1])?.dest_amount;
+                amounts.push(amount);
+            }
+            Ok(amounts)
+        }
 
-            Self::deposit_event(Event::Exchanged {
-                who: sender,
-                source_asset,
-                source_amount,
-                dest_asset,
-                dest_amount,
-            });
+        /// Returns the amount of each asset along `route` required to receive at least
+        /// `dest_amount` of the last asset, working backwards from the last liquidity pool with
+        /// `get_source_amount`. The first amount is the amount of the first asset to exchange
+        /// and the last is `dest_amount`. The same caveat as for `get_amounts_out` applies.
+        pub fn get_amounts_in(
+            route: &[AssetIdOf<T>],
+            dest_amount: AssetBalanceOf<T>,
+        ) -> Result<Vec<AssetBalanceOf<T>>, DispatchError> {
+            Self::ensure_valid_route(route)?;
+            let mut amounts = Vec::with_capacity(route.len());
+            let mut amount = dest_amount;
+            amounts.push(amount);
+            for hop in route.windows(2).rev() {
+                amount = Self::get_source_amount(hop[0], hop[1], amount)?;
+                amounts.push(amount);
+            }
+            amounts.reverse();
+            Ok(amounts)
+        }
 
+        /// Fail with `InvalidRoute` unless `route` has between 2 and `MaxRouteLength` assets.
+        fn ensure_valid_route(route: &[AssetIdOf<T>]) -> DispatchResult {
+            ensure!(
+                route.len() >= 2 && route.len() <= T::MaxRouteLength::get() as usize,
+                Error::<T>::InvalidRoute
+            );
             Ok(())
         }
-    }
 
-    impl<T: Config> Pallet<T> {
-        pub fn get_min_pool_amount(
+        /// Returns the liquidity positions held by `who`, along with the amount of each asset they
+        /// could currently redeem from the corresponding liquidity pool, in asset pair order.
+        ///
+        /// At most `max_results` positions are returned, so that the cost of calling this is
+        /// bounded.
+        pub fn positions_of(
+            who: &T::AccountId,
+            max_results: u32,
+        ) -> Vec<(AssetIdPairOf<T>, LiquidityBalanceOf<T>, AssetBalanceOf<T>, AssetBalanceOf<T>)>
+        {
+            Liquidity::<T>::iter_prefix(who)
+                .take(max_results.saturated_into())
+                .map(|(asset_pair, position)| {
+                    let total_liquidity = TotalLiquidity::<T>::get(asset_pair);
+                    let (pool_amount_0, pool_amount_1) = Reserves::<T>::get(asset_pair);
+                    // The total liquidity is non-zero if there are any positions, so this can
+                    // only fail on overflow, which shouldn't be possible as a position can't
+                    // exceed the total
+                    let redeemable = |pool_amount| {
+                        mul_div_floor(position.amount, pool_amount, total_liquidity)
+                            .unwrap_or_default()
+                    };
+                    (
+                        asset_pair,
+                        position.amount,
+                        redeemable(pool_amount_0),
+                        redeemable(pool_amount_1),
+                    )
+                })
+                .collect()
+        }
+
+        /// Returns the amount of `asset` that the liquidity `who` has locked until at least block
+        /// `locked_until` (see `lock_liquidity`) is worth, summed over the asset pairs including
+        /// `asset`. Locked liquidity cannot be removed or transferred, so it cannot be counted
+        /// twice. Liquidity in weighted pools cannot be locked, so is not counted.
+        ///
+        /// Rather than the current amount of `asset` in each pool, which can be inflated by
+        /// exchanging within a block, this uses the amount the pool would hold at the
+        /// time-weighted average price over the last `window` blocks (with the same product of
+        /// amounts). Pools with insufficient price history are not counted.
+        pub fn locked_amount_of(
+            who: &T::AccountId,
             asset: AssetIdOf<T>,
-        ) -> Result<AssetBalanceOf<T>, ArithmeticError> {
-            let multiple = T::PoolMinAmountMultiple::get();
-            T::Fungibles::minimum_balance(asset)
-                .checked_mul(&multiple)
-                .ok_or(ArithmeticError::Overflow)
+            window: T::BlockNumber,
+            locked_until: T::BlockNumber,
+        ) -> AssetBalanceOf<T> {
+            LiquidityLocks::<T>::iter_prefix(who)
+                .filter(|(asset_pair, _)| asset == asset_pair.0 || asset == asset_pair.1)
+                .filter_map(|(asset_pair, locks)| {
+                    let locked = locks
+                        .iter()
+                        .filter(|lock| lock.until >= locked_until)
+                        .try_fold(Zero::zero(), |sum, lock| add(sum, lock.amount))
+                        .ok()?;
+                    if locked.is_zero() {
+                        return None
+                    }
+                    let (price_0, price_1) = Self::get_average_prices(asset_pair, window).ok()?;
+                    let (pool_amount_0, pool_amount_1) = Reserves::<T>::get(asset_pair);
+                    let (pool_amount, other_pool_amount, other_price) = if asset == asset_pair.0 {
+                        (pool_amount_0, pool_amount_1, price_1)
+                    } else {
+                        (pool_amount_1, pool_amount_0, price_0)
+                    };
+                    // sqrt(pool_amount * other_pool_amount * other_price)
+                    let other_value = other_price
+                        .saturating_mul_int(other_pool_amount.saturated_into::<u128>())
+                        .saturated_into();
+                    let pool_amount = geometric_mean::<T>(pool_amount, other_value).ok()?;
+                    mul_div_floor(locked, pool_amount, TotalLiquidity::<T>::get(asset_pair)).ok()
+                })
+                .fold(Zero::zero(), |total: AssetBalanceOf<T>, amount| total.saturating_add(amount))
         }
 
-        /// Returns the amount of each asset in the liquidity pool for the asset pair.
+        /// Returns up to `limit` of the liquidity positions held by `who`, starting after the
+        /// position with the raw storage key `start_key` (or from the first position if `None`).
+        /// Also returns the raw storage key to pass as `start_key` to get the next page, or `None`
+        /// if there are no more positions.
+        pub fn account_liquidity_paged(
+            who: &T::AccountId,
+            start_key: Option<Vec<u8>>,
+            limit: u32,
+        ) -> (Vec<(AssetIdPairOf<T>, LiquidityBalanceOf<T>)>, Option<Vec<u8>>) {
+            let mut iter = match start_key {
+                Some(start_key) => Liquidity::<T>::iter_prefix_from(who, start_key),
+                None => Liquidity::<T>::iter_prefix(who),
+            };
+            let positions: Vec<_> = iter
+                .by_ref()
+                .take(limit.saturated_into())
+                .map(|(asset_pair, position)| (asset_pair, position.amount))
+                .collect();
+            let next_key = match positions.last() {
+                Some((asset_pair, _)) if iter.next().is_some() =>
+                    Some(Liquidity::<T>::hashed_key_for(who, asset_pair)),
+                _ => None,
+            };
+            (positions, next_key)
+        }
+
+        /// Returns the asset pairs with non-zero liquidity, up to `MaxPools` of them. Any beyond
+        /// that (only possible if `MaxPools` has been lowered) are omitted, so that this is safe to
+        /// call on-chain.
+        pub fn all_pools_bounded() -> BoundedVec<AssetIdPairOf<T>, T::MaxPools> {
+            let mut pools = BoundedVec::default();
+            for (asset_pair, total_liquidity) in TotalLiquidity::<T>::iter() {
+                if !total_liquidity.is_zero() && pools.try_push(asset_pair).is_err() {
+                    break
+                }
+            }
+            pools
+        }
+
+        /// As `all_pools_bounded`, but unbounded. This is for RPC use only.
+        #[cfg(feature = "std")]
+        pub fn all_pools() -> Vec<AssetIdPairOf<T>> {
+            TotalLiquidity::<T>::iter()
+                .filter(|(_, total_liquidity)| !total_liquidity.is_zero())
+                .map(|(asset_pair, _)| asset_pair)
+                .collect()
+        }
+
+        /// Returns the liquidity held by `who` for each asset pair, up to
+        /// `MaxPositionsPerAccount` asset pairs. Any beyond that (only possible if
+        /// `MaxPositionsPerAccount` has been lowered) are omitted, so that this is safe to call
+        /// on-chain.
+        pub fn get_account_liquidity_bounded(
+            who: &T::AccountId,
+        ) -> BoundedVec<(AssetIdPairOf<T>, LiquidityBalanceOf<T>), T::MaxPositionsPerAccount>
+        {
+            let mut positions = BoundedVec::default();
+            for (asset_pair, position) in Liquidity::<T>::iter_prefix(who) {
+                if positions.try_push((asset_pair, position.amount)).is_err() {
+                    break
+                }
+            }
+            positions
+        }
+
+        /// As `get_account_liquidity_bounded`, but unbounded. This is for RPC use only.
+        #[cfg(feature = "std")]
+        pub fn get_account_liquidity(
+            who: &T::AccountId,
+        ) -> Vec<(AssetIdPairOf<T>, LiquidityBalanceOf<T>)> {
+            Liquidity::<T>::iter_prefix(who)
+                .map(|(asset_pair, position)| (asset_pair, position.amount))
+                .collect()
+        }
+
+        /// Returns the amount of each asset that `who` could redeem from the liquidity pool for
+        /// the asset pair which is attributable to exchange fees earned since they added
+        /// liquidity.
         ///
-        /// The ratio of these is the current exchange rate (this is specifically a property of the
-        /// constant product CFMM). `(0, 0)` is returned if there is no liquidity pool (in which
-        /// case it is impossible to exchange `asset_a` for `asset_b` or vice-versa).
-        pub fn get_exchange_rate(
+        /// Exchanges preserve the product of the amounts of each asset in the pool; only fees
+        /// increase it. The fee portion of a position is thus determined by how much the square
+        /// root of the product of the amounts per liquidity token has grown since the snapshot
+        /// taken when liquidity was last added. Note that this is unaffected by changes in the
+        /// exchange rate.
+        ///
+        /// Fees are not tracked separately from the amounts used for pricing exchanges, so that
+        /// they compound for liquidity providers; earnings accrue in proportion to each
+        /// provider's share of the pool. They can be claimed without withdrawing the principal
+        /// using `claim_fees`.
+        pub fn position_earnings(
+            who: &T::AccountId,
             asset_a: AssetIdOf<T>,
             asset_b: AssetIdOf<T>,
-        ) -> (AssetBalanceOf<T>, AssetBalanceOf<T>) {
-            if let Ok(asset_pair) = make_asset_pair::<T>(asset_a, asset_b) {
-                let pool_account = get_pool_account::<T>(asset_pair);
-
-                let pool_amount_a = T::Fungibles::balance(asset_a, &pool_account);
-                let pool_amount_b = T::Fungibles::balance(asset_b, &pool_account);
+        ) -> Result<(AssetBalanceOf<T>, AssetBalanceOf<T>), DispatchError> {
+            let asset_pair = make_asset_pair::<T>(asset_a, asset_b)?;
+            let position = Liquidity::<T>::get(who, asset_pair);
+            if position.amount.is_zero() {
+                return Ok((Zero::zero(), Zero::zero()))
+            }
 
-                (pool_amount_a, pool_amount_b)
-            } else {
-                // Invalid asset pair, no liquidity pool
-                (0u32.into(), 0u32.into())
+            let (root_then, root_now) = get_position_roots::<T>(&position, asset_pair)?;
+            if root_then >= root_now {
+                return Ok((Zero::zero(), Zero::zero()))
             }
+
+            let total_liquidity = TotalLiquidity::<T>::get(asset_pair);
+            let (pool_amount_a, pool_amount_b) = get_reserves::<T>(asset_a, asset_pair);
+
+            let earnings = |pool_amount| -> Result<AssetBalanceOf<T>, ArithmeticError> {
+                let redeemable = mul_div_floor(position.amount, pool_amount, total_liquidity)?;
+                // Round the principal up so that earnings are never overstated
+                let principal = mul_div_ceil(redeemable.into(), root_then, root_now)?;
+                let earnings = <AssetBalanceOf<T> as Into<BalanceMulResult>>::into(redeemable)
+                    .saturating_sub(principal);
+                <AssetBalanceOf<T> as TryFrom<BalanceMulResult>>::try_from(earnings)
+                    .map_err(|_| ArithmeticError::Overflow)
+            };
+            Ok((earnings(pool_amount_a)?, earnings(pool_amount_b)?))
         }
     }
 }
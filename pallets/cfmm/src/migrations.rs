@@ -0,0 +1,631 @@
+//! Storage migrations for the CFMM pallet.
+
+use crate::{Config, Liquidity, Pallet};
+use frame_support::{
+    log,
+    traits::{Get, GetStorageVersion, StorageVersion},
+    weights::Weight,
+};
+use sp_std::vec::Vec;
+
+/// The log target for messages from the migrations.
+const LOG_TARGET: &str = "runtime::cfmm";
+
+/// Run all migrations from the on-chain storage version to the current storage version, in order.
+/// Migrations which have already been applied only cost a read of the storage version.
+pub fn migrate<T: Config>() -> Weight {
+    v1::migrate::<T>()
+        .saturating_add(v2::migrate::<T>())
+        .saturating_add(v3::migrate::<T>())
+        .saturating_add(v4::migrate::<T>())
+        .saturating_add(v5::migrate::<T>())
+        .saturating_add(v6::migrate::<T>())
+        .saturating_add(v7::migrate::<T>())
+        .saturating_add(v8::migrate::<T>())
+        .saturating_add(v9::migrate::<T>())
+        .saturating_add(v10::migrate::<T>())
+        .saturating_add(v11::migrate::<T>())
+}
+
+/// Checks to run before `migrate`: the on-chain storage version must not be newer than the
+/// current storage version, as there is no way to migrate backwards.
+#[cfg(any(feature = "try-runtime", test))]
+pub fn pre_upgrade<T: Config>() -> Result<(), &'static str> {
+    frame_support::ensure!(
+        Pallet::<T>::on_chain_storage_version() <= Pallet::<T>::current_storage_version(),
+        "On-chain storage version is newer than the current storage version"
+    );
+    Ok(())
+}
+
+/// Checks to run after `migrate`: the on-chain storage version must have been brought up to date,
+/// and the storage invariants checked by `do_try_state` must hold.
+#[cfg(any(feature = "try-runtime", test))]
+pub fn post_upgrade<T: Config>() -> Result<(), &'static str> {
+    frame_support::ensure!(
+        Pallet::<T>::on_chain_storage_version() == Pallet::<T>::current_storage_version(),
+        "On-chain storage version was not migrated to the current storage version"
+    );
+    Pallet::<T>::do_try_state()
+}
+
+/// Migrate from storage version 0 to 1.
+///
+/// Version 0 stored the bare liquidity balance of each position in `Liquidity`. Version 1 stores
+/// a `LiquidityPosition`, which additionally contains a snapshot of the amount of each asset in
+/// the pool per liquidity token. The fees earned by existing positions before the migration are
+/// unknown, so the snapshot is taken at the time of the migration.
+pub mod v1 {
+    use super::*;
+    use crate::{
+        pallet::{get_amount_per_share, get_legacy_pool_account},
+        LiquidityPosition, TotalLiquidity,
+    };
+    use frame_support::traits::fungibles::Inspect;
+
+    pub fn migrate<T: Config>() -> Weight {
+        let on_chain_version = Pallet::<T>::on_chain_storage_version();
+        if on_chain_version >= 1 {
+            return T::DbWeight::get().reads(1)
+        }
+
+        let mut translated = 0u64;
+        Liquidity::<T>::translate::<T::AssetBalance, _>(|_who, asset_pair, amount| {
+            translated += 1;
+
+            // Reserves are not tracked in storage until version 3, so use the pool balances
+            let total_liquidity = TotalLiquidity::<T>::get(asset_pair);
+            let pool_account = get_legacy_pool_account::<T>(asset_pair);
+            let per_share = |asset| {
+                let amount = T::Fungibles::balance(asset, &pool_account);
+                get_amount_per_share::<T>(amount, total_liquidity).unwrap_or_default()
+            };
+            let reserve_a_per_share = per_share(asset_pair.0);
+            let reserve_b_per_share = per_share(asset_pair.1);
+            Some(LiquidityPosition { amount, reserve_a_per_share, reserve_b_per_share })
+        });
+
+        StorageVersion::new(1).put::<Pallet<T>>();
+
+        // Per position: read the position, the total liquidity, and the two pool balances, then
+        // write the position. Plus reading and writing the storage version.
+        T::DbWeight::get().reads_writes(translated * 4 + 1, translated + 1)
+    }
+}
+
+/// Migrate from storage version 1 to 2.
+///
+/// Asset pairs used to be ordered by their SCALE encoding; they are now ordered by `Ord`. For
+/// pairs where the two orderings disagree, this migration re-keys the pallet's storage and moves
+/// the pool funds from the old pool account to the new one. Transfers which fail are logged, and
+/// the funds left in the old pool account; the number of failures is logged at the end.
+pub mod v2 {
+    use super::*;
+    use crate::{
+        pallet::get_legacy_pool_account, LiquidityAddedAt, LiquidityPosition, TotalLiquidity,
+    };
+    use frame_support::traits::fungibles::{Inspect, Transfer};
+
+    pub fn migrate<T: Config>() -> Weight {
+        let on_chain_version = Pallet::<T>::on_chain_storage_version();
+        if on_chain_version >= 2 {
+            return T::DbWeight::get().reads(1)
+        }
+
+        let mut reads = 1u64;
+        let mut writes = 1u64;
+
+        // Collect everything that needs re-keying up front, as it isn't safe to modify a map
+        // while iterating over it
+        let mut old_pairs = Vec::new();
+        for asset_pair in TotalLiquidity::<T>::iter_keys() {
+            reads = reads.saturating_add(1);
+            if asset_pair.0 > asset_pair.1 {
+                old_pairs.push(asset_pair);
+            }
+        }
+        let mut old_positions = Vec::new();
+        for (who, asset_pair, position) in Liquidity::<T>::iter() {
+            reads = reads.saturating_add(1);
+            if asset_pair.0 > asset_pair.1 {
+                old_positions.push((who, asset_pair, position));
+            }
+        }
+        let mut old_added_ats = Vec::new();
+        for (who, asset_pair, added_at) in LiquidityAddedAt::<T>::iter() {
+            reads = reads.saturating_add(1);
+            if asset_pair.0 > asset_pair.1 {
+                old_added_ats.push((who, asset_pair, added_at));
+            }
+        }
+
+        let mut moved = 0u32;
+        let mut failed = 0u32;
+        for old_pair in old_pairs {
+            let new_pair = (old_pair.1, old_pair.0);
+            TotalLiquidity::<T>::insert(new_pair, TotalLiquidity::<T>::take(old_pair));
+
+            let old_pool_account = get_legacy_pool_account::<T>(old_pair);
+            let new_pool_account = get_legacy_pool_account::<T>(new_pair);
+            for asset in [old_pair.0, old_pair.1] {
+                let amount = T::Fungibles::balance(asset, &old_pool_account);
+                // If this fails, the funds are left in the old pool account, and the pool will
+                // appear empty. There is not much else we can do here but make some noise.
+                match T::Fungibles::transfer(
+                    asset,
+                    &old_pool_account,
+                    &new_pool_account,
+                    amount,
+                    false,
+                ) {
+                    Ok(_) => moved = moved.saturating_add(1),
+                    Err(error) => {
+                        failed = failed.saturating_add(1);
+                        log::warn!(
+                            target: LOG_TARGET,
+                            "v2: failed to move {:?} of asset {:?} from {:?} to {:?}: {:?}",
+                            amount,
+                            asset,
+                            old_pool_account,
+                            new_pool_account,
+                            error,
+                        );
+                    },
+                }
+            }
+
+            // Total liquidity, plus per asset: the balance, and the two balances modified by the
+            // transfer
+            reads = reads.saturating_add(1 + 2 * 3);
+            writes = writes.saturating_add(2 + 2 * 2);
+        }
+
+        for (who, old_pair, position) in old_positions {
+            Liquidity::<T>::remove(&who, old_pair);
+            Liquidity::<T>::insert(
+                &who,
+                (old_pair.1, old_pair.0),
+                LiquidityPosition {
+                    amount: position.amount,
+                    reserve_a_per_share: position.reserve_b_per_share,
+                    reserve_b_per_share: position.reserve_a_per_share,
+                },
+            );
+            writes = writes.saturating_add(2);
+        }
+
+        for (who, old_pair, added_at) in old_added_ats {
+            LiquidityAddedAt::<T>::remove(&who, old_pair);
+            LiquidityAddedAt::<T>::insert(&who, (old_pair.1, old_pair.0), added_at);
+            writes = writes.saturating_add(2);
+        }
+
+        if failed == 0 {
+            log::info!(target: LOG_TARGET, "v2: moved {} pool balances", moved);
+        } else {
+            log::error!(
+                target: LOG_TARGET,
+                "v2: moved {} pool balances, failed to move {}",
+                moved,
+                failed
+            );
+        }
+
+        StorageVersion::new(2).put::<Pallet<T>>();
+
+        T::DbWeight::get().reads_writes(reads, writes)
+    }
+}
+
+/// Migrate from storage version 2 to 3.
+///
+/// The reserves of each liquidity pool are now tracked in `Reserves`, rather than read from the
+/// balances of the pool account. This migration initialises `Reserves` from the pool balances.
+pub mod v3 {
+    use super::*;
+    use crate::{pallet::get_legacy_pool_account, Reserves, TotalLiquidity};
+    use frame_support::traits::fungibles::Inspect;
+
+    pub fn migrate<T: Config>() -> Weight {
+        let on_chain_version = Pallet::<T>::on_chain_storage_version();
+        if on_chain_version >= 3 {
+            return T::DbWeight::get().reads(1)
+        }
+
+        let mut pools = 0u64;
+        for asset_pair in TotalLiquidity::<T>::iter_keys() {
+            pools += 1;
+
+            let pool_account = get_legacy_pool_account::<T>(asset_pair);
+            Reserves::<T>::insert(
+                asset_pair,
+                (
+                    T::Fungibles::balance(asset_pair.0, &pool_account),
+                    T::Fungibles::balance(asset_pair.1, &pool_account),
+                ),
+            );
+        }
+
+        StorageVersion::new(3).put::<Pallet<T>>();
+
+        // Per pool: read the total liquidity and the two pool balances, then write the reserves.
+        // Plus reading and writing the storage version.
+        T::DbWeight::get().reads_writes(pools * 3 + 1, pools + 1)
+    }
+}
+
+/// Migrate from storage version 3 to 4.
+///
+/// The number of liquidity pools is now tracked in `PoolCount`, to enforce `MaxPools`. This
+/// migration initialises it by counting the asset pairs with liquidity. Existing pools are kept
+/// even if there are more than `MaxPools` of them; no new pools can be created until enough have
+/// been drained.
+pub mod v4 {
+    use super::*;
+    use crate::{PoolCount, TotalLiquidity};
+    use sp_runtime::traits::Zero;
+
+    pub fn migrate<T: Config>() -> Weight {
+        let on_chain_version = Pallet::<T>::on_chain_storage_version();
+        if on_chain_version >= 4 {
+            return T::DbWeight::get().reads(1)
+        }
+
+        let mut pairs = 0u64;
+        let mut pools = 0u32;
+        for total_liquidity in TotalLiquidity::<T>::iter_values() {
+            pairs += 1;
+            if !total_liquidity.is_zero() {
+                pools = pools.saturating_add(1);
+            }
+        }
+        PoolCount::<T>::put(pools);
+
+        StorageVersion::new(4).put::<Pallet<T>>();
+
+        // Reading the total liquidity of every asset pair, then writing the pool count. Plus
+        // reading and writing the storage version.
+        T::DbWeight::get().reads_writes(pairs + 1, 2)
+    }
+}
+
+/// Migrate from storage version 4 to 5.
+///
+/// The number of positions held by each account is now tracked in `PositionCount`, to enforce
+/// `MaxPositionsPerAccount`. This migration initialises it by counting the `Liquidity` entries of
+/// each account. Accounts which already hold more than `MaxPositionsPerAccount` positions keep
+/// them, but cannot open any more.
+pub mod v5 {
+    use super::*;
+    use crate::PositionCount;
+
+    pub fn migrate<T: Config>() -> Weight {
+        let on_chain_version = Pallet::<T>::on_chain_storage_version();
+        if on_chain_version >= 5 {
+            return T::DbWeight::get().reads(1)
+        }
+
+        let mut positions = 0u64;
+        for (who, _asset_pair) in Liquidity::<T>::iter_keys() {
+            positions += 1;
+            PositionCount::<T>::mutate(who, |count| *count = count.saturating_add(1));
+        }
+
+        StorageVersion::new(5).put::<Pallet<T>>();
+
+        // Per position: read the position, and read and write the count. Plus reading and writing
+        // the storage version.
+        T::DbWeight::get().reads_writes(positions * 2 + 1, positions + 1)
+    }
+}
+
+/// Migrate from storage version 5 to 6.
+///
+/// `PoolInfo` now records the creator of each liquidity pool and the block it was created in. The
+/// creation block of existing pools is unknown, so is left unset; the creator is taken to be the
+/// account the pool deposit was reserved from, if any.
+pub mod v6 {
+    use super::*;
+    use crate::Pools;
+    use codec::{Decode, Encode};
+    use frame_support::{storage::unhashed, traits::Currency};
+
+    type BalanceOf<T> =
+        <<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
+
+    #[derive(Decode, Encode)]
+    struct OldPoolInfo<AccountId, Balance> {
+        paused: bool,
+        fee: Option<sp_runtime::Permill>,
+        deposit: Option<(AccountId, Balance)>,
+    }
+
+    /// The version 6 layout of `PoolInfo`. This is written directly, rather than via
+    /// `Pools::translate`, as `PoolInfo` has since changed again (see `v7`).
+    #[derive(Decode, Encode)]
+    pub(super) struct PoolInfo<AccountId, Balance, BlockNumber> {
+        pub paused: bool,
+        pub fee: Option<sp_runtime::Permill>,
+        pub deposit: Option<(AccountId, Balance)>,
+        pub creator: Option<AccountId>,
+        pub created_at: Option<BlockNumber>,
+    }
+
+    pub fn migrate<T: Config>() -> Weight {
+        let on_chain_version = Pallet::<T>::on_chain_storage_version();
+        if on_chain_version >= 6 {
+            return T::DbWeight::get().reads(1)
+        }
+
+        // Collect the keys up front, as it isn't safe to modify a map while iterating over it
+        let asset_pairs = Pools::<T>::iter_keys().collect::<Vec<_>>();
+        let mut translated = 0u64;
+        for asset_pair in asset_pairs {
+            let key = Pools::<T>::hashed_key_for(asset_pair);
+            if let Some(old) = unhashed::get::<OldPoolInfo<T::AccountId, BalanceOf<T>>>(&key) {
+                translated += 1;
+                unhashed::put(
+                    &key,
+                    &PoolInfo::<_, _, T::BlockNumber> {
+                        paused: old.paused,
+                        fee: old.fee,
+                        creator: old.deposit.as_ref().map(|(who, _)| who.clone()),
+                        deposit: old.deposit,
+                        created_at: None,
+                    },
+                );
+            }
+        }
+
+        StorageVersion::new(6).put::<Pallet<T>>();
+
+        // Per pool: read and write the settings. Plus reading and writing the storage version.
+        T::DbWeight::get().reads_writes(translated + 1, translated + 1)
+    }
+}
+
+/// Migrate from storage version 6 to 7.
+///
+/// `PoolInfo` now holds an optional price band for each liquidity pool (see `set_price_band`).
+/// Existing pools have no band.
+pub mod v7 {
+    use super::*;
+    use crate::{PoolInfo, PoolStatus, Pools};
+    use frame_support::traits::Currency;
+
+    type BalanceOf<T> =
+        <<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
+
+    pub fn migrate<T: Config>() -> Weight {
+        let on_chain_version = Pallet::<T>::on_chain_storage_version();
+        if on_chain_version >= 7 {
+            return T::DbWeight::get().reads(1)
+        }
+
+        let mut translated = 0u64;
+        Pools::<T>::translate::<v6::PoolInfo<T::AccountId, BalanceOf<T>, T::BlockNumber>, _>(
+            |_asset_pair, old| {
+                translated += 1;
+                Some(PoolInfo {
+                    status: if old.paused {
+                        PoolStatus::TradingDisabled
+                    } else {
+                        PoolStatus::Active
+                    },
+                    fee: old.fee,
+                    deposit: old.deposit,
+                    creator: old.creator,
+                    created_at: old.created_at,
+                    min_rate: None,
+                    max_rate: None,
+                })
+            },
+        );
+
+        StorageVersion::new(7).put::<Pallet<T>>();
+
+        // Per pool: read and write the settings. Plus reading and writing the storage version.
+        T::DbWeight::get().reads_writes(translated + 1, translated + 1)
+    }
+}
+
+/// Migrate from storage version 7 to 8.
+///
+/// Pool accounts used to be sub-accounts of `PalletId`, truncated to fit in `AccountId`, so that
+/// distinct asset pairs could share an account. If the asset pair does not fit, they are now
+/// derived from a hash instead (see `get_pool_account`). This migration moves the reserves of each
+/// liquidity pool from its old account to its new one, if they differ. Only the reserves are
+/// moved, as the old account may be shared with other pools; anything else left in it must be
+/// recovered by governance. Transfers which fail are logged, as for `v2`.
+pub mod v8 {
+    use super::*;
+    use crate::{
+        pallet::{get_legacy_pool_account, get_pool_account},
+        Reserves,
+    };
+    use frame_support::traits::fungibles::{Inspect, Transfer};
+    use sp_runtime::traits::Zero;
+    use sp_std::cmp::min;
+
+    pub fn migrate<T: Config>() -> Weight {
+        let on_chain_version = Pallet::<T>::on_chain_storage_version();
+        if on_chain_version >= 8 {
+            return T::DbWeight::get().reads(1)
+        }
+
+        let mut reads = 1u64;
+        let mut writes = 1u64;
+        let mut moved = 0u32;
+        let mut failed = 0u32;
+        for (asset_pair, (reserve_0, reserve_1)) in Reserves::<T>::iter() {
+            reads = reads.saturating_add(1);
+            let legacy_account = get_legacy_pool_account::<T>(asset_pair);
+            let pool_account = get_pool_account::<T>(asset_pair);
+            if legacy_account == pool_account {
+                continue
+            }
+            for (asset, reserve) in [(asset_pair.0, reserve_0), (asset_pair.1, reserve_1)] {
+                let amount = min(reserve, T::Fungibles::balance(asset, &legacy_account));
+                if amount < reserve {
+                    failed = failed.saturating_add(1);
+                    log::warn!(
+                        target: LOG_TARGET,
+                        "v8: {:?} holds only {:?} of the {:?} of asset {:?} in its pool",
+                        legacy_account,
+                        amount,
+                        reserve,
+                        asset,
+                    );
+                }
+                if amount.is_zero() {
+                    continue
+                }
+                match T::Fungibles::transfer(asset, &legacy_account, &pool_account, amount, false) {
+                    Ok(_) => moved = moved.saturating_add(1),
+                    Err(error) => {
+                        failed = failed.saturating_add(1);
+                        log::warn!(
+                            target: LOG_TARGET,
+                            "v8: failed to move {:?} of asset {:?} from {:?} to {:?}: {:?}",
+                            amount,
+                            asset,
+                            legacy_account,
+                            pool_account,
+                            error,
+                        );
+                    },
+                }
+                // The balance, and the two balances modified by the transfer
+                reads = reads.saturating_add(3);
+                writes = writes.saturating_add(2);
+            }
+        }
+
+        if failed == 0 {
+            log::info!(target: LOG_TARGET, "v8: moved {} pool balances", moved);
+        } else {
+            log::error!(
+                target: LOG_TARGET,
+                "v8: moved {} pool balances, failed to move {}",
+                moved,
+                failed
+            );
+        }
+
+        StorageVersion::new(8).put::<Pallet<T>>();
+
+        T::DbWeight::get().reads_writes(reads, writes)
+    }
+}
+
+/// Migrate from storage version 8 to 9.
+///
+/// Pool accounts now hold a provider reference for as long as the pool has liquidity, so that they
+/// are kept alive regardless of the balances they hold. Pools created before the reference was
+/// introduced do not hold one; this migration adds it for every pool with liquidity, matching the
+/// reference released when the pool is drained.
+pub mod v9 {
+    use super::*;
+    use crate::{pallet::get_pool_account, TotalLiquidity};
+    use sp_runtime::traits::Zero;
+
+    pub fn migrate<T: Config>() -> Weight {
+        let on_chain_version = Pallet::<T>::on_chain_storage_version();
+        if on_chain_version >= 9 {
+            return T::DbWeight::get().reads(1)
+        }
+
+        let mut pools = 0u64;
+        for (asset_pair, total_liquidity) in TotalLiquidity::<T>::iter() {
+            if total_liquidity.is_zero() {
+                continue
+            }
+            frame_system::Pallet::<T>::inc_providers(&get_pool_account::<T>(asset_pair));
+            pools = pools.saturating_add(1);
+        }
+        log::info!(target: LOG_TARGET, "v9: added a provider reference to {} pool accounts", pools);
+
+        StorageVersion::new(9).put::<Pallet<T>>();
+
+        T::DbWeight::get()
+            .reads_writes(pools.saturating_mul(2).saturating_add(1), pools.saturating_add(1))
+    }
+}
+
+/// Migrate from storage version 9 to 10.
+///
+/// Positions in weighted liquidity pools now count towards `MaxPositionsPerAccount`, so this
+/// migration adds each existing `WeightedLiquidity` entry to `PositionCount`. No deposit is taken
+/// for existing positions; as for positions created while `PositionDeposit` was zero, none is
+/// released when they are closed.
+pub mod v10 {
+    use super::*;
+    use crate::{PositionCount, WeightedLiquidity};
+
+    pub fn migrate<T: Config>() -> Weight {
+        let on_chain_version = Pallet::<T>::on_chain_storage_version();
+        if on_chain_version >= 10 {
+            return T::DbWeight::get().reads(1)
+        }
+
+        let mut positions = 0u64;
+        for (who, _, _) in WeightedLiquidity::<T>::iter() {
+            PositionCount::<T>::mutate(who, |count| *count = count.saturating_add(1));
+            positions = positions.saturating_add(1);
+        }
+        log::info!(target: LOG_TARGET, "v10: counted {} weighted pool positions", positions);
+
+        StorageVersion::new(10).put::<Pallet<T>>();
+
+        T::DbWeight::get().reads_writes(
+            positions.saturating_mul(2).saturating_add(1),
+            positions.saturating_add(1),
+        )
+    }
+}
+
+/// Migrate from storage version 10 to 11.
+///
+/// Pool creation now records the asset pair for the asset ID of its liquidity tokens in
+/// `LpAssetLookup`, and fails if the ID is already taken. Pools created before the lookup was
+/// introduced have no entry; this migration adds one for every pool with liquidity or settings,
+/// unless the ID is already recorded for another asset pair, in which case the collision is
+/// logged and the existing entry kept.
+pub mod v11 {
+    use super::*;
+    use crate::{pallet::get_lp_asset_id, LpAssetLookup, Pools, TotalLiquidity};
+
+    pub fn migrate<T: Config>() -> Weight {
+        let on_chain_version = Pallet::<T>::on_chain_storage_version();
+        if on_chain_version >= 11 {
+            return T::DbWeight::get().reads(1)
+        }
+
+        let asset_pairs: Vec<_> =
+            TotalLiquidity::<T>::iter_keys().chain(Pools::<T>::iter_keys()).collect();
+        let mut reads = asset_pairs.len() as u64;
+        let mut writes = 0u64;
+        for asset_pair in asset_pairs {
+            let id = get_lp_asset_id::<T>(asset_pair);
+            reads = reads.saturating_add(1);
+            match LpAssetLookup::<T>::get(id) {
+                Some(pair) if pair == asset_pair => {},
+                Some(pair) => log::warn!(
+                    target: LOG_TARGET,
+                    "v11: liquidity token asset ID of {:?} already used by {:?}",
+                    asset_pair,
+                    pair
+                ),
+                None => {
+                    LpAssetLookup::<T>::insert(id, asset_pair);
+                    writes = writes.saturating_add(1);
+                },
+            }
+        }
+        log::info!(target: LOG_TARGET, "v11: recorded {} liquidity token asset IDs", writes);
+
+        StorageVersion::new(11).put::<Pallet<T>>();
+
+        T::DbWeight::get().reads_writes(reads.saturating_add(1), writes.saturating_add(1))
+    }
+}
@@ -1,22 +1,40 @@
 use crate as pallet_cfmm;
+use crate::{
+    native::NativeOrFungibles,
+    payment::SwapForFees,
+    traits::{MetadataDecimals, OnLiquidityChanged, OnSwap},
+    InitialLiquidityFormula, RoundingPolicy,
+};
 use frame_support::{
-    parameter_types,
-    traits::{ConstU16, ConstU32, ConstU64, StorageMapShim},
+    ord_parameter_types, parameter_types,
+    traits::{
+        ConstU16, ConstU32, ConstU64, ConstU8, EnsureOrigin, GenesisBuild, Get, StorageMapShim,
+    },
+    weights::IdentityFee,
     PalletId,
 };
 use frame_system as system;
-use frame_system::EnsureRoot;
+use frame_system::{EnsureRoot, EnsureSignedBy, RawOrigin};
+use pallet_transaction_payment::CurrencyAdapter;
 use sp_core::H256;
 use sp_runtime::{
     testing::Header,
-    traits::{BlakeTwo256, IdentityLookup},
+    traits::{BlakeTwo256, Convert, IdentityLookup},
+    transaction_validity::TransactionPriority,
     Permill,
 };
+use std::{
+    cell::RefCell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
 
 type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
 type Block = frame_system::mocking::MockBlock<Test>;
 
-type AccountId = u128; // Should be at least 128 bits with 32-bit AssetId to avoid get_pool_account collisions
+// Too narrow to hold an asset pair, so pool accounts are derived by hashing. Any narrower and
+// the pallet's integrity test would fail.
+type AccountId = u128;
 type Balance = u32;
 type AssetBalance = u32;
 type AssetId = u32;
@@ -31,6 +49,7 @@ frame_support::construct_runtime!(
         System: frame_system,
         Balances: pallet_balances,
         Assets: pallet_assets,
+        TransactionPayment: pallet_transaction_payment,
         Cfmm: pallet_cfmm,
     }
 );
@@ -96,25 +115,304 @@ impl pallet_assets::Config for Test {
     type WeightInfo = pallet_assets::weights::SubstrateWeight<Test>;
 }
 
+parameter_types!(
+    pub static FeeAsset: Option<AssetId> = None;
+    pub const MaxFeeSlippage: Permill = Permill::from_percent(20);
+);
+
+pub struct FeeAssetOf;
+impl Convert<AccountId, Option<AssetId>> for FeeAssetOf {
+    fn convert(_who: AccountId) -> Option<AssetId> {
+        FeeAsset::get()
+    }
+}
+
+impl pallet_transaction_payment::Config for Test {
+    type Event = Event;
+    type OnChargeTransaction =
+        SwapForFees<CurrencyAdapter<Balances, ()>, CfmmNativeAssetId, FeeAssetOf, MaxFeeSlippage>;
+    type OperationalFeeMultiplier = ConstU8<5>;
+    type WeightToFee = IdentityFee<Balance>;
+    type LengthToFee = IdentityFee<Balance>;
+    type FeeMultiplierUpdate = ();
+}
+
 parameter_types!(
     pub const CfmmPalletId: PalletId = PalletId(*b"cfmm____");
+    pub static CfmmPoolDeposit: Balance = 0;
+    pub static CfmmPositionDeposit: Balance = 0;
+    pub static CfmmMaxPools: u32 = 100;
+    pub static CfmmMaxWeightedPoolAssets: u32 = 4;
+    pub static CfmmRestrictPoolCreation: bool = false;
+    pub const CfmmMaxBootstrapProviders: u32 = 2;
+    pub const CfmmNativeAssetId: AssetId = 1_000;
     pub const CfmmPoolMinAmountMultiple: AssetBalance = 10;
     pub const CfmmInitialLiquidityPerAssetUnit: AssetBalance = 10;
-    pub const CfmmExchangeFee: Permill = Permill::from_percent(10);
+    pub static CfmmInitialLiquidityFormula: InitialLiquidityFormula = InitialLiquidityFormula::Max;
+    pub static CfmmRoundingPolicy: RoundingPolicy = RoundingPolicy::FavorPool;
+    pub static CfmmMinimumLiquidity: AssetBalance = 1_000;
+    pub static CfmmExchangeFee: Permill = Permill::from_percent(10);
+    pub const CfmmReferralShare: Permill = Permill::from_percent(50);
+    pub static CfmmMinLockPeriod: u64 = 0;
+    pub static CfmmLiquidityCooldown: u64 = 0;
+    pub static CfmmMaxPriceImpact: Option<Permill> = None;
+    pub static CfmmMaxTradeFraction: Option<Permill> = None;
+    pub static CfmmCircuitBreakerThreshold: Option<Permill> = None;
+    pub const CfmmMaxScheduledRemovalsPerBlock: u32 = 2;
+    pub const CfmmMaxLiquidityLocks: u32 = 2;
+    pub static CfmmMaxPositionsPerAccount: u32 = 10;
+    pub const CfmmMaxOracleUpdatesPerBlock: u32 = 2;
+    pub const CfmmObservationCardinality: u32 = 4;
+    pub static CfmmMaxHistoryLen: u32 = 3;
+    pub const CfmmExpectedBlockTime: u64 = 6_000;
+    pub static CfmmOffchainPriceReporting: bool = false;
+    pub const CfmmMaxPriceReports: u32 = 2;
+    pub const CfmmUnsignedPriority: TransactionPriority = 100;
+    pub const CfmmMaxRouteLength: u32 = 3;
+    pub const CfmmDustSink: AccountId = 99;
+    pub const CfmmRootSwapAccount: AccountId = 3;
 );
 
+ord_parameter_types! {
+    pub const CfmmPoolCreator: AccountId = 1;
+    pub const CfmmGovernance: AccountId = 4;
+}
+
 impl pallet_cfmm::Config for Test {
     type Event = Event;
     type PalletId = CfmmPalletId;
     type AssetId = AssetId;
     type AssetBalance = AssetBalance;
-    type Fungibles = Assets;
+    type Fungibles = NativeOrFungibles<CfmmNativeAssetId, Balances, Assets>;
+    type Currency = Balances;
+    type PoolDeposit = CfmmPoolDeposit;
+    type PositionDeposit = CfmmPositionDeposit;
+    type MaxPools = CfmmMaxPools;
+    type MaxWeightedPoolAssets = CfmmMaxWeightedPoolAssets;
+    type RestrictPoolCreation = CfmmRestrictPoolCreation;
+    type MaxBootstrapProviders = CfmmMaxBootstrapProviders;
     type PoolMinAmountMultiple = CfmmPoolMinAmountMultiple;
     type InitialLiquidityPerAssetUnit = CfmmInitialLiquidityPerAssetUnit;
+    type InitialLiquidityFormula = CfmmInitialLiquidityFormula;
+    type RoundingPolicy = CfmmRoundingPolicy;
+    type MinimumLiquidity = CfmmMinimumLiquidity;
     type ExchangeFee = CfmmExchangeFee;
+    type ReferralShare = CfmmReferralShare;
+    type MinLockPeriod = CfmmMinLockPeriod;
+    type LiquidityCooldown = CfmmLiquidityCooldown;
+    type MaxPriceImpact = CfmmMaxPriceImpact;
+    type MaxTradeFraction = CfmmMaxTradeFraction;
+    type CircuitBreakerThreshold = CfmmCircuitBreakerThreshold;
+    type MaxScheduledRemovalsPerBlock = CfmmMaxScheduledRemovalsPerBlock;
+    type MaxLiquidityLocks = CfmmMaxLiquidityLocks;
+    type MaxPositionsPerAccount = CfmmMaxPositionsPerAccount;
+    type MaxOracleUpdatesPerBlock = CfmmMaxOracleUpdatesPerBlock;
+    type ObservationCardinality = CfmmObservationCardinality;
+    type MaxHistoryLen = CfmmMaxHistoryLen;
+    type ExpectedBlockTime = CfmmExpectedBlockTime;
+    type OffchainPriceReporting = CfmmOffchainPriceReporting;
+    type MaxPriceReports = CfmmMaxPriceReports;
+    type UnsignedPriority = CfmmUnsignedPriority;
+    type MaxRouteLength = CfmmMaxRouteLength;
+    type SwapOrigin = EnsureSignedOrRootAs<CfmmRootSwapAccount>;
+    type PauseOrigin = EnsureRoot<AccountId>;
+    type EmergencyOrigin = EnsureRoot<AccountId>;
+    type FeeAdminOrigin = EnsureRoot<AccountId>;
+    type PriceBandOrigin = EnsureRoot<AccountId>;
+    type BlacklistOrigin = EnsureRoot<AccountId>;
+    type WhitelistOrigin = EnsureRoot<AccountId>;
+    type PoolCreationOrigin = EnsureSignedBy<CfmmPoolCreator, AccountId>;
+    type LiquidityProvisionOrigin = EnsureSignedBy<CfmmGovernance, AccountId>;
+    type RewardOrigin = EnsureRoot<AccountId>;
+    type RescueOrigin = EnsureRoot<AccountId>;
+    type AdminOrigin = EnsureRoot<AccountId>;
+    type DustSink = CfmmDustSink;
+    type AssetDecimals = MetadataDecimals<AccountId, Assets>;
+    type OnSwap = RecordHookCalls;
+    type OnLiquidityChanged = RecordHookCalls;
+}
+
+impl<C> system::offchain::SendTransactionTypes<C> for Test
+where
+    Call: From<C>,
+{
+    type OverarchingCall = Call;
+    type Extrinsic = UncheckedExtrinsic;
+}
+
+/// Behaves like `EnsureSigned`, except that the root origin is also accepted and mapped to
+/// `Account`.
+pub struct EnsureSignedOrRootAs<Account>(PhantomData<Account>);
+
+impl<Account: Get<AccountId>> EnsureOrigin<Origin> for EnsureSignedOrRootAs<Account> {
+    type Success = AccountId;
+
+    fn try_origin(o: Origin) -> Result<AccountId, Origin> {
+        o.into().and_then(|o| match o {
+            RawOrigin::Signed(who) => Ok(who),
+            RawOrigin::Root => Ok(Account::get()),
+            o => Err(Origin::from(o)),
+        })
+    }
+
+    #[cfg(feature = "runtime-benchmarks")]
+    fn successful_origin() -> Origin {
+        Origin::root()
+    }
+}
+
+/// A call to one of the pallet's hooks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HookCall {
+    Swap(AccountId, AssetId, AssetBalance, AssetId, AssetBalance),
+    LiquidityAdded(AccountId, AssetId, AssetBalance, AssetId, AssetBalance, AssetBalance),
+    LiquidityRemoved(AccountId, AssetId, AssetBalance, AssetId, AssetBalance, AssetBalance),
+}
+
+thread_local! {
+    static HOOK_CALLS: RefCell<Vec<HookCall>> = RefCell::new(Vec::new());
+}
+
+/// Returns the hook calls made so far, clearing the record.
+pub fn take_hook_calls() -> Vec<HookCall> {
+    HOOK_CALLS.with(|calls| calls.take())
+}
+
+/// Records every hook call, for checking with `take_hook_calls`.
+pub struct RecordHookCalls;
+
+impl OnSwap<AccountId, AssetId, AssetBalance> for RecordHookCalls {
+    fn on_swap(
+        who: &AccountId,
+        source_asset: AssetId,
+        source_amount: AssetBalance,
+        dest_asset: AssetId,
+        dest_amount: AssetBalance,
+    ) {
+        HOOK_CALLS.with(|calls| {
+            calls.borrow_mut().push(HookCall::Swap(
+                *who,
+                source_asset,
+                source_amount,
+                dest_asset,
+                dest_amount,
+            ))
+        });
+    }
+}
+
+impl OnLiquidityChanged<AccountId, AssetId, AssetBalance> for RecordHookCalls {
+    fn on_liquidity_added(
+        who: &AccountId,
+        asset_a: AssetId,
+        amount_a: AssetBalance,
+        asset_b: AssetId,
+        amount_b: AssetBalance,
+        liquidity: AssetBalance,
+    ) {
+        HOOK_CALLS.with(|calls| {
+            calls.borrow_mut().push(HookCall::LiquidityAdded(
+                *who, asset_a, amount_a, asset_b, amount_b, liquidity,
+            ))
+        });
+    }
+
+    fn on_liquidity_removed(
+        who: &AccountId,
+        asset_a: AssetId,
+        amount_a: AssetBalance,
+        asset_b: AssetId,
+        amount_b: AssetBalance,
+        liquidity: AssetBalance,
+    ) {
+        HOOK_CALLS.with(|calls| {
+            calls.borrow_mut().push(HookCall::LiquidityRemoved(
+                *who, asset_a, amount_a, asset_b, amount_b, liquidity,
+            ))
+        });
+    }
+}
+
+/// Test externalities which, with the `try-runtime` feature, check the pallet's storage
+/// invariants (see `do_try_state`) at the end of every `execute_with`.
+pub struct TestExt(sp_io::TestExternalities);
+
+impl TestExt {
+    pub fn execute_with<R>(&mut self, execute: impl FnOnce() -> R) -> R {
+        self.0.execute_with(|| {
+            let result = execute();
+            #[cfg(feature = "try-runtime")]
+            Cfmm::do_try_state().expect("Storage invariants do not hold");
+            result
+        })
+    }
+}
+
+impl Deref for TestExt {
+    type Target = sp_io::TestExternalities;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for TestExt {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
 }
 
 // Build genesis storage according to the mock runtime.
-pub fn new_test_ext() -> sp_io::TestExternalities {
-    system::GenesisConfig::default().build_storage::<Test>().unwrap().into()
+pub fn new_test_ext() -> TestExt {
+    let mut ext: sp_io::TestExternalities =
+        system::GenesisConfig::default().build_storage::<Test>().unwrap().into();
+    // Events are not deposited in block 0
+    ext.execute_with(|| System::set_block_number(1));
+    TestExt(ext)
+}
+
+/// Builds genesis storage with assets 0, 1, and 2 (with minimum balances of 10, 20, and 30)
+/// created, and 10_000 of each minted to accounts 1 and 2. Liquidity pools can be added with
+/// `with_pool`.
+#[derive(Default)]
+pub struct ExtBuilder {
+    pools: Vec<(AccountId, AssetId, AssetBalance, AssetId, AssetBalance)>,
+}
+
+impl ExtBuilder {
+    /// Add liquidity from `who` at genesis.
+    pub fn with_pool(
+        mut self,
+        who: AccountId,
+        asset_a: AssetId,
+        amount_a: AssetBalance,
+        asset_b: AssetId,
+        amount_b: AssetBalance,
+    ) -> Self {
+        self.pools.push((who, asset_a, amount_a, asset_b, amount_b));
+        self
+    }
+
+    pub fn build(self) -> TestExt {
+        let mut storage = system::GenesisConfig::default().build_storage::<Test>().unwrap();
+        pallet_assets::GenesisConfig::<Test> {
+            assets: vec![(0, 1, true, 10), (1, 1, true, 20), (2, 1, true, 30)],
+            metadata: vec![],
+            accounts: [1, 2]
+                .into_iter()
+                .flat_map(|who| (0..3).map(move |asset| (asset, who, 10_000)))
+                .collect(),
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+        pallet_cfmm::GenesisConfig::<Test> { pools: self.pools }
+            .assimilate_storage(&mut storage)
+            .unwrap();
+        // Don't report the hook calls made while adding genesis liquidity
+        take_hook_calls();
+
+        let mut ext: sp_io::TestExternalities = storage.into();
+        ext.execute_with(|| System::set_block_number(1));
+        TestExt(ext)
+    }
 }
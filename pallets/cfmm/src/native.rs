@@ -0,0 +1,160 @@
+//! Adapter allowing the native currency to be used as one leg of a liquidity pool.
+
+use frame_support::traits::{
+    tokens::{fungible, fungibles, DepositConsequence, WithdrawConsequence},
+    Get,
+};
+use sp_runtime::{DispatchError, DispatchResult, TokenError};
+use sp_std::marker::PhantomData;
+
+/// Implements `fungibles::Inspect` and `fungibles::Transfer` by routing operations on the asset
+/// with ID `NativeAssetId` to `Native` (typically `pallet_balances`), and operations on all other
+/// assets to `Fungibles` (typically `pallet_assets`). Approvals (`fungibles::approvals`) are
+/// routed to `Fungibles` too, but are not supported for the native currency.
+///
+/// Use this as the pallet's `Fungibles` to support liquidity pools where one of the assets is the
+/// native currency. `NativeAssetId` should be an asset ID that is never used by `Fungibles`.
+pub struct NativeOrFungibles<NativeAssetId, Native, Fungibles>(
+    PhantomData<(NativeAssetId, Native, Fungibles)>,
+);
+
+impl<AccountId, NativeAssetId, Native, Fungibles> fungibles::Inspect<AccountId>
+    for NativeOrFungibles<NativeAssetId, Native, Fungibles>
+where
+    NativeAssetId: Get<Fungibles::AssetId>,
+    Native: fungible::Inspect<AccountId, Balance = Fungibles::Balance>,
+    Fungibles: fungibles::Inspect<AccountId>,
+{
+    type AssetId = Fungibles::AssetId;
+    type Balance = Fungibles::Balance;
+
+    fn total_issuance(asset: Self::AssetId) -> Self::Balance {
+        if asset == NativeAssetId::get() {
+            Native::total_issuance()
+        } else {
+            Fungibles::total_issuance(asset)
+        }
+    }
+
+    fn minimum_balance(asset: Self::AssetId) -> Self::Balance {
+        if asset == NativeAssetId::get() {
+            Native::minimum_balance()
+        } else {
+            Fungibles::minimum_balance(asset)
+        }
+    }
+
+    fn balance(asset: Self::AssetId, who: &AccountId) -> Self::Balance {
+        if asset == NativeAssetId::get() {
+            Native::balance(who)
+        } else {
+            Fungibles::balance(asset, who)
+        }
+    }
+
+    fn reducible_balance(asset: Self::AssetId, who: &AccountId, keep_alive: bool) -> Self::Balance {
+        if asset == NativeAssetId::get() {
+            Native::reducible_balance(who, keep_alive)
+        } else {
+            Fungibles::reducible_balance(asset, who, keep_alive)
+        }
+    }
+
+    fn can_deposit(
+        asset: Self::AssetId,
+        who: &AccountId,
+        amount: Self::Balance,
+        mint: bool,
+    ) -> DepositConsequence {
+        if asset == NativeAssetId::get() {
+            Native::can_deposit(who, amount, mint)
+        } else {
+            Fungibles::can_deposit(asset, who, amount, mint)
+        }
+    }
+
+    fn can_withdraw(
+        asset: Self::AssetId,
+        who: &AccountId,
+        amount: Self::Balance,
+    ) -> WithdrawConsequence<Self::Balance> {
+        if asset == NativeAssetId::get() {
+            Native::can_withdraw(who, amount)
+        } else {
+            Fungibles::can_withdraw(asset, who, amount)
+        }
+    }
+}
+
+impl<AccountId, NativeAssetId, Native, Fungibles> fungibles::Transfer<AccountId>
+    for NativeOrFungibles<NativeAssetId, Native, Fungibles>
+where
+    NativeAssetId: Get<Fungibles::AssetId>,
+    Native: fungible::Transfer<AccountId, Balance = Fungibles::Balance>,
+    Fungibles: fungibles::Transfer<AccountId>,
+{
+    fn transfer(
+        asset: Self::AssetId,
+        source: &AccountId,
+        dest: &AccountId,
+        amount: Self::Balance,
+        keep_alive: bool,
+    ) -> Result<Self::Balance, DispatchError> {
+        if asset == NativeAssetId::get() {
+            Native::transfer(source, dest, amount, keep_alive)
+        } else {
+            Fungibles::transfer(asset, source, dest, amount, keep_alive)
+        }
+    }
+}
+
+impl<AccountId, NativeAssetId, Native, Fungibles> fungibles::approvals::Inspect<AccountId>
+    for NativeOrFungibles<NativeAssetId, Native, Fungibles>
+where
+    NativeAssetId: Get<Fungibles::AssetId>,
+    Native: fungible::Inspect<AccountId, Balance = Fungibles::Balance>,
+    Fungibles: fungibles::approvals::Inspect<AccountId>,
+{
+    fn allowance(asset: Self::AssetId, owner: &AccountId, delegate: &AccountId) -> Self::Balance {
+        if asset == NativeAssetId::get() {
+            Default::default()
+        } else {
+            Fungibles::allowance(asset, owner, delegate)
+        }
+    }
+}
+
+impl<AccountId, NativeAssetId, Native, Fungibles> fungibles::approvals::Mutate<AccountId>
+    for NativeOrFungibles<NativeAssetId, Native, Fungibles>
+where
+    NativeAssetId: Get<Fungibles::AssetId>,
+    Native: fungible::Inspect<AccountId, Balance = Fungibles::Balance>,
+    Fungibles: fungibles::approvals::Mutate<AccountId>,
+{
+    fn approve(
+        asset: Self::AssetId,
+        owner: &AccountId,
+        delegate: &AccountId,
+        amount: Self::Balance,
+    ) -> DispatchResult {
+        if asset == NativeAssetId::get() {
+            Err(TokenError::Unsupported.into())
+        } else {
+            Fungibles::approve(asset, owner, delegate, amount)
+        }
+    }
+
+    fn transfer_from(
+        asset: Self::AssetId,
+        owner: &AccountId,
+        delegate: &AccountId,
+        dest: &AccountId,
+        amount: Self::Balance,
+    ) -> DispatchResult {
+        if asset == NativeAssetId::get() {
+            Err(TokenError::Unsupported.into())
+        } else {
+            Fungibles::transfer_from(asset, owner, delegate, dest, amount)
+        }
+    }
+}
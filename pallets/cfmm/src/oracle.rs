@@ -0,0 +1,23 @@
+//! Support for using the CFMM as a price oracle for other pallets.
+
+use crate::{Config, Pallet};
+use sp_runtime::{traits::Zero, FixedPointNumber, FixedU128};
+
+/// Provides the price of one asset in units of another, for use by eg a lending pallet. `None` is
+/// returned if no price is available.
+pub trait PriceProvider<AssetId, Price> {
+    fn price(base: AssetId, quote: AssetId) -> Option<Price>;
+}
+
+/// Prices are taken from the liquidity pool for the asset pair, as of the start of the current
+/// block (see `opening_exchange_rate`), so they cannot be moved by an exchange earlier in the same
+/// block. No price is available if the pool is empty.
+impl<T: Config> PriceProvider<T::AssetId, FixedU128> for Pallet<T> {
+    fn price(base: T::AssetId, quote: T::AssetId) -> Option<FixedU128> {
+        let (reserve_base, reserve_quote) = Pallet::<T>::opening_exchange_rate(base, quote);
+        if reserve_base.is_zero() || reserve_quote.is_zero() {
+            return None
+        }
+        FixedU128::checked_from_rational(reserve_quote, reserve_base)
+    }
+}
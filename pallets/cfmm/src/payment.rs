@@ -0,0 +1,91 @@
+//! Support for paying transaction fees in any asset with a liquidity pool against the native
+//! currency.
+
+use crate::{Config, Pallet};
+use frame_support::traits::{fungibles::Inspect, Get};
+use pallet_transaction_payment::OnChargeTransaction;
+use sp_runtime::{
+    traits::{Convert, DispatchInfoOf, PostDispatchInfoOf, Saturating, Zero},
+    transaction_validity::{InvalidTransaction, TransactionValidityError},
+    Permill,
+};
+use sp_std::marker::PhantomData;
+
+/// An `OnChargeTransaction` implementation which allows accounts to pay transaction fees in an
+/// asset of their choosing.
+///
+/// If `FeeAsset` returns an asset for the paying account, enough of it is first exchanged for the
+/// native currency (the asset with ID `NativeAssetId`) to cover the fee and tip, using the
+/// liquidity pool for the two assets. The fee is then withdrawn by `Inner` (eg `CurrencyAdapter`),
+/// which also handles any refund; refunds are paid in the native currency. If the paying account
+/// has less than the minimum balance of the native currency, enough is acquired to keep it alive.
+///
+/// The exchange is rejected if the effective exchange rate is worse than the current exchange rate
+/// by more than `MaxSlippage`. Note that the effective exchange rate includes the exchange fee, so
+/// `MaxSlippage` should be somewhat larger than `ExchangeFee`. If there is no liquidity pool for
+/// the asset, or the exchange fails for any other reason, the transaction is invalid.
+///
+/// The pallet's `Fungibles` must handle the native currency; see `NativeOrFungibles`.
+pub struct SwapForFees<Inner, NativeAssetId, FeeAsset, MaxSlippage>(
+    PhantomData<(Inner, NativeAssetId, FeeAsset, MaxSlippage)>,
+);
+
+impl<T, Inner, NativeAssetId, FeeAsset, MaxSlippage> OnChargeTransaction<T>
+    for SwapForFees<Inner, NativeAssetId, FeeAsset, MaxSlippage>
+where
+    T: Config + pallet_transaction_payment::Config,
+    Inner: OnChargeTransaction<T, Balance = T::AssetBalance>,
+    NativeAssetId: Get<T::AssetId>,
+    FeeAsset: Convert<T::AccountId, Option<T::AssetId>>,
+    MaxSlippage: Get<Permill>,
+{
+    type Balance = Inner::Balance;
+    type LiquidityInfo = Inner::LiquidityInfo;
+
+    fn withdraw_fee(
+        who: &T::AccountId,
+        call: &T::Call,
+        dispatch_info: &DispatchInfoOf<T::Call>,
+        fee: Self::Balance,
+        tip: Self::Balance,
+    ) -> Result<Self::LiquidityInfo, TransactionValidityError> {
+        let native_asset = NativeAssetId::get();
+        if let Some(asset) = FeeAsset::convert(who.clone()).filter(|asset| *asset != native_asset) {
+            let mut amount = fee.saturating_add(tip);
+            let min_balance = T::Fungibles::minimum_balance(native_asset);
+            if T::Fungibles::balance(native_asset, who) < min_balance {
+                amount = amount.saturating_add(min_balance);
+            }
+            if !amount.is_zero() {
+                Pallet::<T>::do_exchange_for_exact(
+                    who,
+                    asset,
+                    native_asset,
+                    amount,
+                    MaxSlippage::get(),
+                )
+                .map_err(|_| InvalidTransaction::Payment)?;
+            }
+        }
+
+        Inner::withdraw_fee(who, call, dispatch_info, fee, tip)
+    }
+
+    fn correct_and_deposit_fee(
+        who: &T::AccountId,
+        dispatch_info: &DispatchInfoOf<T::Call>,
+        post_info: &PostDispatchInfoOf<T::Call>,
+        corrected_fee: Self::Balance,
+        tip: Self::Balance,
+        already_withdrawn: Self::LiquidityInfo,
+    ) -> Result<(), TransactionValidityError> {
+        Inner::correct_and_deposit_fee(
+            who,
+            dispatch_info,
+            post_info,
+            corrected_fee,
+            tip,
+            already_withdrawn,
+        )
+    }
+}
@@ -1,48 +1,168 @@
-use crate::{mock::*, Error};
-use frame_support::{assert_noop, assert_ok};
-use sp_runtime::{ArithmeticError, DispatchResult};
+use crate::{
+    chain_extension,
+    check_deadline::CheckSwapDeadline,
+    migrations,
+    mock::*,
+    oracle::PriceProvider,
+    voting::{LiquidityVotingPower, VotingPower},
+    Bootstrap, Bootstraps, CumulativePrices, CumulativeVolume, Error, Event as CfmmEvent,
+    ExchangeSimulation, InitialLiquidityFormula, InitialLiquidityPerAssetUnitOverrides,
+    LastLiquidityChange, Liquidity, LiquidityLocks, LiquidityPosition, LpAssetLookup, MinAddAmount,
+    MinAddAmounts, Observations, OraclePrice, OraclePrices, PoolCount, PoolInfo, PoolStatus, Pools,
+    PositionCount, PriceCumulative, PriceHistory, PriceHistoryHead, Reserves, RewardPositions,
+    RewardPots, RoundingPolicy, ScheduledRemovals, TotalLiquidity, UndistributedRewards,
+    VolumeHistory, VolumeHistoryHead, VolumePoint, WeightSchedules, WeightedPools,
+    WeightedTotalLiquidity,
+};
+use codec::{Decode, Encode};
+use frame_support::{
+    assert_noop, assert_ok,
+    storage::{unhashed, with_transaction},
+    traits::{ConstU32, ConstU64, Hooks, StorageVersion},
+    weights::{DispatchInfo, PostDispatchInfo},
+};
+use pallet_transaction_payment::OnChargeTransaction;
+use sp_core::offchain::{testing::TestTransactionPoolExt, TransactionPoolExt};
+use sp_runtime::{
+    traits::{BadOrigin, SignedExtension, ValidateUnsigned},
+    transaction_validity::{InvalidTransaction, TransactionSource},
+    DispatchError, FixedPointNumber, FixedU128, Perbill, Permill, TransactionOutcome,
+};
+use sp_std::cmp::{max, min};
 
-fn create_assets() -> DispatchResult {
-    Assets::force_create(Origin::root(), 0, 1, true, 10)?;
-    Assets::force_create(Origin::root(), 1, 1, true, 20)?;
-    Assets::force_create(Origin::root(), 2, 1, true, 30)?;
+#[test]
+fn basic_add_remove_liquidity() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_000, 2_000)));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::LiquidityAdded {
+            who: 2,
+            asset_a: 0,
+            amount_a: 500,
+            asset_b: 1,
+            amount_b: 1_000,
+            liquidity: 10_000,
+            new_reserve_a: 1_500,
+            new_reserve_b: 3_000,
+        }));
+        assert_eq!(Cfmm::get_exchange_rate(1, 0), Ok((3_000, 1_500)));
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 20_000));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::LiquidityRemoved {
+            who: 1,
+            asset_a: 0,
+            amount_a: 1_000,
+            asset_b: 1,
+            amount_b: 2_000,
+            liquidity: 20_000,
+            new_reserve_a: 500,
+            new_reserve_b: 1_000,
+        }));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((500, 1_000)));
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(2), 0, 1, 10_000));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Err(Error::<Test>::NoLiquidity.into()));
+
+        // The pool is re-created by the next liquidity provider
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 1, 0, 2_000, 0, 0, 1_000, 0, false));
+
+        // Each transition of the pool between empty and non-empty is reported exactly once
+        let pool_account = crate::pallet::get_pool_account::<Test>((0, 1));
+        let lifecycle_events: Vec<_> = System::events()
+            .into_iter()
+            .map(|record| record.event)
+            .filter(|event| {
+                matches!(
+                    event,
+                    Event::Cfmm(CfmmEvent::PoolCreated { .. } | CfmmEvent::PoolDestroyed { .. })
+                )
+            })
+            .collect();
+        assert_eq!(
+            lifecycle_events,
+            vec![
+                Event::Cfmm(CfmmEvent::PoolCreated { asset_a: 0, asset_b: 1, pool_account }),
+                Event::Cfmm(CfmmEvent::PoolDestroyed { asset_a: 0, asset_b: 1, pool_account }),
+                Event::Cfmm(CfmmEvent::PoolCreated { asset_a: 1, asset_b: 0, pool_account }),
+            ]
+        );
+    });
+}
 
-    Assets::mint(Origin::signed(1), 0, 1, 10_000)?;
-    Assets::mint(Origin::signed(1), 1, 1, 10_000)?;
-    Assets::mint(Origin::signed(1), 2, 1, 10_000)?;
+#[test]
+fn add_liquidity_from() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Assets::approve_transfer(Origin::signed(1), 0, 2, 1_000));
+        assert_ok!(Assets::approve_transfer(Origin::signed(1), 1, 2, 2_000));
 
-    Assets::mint(Origin::signed(1), 0, 2, 10_000)?;
-    Assets::mint(Origin::signed(1), 1, 2, 10_000)?;
-    Assets::mint(Origin::signed(1), 2, 2, 10_000)?;
+        // The assets come from, and the liquidity goes to, the owner
+        assert_ok!(Cfmm::add_liquidity_from(Origin::signed(2), 1, 0, 0, 1_000, 1, 0, 2_000, 0));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::LiquidityAdded {
+            who: 1,
+            asset_a: 0,
+            amount_a: 1_000,
+            asset_b: 1,
+            amount_b: 2_000,
+            liquidity: 20_000,
+            new_reserve_a: 1_000,
+            new_reserve_b: 2_000,
+        }));
+        assert_eq!(Liquidity::<Test>::get(1u128, (0u32, 1u32)).amount, 20_000);
+        assert_eq!(Liquidity::<Test>::get(2u128, (0u32, 1u32)).amount, 0);
+        assert_eq!((Assets::balance(0, 1), Assets::balance(1, 1)), (9_000, 8_000));
+        assert_eq!((Assets::balance(0, 2), Assets::balance(1, 2)), (10_000, 10_000));
 
-    Ok(())
+        // The approvals have been used up
+        assert_noop!(
+            Cfmm::add_liquidity_from(Origin::signed(2), 1, 0, 0, 100, 1, 0, 200, 0),
+            pallet_assets::pallet::Error::<Test>::Unapproved
+        );
+    });
 }
 
 #[test]
-fn basic_add_remove_liquidity() {
-    new_test_ext().execute_with(|| {
-        assert_ok!(create_assets());
-        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000));
-        assert_eq!(Cfmm::get_exchange_rate(0, 1), (1_000, 2_000));
-        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000));
-        assert_eq!(Cfmm::get_exchange_rate(1, 0), (3_000, 1_500));
-        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 20_000));
-        assert_eq!(Cfmm::get_exchange_rate(0, 1), (500, 1_000));
-        assert_ok!(Cfmm::remove_liquidity(Origin::signed(2), 0, 1, 10_000));
-        assert_eq!(Cfmm::get_exchange_rate(0, 1), (0, 0));
+fn force_add_liquidity() {
+    ExtBuilder::default().build().execute_with(|| {
+        // Account 5 stands in for a treasury, and account 4 for governance
+        assert_ok!(Assets::mint(Origin::signed(1), 0, 5, 1_000));
+        assert_ok!(Assets::mint(Origin::signed(1), 1, 5, 3_000));
+
+        for origin in [Origin::signed(5), Origin::root()] {
+            assert_noop!(
+                Cfmm::force_add_liquidity(origin, 5, 0, 0, 1_000, 1, 0, 2_000, 0),
+                BadOrigin
+            );
+        }
+        // The provider is kept alive
+        assert_noop!(
+            Cfmm::force_add_liquidity(Origin::signed(4), 5, 0, 0, 1_000, 1, 0, 2_000, 0),
+            pallet_assets::pallet::Error::<Test>::BalanceLow
+        );
+        // The minimums apply as for add_liquidity
+        assert_noop!(
+            Cfmm::force_add_liquidity(Origin::signed(4), 5, 0, 0, 990, 1, 0, 1_980, 19_801),
+            Error::<Test>::TooLittleLiquidityMinted
+        );
+        assert_ok!(Cfmm::force_add_liquidity(Origin::signed(4), 5, 0, 0, 990, 1, 0, 1_980, 19_800));
+        assert_eq!(Liquidity::<Test>::get(5u128, (0u32, 1u32)).amount, 19_800);
+        assert_eq!((Assets::balance(0, 5), Assets::balance(1, 5)), (10, 1_020));
+
+        // Governance removes the liquidity again
+        assert_noop!(Cfmm::force_remove_liquidity(Origin::signed(5), 5, 0, 1, 19_800), BadOrigin);
+        assert_ok!(Cfmm::force_remove_liquidity(Origin::signed(4), 5, 0, 1, 19_800));
+        assert!(!Liquidity::<Test>::contains_key(5u128, (0u32, 1u32)));
+        assert_eq!((Assets::balance(0, 5), Assets::balance(1, 5)), (1_000, 3_000));
     });
 }
 
 #[test]
 fn add_liquidity_insufficient_assets() {
-    new_test_ext().execute_with(|| {
-        assert_ok!(create_assets());
+    ExtBuilder::default().build().execute_with(|| {
         assert_noop!(
-            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 15_000, 1, 0, 2_000),
+            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 15_000, 1, 0, 2_000, 0, false),
             pallet_assets::pallet::Error::<Test>::BalanceLow
         );
         assert_noop!(
-            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 25_000),
+            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 25_000, 0, false),
             pallet_assets::pallet::Error::<Test>::BalanceLow
         );
     });
@@ -50,26 +170,88 @@ fn add_liquidity_insufficient_assets() {
 
 #[test]
 fn add_liquidity_maintain_exchange_rate() {
-    new_test_ext().execute_with(|| {
-        assert_ok!(create_assets());
-        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000));
-        assert_eq!(Cfmm::get_exchange_rate(0, 1), (1_000, 2_000));
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_000, 2_000)));
         assert_noop!(
-            Cfmm::add_liquidity(Origin::signed(1), 0, 1_001, 2_000, 1, 0, 2_000),
+            Cfmm::add_liquidity(Origin::signed(1), 0, 1_001, 2_000, 1, 0, 2_000, 0, false),
             Error::<Test>::UnexpectedExchangeRate
         );
-        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 2_000, 1, 0, 2_000));
-        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 2_000, 1, 0, 2_000));
-        assert_eq!(Cfmm::get_exchange_rate(0, 1), (3_000, 6_000));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 2_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 2_000, 1, 0, 2_000, 0, false));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((3_000, 6_000)));
+    });
+}
+
+#[test]
+fn quote_add_liquidity() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(Cfmm::quote_add_liquidity(0, 1_000, 1), Error::<Test>::NoLiquidity);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 20, 1, 0));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 1, 40, 0, 0));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_002, 2_005)));
+
+        assert_eq!(Cfmm::quote_add_liquidity(0, 501, 1), Ok((1_003, 10_000)));
+        assert_eq!(Cfmm::quote_add_liquidity(1, 1_000, 0), Ok((500, 9_975)));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 1, 0, 1_000, 0, 0, 500, 9_975, false));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::LiquidityAdded {
+            who: 2,
+            asset_a: 1,
+            amount_a: 1_000,
+            asset_b: 0,
+            amount_b: 500,
+            liquidity: 9_975,
+            new_reserve_a: 3_005,
+            new_reserve_b: 1_502,
+        }));
+    });
+}
+
+#[test]
+fn swap_and_add_liquidity() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(
+            Cfmm::swap_and_add_liquidity(Origin::signed(2), 0, 400, 1, 0),
+            Error::<Test>::NoLiquidity
+        );
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_noop!(
+            Cfmm::swap_and_add_liquidity(Origin::signed(2), 0, 400, 1, 3_433),
+            Error::<Test>::TooLittleLiquidityMinted
+        );
+        assert_ok!(Cfmm::swap_and_add_liquidity(Origin::signed(2), 0, 400, 1, 3_432));
+        System::assert_has_event(Event::Cfmm(CfmmEvent::Exchanged {
+            who: 2,
+            source_asset: 0,
+            source_amount: 192,
+            dest_asset: 1,
+            dest_amount: 293,
+            fee_amount: 20,
+            new_reserve_source: 1_192,
+            new_reserve_dest: 1_707,
+        }));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::LiquidityAdded {
+            who: 2,
+            asset_a: 0,
+            amount_a: 205,
+            asset_b: 1,
+            amount_b: 293,
+            liquidity: 3_432,
+            new_reserve_a: 1_397,
+            new_reserve_b: 2_000,
+        }));
+        // 3 of asset 0 is left over due to rounding
+        assert_eq!(Assets::balance(0, 2), 9_603);
+        assert_eq!(Assets::balance(1, 2), 10_000);
     });
 }
 
 #[test]
 fn add_liquidity_one_asset() {
-    new_test_ext().execute_with(|| {
-        assert_ok!(create_assets());
+    ExtBuilder::default().build().execute_with(|| {
         assert_noop!(
-            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 0, 0, 1_000),
+            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 0, 0, 1_000, 0, false),
             Error::<Test>::AssetsIdentical
         );
     });
@@ -77,76 +259,3539 @@ fn add_liquidity_one_asset() {
 
 #[test]
 fn add_too_little_liquidity() {
-    new_test_ext().execute_with(|| {
-        assert_ok!(create_assets());
+    ExtBuilder::default().build().execute_with(|| {
+        let (min_a, min_b) = Cfmm::min_add_amounts(0, 1).unwrap();
+        assert_eq!((min_a, min_b), (100, 200));
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(1), 0, 0, min_a - 1, 1, 0, min_b, 0, false),
+            Error::<Test>::InsufficientPoolAmount
+        );
         assert_noop!(
-            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 99, 1, 0, 200),
+            Cfmm::add_liquidity(Origin::signed(1), 0, 0, min_a, 1, 0, min_b - 1, 0, false),
             Error::<Test>::InsufficientPoolAmount
         );
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, min_a, 1, 0, min_b, 0, false));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((100, 200)));
+
+        // The minimum amounts for subsequent providers depend on the exchange rate
+        let (min_b, min_a) = Cfmm::min_add_amounts(1, 0).unwrap();
+        assert_eq!((min_a, min_b), (100, 200));
         assert_noop!(
-            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 100, 1, 0, 199),
+            Cfmm::add_liquidity(Origin::signed(2), 0, 0, min_a - 1, 1, 0, min_b, 0, false),
             Error::<Test>::InsufficientPoolAmount
         );
-        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 100, 1, 0, 200));
-        assert_eq!(Cfmm::get_exchange_rate(0, 1), (100, 200));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, min_a, 1, 0, min_b, 0, false));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((200, 400)));
     });
 }
 
 #[test]
-fn remove_too_much_liquidity() {
+fn add_too_little_initial_liquidity() {
+    ExtBuilder::default().build().execute_with(|| {
+        CfmmMinimumLiquidity::set(5_000);
+        // The minimum pool amounts would only give 2_000 liquidity tokens
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 100, 1, 0, 200, 0, false),
+            Error::<Test>::InsufficientLiquidityMinted
+        );
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 250, 1, 0, 500, 0, false));
+        assert_eq!(TotalLiquidity::<Test>::get((0u32, 1u32)), 5_000);
+
+        // The minimum only applies to the first liquidity provider
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 100, 1, 0, 200, 0, false));
+        assert_eq!(TotalLiquidity::<Test>::get((0u32, 1u32)), 7_000);
+    });
+}
+
+#[test]
+fn add_liquidity_min_liquidity() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_001, 1, 0, 2_000, 20_001, false),
+            Error::<Test>::TooLittleLiquidityMinted
+        );
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_001, 1, 0, 2_000, 20_000, false));
+
+        // 501 / 1_001 of the 20_000 existing liquidity tokens is just under 10_010, which is
+        // rounded down
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(2), 0, 0, 501, 1, 0, 1_001, 10_010, false),
+            Error::<Test>::TooLittleLiquidityMinted
+        );
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 501, 1, 0, 1_001, 10_009, false));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::LiquidityAdded {
+            who: 2,
+            asset_a: 0,
+            amount_a: 501,
+            asset_b: 1,
+            amount_b: 1_001,
+            liquidity: 10_009,
+            new_reserve_a: 1_502,
+            new_reserve_b: 3_001,
+        }));
+    });
+}
+
+#[test]
+fn geometric_mean_initial_liquidity() {
     new_test_ext().execute_with(|| {
-        assert_ok!(create_assets());
-        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000));
-        assert_eq!(Cfmm::get_exchange_rate(0, 1), (1_000, 2_000));
+        CfmmInitialLiquidityFormula::set(InitialLiquidityFormula::GeometricMean);
+        assert_ok!(Assets::force_create(Origin::root(), 3, 1, true, 1));
+        assert_ok!(Assets::force_create(Origin::root(), 4, 1, true, 1));
+        for who in [1, 2] {
+            assert_ok!(Assets::mint(Origin::signed(1), 3, who, 100));
+            assert_ok!(Assets::mint(Origin::signed(1), 4, who, 100_000_000));
+        }
+
+        // sqrt(10 * 10_000_000) = 10_000
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 3, 0, 10, 4, 0, 10_000_000, 0, false));
+        assert_eq!(TotalLiquidity::<Test>::get((3u32, 4u32)), 100_000);
+
+        // Subsequent additions are proportional as usual
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 3, 0, 20, 4, 0, 20_000_000, 0, false));
+        assert_eq!(Liquidity::<Test>::get(2u128, (3u32, 4u32)).amount, 200_000);
+        assert_eq!(Cfmm::get_exchange_rate(3, 4), Ok((30, 30_000_000)));
+    });
+}
+
+#[test]
+fn remove_too_much_liquidity() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_000, 2_000)));
         assert_noop!(
             Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 20_001),
-            ArithmeticError::Underflow
+            Error::<Test>::LiquidityExceedsPosition
         );
         assert_noop!(
             Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 18_100),
             Error::<Test>::InsufficientPoolAmount
         );
         assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 18_000));
-        assert_eq!(Cfmm::get_exchange_rate(0, 1), (100, 200));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((100, 200)));
         assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 2_000));
-        assert_eq!(Cfmm::get_exchange_rate(0, 1), (0, 0));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Err(Error::<Test>::NoLiquidity.into()));
+    });
+}
+
+#[test]
+fn max_removable_liquidity() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_eq!(Cfmm::max_removable_liquidity(&1, 0, 1), 0);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 333, 1, 0, 667, 0, false));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 57, 1, 0));
+
+        for who in [1, 2] {
+            let max = Cfmm::max_removable_liquidity(&who, 0, 1);
+            assert!(max < Liquidity::<Test>::get(who, (0u32, 1u32)).amount);
+            assert_noop!(
+                Cfmm::remove_liquidity(Origin::signed(who), 0, 1, max + 1),
+                Error::<Test>::InsufficientPoolAmount
+            );
+            assert_ok!(Cfmm::remove_liquidity(Origin::signed(who), 0, 1, max));
+        }
     });
 }
 
 #[test]
 fn below_min_balance_transferred_not_burned() {
-    new_test_ext().execute_with(|| {
-        assert_ok!(create_assets());
-        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 9_990, 1, 0, 9_980));
-        assert_eq!(Cfmm::get_exchange_rate(0, 1), (9_990, 9_980));
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 9_990, 1, 0, 9_980, 0, false));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((9_990, 9_980)));
         assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 99_900));
-        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 9_991, 1, 0, 9_981));
-        assert_eq!(Cfmm::get_exchange_rate(0, 1), (10_000, 10_000));
-        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 99_910));
-        assert_eq!(Cfmm::get_exchange_rate(0, 1), (0, 0));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 9_991, 1, 0, 9_981, 0, false));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((10_000, 10_000)));
+        // The sender is credited for the full amounts transferred
+        System::assert_last_event(Event::Cfmm(CfmmEvent::LiquidityAdded {
+            who: 1,
+            asset_a: 0,
+            amount_a: 10_000,
+            asset_b: 1,
+            amount_b: 10_000,
+            liquidity: 100_000,
+            new_reserve_a: 10_000,
+            new_reserve_b: 10_000,
+        }));
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 100_000));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Err(Error::<Test>::NoLiquidity.into()));
+
+        // Likewise when adding to an existing pool
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 1_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 9_995, 2, 0, 9_995, 0, false));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::LiquidityAdded {
+            who: 2,
+            asset_a: 0,
+            amount_a: 10_000,
+            asset_b: 2,
+            amount_b: 10_000,
+            liquidity: 100_000,
+            new_reserve_a: 11_000,
+            new_reserve_b: 11_000,
+        }));
+        assert_eq!(Liquidity::<Test>::get(2u128, (0u32, 2u32)).amount, 100_000);
     });
 }
 
 #[test]
-fn exchange_no_liquidity() {
-    new_test_ext().execute_with(|| {
-        assert_ok!(create_assets());
-        assert_noop!(Cfmm::exchange(Origin::signed(1), 0, 1_000, 1, 0), Error::<Test>::NoLiquidity);
+fn below_min_balance_keep_alive() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 9_991, 1, 0, 9_980, 0, true),
+            pallet_assets::pallet::Error::<Test>::BalanceLow
+        );
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 9_990, 1, 0, 9_980, 0, true));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((9_990, 9_980)));
+        assert_eq!(Assets::balance(0, 1), 10);
+        assert_eq!(Assets::balance(1, 1), 20);
     });
 }
 
 #[test]
-fn basic_exchange() {
-    new_test_ext().execute_with(|| {
-        assert_ok!(create_assets());
-        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 5_000, 1, 0, 10_000));
+fn exchange_below_min_balance_keep_alive() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+
         assert_noop!(
-            Cfmm::exchange(Origin::signed(2), 0, 20, 1, 36),
+            Cfmm::exchange_keep_alive(Origin::signed(2), 0, 9_991, 1, 0),
+            pallet_assets::pallet::Error::<Test>::BalanceLow
+        );
+        assert_ok!(Cfmm::exchange_keep_alive(Origin::signed(2), 0, 9_990, 1, 0));
+        assert_eq!(Assets::balance(0, 2), 10);
+        assert_eq!(Cfmm::get_exchange_rate(0, 1).unwrap().0, 10_990);
+
+        // Without keep-alive, the remaining balance is swept into the pool instead
+        assert_ok!(Cfmm::exchange(Origin::signed(1), 0, 8_995, 1, 0));
+        assert_eq!(Assets::balance(0, 1), 0);
+        assert_eq!(Cfmm::get_exchange_rate(0, 1).unwrap().0, 19_990);
+    });
+}
+
+#[test]
+fn remove_and_swap() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+        assert_noop!(
+            Cfmm::remove_and_swap(Origin::signed(2), 0, 1, 10_000, 2, 0),
+            Error::<Test>::AssetNotInPair
+        );
+        assert_noop!(
+            Cfmm::remove_and_swap(Origin::signed(2), 0, 1, 10_000, 0, 811),
             Error::<Test>::UnexpectedExchangeRate
         );
-        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 20, 1, 35));
-        assert_eq!(Assets::balance(0, 2), 9_980);
-        assert_eq!(Assets::balance(1, 2), 10_035);
-        assert_eq!(Cfmm::get_exchange_rate(0, 1), (5_020, 9_965));
+        assert_ok!(Cfmm::remove_and_swap(Origin::signed(2), 0, 1, 10_000, 0, 810));
+        System::assert_has_event(Event::Cfmm(CfmmEvent::LiquidityRemoved {
+            who: 2,
+            asset_a: 0,
+            amount_a: 500,
+            asset_b: 1,
+            amount_b: 1_000,
+            liquidity: 10_000,
+            new_reserve_a: 1_000,
+            new_reserve_b: 2_000,
+        }));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::Exchanged {
+            who: 2,
+            source_asset: 1,
+            source_amount: 1_000,
+            dest_asset: 0,
+            dest_amount: 310,
+            fee_amount: 100,
+            new_reserve_source: 3_000,
+            new_reserve_dest: 690,
+        }));
+        assert_eq!(Assets::balance(0, 2), 10_310);
+        assert_eq!(Assets::balance(1, 2), 9_000);
+    });
+}
+
+#[test]
+fn remove_and_swap_below_min_balance() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Assets::mint(Origin::signed(1), 1, 3, 300_000));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        // Drain the pool of asset 0
+        assert_ok!(Cfmm::exchange(Origin::signed(3), 1, 300_000, 0, 0));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((30, 304_000)));
+
+        // Removing account 2's liquidity yields 15 of asset 0 and 152_000 of asset 1. Exchanging
+        // the latter would yield 7 of asset 0, but that would leave the pool with less than the
+        // minimum balance, so only 5 can be received.
+        assert_noop!(
+            Cfmm::remove_and_swap(Origin::signed(2), 0, 1, 20_000, 0, 21),
+            Error::<Test>::UnexpectedExchangeRate
+        );
+        assert_ok!(Cfmm::remove_and_swap(Origin::signed(2), 0, 1, 20_000, 0, 20));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::Exchanged {
+            who: 2,
+            source_asset: 1,
+            source_amount: 152_000,
+            dest_asset: 0,
+            dest_amount: 5,
+            fee_amount: 15_200,
+            new_reserve_source: 304_000,
+            new_reserve_dest: 10,
+        }));
+        assert_eq!(Assets::balance(0, 2), 9_020);
+        assert_eq!(Assets::balance(1, 2), 8_000);
+    });
+}
+
+#[test]
+fn scheduled_removal() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 3_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+
+        assert_noop!(
+            Cfmm::schedule_remove_liquidity(Origin::signed(2), 0, 1, 10_001, 3),
+            Error::<Test>::LiquidityExceedsPosition
+        );
+        assert_noop!(
+            Cfmm::schedule_remove_liquidity(Origin::signed(2), 0, 1, 5_000, 1),
+            Error::<Test>::ScheduleNotInFuture
+        );
+        assert_ok!(Cfmm::schedule_remove_liquidity(Origin::signed(2), 0, 1, 5_000, 3));
+        assert_noop!(
+            Cfmm::schedule_remove_liquidity(Origin::signed(2), 1, 0, 5_000, 3),
+            Error::<Test>::RemovalAlreadyScheduled
+        );
+        assert_ok!(Cfmm::schedule_remove_liquidity(Origin::signed(1), 0, 1, 20_000, 3));
+        assert_noop!(
+            Cfmm::schedule_remove_liquidity(Origin::signed(1), 0, 2, 10_000, 3),
+            Error::<Test>::TooManyScheduledRemovals
+        );
+        assert_ok!(Cfmm::cancel_scheduled_removal(Origin::signed(1), 0, 1, 3));
+        assert_noop!(
+            Cfmm::cancel_scheduled_removal(Origin::signed(1), 0, 1, 3),
+            Error::<Test>::ScheduledRemovalNotFound
+        );
+        assert_ok!(Cfmm::schedule_remove_liquidity(Origin::signed(1), 0, 2, 10_000, 3));
+
+        // Account 2's position shrinks before its removal is executed
+        System::set_block_number(2);
+        Cfmm::on_initialize(2);
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(2), 0, 1, 6_000));
+
+        System::set_block_number(3);
+        Cfmm::on_initialize(3);
+        System::assert_has_event(Event::Cfmm(CfmmEvent::ScheduledRemovalFailed {
+            who: 2,
+            asset_a: 0,
+            asset_b: 1,
+            liquidity: 5_000,
+            error: Error::<Test>::LiquidityExceedsPosition.into(),
+        }));
+        assert_eq!(Liquidity::<Test>::get(2u128, (0u32, 1u32)).amount, 4_000);
+        System::assert_has_event(Event::Cfmm(CfmmEvent::LiquidityRemoved {
+            who: 1,
+            asset_a: 0,
+            amount_a: 333,
+            asset_b: 2,
+            amount_b: 1_000,
+            liquidity: 10_000,
+            new_reserve_a: 667,
+            new_reserve_b: 2_000,
+        }));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::ScheduledRemovalExecuted {
+            who: 1,
+            asset_a: 0,
+            asset_b: 2,
+            liquidity: 10_000,
+        }));
+        assert_eq!(Liquidity::<Test>::get(1u128, (0u32, 2u32)).amount, 20_000);
+        assert!(!ScheduledRemovals::<Test>::contains_key(3));
+        assert_ok!(Cfmm::do_try_state());
+    });
+}
+
+#[test]
+fn cleanup_pool() {
+    ExtBuilder::default().build().execute_with(|| {
+        let pool_account = crate::pallet::get_pool_account::<Test>((0, 1));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_noop!(Cfmm::cleanup_pool(Origin::signed(3), 0, 1), Error::<Test>::PoolNotEmpty);
+
+        assert_ok!(Cfmm::set_pool_fee(Origin::root(), 0, 1, Some(Permill::zero())));
+        System::set_block_number(2);
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+
+        // Draining the pool leaves no assets behind, but the pool's storage remains until it is
+        // cleaned up
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 20_000));
+        assert_eq!(Assets::balance(0, pool_account), 0);
+        assert_eq!(Assets::balance(1, pool_account), 0);
+        assert!(Pools::<Test>::contains_key((0u32, 1u32)));
+        assert!(PriceCumulative::<Test>::contains_key((0u32, 1u32)));
+        assert!(Observations::<Test>::contains_key((0u32, 1u32)));
+        assert_ok!(Cfmm::cleanup_pool(Origin::signed(3), 0, 1));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::PoolRemoved { asset_a: 0, asset_b: 1 }));
+        // Except for the settings made by governance
+        assert_eq!(
+            Pools::<Test>::get((0u32, 1u32)),
+            PoolInfo { fee: Some(Permill::zero()), ..Default::default() }
+        );
+        assert!(!PriceCumulative::<Test>::contains_key((0u32, 1u32)));
+        assert!(!Observations::<Test>::contains_key((0u32, 1u32)));
+        assert_noop!(Cfmm::cleanup_pool(Origin::signed(3), 0, 1), Error::<Test>::NothingToCleanUp);
+
+        // Assets transferred to the drained pool are swept to the dust sink
+        assert_ok!(Assets::transfer(Origin::signed(2), 0, pool_account, 50));
+        assert_ok!(Cfmm::cleanup_pool(Origin::signed(3), 1, 0));
+        System::assert_has_event(Event::Cfmm(CfmmEvent::PoolCleanedUp {
+            asset_a: 1,
+            amount_a: 0,
+            asset_b: 0,
+            amount_b: 50,
+        }));
+        assert_eq!(Assets::balance(0, pool_account), 0);
+        assert_eq!(Assets::balance(0, CfmmDustSink::get()), 50);
+
+        // The pool can be recreated
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((500, 1_000)));
+        assert_eq!(TotalLiquidity::<Test>::get((0u32, 1u32)), 10_000);
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(2), 0, 1, 10_000));
+        assert_ok!(Cfmm::cleanup_pool(Origin::signed(3), 0, 1));
+
+        // A paused pool cannot be reset by draining and cleaning it up
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+        assert_ok!(Cfmm::set_pool_status(Origin::root(), 0, 1, PoolStatus::TradingDisabled));
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(2), 0, 1, 10_000));
+        assert_ok!(Cfmm::cleanup_pool(Origin::signed(3), 0, 1));
+        assert_eq!(Pools::<Test>::get((0u32, 1u32)).status, PoolStatus::TradingDisabled);
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false),
+            Error::<Test>::PoolPaused
+        );
+    });
+}
+
+#[test]
+fn max_pools() {
+    CfmmMaxPools::set(2);
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 3_000, 0, false));
+        assert_eq!(PoolCount::<Test>::get(), 2);
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(1), 1, 0, 1_000, 2, 0, 3_000, 0, false),
+            Error::<Test>::TooManyPools
+        );
+        // Adding liquidity to existing pools is still fine
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+
+        // Draining a pool frees up its slot
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 2, 30_000));
+        assert_eq!(PoolCount::<Test>::get(), 1);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 1, 0, 1_000, 2, 0, 3_000, 0, false));
+        assert_eq!(PoolCount::<Test>::get(), 2);
+    });
+}
+
+#[test]
+fn max_positions_per_account() {
+    CfmmMaxPositionsPerAccount::set(2);
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 3_000, 0, false));
+        assert_eq!(PositionCount::<Test>::get(1u128), 2);
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(1), 1, 0, 1_000, 2, 0, 3_000, 0, false),
+            Error::<Test>::TooManyPositions
+        );
+        // Adding to an existing position is still fine
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 500, 1, 0, 1_000, 0, false));
+
+        // Transfers can't open a position either
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 1, 0, 1_000, 2, 0, 3_000, 0, false));
+        assert_noop!(
+            Cfmm::transfer_liquidity(Origin::signed(2), 1, 2, 1, 10_000),
+            Error::<Test>::TooManyPositions
+        );
+
+        // Fully exiting a position frees up a slot
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 2, 30_000));
+        assert_eq!(PositionCount::<Test>::get(1u128), 1);
+        assert_ok!(Cfmm::transfer_liquidity(Origin::signed(2), 1, 2, 1, 30_000));
+        assert_eq!(PositionCount::<Test>::get(1u128), 2);
+        assert!(!PositionCount::<Test>::contains_key(2u128));
+    });
+}
+
+#[test]
+fn weighted_pool() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(
+            Cfmm::create_weighted_pool(
+                Origin::signed(1),
+                vec![(0, Perbill::from_percent(80), 1_000), (1, Perbill::from_percent(30), 2_000)]
+            ),
+            Error::<Test>::InvalidWeightedPool
+        );
+        assert_noop!(
+            Cfmm::create_weighted_pool(
+                Origin::signed(1),
+                vec![(0, Perbill::from_percent(80), 1_000), (0, Perbill::from_percent(20), 2_000)]
+            ),
+            Error::<Test>::InvalidWeightedPool
+        );
+        // Weights must be between 1% and 99%
+        assert_noop!(
+            Cfmm::create_weighted_pool(
+                Origin::signed(1),
+                vec![
+                    (0, Perbill::from_perthousand(995), 1_000),
+                    (1, Perbill::from_perthousand(5), 2_000)
+                ]
+            ),
+            Error::<Test>::InvalidWeightedPool
+        );
+        assert_noop!(
+            Cfmm::create_lbp(
+                Origin::signed(1),
+                vec![
+                    (0, Perbill::from_percent(50), Perbill::from_perthousand(995), 1_000),
+                    (1, Perbill::from_percent(50), Perbill::from_perthousand(5), 2_000)
+                ],
+                10,
+                20
+            ),
+            Error::<Test>::InvalidWeightedPool
+        );
+        assert_ok!(Cfmm::create_weighted_pool(
+            Origin::signed(1),
+            vec![(0, Perbill::from_percent(80), 1_000), (1, Perbill::from_percent(20), 2_000)]
+        ));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::WeightedLiquidityAdded {
+            who: 1,
+            pool_id: 0,
+            amounts: vec![1_000, 2_000],
+            liquidity: 20_000,
+        }));
+        assert_ok!(Cfmm::create_weighted_pool(
+            Origin::signed(1),
+            vec![(0, Perbill::from_percent(50), 1_000), (1, Perbill::from_percent(50), 2_000)]
+        ));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_eq!(PoolCount::<Test>::get(), 3);
+
+        // An equally weighted pool prices exactly like the liquidity pool for an asset pair
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 165));
+        assert_ok!(Cfmm::exchange_weighted(Origin::signed(2), 1, 0, 100, 1, 165));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::WeightedExchanged {
+            who: 2,
+            pool_id: 1,
+            source_asset: 0,
+            source_amount: 100,
+            dest_asset: 1,
+            dest_amount: 165,
+            fee_amount: 10,
+        }));
+
+        // With 80% of the weight, the 1_000 of asset 0 is worth four times the 2_000 of asset 1,
+        // so asset 0 buys much more of asset 1 than in the equally weighted pool, and vice versa
+        assert_ok!(Cfmm::exchange_weighted(Origin::signed(2), 0, 0, 100, 1, 0));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::WeightedExchanged {
+            who: 2,
+            pool_id: 0,
+            source_asset: 0,
+            source_amount: 100,
+            dest_asset: 1,
+            dest_amount: 583,
+            fee_amount: 10,
+        }));
+        assert_ok!(Cfmm::exchange_weighted(Origin::signed(2), 0, 1, 200, 0, 0));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::WeightedExchanged {
+            who: 2,
+            pool_id: 0,
+            source_asset: 1,
+            source_amount: 200,
+            dest_asset: 0,
+            dest_amount: 32,
+            fee_amount: 20,
+        }));
+        let reserves = |pool_id| {
+            WeightedPools::<Test>::get(pool_id)
+                .unwrap()
+                .assets
+                .iter()
+                .map(|pool_asset| pool_asset.reserve)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(reserves(0), vec![1_068, 1_617]);
+        assert_noop!(
+            Cfmm::exchange_weighted(Origin::signed(2), 0, 0, 100, 2, 0),
+            Error::<Test>::AssetNotInPool
+        );
+
+        // Liquidity is added and removed in proportion to the amounts in the pool
+        assert_noop!(
+            Cfmm::join_weighted_pool(Origin::signed(2), 0, 10_000, vec![534, 808]),
+            Error::<Test>::UnexpectedExchangeRate
+        );
+        assert_ok!(Cfmm::join_weighted_pool(Origin::signed(2), 0, 10_000, vec![534, 809]));
+        assert_eq!(reserves(0), vec![1_602, 2_426]);
+        assert_eq!(WeightedTotalLiquidity::<Test>::get(0), 30_000);
+        assert_ok!(Cfmm::exit_weighted_pool(Origin::signed(1), 0, 20_000));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::WeightedLiquidityRemoved {
+            who: 1,
+            pool_id: 0,
+            amounts: vec![1_068, 1_617],
+            liquidity: 20_000,
+        }));
+        assert_ok!(Cfmm::exit_weighted_pool(Origin::signed(2), 0, 10_000));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::WeightedPoolDestroyed { pool_id: 0 }));
+        assert!(!WeightedPools::<Test>::contains_key(0));
+        assert_eq!(PoolCount::<Test>::get(), 2);
+    });
+}
+
+#[test]
+fn weighted_pool_positions() {
+    ExtBuilder::default().build().execute_with(|| {
+        CfmmPositionDeposit::set(10);
+        assert_ok!(Balances::set_balance(Origin::root(), 1, 1_000, 0));
+        assert_ok!(Balances::set_balance(Origin::root(), 2, 1_000, 0));
+        let assets =
+            vec![(0, Perbill::from_percent(80), 1_000), (1, Perbill::from_percent(20), 2_000)];
+
+        // Positions in weighted pools take a deposit and count towards the limit, as for asset
+        // pairs
+        assert_ok!(Cfmm::create_weighted_pool(Origin::signed(1), assets.clone()));
+        assert_eq!(PositionCount::<Test>::get(1u128), 1);
+        assert_eq!(Balances::reserved_balance(1), 10);
+        assert_ok!(Cfmm::join_weighted_pool(Origin::signed(2), 0, 10_000, vec![500, 1_000]));
+        assert_ok!(Cfmm::join_weighted_pool(Origin::signed(2), 0, 10_000, vec![500, 1_000]));
+        assert_eq!(PositionCount::<Test>::get(2u128), 1);
+        assert_eq!(Balances::reserved_balance(2), 10);
+
+        CfmmMaxPositionsPerAccount::set(1);
+        assert_noop!(
+            Cfmm::create_weighted_pool(Origin::signed(1), assets),
+            Error::<Test>::TooManyPositions
+        );
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(2), 0, 0, 1_000, 1, 0, 2_000, 0, false),
+            Error::<Test>::TooManyPositions
+        );
+
+        // Fully exiting releases the deposit and the slot
+        assert_ok!(Cfmm::exit_weighted_pool(Origin::signed(2), 0, 10_000));
+        assert_eq!(Balances::reserved_balance(2), 10);
+        assert_ok!(Cfmm::exit_weighted_pool(Origin::signed(2), 0, 10_000));
+        assert_eq!(Balances::reserved_balance(2), 0);
+        assert!(!PositionCount::<Test>::contains_key(2u128));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+    });
+}
+
+#[test]
+fn liquidity_bootstrapping_pool() {
+    ExtBuilder::default().build().execute_with(|| {
+        let assets = vec![
+            (0, Perbill::from_percent(90), Perbill::from_percent(10), 1_000),
+            (1, Perbill::from_percent(10), Perbill::from_percent(90), 2_000),
+        ];
+        assert_noop!(
+            Cfmm::create_lbp(Origin::signed(1), assets.clone(), 20, 10),
+            Error::<Test>::InvalidWeightSchedule
+        );
+        assert_ok!(Cfmm::create_lbp(Origin::signed(1), assets, 10, 20));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::LbpCreated {
+            pool_id: 0,
+            start_block: 10,
+            end_block: 20,
+        }));
+
+        // With constant reserves, the price of asset 0 falls steadily over the window...
+        System::set_block_number(5);
+        let mut last_price = Cfmm::get_weighted_spot_price(0, 0, 1).unwrap();
+        assert_eq!(last_price, FixedU128::saturating_from_integer(18));
+        for block in 11..=20 {
+            System::set_block_number(block);
+            let price = Cfmm::get_weighted_spot_price(0, 0, 1).unwrap();
+            assert!(price < last_price);
+            if block == 15 {
+                assert_eq!(price, FixedU128::saturating_from_integer(2));
+            }
+            last_price = price;
+        }
+        assert_eq!(last_price, FixedU128::saturating_from_rational(2, 9));
+
+        // ...and stays put afterwards
+        System::set_block_number(25);
+        assert_eq!(Cfmm::get_weighted_spot_price(0, 0, 1), Some(last_price));
+
+        // The next exchange freezes the end weights
+        assert_ok!(Cfmm::exchange_weighted(Origin::signed(2), 0, 1, 100, 0, 0));
+        assert!(!WeightSchedules::<Test>::contains_key(0));
+        let weights = WeightedPools::<Test>::get(0)
+            .unwrap()
+            .assets
+            .iter()
+            .map(|pool_asset| pool_asset.weight)
+            .collect::<Vec<_>>();
+        assert_eq!(weights, vec![Perbill::from_percent(10), Perbill::from_percent(90)]);
+    });
+}
+
+#[test]
+fn pool_creator() {
+    ExtBuilder::default().build().execute_with(|| {
+        System::set_block_number(3);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        let info = Cfmm::pool_info(1, 0).unwrap();
+        assert_eq!((info.creator, info.created_at), (Some(1), Some(3)));
+
+        // Later liquidity providers don't count as creators
+        System::set_block_number(4);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+        let info = Cfmm::pool_info(0, 1).unwrap();
+        assert_eq!((info.creator, info.created_at), (Some(1), Some(3)));
+
+        // Draining and re-seeding the pool resets them
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 20_000));
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(2), 0, 1, 10_000));
+        System::set_block_number(7);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        let info = Cfmm::pool_info(0, 1).unwrap();
+        assert_eq!((info.creator, info.created_at), (Some(2), Some(7)));
+
+        assert_eq!(Cfmm::pool_info(0, 0), None);
+    });
+}
+
+#[test]
+fn price_band() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+
+        let min_rate = Some(FixedU128::saturating_from_rational(45, 100));
+        let max_rate = Some(FixedU128::saturating_from_rational(55, 100));
+        assert_noop!(Cfmm::set_price_band(Origin::signed(1), 1, 0, min_rate, max_rate), BadOrigin);
+        assert_noop!(
+            Cfmm::set_price_band(Origin::root(), 1, 0, max_rate, min_rate),
+            Error::<Test>::InvalidPriceBand
+        );
+        assert_noop!(
+            Cfmm::set_price_band(Origin::root(), 1, 0, Some(FixedU128::zero()), max_rate),
+            Error::<Test>::InvalidPriceBand
+        );
+        assert_ok!(Cfmm::set_price_band(Origin::root(), 1, 0, min_rate, max_rate));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::PriceBandChanged {
+            asset_a: 1,
+            asset_b: 0,
+            min_rate,
+            max_rate,
+        }));
+
+        // The price of asset 1 is 0.5 units of asset 0. Exchanges which would take it outside
+        // 0.45-0.55 fail, in either direction.
+        assert_noop!(
+            Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0),
+            Error::<Test>::PriceOutOfBand
+        );
+        assert_noop!(Cfmm::simulate_exchange(0, 100, 1), Error::<Test>::PriceOutOfBand);
+        assert_noop!(
+            Cfmm::exchange(Origin::signed(2), 1, 200, 0, 0),
+            Error::<Test>::PriceOutOfBand
+        );
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 10, 1, 0));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_010, 1_983)));
+
+        // Liquidity can still be added and removed
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 505, 1, 0, 1_000, 0, false));
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 10_000));
+
+        assert_ok!(Cfmm::set_price_band(Origin::root(), 0, 1, None, None));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+    });
+}
+
+#[test]
+fn liquidity_voting_power() {
+    ExtBuilder::default().build().execute_with(|| {
+        type GovernanceVotingPower =
+            LiquidityVotingPower<Test, ConstU32<0>, ConstU64<20>, ConstU64<50>>;
+        System::set_block_number(1);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+        assert_eq!(Liquidity::<Test>::get(&2, (0, 1)).amount, 10_000);
+        // Pools without the governance token don't count
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 1, 0, 1_000, 2, 0, 1_000, 0, false));
+        assert_ok!(Cfmm::lock_liquidity(Origin::signed(2), 1, 2, 10_000, 100));
+        // Only liquidity locked for at least 50 more blocks counts
+        assert_ok!(Cfmm::lock_liquidity(Origin::signed(2), 0, 1, 6_000, 100));
+        assert_ok!(Cfmm::lock_liquidity(Origin::signed(2), 0, 1, 4_000, 30));
+        System::set_block_number(21);
+
+        // A third of the locked liquidity is worth a third of the 1_500 governance tokens in the
+        // pool
+        assert_eq!(GovernanceVotingPower::voting_power(&2), 300);
+        assert_eq!(GovernanceVotingPower::voting_power(&3), 0);
+
+        // Exchanging governance tokens into the pool barely changes the voting power, as the
+        // average price is used. At the spot price, the 2_500 governance tokens in the pool
+        // would give 500.
+        assert_ok!(Cfmm::exchange(Origin::signed(1), 0, 1_000, 1, 0));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((2_500, 1_875)));
+        assert_eq!(GovernanceVotingPower::voting_power(&2), 306);
+
+        // The counted liquidity can't be transferred and counted again
+        assert_noop!(
+            Cfmm::transfer_liquidity(Origin::signed(2), 0, 1, 3, 1_000),
+            Error::<Test>::LiquidityTimelocked
+        );
+
+        // Locks expiring within the lock period don't count
+        System::set_block_number(51);
+        assert_eq!(GovernanceVotingPower::voting_power(&2), 0);
+    });
+}
+
+#[test]
+fn bounded_queries() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 1_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 1, 0, 1_000, 2, 0, 1_000, 0, false));
+
+        let mut pools = Cfmm::all_pools();
+        pools.sort();
+        assert_eq!(pools, vec![(0, 1), (0, 2), (1, 2)]);
+        assert_eq!(Cfmm::all_pools_bounded().into_inner(), Cfmm::all_pools());
+        let positions = Cfmm::get_account_liquidity(&1);
+        assert_eq!(positions.len(), 3);
+        assert_eq!(Cfmm::get_account_liquidity_bounded(&1).into_inner(), positions);
+
+        // If the bounds are lowered, the bounded variants are truncated
+        CfmmMaxPools::set(2);
+        CfmmMaxPositionsPerAccount::set(2);
+        assert_eq!(Cfmm::all_pools_bounded().into_inner(), Cfmm::all_pools()[..2]);
+        assert_eq!(Cfmm::get_account_liquidity_bounded(&1).into_inner(), positions[..2]);
+    });
+}
+
+#[test]
+fn rounding_policy() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_eq!(Cfmm::simulate_exchange(0, 100, 1).unwrap().dest_amount, 165);
+        assert_eq!(Cfmm::get_source_amount(0, 1, 166), Ok(102));
+
+        // The fee covers rounding the other way
+        CfmmRoundingPolicy::set(RoundingPolicy::FavorTrader);
+        assert_eq!(Cfmm::simulate_exchange(0, 100, 1).unwrap().dest_amount, 166);
+        assert_eq!(Cfmm::get_source_amount(0, 1, 166), Ok(101));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 101, 1, 166));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_101, 1_834)));
+
+        // Without a fee, rounding in favour of the trader would shrink the pool, so it is not done
+        assert_ok!(Cfmm::set_pool_fee(Origin::root(), 0, 1, Some(Permill::zero())));
+        CfmmRoundingPolicy::set(RoundingPolicy::FavorPool);
+        let dest_amount = Cfmm::simulate_exchange(0, 1, 1).unwrap().dest_amount;
+        CfmmRoundingPolicy::set(RoundingPolicy::FavorTrader);
+        assert_eq!(Cfmm::simulate_exchange(0, 1, 1).unwrap().dest_amount, dest_amount);
+    });
+}
+
+#[test]
+fn product_never_decreases() {
+    for policy in [RoundingPolicy::FavorPool, RoundingPolicy::FavorTrader] {
+        ExtBuilder::default().build().execute_with(|| {
+            CfmmRoundingPolicy::set(policy);
+            assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 3_000, 0, false));
+
+            // Deterministic xorshift, so failures are reproducible
+            let mut state = 0x2545_f491_u32;
+            let mut next = |bound: u32| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state % bound
+            };
+            let product = || {
+                let (amount_0, amount_1) = Cfmm::get_exchange_rate(0, 1).unwrap();
+                u64::from(amount_0) * u64::from(amount_1)
+            };
+
+            let mut prev = product();
+            for i in 0..500 {
+                if i % 50 == 0 {
+                    let fee =
+                        [Permill::zero(), Permill::from_perthousand(3), Permill::from_percent(10)]
+                            [next(3) as usize];
+                    assert_ok!(Cfmm::set_pool_fee(Origin::root(), 0, 1, Some(fee)));
+                }
+                let (source, dest) = if next(2) == 0 { (0, 1) } else { (1, 0) };
+                let amount = 1 + next(200);
+                // Some exchanges fail, eg if the amount received would be zero, but none may
+                // fail with InvariantViolation
+                if let Err(err) = Cfmm::exchange(Origin::signed(2), source, amount, dest, 0) {
+                    assert_ne!(err, Error::<Test>::InvariantViolation.into());
+                }
+                let product = product();
+                assert!(product >= prev);
+                prev = product;
+            }
+        });
+    }
+}
+
+#[test]
+fn force_migrate_pool() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Assets::force_create(Origin::root(), 3, 1, true, 10));
+        assert_ok!(Assets::mint(Origin::signed(1), 3, 1, 10_000));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 1_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 3, 0, 1_000, 2, 0, 1_000, 0, false));
+
+        // Asset 0 is redenominated as asset 3, with two units of asset 3 per unit of asset 0
+        assert_noop!(Cfmm::force_migrate_pool(Origin::signed(1), 0, 3, 2, 1, 1, 4), BadOrigin);
+        assert_noop!(
+            Cfmm::force_migrate_pool(Origin::root(), 0, 3, 2, 1, 2, 4),
+            Error::<Test>::MigrationTargetExists
+        );
+        assert_noop!(
+            Cfmm::force_migrate_pool(Origin::root(), 0, 3, 2, 1, 1, 4),
+            Error::<Test>::MigrationNotFunded
+        );
+        let new_account = crate::pallet::get_pool_account::<Test>((1, 3));
+        assert_ok!(Assets::mint(Origin::signed(1), 3, new_account, 3_000));
+        // All four liquidity positions are visited, not just those in the pool
+        assert_noop!(
+            Cfmm::force_migrate_pool(Origin::root(), 0, 3, 2, 1, 1, 3),
+            Error::<Test>::MigrationTooLarge
+        );
+        assert_ok!(Cfmm::force_migrate_pool(Origin::root(), 0, 3, 2, 1, 1, 4));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::PoolMigrated {
+            old_asset: 0,
+            new_asset: 3,
+            counterpart_asset: 1,
+            old_reserve: 1_500,
+            new_reserve: 3_000,
+        }));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Err(Error::<Test>::PoolNotFound.into()));
+        assert_eq!(Cfmm::get_exchange_rate(3, 1), Ok((3_000, 3_000)));
+        assert_eq!(Assets::balance(0, CfmmDustSink::get()), 1_500);
+        assert_eq!(TotalLiquidity::<Test>::get((0u32, 1u32)), 0);
+        assert_eq!(TotalLiquidity::<Test>::get((1u32, 3u32)), 30_000);
+        assert_eq!(Liquidity::<Test>::get(1u128, (1u32, 3u32)).amount, 20_000);
+        assert_eq!(Liquidity::<Test>::get(2u128, (1u32, 3u32)).amount, 10_000);
+        assert!(!Liquidity::<Test>::contains_key(2u128, (0u32, 1u32)));
+
+        // The migrated pool works as normal
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(2), 3, 1, 10_000));
+        assert_eq!(Assets::balance(3, 2), 1_000);
+        assert_ok!(Cfmm::exchange(Origin::signed(1), 3, 100, 1, 0));
+    });
+}
+
+#[test]
+fn genesis_pools() {
+    let position = ExtBuilder::default()
+        .with_pool(1, 0, 1_000, 1, 2_000)
+        .with_pool(2, 1, 1_000, 0, 500)
+        .build()
+        .execute_with(|| {
+            let pool_account = crate::pallet::get_pool_account::<Test>((0, 1));
+            assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_500, 3_000)));
+            assert_eq!(Assets::balance(0, pool_account), 1_500);
+            assert_eq!(Assets::balance(1, pool_account), 3_000);
+            assert_eq!(Assets::balance(0, 1), 9_000);
+            assert_eq!(Assets::balance(1, 2), 9_000);
+            assert_eq!(TotalLiquidity::<Test>::get((0u32, 1u32)), 30_000);
+            assert_eq!(Liquidity::<Test>::get(2u128, (0u32, 1u32)).amount, 10_000);
+            assert_eq!(take_hook_calls(), vec![]);
+
+            // Genesis liquidity can be removed like any other
+            assert_ok!(Cfmm::remove_liquidity(Origin::signed(2), 0, 1, 10_000));
+            assert_eq!(Assets::balance(0, 2), 10_000);
+            assert_eq!(Assets::balance(1, 2), 10_000);
+            Liquidity::<Test>::get(1u128, (0u32, 1u32))
+        });
+
+    // Genesis positions match those created by add_liquidity
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_eq!(Liquidity::<Test>::get(1u128, (0u32, 1u32)), position);
+    });
+}
+
+#[test]
+fn exchange_no_liquidity() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(Cfmm::exchange(Origin::signed(1), 0, 1_000, 1, 0), Error::<Test>::NoLiquidity);
+    });
+}
+
+#[test]
+fn basic_exchange() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 5_000, 1, 0, 10_000, 0, false));
+        assert_noop!(
+            Cfmm::exchange(Origin::signed(2), 0, 20, 1, 36),
+            Error::<Test>::UnexpectedExchangeRate
+        );
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 20, 1, 35));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::Exchanged {
+            who: 2,
+            source_asset: 0,
+            source_amount: 20,
+            dest_asset: 1,
+            dest_amount: 35,
+            fee_amount: 2,
+            new_reserve_source: 5_020,
+            new_reserve_dest: 9_965,
+        }));
+        assert_eq!(Assets::balance(0, 2), 9_980);
+        assert_eq!(Assets::balance(1, 2), 10_035);
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((5_020, 9_965)));
+    });
+}
+
+#[test]
+fn exchange_with_referral() {
+    let exchange = |referrer| {
+        ExtBuilder::default().build().execute_with(|| {
+            assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 5_000, 1, 0, 10_000, 0, false));
+            assert_ok!(Cfmm::exchange_with_referral(Origin::signed(2), 0, 1_000, 1, 0, referrer));
+            (Assets::balance(1, 2), Assets::balance(0, 3), Cfmm::get_exchange_rate(0, 1).unwrap())
+        })
+    };
+
+    // Without a referrer, the whole fee (100) is added to the pool
+    let (received, referral, reserves) = exchange(None);
+    assert_eq!((referral, reserves.0), (0, 6_000));
+
+    // With a referrer, half of it is paid to them instead. The exchange rate given to the trader
+    // is unaffected.
+    assert_eq!(exchange(Some(3)), (received, 50, (5_950, reserves.1)));
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 5_000, 1, 0, 10_000, 0, false));
+        // Traders cannot refer themselves to claw back part of the fee
+        assert_noop!(
+            Cfmm::exchange_with_referral(Origin::signed(2), 0, 1_000, 1, 0, Some(2)),
+            Error::<Test>::SelfReferral
+        );
+        assert_ok!(Cfmm::exchange_with_referral(Origin::signed(2), 0, 1_000, 1, 0, Some(3)));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::ReferralFeePaid {
+            referrer: 3,
+            asset: 0,
+            amount: 50,
+        }));
+    });
+}
+
+#[test]
+fn price_impact() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(Cfmm::price_impact(0, 20, 1), Error::<Test>::NoLiquidity);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 5_000, 1, 0, 10_000, 0, false));
+        assert_eq!(Cfmm::price_impact(0, 0, 1), Ok(Permill::zero()));
+        // 35 of asset 1 for 20 of asset 0, at a spot rate of 2: 1 - 1.75 / 2
+        assert_eq!(Cfmm::price_impact(0, 20, 1), Ok(Permill::from_parts(125_000)));
+
+        // The estimate matches the rate actually received, and grows with the size of the trade,
+        // but is never less than the fee (10%)
+        let mut last_impact = Permill::zero();
+        for (source, amount, dest) in [(0, 20, 1), (1, 1_000, 0), (0, 2_000, 1), (1, 8_000, 0)] {
+            let impact = Cfmm::price_impact(source, amount, dest).unwrap();
+            let (pool_source_amount, pool_dest_amount) =
+                Cfmm::get_exchange_rate(source, dest).unwrap();
+            let before = Assets::balance(dest, 2);
+            assert_ok!(Cfmm::exchange(Origin::signed(2), source, amount, dest, 0));
+            let received = u64::from(Assets::balance(dest, 2) - before);
+            let retained = received * u64::from(pool_source_amount) * 1_000_000 /
+                (u64::from(amount) * u64::from(pool_dest_amount));
+            let retained = Permill::from_parts(retained as u32);
+            assert_eq!(impact, Permill::one() - retained);
+            assert!(impact >= Permill::from_percent(10));
+            assert!(impact > last_impact);
+            last_impact = impact;
+        }
+        // The last exchange was large relative to the pool
+        assert!(last_impact > Permill::from_percent(50));
+    });
+}
+
+#[test]
+fn simulate_exchange() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(Cfmm::simulate_exchange(0, 20, 1), Error::<Test>::NoLiquidity);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 5_000, 1, 0, 10_000, 0, false));
+
+        let simulation = Cfmm::simulate_exchange(0, 20, 1).unwrap();
+        assert_eq!(
+            simulation,
+            ExchangeSimulation {
+                dest_amount: 35,
+                fee_amount: 2,
+                new_reserve_source: 5_020,
+                new_reserve_dest: 9_965,
+                price: FixedU128::saturating_from_rational(35, 20),
+            }
+        );
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 20, 1, 0));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::Exchanged {
+            who: 2,
+            source_asset: 0,
+            source_amount: 20,
+            dest_asset: 1,
+            dest_amount: simulation.dest_amount,
+            fee_amount: simulation.fee_amount,
+            new_reserve_source: simulation.new_reserve_source,
+            new_reserve_dest: simulation.new_reserve_dest,
+        }));
+
+        // Also in the other direction, where rounding differs
+        let simulation = Cfmm::simulate_exchange(1, 333, 0).unwrap();
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 1, 333, 0, 0));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::Exchanged {
+            who: 2,
+            source_asset: 1,
+            source_amount: 333,
+            dest_asset: 0,
+            dest_amount: simulation.dest_amount,
+            fee_amount: simulation.fee_amount,
+            new_reserve_source: simulation.new_reserve_source,
+            new_reserve_dest: simulation.new_reserve_dest,
+        }));
+        assert_eq!(
+            Cfmm::get_exchange_rate(1, 0),
+            Ok((simulation.new_reserve_source, simulation.new_reserve_dest))
+        );
+    });
+}
+
+#[test]
+fn hooks() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 20, 1, 0));
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 1, 0, 10_000));
+        assert_eq!(
+            take_hook_calls(),
+            vec![
+                HookCall::LiquidityAdded(1, 0, 1_000, 1, 2_000, 20_000),
+                HookCall::Swap(2, 0, 20, 1, 35),
+                HookCall::LiquidityRemoved(1, 1, 982, 0, 510, 10_000),
+            ]
+        );
+
+        // Hooks are not called for failed exchanges
+        assert_noop!(
+            Cfmm::exchange(Origin::signed(2), 0, 20, 1, 1_000),
+            Error::<Test>::UnexpectedExchangeRate
+        );
+        assert_eq!(take_hook_calls(), vec![]);
+    });
+}
+
+#[test]
+fn get_amounts_along_route() {
+    let builder = ExtBuilder::default().with_pool(1, 0, 1_000, 1, 2_000);
+    builder.with_pool(1, 1, 2_000, 2, 3_000).build().execute_with(|| {
+        assert_noop!(Cfmm::get_amounts_out(&[0], 100), Error::<Test>::InvalidRoute);
+        assert_noop!(Cfmm::get_amounts_out(&[0, 1, 2, 0], 100), Error::<Test>::InvalidRoute);
+        assert_noop!(Cfmm::get_amounts_in(&[0, 0, 1], 100), Error::<Test>::AssetsIdentical);
+
+        assert_eq!(Cfmm::get_amounts_in(&[0, 1, 2], 100), Ok(vec![46, 77, 100]));
+        assert_eq!(Cfmm::get_amounts_out(&[0, 1, 2], 46), Ok(vec![46, 78, 101]));
+        assert_eq!(Cfmm::get_amounts_out(&[0, 1, 2], 100), Ok(vec![100, 165, 206]));
+
+        // The amounts match actually performing the exchanges
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 165));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 1, 165, 2, 206));
+        assert_eq!(Assets::balance(2, 2), 10_206);
+    });
+}
+
+#[test]
+fn lp_asset_id_round_trip() {
+    ExtBuilder::default().build().execute_with(|| {
+        let id = Cfmm::lp_asset_id(0, 1);
+        assert_eq!(Cfmm::lp_asset_id(1, 0), id);
+        assert_ne!(Cfmm::lp_asset_id(0, 2), id);
+        // Recorded when the pool is created
+        assert_eq!(Cfmm::pair_from_lp_asset(id), None);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 2, 0, 3_000, 1, 0, 1_000, 0, false));
+        assert_eq!(Cfmm::pair_from_lp_asset(id), Some((0, 1)));
+        let id = Cfmm::lp_asset_id(2, 1);
+        assert_eq!(Cfmm::pair_from_lp_asset(id), Some((1, 2)));
+        let (asset_a, asset_b) = Cfmm::pair_from_lp_asset(id).unwrap();
+        assert_eq!(Cfmm::lp_asset_id(asset_a, asset_b), id);
+        assert_eq!(Cfmm::pair_from_lp_asset(Cfmm::lp_asset_id(0, 2)), None);
+
+        // Pools cannot be created for asset pairs whose liquidity token asset ID is taken, by
+        // another asset pair or by an existing asset
+        let id = Cfmm::lp_asset_id(0, 2);
+        LpAssetLookup::<Test>::insert(id, (0u32, 1u32));
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 3_000, 0, false),
+            Error::<Test>::LpAssetIdTaken
+        );
+        LpAssetLookup::<Test>::remove(id);
+        assert_ok!(Assets::force_create(Origin::root(), id, 1, true, 10));
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 3_000, 0, false),
+            Error::<Test>::LpAssetIdTaken
+        );
+    });
+}
+
+#[test]
+fn spot_price() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_eq!(Cfmm::spot_price(0, 1), None);
+        assert_eq!(Cfmm::spot_price(0, 0), None);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 3_000, 0, false));
+        assert_eq!(Cfmm::spot_price(0, 1), Some(FixedU128::saturating_from_integer(3)));
+        assert_eq!(Cfmm::spot_price(1, 0), Some(FixedU128::saturating_from_rational(1, 3)));
+        // Decimal places are ignored
+        assert_ok!(Assets::force_set_metadata(Origin::root(), 0, vec![], vec![], 12, false));
+        assert_eq!(Cfmm::spot_price(0, 1), Some(FixedU128::saturating_from_integer(3)));
+
+        // The numeric edges, with the reserves set directly. These are inconsistent with the pool,
+        // so are rolled back afterwards.
+        assert_ok!(with_transaction(|| {
+            Reserves::<Test>::insert((0u32, 1u32), (1, AssetBalance::MAX));
+            assert_eq!(
+                Cfmm::spot_price(0, 1),
+                Some(FixedU128::saturating_from_integer(AssetBalance::MAX))
+            );
+            let price = Cfmm::spot_price(1, 0).unwrap();
+            assert_eq!(price, FixedU128::saturating_from_rational(1, AssetBalance::MAX));
+            Reserves::<Test>::insert((0u32, 1u32), (AssetBalance::MAX, AssetBalance::MAX));
+            assert_eq!(Cfmm::spot_price(0, 1), Some(FixedU128::one()));
+            Reserves::<Test>::insert((0u32, 1u32), (0, AssetBalance::MAX));
+            assert_eq!(Cfmm::spot_price(0, 1), None);
+            assert_eq!(Cfmm::spot_price(1, 0), None);
+            TransactionOutcome::Rollback(Ok::<_, DispatchError>(()))
+        }));
+        assert_eq!(Cfmm::spot_price(0, 1), Some(FixedU128::saturating_from_integer(3)));
+    });
+}
+
+#[test]
+fn get_normalized_price() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_eq!(Cfmm::get_normalized_price(0, 1), None);
+        assert_ok!(Assets::force_set_metadata(Origin::root(), 0, vec![], vec![], 12, false));
+        assert_ok!(Assets::force_set_metadata(Origin::root(), 1, vec![], vec![], 6, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 3_000, 0, false));
+
+        // 1_000 of asset 0 is 1e-9 units, 2_000 of asset 1 is 2e-3 units
+        assert_eq!(
+            Cfmm::get_normalized_price(0, 1),
+            Some(FixedU128::saturating_from_integer(2_000_000))
+        );
+        assert_eq!(
+            Cfmm::get_normalized_price(1, 0),
+            Some(FixedU128::saturating_from_rational(5, 10_000_000))
+        );
+        // Asset 2 has no metadata, so zero decimals
+        assert_eq!(
+            Cfmm::get_normalized_price(2, 0),
+            Some(FixedU128::saturating_from_rational(1, 3_000_000_000_000u64))
+        );
+    });
+}
+
+#[test]
+fn position_earnings() {
+    ExtBuilder::default().with_pool(1, 0, 1_000, 1, 2_000).build().execute_with(|| {
+        assert_eq!(Cfmm::position_earnings(&1, 0, 1), Ok((0, 0)));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 20, 1, 0));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 1, 40, 0, 0));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_002, 2_005)));
+        assert_eq!(Cfmm::position_earnings(&1, 0, 1), Ok((2, 4)));
+        assert_eq!(Cfmm::position_earnings(&1, 1, 0), Ok((4, 2)));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 501, 1, 0, 1_003, 0, false));
+        assert_eq!(Cfmm::position_earnings(&2, 0, 1), Ok((0, 0)));
+        assert_eq!(Cfmm::position_earnings(&1, 0, 1), Ok((2, 4)));
+    });
+}
+
+#[test]
+fn migrate_liquidity_to_positions() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+
+        // Rewrite the position using the version 0 layout
+        unhashed::put(&Liquidity::<Test>::hashed_key_for(1u128, (0u32, 1u32)), &20_000u32);
+        StorageVersion::new(0).put::<Cfmm>();
+
+        migrations::v1::migrate::<Test>();
+        assert_eq!(StorageVersion::get::<Cfmm>(), StorageVersion::new(1));
+        assert_eq!(
+            Liquidity::<Test>::get(1u128, (0u32, 1u32)),
+            LiquidityPosition {
+                amount: 20_000,
+                reserve_a_per_share: FixedU128::saturating_from_rational(1, 20),
+                reserve_b_per_share: FixedU128::saturating_from_rational(1, 10),
+            }
+        );
+    });
+}
+
+/// Moves the funds of the liquidity pool for the asset pair to the account which held it before
+/// storage version 8.
+fn move_to_legacy_pool_account(asset_pair: (u32, u32)) {
+    let pool_account = crate::pallet::get_pool_account::<Test>(asset_pair);
+    let legacy_account = crate::pallet::get_legacy_pool_account::<Test>(asset_pair);
+    for asset in [asset_pair.0, asset_pair.1] {
+        let balance = Assets::balance(asset, pool_account);
+        assert_ok!(Assets::transfer(Origin::signed(pool_account), asset, legacy_account, balance));
+    }
+}
+
+#[test]
+fn migrate_pool_accounts() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 3_000, 0, false));
+        // The mock's AccountId is too narrow for the sub-accounts, so the accounts differ
+        let pool_account = crate::pallet::get_pool_account::<Test>((0, 1));
+        let legacy_account = crate::pallet::get_legacy_pool_account::<Test>((0, 1));
+        assert_ne!(pool_account, legacy_account);
+        move_to_legacy_pool_account((0, 1));
+        move_to_legacy_pool_account((0, 2));
+        // Something else in an old account is left there
+        assert_ok!(Assets::transfer(Origin::signed(2), 1, legacy_account, 100));
+        StorageVersion::new(7).put::<Cfmm>();
+
+        migrations::v8::migrate::<Test>();
+        assert_eq!(StorageVersion::get::<Cfmm>(), StorageVersion::new(8));
+        assert_eq!(
+            (Assets::balance(0, pool_account), Assets::balance(1, pool_account)),
+            (1_000, 2_000)
+        );
+        assert_eq!(
+            (Assets::balance(0, legacy_account), Assets::balance(1, legacy_account)),
+            (0, 100)
+        );
+        let pool_account = crate::pallet::get_pool_account::<Test>((0, 2));
+        assert_eq!(
+            (Assets::balance(0, pool_account), Assets::balance(2, pool_account)),
+            (1_000, 3_000)
+        );
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 20_000));
+    });
+}
+
+#[test]
+fn run_migrations() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+
+        // Nothing to do if the storage is already current
+        StorageVersion::new(11).put::<Cfmm>();
+        assert_ok!(migrations::pre_upgrade::<Test>());
+        <Cfmm as Hooks<u64>>::on_runtime_upgrade();
+        assert_ok!(migrations::post_upgrade::<Test>());
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_100, 1_835)));
+
+        // Roll back to the version 2 layout, without tracked reserves, pool/position counts or
+        // liquidity token asset IDs
+        crate::Reserves::<Test>::remove((0u32, 1u32));
+        LpAssetLookup::<Test>::remove(Cfmm::lp_asset_id(0, 1));
+        PoolCount::<Test>::kill();
+        PositionCount::<Test>::remove(1u128);
+        unhashed::put_raw(
+            &Pools::<Test>::hashed_key_for((0u32, 1u32)),
+            &(false, None::<Permill>, None::<()>).encode(),
+        );
+        move_to_legacy_pool_account((0, 1));
+        assert_ok!(System::dec_providers(&crate::pallet::get_pool_account::<Test>((0, 1))));
+        StorageVersion::new(2).put::<Cfmm>();
+        assert_ok!(migrations::pre_upgrade::<Test>());
+        assert!(migrations::post_upgrade::<Test>().is_err());
+        <Cfmm as Hooks<u64>>::on_runtime_upgrade();
+        assert_eq!(StorageVersion::get::<Cfmm>(), StorageVersion::new(11));
+        assert_ok!(migrations::post_upgrade::<Test>());
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_100, 1_835)));
+        assert_eq!(PoolCount::<Test>::get(), 1);
+        assert_eq!(PositionCount::<Test>::get(1u128), 1);
+        assert_eq!(Pools::<Test>::get((0u32, 1u32)), PoolInfo::default());
+        assert_eq!(Cfmm::pair_from_lp_asset(Cfmm::lp_asset_id(0, 1)), Some((0, 1)));
+
+        // Downgrades are not supported
+        StorageVersion::new(12).put::<Cfmm>();
+        assert!(migrations::pre_upgrade::<Test>().is_err());
+    });
+}
+
+#[test]
+fn asset_pair_ordered_by_value() {
+    ExtBuilder::default().build().execute_with(|| {
+        // 256 encodes as [0, 1, 0, 0], which sorts before the encoding of 1, [1, 0, 0, 0]
+        assert_ok!(Assets::force_create(Origin::root(), 256, 1, true, 10));
+        assert_ok!(Assets::mint(Origin::signed(1), 256, 1, 10_000));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 256, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert!(TotalLiquidity::<Test>::contains_key((1u32, 256u32)));
+        assert_eq!(Cfmm::get_exchange_rate(256, 1), Ok((1_000, 2_000)));
+    });
+}
+
+#[test]
+fn migrate_asset_pair_order() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Assets::force_create(Origin::root(), 256, 1, true, 10));
+
+        // Set up a pool keyed using the version 1 (encoding) order
+        let old_pair = (256u32, 1u32);
+        let old_account = crate::pallet::get_legacy_pool_account::<Test>(old_pair);
+        assert_ok!(Assets::mint(Origin::signed(1), 256, old_account, 1_000));
+        assert_ok!(Assets::mint(Origin::signed(1), 1, old_account, 2_000));
+        TotalLiquidity::<Test>::insert(old_pair, 20_000u32);
+        Liquidity::<Test>::insert(
+            1u128,
+            old_pair,
+            LiquidityPosition {
+                amount: 20_000u32,
+                reserve_a_per_share: FixedU128::saturating_from_rational(1, 20),
+                reserve_b_per_share: FixedU128::saturating_from_rational(1, 10),
+            },
+        );
+        StorageVersion::new(1).put::<Cfmm>();
+
+        migrations::v2::migrate::<Test>();
+        assert_eq!(StorageVersion::get::<Cfmm>(), StorageVersion::new(2));
+        migrations::v3::migrate::<Test>();
+        assert_eq!(StorageVersion::get::<Cfmm>(), StorageVersion::new(3));
+        assert!(!TotalLiquidity::<Test>::contains_key(old_pair));
+        assert_eq!(TotalLiquidity::<Test>::get((1u32, 256u32)), 20_000);
+        assert_eq!(
+            Liquidity::<Test>::get(1u128, (1u32, 256u32)),
+            LiquidityPosition {
+                amount: 20_000,
+                reserve_a_per_share: FixedU128::saturating_from_rational(1, 10),
+                reserve_b_per_share: FixedU128::saturating_from_rational(1, 20),
+            }
+        );
+        assert_eq!(Cfmm::get_exchange_rate(256, 1), Ok((1_000, 2_000)));
+        assert_eq!(Assets::balance(256, old_account), 0);
+        migrations::migrate::<Test>();
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 1, 256, 20_000));
+    });
+}
+
+#[test]
+fn positions_of() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_eq!(Cfmm::positions_of(&1, 10), vec![]);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 2, 0, 3_000, 1, 0, 1_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+
+        let mut positions = Cfmm::positions_of(&1, 10);
+        positions.sort();
+        assert_eq!(positions, vec![((0, 1), 20_000, 1_000, 2_000), ((1, 2), 30_000, 1_000, 3_000)]);
+        assert_eq!(Cfmm::positions_of(&2, 10), vec![((0, 1), 10_000, 500, 1_000)]);
+        assert_eq!(Cfmm::positions_of(&1, 1).len(), 1);
+        assert_eq!(Cfmm::positions_of(&1, 0), vec![]);
+    });
+}
+
+#[test]
+fn get_pool_share() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_eq!(Cfmm::get_pool_share(&1, 0, 1), Permill::zero());
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+        assert_eq!(Cfmm::get_pool_share(&1, 0, 1), Permill::from_rational(2u32, 3u32));
+        assert_eq!(Cfmm::get_pool_share(&2, 1, 0), Permill::from_rational(1u32, 3u32));
+        assert_eq!(Cfmm::get_pool_share(&3, 0, 1), Permill::zero());
+        assert_eq!(Cfmm::get_pool_share(&1, 0, 0), Permill::zero());
+    });
+}
+
+#[test]
+fn distinct_pool_accounts() {
+    ExtBuilder::default().build().execute_with(|| {
+        // These pairs only differ in the second asset, which doesn't fit in a sub-account of
+        // the pallet ID
+        let account_0_1 = crate::pallet::get_pool_account::<Test>((0, 1));
+        let account_0_2 = crate::pallet::get_pool_account::<Test>((0, 2));
+        let account_1_2 = crate::pallet::get_pool_account::<Test>((1, 2));
+        assert_ne!(account_0_1, account_0_2);
+        assert_ne!(account_0_1, account_1_2);
+        assert_ne!(account_0_2, account_1_2);
+
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 500, 2, 0, 3_000, 0, false));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_000, 2_000)));
+        assert_eq!(Cfmm::get_exchange_rate(0, 2), Ok((500, 3_000)));
+    });
+}
+
+#[test]
+fn native_asset_pool() {
+    ExtBuilder::default().build().execute_with(|| {
+        let native = CfmmNativeAssetId::get();
+        assert_ok!(Balances::set_balance(Origin::root(), 1, 10_000, 0));
+        assert_ok!(Balances::set_balance(Origin::root(), 2, 10_000, 0));
+
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, native, 0, 2_000, 0, false));
+        assert_eq!(Balances::free_balance(1), 8_000);
+        assert_eq!(Cfmm::get_exchange_rate(0, native), Ok((1_000, 2_000)));
+
+        assert_ok!(Cfmm::exchange(Origin::signed(2), native, 100, 0, 0));
+        assert_eq!(Balances::free_balance(2), 9_900);
+        assert_eq!(Assets::balance(0, 2), 10_043);
+        assert_eq!(Cfmm::get_exchange_rate(0, native), Ok((957, 2_100)));
+
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 43, native, 0));
+        assert_eq!(Assets::balance(0, 2), 10_000);
+        assert_eq!(Balances::free_balance(2), 9_980);
+
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, native, 20_000));
+        assert_eq!(Cfmm::get_exchange_rate(0, native), Err(Error::<Test>::NoLiquidity.into()));
+        assert_eq!(Balances::free_balance(1), 10_020);
+    });
+}
+
+#[test]
+fn pay_fees_via_pool() {
+    type FeePayment = <Test as pallet_transaction_payment::Config>::OnChargeTransaction;
+
+    ExtBuilder::default().build().execute_with(|| {
+        let native = CfmmNativeAssetId::get();
+        assert_ok!(Balances::set_balance(Origin::root(), 1, 10_000, 0));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, native, 0, 2_000, 0, false));
+
+        let call = Call::System(frame_system::Call::remark { remark: vec![] });
+        let info = DispatchInfo::default();
+
+        // No liquidity pool for asset 2
+        FeeAsset::set(Some(2));
+        assert!(<FeePayment as OnChargeTransaction<Test>>::withdraw_fee(&2, &call, &info, 100, 0)
+            .is_err());
+
+        // Account 2 has no native balance, so the minimum balance is acquired in addition to the
+        // fee
+        FeeAsset::set(Some(0));
+        assert_eq!(Cfmm::get_source_amount(0, native, 101), Ok(60));
+        let liquidity_info =
+            <FeePayment as OnChargeTransaction<Test>>::withdraw_fee(&2, &call, &info, 100, 0)
+                .unwrap();
+        assert_eq!(Assets::balance(0, 2), 9_940);
+        assert_eq!(Balances::free_balance(2), 2);
+        assert_eq!(Cfmm::get_exchange_rate(0, native), Ok((1_060, 1_898)));
+
+        // Refunds are paid in the native currency
+        assert_ok!(<FeePayment as OnChargeTransaction<Test>>::correct_and_deposit_fee(
+            &2,
+            &info,
+            &PostDispatchInfo::default(),
+            80,
+            0,
+            liquidity_info,
+        ));
+        assert_eq!(Balances::free_balance(2), 22);
+    });
+}
+
+#[test]
+fn try_state() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::do_try_state());
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        assert_ok!(Cfmm::do_try_state());
+
+        TotalLiquidity::<Test>::insert((0u32, 1u32), 30_001u32);
+        assert_eq!(Cfmm::do_try_state(), Err("Liquidity does not sum to TotalLiquidity"));
+        TotalLiquidity::<Test>::remove((0u32, 1u32));
+        assert_eq!(
+            Cfmm::do_try_state(),
+            Err("Liquidity held for asset pair without TotalLiquidity")
+        );
+        TotalLiquidity::<Test>::insert((0u32, 1u32), 30_000u32);
+        assert_ok!(Cfmm::do_try_state());
+
+        // The pool account balances must cover the reserves, and must not have been reaped
+        let pool_account = crate::pallet::get_pool_account::<Test>((0, 1));
+        assert_ok!(Assets::mint(Origin::signed(1), 0, pool_account, 5));
+        assert_ok!(Cfmm::do_try_state());
+        assert_ok!(Assets::burn(Origin::signed(1), 0, pool_account, 10));
+        assert_eq!(Cfmm::do_try_state(), Err("Reserves exceed the balances of the pool account"));
+        assert_ok!(Assets::burn(Origin::signed(1), 0, pool_account, 2_000));
+        assert_eq!(Cfmm::do_try_state(), Err("Pool account balance is below the minimum balance"));
+        assert_ok!(Assets::mint(Origin::signed(1), 0, pool_account, 1_600));
+        assert_ok!(Cfmm::do_try_state());
+
+        TotalLiquidity::<Test>::insert((0u32, 2u32), 10_000u32);
+        Liquidity::<Test>::insert(
+            1u128,
+            (0u32, 2u32),
+            LiquidityPosition { amount: 10_000u32, ..Default::default() },
+        );
+        assert_eq!(
+            Cfmm::do_try_state(),
+            Err("Liquidity pool is empty despite non-zero TotalLiquidity")
+        );
+
+        // The invariants are checked again at the end of the test with the try-runtime feature
+        TotalLiquidity::<Test>::remove((0u32, 2u32));
+        Liquidity::<Test>::remove(1u128, (0u32, 2u32));
+    });
+}
+
+#[test]
+fn account_liquidity_paged() {
+    ExtBuilder::default().build().execute_with(|| {
+        for asset in [3, 4] {
+            assert_ok!(Assets::force_create(Origin::root(), asset, 1, true, 10));
+            assert_ok!(Assets::mint(Origin::signed(1), asset, 1, 10_000));
+        }
+        let pairs = [(0, 1), (0, 2), (0, 3), (0, 4), (1, 2)];
+        for (asset_a, asset_b) in pairs {
+            assert_ok!(Cfmm::add_liquidity(
+                Origin::signed(1),
+                asset_a,
+                0,
+                1_000,
+                asset_b,
+                0,
+                1_000,
+                0,
+                false
+            ));
+        }
+
+        let mut positions = vec![];
+        let mut page_lens = vec![];
+        let mut start_key = None;
+        loop {
+            let (page, next_key) = Cfmm::account_liquidity_paged(&1, start_key, 2);
+            page_lens.push(page.len());
+            positions.extend(page);
+            if next_key.is_none() {
+                break
+            }
+            start_key = next_key;
+        }
+        assert_eq!(page_lens, vec![2, 2, 1]);
+        positions.sort();
+        assert_eq!(positions, pairs.map(|pair| (pair, 10_000)).to_vec());
+
+        assert_eq!(Cfmm::account_liquidity_paged(&2, None, 2), (vec![], None));
+    });
+}
+
+#[test]
+fn claim_fees() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_noop!(Cfmm::claim_fees(Origin::signed(1), 0, 1), Error::<Test>::NoFeesEarned);
+        assert_noop!(Cfmm::claim_fees(Origin::signed(2), 0, 1), Error::<Test>::NoFeesEarned);
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 20, 1, 0));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 1, 40, 0, 0));
+        assert_eq!(Cfmm::position_earnings(&1, 0, 1), Ok((2, 4)));
+        assert_ok!(Cfmm::claim_fees(Origin::signed(1), 0, 1));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::FeesClaimed {
+            who: 1,
+            asset_a: 0,
+            amount_a: 2,
+            asset_b: 1,
+            amount_b: 4,
+        }));
+        assert_eq!(Assets::balance(0, 1), 9_002);
+        assert_eq!(Assets::balance(1, 1), 8_004);
+        assert_eq!(Liquidity::<Test>::get(1u128, (0u32, 1u32)).amount, 19_956);
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_000, 2_001)));
+        assert_eq!(Cfmm::position_earnings(&1, 0, 1), Ok((0, 0)));
+    });
+}
+
+#[test]
+fn claim_fees_insufficient_pool_amount() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 100, 1, 0, 200, 0, false));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 10, 1, 0));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((110, 184)));
+        assert_noop!(
+            Cfmm::claim_fees(Origin::signed(1), 0, 1),
+            Error::<Test>::InsufficientPoolAmount
+        );
+    });
+}
+
+#[test]
+fn transfer_liquidity() {
+    ExtBuilder::default().with_pool(1, 0, 1_000, 1, 2_000).build().execute_with(|| {
+        assert_noop!(
+            Cfmm::transfer_liquidity(Origin::signed(1), 0, 1, 3, 20_001),
+            Error::<Test>::LiquidityExceedsPosition
+        );
+        // The sender would be left with less than the minimum of each asset
+        assert_noop!(
+            Cfmm::transfer_liquidity(Origin::signed(1), 0, 1, 3, 19_000),
+            Error::<Test>::InsufficientPoolAmount
+        );
+        // As would the recipient
+        assert_noop!(
+            Cfmm::transfer_liquidity(Origin::signed(1), 0, 1, 3, 1_000),
+            Error::<Test>::InsufficientPoolAmount
+        );
+
+        assert_ok!(Cfmm::transfer_liquidity(Origin::signed(1), 1, 0, 3, 5_000));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::LiquidityTransferred {
+            from: 1,
+            to: 3,
+            asset_a: 1,
+            asset_b: 0,
+            liquidity: 5_000,
+        }));
+        assert_eq!(Liquidity::<Test>::get(1u128, (0u32, 1u32)).amount, 15_000);
+        assert_eq!(Liquidity::<Test>::get(3u128, (0u32, 1u32)).amount, 5_000);
+        assert_eq!(TotalLiquidity::<Test>::get((0u32, 1u32)), 20_000);
+
+        // Once the recipient has a position, smaller amounts can be transferred to them
+        assert_ok!(Cfmm::transfer_liquidity(Origin::signed(1), 0, 1, 3, 1_000));
+
+        // Locked liquidity cannot be transferred
+        assert_ok!(Cfmm::lock_liquidity(Origin::signed(1), 0, 1, 10_000, 10));
+        assert_noop!(
+            Cfmm::transfer_liquidity(Origin::signed(1), 0, 1, 3, 5_000),
+            Error::<Test>::LiquidityTimelocked
+        );
+
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(3), 0, 1, 6_000));
+        assert_eq!(Assets::balance(0, 3), 300);
+        assert_eq!(Assets::balance(1, 3), 600);
+    });
+}
+
+#[test]
+fn transfer_liquidity_restrictions() {
+    ExtBuilder::default().with_pool(1, 0, 1_000, 1, 2_000).build().execute_with(|| {
+        assert_noop!(
+            Cfmm::transfer_liquidity(Origin::signed(1), 0, 1, 3, 0),
+            Error::<Test>::InvalidLiquidityTransfer
+        );
+        assert_noop!(
+            Cfmm::transfer_liquidity(Origin::signed(1), 0, 1, 1, 5_000),
+            Error::<Test>::InvalidLiquidityTransfer
+        );
+
+        // Transfers are subject to the minimum addition, as for the recipient they are one
+        assert_ok!(Cfmm::set_min_add_amount(
+            Origin::signed(1),
+            0,
+            1,
+            Some(MinAddAmount::Fraction(Permill::from_percent(30)))
+        ));
+        assert_noop!(
+            Cfmm::transfer_liquidity(Origin::signed(1), 0, 1, 3, 5_000),
+            Error::<Test>::AddTooSmall
+        );
+        assert_ok!(Cfmm::transfer_liquidity(Origin::signed(1), 0, 1, 3, 6_000));
+
+        // Only the sender is subject to the cooldown, so transfers cannot stop the recipient
+        // removing their liquidity
+        CfmmLiquidityCooldown::set(5);
+        assert_ok!(Cfmm::transfer_liquidity(Origin::signed(1), 0, 1, 3, 6_000));
+        assert_noop!(
+            Cfmm::transfer_liquidity(Origin::signed(1), 0, 1, 3, 6_000),
+            Error::<Test>::CooldownActive
+        );
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(3), 0, 1, 12_000));
+    });
+}
+
+#[test]
+fn lock_liquidity() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_noop!(
+            Cfmm::lock_liquidity(Origin::signed(1), 0, 1, 10_000, 1),
+            Error::<Test>::LockNotInFuture
+        );
+        assert_noop!(
+            Cfmm::lock_liquidity(Origin::signed(1), 0, 1, 20_001, 10),
+            Error::<Test>::LiquidityExceedsPosition
+        );
+        assert_ok!(Cfmm::lock_liquidity(Origin::signed(1), 0, 1, 10_000, 10));
+        assert_ok!(Cfmm::lock_liquidity(Origin::signed(1), 1, 0, 5_000, 20));
+        assert_noop!(
+            Cfmm::lock_liquidity(Origin::signed(1), 0, 1, 5_001, 20),
+            Error::<Test>::LiquidityExceedsPosition
+        );
+        assert_noop!(
+            Cfmm::lock_liquidity(Origin::signed(1), 0, 1, 1, 20),
+            Error::<Test>::TooManyLocks
+        );
+
+        assert_noop!(
+            Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 5_001),
+            Error::<Test>::LiquidityTimelocked
+        );
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 5_000));
+
+        System::set_block_number(9);
+        assert_noop!(
+            Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 1),
+            Error::<Test>::LiquidityTimelocked
+        );
+        assert_noop!(
+            Cfmm::unlock_liquidity(Origin::signed(1), 0, 1),
+            Error::<Test>::NoExpiredLocks
+        );
+
+        // The first lock has expired
+        System::set_block_number(10);
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 5_000));
+        assert_ok!(Cfmm::unlock_liquidity(Origin::signed(1), 0, 1));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::LiquidityUnlocked {
+            who: 1,
+            asset_a: 0,
+            asset_b: 1,
+            amount: 10_000,
+        }));
+        assert_noop!(
+            Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 5_001),
+            Error::<Test>::LiquidityTimelocked
+        );
+
+        // Both locks have expired
+        System::set_block_number(20);
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 10_000));
+        assert!(!LiquidityLocks::<Test>::contains_key(1u128, (0u32, 1u32)));
+    });
+}
+
+#[test]
+fn min_lock_period() {
+    ExtBuilder::default().build().execute_with(|| {
+        CfmmMinLockPeriod::set(10);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        System::set_block_number(10);
+        assert_noop!(
+            Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 1_000),
+            Error::<Test>::LiquidityLocked
+        );
+        System::set_block_number(11);
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 1_000));
+
+        // Adding more liquidity locks the whole position again
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 100, 1, 0, 200, 0, false));
+        System::set_block_number(20);
+        assert_noop!(
+            Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 1_000),
+            Error::<Test>::LiquidityLocked
+        );
+        System::set_block_number(21);
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 21_000));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Err(Error::<Test>::NoLiquidity.into()));
+    });
+}
+
+#[test]
+fn liquidity_cooldown() {
+    ExtBuilder::default().build().execute_with(|| {
+        CfmmLiquidityCooldown::set(5);
+        System::set_block_number(1);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_noop!(
+            Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 1_000),
+            Error::<Test>::CooldownActive
+        );
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 100, 1, 0, 200, 0, false),
+            Error::<Test>::CooldownActive
+        );
+        // Other accounts are unaffected
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 100, 1, 0, 200, 0, false));
+
+        System::set_block_number(5);
+        assert_noop!(
+            Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 1_000),
+            Error::<Test>::CooldownActive
+        );
+        System::set_block_number(6);
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 1_000));
+
+        // The removal starts a new cooldown
+        assert_noop!(
+            Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 1_000),
+            Error::<Test>::CooldownActive
+        );
+        System::set_block_number(11);
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 19_000));
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false),
+            Error::<Test>::CooldownActive
+        );
+    });
+}
+
+#[test]
+fn max_price_impact() {
+    ExtBuilder::default().build().execute_with(|| {
+        CfmmMaxPriceImpact::set(Some(Permill::from_percent(20)));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        // 305 received for 200 at a rate of 2 is an impact of 23.75%
+        assert_noop!(
+            Cfmm::exchange(Origin::signed(2), 0, 200, 1, 0),
+            Error::<Test>::ExcessivePriceImpact
+        );
+        // 17 received for 10 at a rate of 2 is an impact of 15%
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 10, 1, 0));
+        assert_eq!(Assets::balance(1, 2), 10_017);
+    });
+}
+
+#[test]
+fn max_trade_fraction() {
+    ExtBuilder::default().build().execute_with(|| {
+        CfmmMaxTradeFraction::set(Some(Permill::from_percent(10)));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_noop!(Cfmm::exchange(Origin::signed(2), 0, 101, 1, 0), Error::<Test>::TradeTooLarge);
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_100, 1_835)));
+        assert_noop!(Cfmm::exchange(Origin::signed(2), 1, 184, 0, 0), Error::<Test>::TradeTooLarge);
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 1, 183, 0, 0));
+    });
+}
+
+#[test]
+fn pause_pool() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 3_000, 0, false));
+        assert_noop!(Cfmm::pause_pool(Origin::signed(1), 0, 1), BadOrigin);
+        assert_noop!(Cfmm::resume_pool(Origin::root(), 0, 1), Error::<Test>::PoolNotPaused);
+        assert_ok!(Cfmm::pause_pool(Origin::root(), 1, 0));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::PoolPaused { asset_a: 1, asset_b: 0 }));
+        assert_noop!(Cfmm::pause_pool(Origin::root(), 0, 1), Error::<Test>::PoolPaused);
+
+        assert_noop!(Cfmm::exchange(Origin::signed(2), 0, 20, 1, 0), Error::<Test>::PoolPaused);
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false),
+            Error::<Test>::PoolPaused
+        );
+        // Liquidity can still be removed, and other pools are unaffected
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 10_000));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 20, 2, 0));
+
+        assert_ok!(Cfmm::resume_pool(Origin::root(), 0, 1));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::PoolResumed { asset_a: 0, asset_b: 1 }));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 20, 1, 0));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+    });
+}
+
+#[test]
+fn pool_status() {
+    ExtBuilder::default().with_pool(1, 0, 1_000, 1, 2_000).build().execute_with(|| {
+        assert_noop!(Cfmm::set_pool_status(Origin::signed(1), 0, 1, PoolStatus::Frozen), BadOrigin);
+        assert_noop!(
+            Cfmm::set_pool_status(Origin::root(), 0, 2, PoolStatus::Frozen),
+            Error::<Test>::PoolNotFound
+        );
+        assert!(!Pools::<Test>::contains_key((0u32, 2u32)));
+
+        // Each status, with whether exchanging, adding liquidity, and removing liquidity succeed
+        for (status, exchange, add, remove) in [
+            (PoolStatus::TradingDisabled, false, false, true),
+            (PoolStatus::WithdrawOnly, false, false, true),
+            (PoolStatus::Frozen, false, false, false),
+            (PoolStatus::Active, true, true, true),
+        ] {
+            assert_ok!(Cfmm::set_pool_status(Origin::root(), 1, 0, status));
+            System::assert_last_event(Event::Cfmm(CfmmEvent::PoolStatusChanged {
+                asset_a: 1,
+                asset_b: 0,
+                status,
+            }));
+            assert_eq!(Pools::<Test>::get((0u32, 1u32)).status, status);
+
+            let result = Cfmm::exchange(Origin::signed(2), 0, 20, 1, 0);
+            if exchange {
+                assert_ok!(result);
+            } else {
+                assert_eq!(result, Err(Error::<Test>::PoolPaused.into()));
+            }
+            let result = Cfmm::add_liquidity(Origin::signed(2), 0, 0, 100, 1, 0, 1_000, 0, false);
+            if add {
+                assert_ok!(result);
+            } else {
+                assert_eq!(result, Err(Error::<Test>::PoolPaused.into()));
+            }
+            let result = Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 1_000);
+            if remove {
+                assert_ok!(result);
+            } else {
+                assert_eq!(result, Err(Error::<Test>::PoolFrozen.into()));
+            }
+            let result = Cfmm::transfer_liquidity(Origin::signed(1), 0, 1, 2, 1_000);
+            if remove {
+                assert_ok!(result);
+            } else {
+                assert_eq!(result, Err(Error::<Test>::PoolFrozen.into()));
+            }
+        }
+    });
+}
+
+#[test]
+fn set_paused() {
+    let builder = ExtBuilder::default().with_pool(1, 0, 1_000, 1, 2_000);
+    builder.with_pool(1, 0, 1_000, 2, 3_000).build().execute_with(|| {
+        assert_noop!(Cfmm::set_paused(Origin::signed(1), true), BadOrigin);
+        assert_ok!(Cfmm::set_paused(Origin::root(), true));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::PalletPaused));
+
+        // All pools are affected
+        for asset in [1, 2] {
+            assert_noop!(
+                Cfmm::exchange(Origin::signed(2), 0, 20, asset, 0),
+                Error::<Test>::PalletPaused
+            );
+            assert_noop!(
+                Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, asset, 0, 1_500, 0, false),
+                Error::<Test>::PalletPaused
+            );
+        }
+        // Liquidity can still be removed
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 10_000));
+
+        assert_ok!(Cfmm::set_paused(Origin::root(), false));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::PalletUnpaused));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 20, 1, 0));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 20, 2, 0));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+    });
+}
+
+#[test]
+fn exchange_all() {
+    ExtBuilder::default().with_pool(1, 0, 1_000, 1, 2_000).build().execute_with(|| {
+        assert_ok!(Assets::mint(Origin::signed(1), 0, 3, 100));
+        assert_noop!(
+            Cfmm::exchange_all(Origin::signed(3), 0, 1, 200),
+            Error::<Test>::UnexpectedExchangeRate
+        );
+        assert_ok!(Cfmm::exchange_all(Origin::signed(3), 0, 1, 0));
+        assert_eq!(Assets::balance(0, 3), 0);
+        assert_eq!(Assets::balance(1, 3), 165);
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_100, 1_835)));
+    });
+}
+
+#[test]
+fn circuit_breaker() {
+    CfmmCircuitBreakerThreshold::set(Some(Permill::from_percent(25)));
+    ExtBuilder::default().with_pool(1, 0, 1_000, 1, 2_000).build().execute_with(|| {
+        // The price moves by ~20% in block 1 and ~18% in block 2
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        System::set_block_number(2);
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        assert_eq!(Pools::<Test>::get((0u32, 1u32)).status, PoolStatus::Active);
+
+        // Moves within a block accumulate. An exchange which would trip the breaker fails...
+        assert_noop!(
+            Cfmm::do_exchange(&2, 0, 100, 1, 0, false),
+            Error::<Test>::CircuitBreakerTripped
+        );
+        assert_noop!(
+            Cfmm::do_exchange_for_exact(&2, 0, 1, 150, Permill::one()),
+            Error::<Test>::CircuitBreakerTripped
+        );
+
+        // ...but when made directly, pauses the pool instead of being executed
+        let balances = (Assets::balance(0, 2), Assets::balance(1, 2));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::CircuitBreakerTripped {
+            asset_a: 0,
+            asset_b: 1,
+        }));
+        assert_eq!(Pools::<Test>::get((0u32, 1u32)).status, PoolStatus::TradingDisabled);
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_200, 1_697)));
+        assert_eq!((Assets::balance(0, 2), Assets::balance(1, 2)), balances);
+        assert_noop!(Cfmm::exchange(Origin::signed(2), 0, 10, 1, 0), Error::<Test>::PoolPaused);
+        assert_noop!(Cfmm::exchange(Origin::signed(2), 1, 100, 0, 0), Error::<Test>::PoolPaused);
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 10_000));
+
+        assert_ok!(Cfmm::resume_pool(Origin::root(), 0, 1));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 1, 50, 0, 0));
+        assert_eq!(Pools::<Test>::get((0u32, 1u32)).status, PoolStatus::Active);
+    });
+}
+
+#[test]
+fn set_pool_fee() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_noop!(Cfmm::set_pool_fee(Origin::signed(1), 0, 1, None), BadOrigin);
+        assert_noop!(
+            Cfmm::set_pool_fee(Origin::root(), 0, 1, Some(Permill::one())),
+            Error::<Test>::InvalidFee
+        );
+
+        // Changing the fee does not affect the value of existing liquidity
+        let positions = Cfmm::positions_of(&1, 10);
+        assert_ok!(Cfmm::set_pool_fee(Origin::root(), 1, 0, Some(Permill::zero())));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::PoolFeeChanged {
+            asset_a: 1,
+            asset_b: 0,
+            fee: Some(Permill::zero()),
+        }));
+        assert_eq!(Cfmm::positions_of(&1, 10), positions);
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_000, 2_000)));
+
+        let expect_exchange = |dest_amount, fee_amount, new_reserve_source, new_reserve_dest| {
+            assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+            System::assert_last_event(Event::Cfmm(CfmmEvent::Exchanged {
+                who: 2,
+                source_asset: 0,
+                source_amount: 100,
+                dest_asset: 1,
+                dest_amount,
+                fee_amount,
+                new_reserve_source,
+                new_reserve_dest,
+            }));
+        };
+        expect_exchange(181, 0, 1_100, 1_819);
+        // The default fee of 10%
+        assert_ok!(Cfmm::set_pool_fee(Origin::root(), 0, 1, None));
+        expect_exchange(137, 10, 1_200, 1_682);
+        assert_ok!(Cfmm::set_pool_fee(Origin::root(), 0, 1, Some(Permill::from_percent(30))));
+        expect_exchange(92, 30, 1_300, 1_590);
+
+        // Other pools still use the default fee
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 3_000, 0, false));
+        assert_eq!(Cfmm::simulate_exchange(0, 100, 2).unwrap().fee_amount, 10);
+    });
+}
+
+#[test]
+fn earnings_proportional_to_share() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 2_000, 1, 0, 4_000, 0, false));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 1, 200, 0, 0));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((3_008, 6_026)));
+        assert_eq!(Cfmm::position_earnings(&1, 0, 1), Ok((3, 7)));
+        assert_eq!(Cfmm::position_earnings(&2, 0, 1), Ok((6, 14)));
+    });
+}
+
+#[test]
+fn donate() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(Cfmm::donate(Origin::signed(2), 0, 300, 1, 600), Error::<Test>::NoLiquidity);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+        assert_ok!(Cfmm::donate(Origin::signed(2), 0, 300, 1, 600));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_800, 3_600)));
+        assert_eq!(TotalLiquidity::<Test>::get((0u32, 1u32)), 30_000);
+        assert_eq!(Liquidity::<Test>::get(2u128, (0u32, 1u32)).amount, 10_000);
+
+        // Each liquidity provider's share of the donation is proportional to their liquidity
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 20_000));
+        assert_eq!(Assets::balance(0, 1), 10_200);
+        assert_eq!(Assets::balance(1, 1), 10_400);
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(2), 0, 1, 10_000));
+        assert_eq!(Assets::balance(0, 2), 9_800);
+        assert_eq!(Assets::balance(1, 2), 9_600);
+    });
+}
+
+#[test]
+fn sync() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(Cfmm::sync(Origin::signed(3), 0, 1), Error::<Test>::NoLiquidity);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+
+        // Assets transferred directly to the pool account don't affect the exchange rate, even
+        // once the pool is synced
+        let pool_account = crate::pallet::get_pool_account::<Test>((0, 1));
+        assert_ok!(Assets::transfer(Origin::signed(2), 0, pool_account, 100));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_000, 2_000)));
+        assert_eq!(Cfmm::simulate_exchange(0, 100, 1).map(|s| s.dest_amount), Ok(165));
+        assert_ok!(Cfmm::sync(Origin::signed(3), 1, 0));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::Synced {
+            asset_a: 1,
+            reserve_a: 2_000,
+            asset_b: 0,
+            reserve_b: 1_000,
+        }));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_000, 2_000)));
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 20_000));
+        assert_eq!(Assets::balance(0, 1), 10_000);
+    });
+}
+
+#[test]
+fn sync_after_balance_reduction() {
+    ExtBuilder::default().with_pool(1, 0, 1_000, 1, 2_000).build().execute_with(|| {
+        // Account 1 is the asset admin, so can burn directly from the pool account
+        let pool_account = crate::pallet::get_pool_account::<Test>((0, 1));
+        assert_ok!(Assets::burn(Origin::signed(1), 0, pool_account, 100));
+
+        // Exchanges are still priced against the reserves, not the reduced balance
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_000, 2_000)));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 165));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_100, 1_835)));
+        assert_eq!(Assets::balance(0, pool_account), 1_000);
+
+        // Syncing lowers the reserve to the balance
+        assert_ok!(Cfmm::sync(Origin::signed(3), 0, 1));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_000, 1_835)));
+
+        // But never raises it: a direct transfer into the pool account is ignored
+        assert_ok!(Assets::transfer(Origin::signed(2), 0, pool_account, 500));
+        assert_ok!(Assets::transfer(Origin::signed(2), 1, pool_account, 500));
+        assert_ok!(Cfmm::sync(Origin::signed(3), 0, 1));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_000, 1_835)));
+    });
+}
+
+#[test]
+fn check_swap_deadline() {
+    new_test_ext().execute_with(|| {
+        let exchange = Call::Cfmm(crate::Call::exchange {
+            source_asset: 0,
+            source_amount: 100,
+            dest_asset: 1,
+            min_dest_amount: 0,
+        });
+        let remark = Call::System(frame_system::Call::remark { remark: vec![] });
+        let info = DispatchInfo::default();
+        let check = |deadline, call: &Call| {
+            CheckSwapDeadline::<Test>::new(deadline).validate(&1, call, &info, 0)
+        };
+
+        System::set_block_number(5);
+        assert_eq!(check(Some(7), &exchange).map(|v| v.longevity), Ok(3));
+        assert!(check(Some(5), &exchange).is_ok());
+        assert_eq!(check(Some(4), &exchange), Err(InvalidTransaction::Stale.into()));
+        assert_eq!(
+            CheckSwapDeadline::<Test>::new(Some(4)).pre_dispatch(&1, &exchange, &info, 0),
+            Err(InvalidTransaction::Stale.into())
+        );
+
+        // Transactions without a deadline, or not calling the CFMM pallet, are unaffected
+        assert!(check(None, &exchange).is_ok());
+        assert!(check(Some(4), &remark).is_ok());
+    });
+}
+
+#[test]
+#[should_panic(expected = "ExchangeFee must be less than 100%")]
+fn integrity_test_full_exchange_fee() {
+    CfmmExchangeFee::set(Permill::one());
+    new_test_ext().execute_with(|| <Cfmm as Hooks<u64>>::integrity_test());
+}
+
+#[test]
+fn integrity_test_account_id_width() {
+    use crate::pallet::is_account_id_wide_enough;
+
+    // The "modl" prefix, the pallet ID, and two u32 asset IDs
+    let sub_account_len = 4 + 8 + 2 * 4;
+    // Pool accounts are derived by hashing for both of these
+    assert!(is_account_id_wide_enough::<u128>(sub_account_len));
+    assert!(is_account_id_wide_enough::<[u8; 32]>(sub_account_len));
+    // Collisions would be easy to find with 64-bit account IDs, unless the sub-accounts fit
+    assert!(!is_account_id_wide_enough::<u64>(sub_account_len));
+    assert!(is_account_id_wide_enough::<u64>(8));
+
+    new_test_ext().execute_with(|| <Cfmm as Hooks<u64>>::integrity_test());
+}
+
+#[test]
+fn blacklist_asset() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_noop!(Cfmm::blacklist_asset(Origin::signed(1), 1), BadOrigin);
+        assert_ok!(Cfmm::blacklist_asset(Origin::root(), 1));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::AssetBlacklisted { asset: 1 }));
+        assert_noop!(Cfmm::blacklist_asset(Origin::root(), 1), Error::<Test>::AssetBlacklisted);
+
+        // Pools containing the asset cannot be added to, created, or used for exchanges
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false),
+            Error::<Test>::AssetBlacklisted
+        );
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(2), 1, 0, 1_000, 2, 0, 1_000, 0, false),
+            Error::<Test>::AssetBlacklisted
+        );
+        assert_noop!(
+            Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0),
+            Error::<Test>::AssetBlacklisted
+        );
+        assert_noop!(
+            Cfmm::exchange(Origin::signed(2), 1, 100, 0, 0),
+            Error::<Test>::AssetBlacklisted
+        );
+
+        // Other pools are unaffected
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 1_000, 2, 0, 1_000, 0, false));
+        assert_ok!(Cfmm::exchange(Origin::signed(1), 0, 100, 2, 0));
+
+        // Liquidity can still be removed
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 10_000));
+
+        assert_ok!(Cfmm::unblacklist_asset(Origin::root(), 1));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::AssetUnblacklisted { asset: 1 }));
+        assert_noop!(
+            Cfmm::unblacklist_asset(Origin::root(), 1),
+            Error::<Test>::AssetNotBlacklisted
+        );
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+    });
+}
+
+#[test]
+fn swap_origin() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Assets::mint(Origin::signed(1), 0, 3, 1_000));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_noop!(Cfmm::exchange(Origin::none(), 0, 100, 1, 0), BadOrigin);
+
+        // The mock maps the root origin to account 3
+        assert_ok!(Cfmm::exchange(Origin::root(), 0, 100, 1, 0));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::Exchanged {
+            who: 3,
+            source_asset: 0,
+            source_amount: 100,
+            dest_asset: 1,
+            dest_amount: 165,
+            fee_amount: 10,
+            new_reserve_source: 1_100,
+            new_reserve_dest: 1_835,
+        }));
+        assert_eq!(Assets::balance(0, 3), 900);
+        assert_eq!(Assets::balance(1, 3), 165);
+    });
+}
+
+#[test]
+fn do_add_remove_liquidity() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_eq!(
+            Cfmm::do_add_liquidity(&1, 0, 0, 1_000, 1, 0, 2_000, 0, false),
+            Ok((1_000, 2_000, 20_000))
+        );
+        assert_eq!(
+            Cfmm::do_add_liquidity(&2, 0, 0, 500, 1, 0, 2_000, 0, false),
+            Ok((500, 1_000, 10_000))
+        );
+        assert_eq!(Cfmm::do_remove_liquidity(&2, 1, 0, 4_000), Ok((400, 200)));
+
+        // Failures leave no trace, even outside of an extrinsic
+        assert_noop!(
+            Cfmm::do_add_liquidity(&2, 0, 0, 500, 1, 0, 1_000, 100_000, false),
+            Error::<Test>::TooLittleLiquidityMinted
+        );
+        assert_noop!(
+            Cfmm::do_remove_liquidity(&2, 0, 1, 5_900),
+            Error::<Test>::InsufficientPoolAmount
+        );
+        assert_eq!(Cfmm::do_remove_liquidity(&2, 0, 1, 6_000), Ok((300, 600)));
+    });
+}
+
+#[test]
+fn whitelist_only() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_noop!(Cfmm::set_whitelist_only(Origin::signed(1), true), BadOrigin);
+        assert_ok!(Cfmm::set_whitelist_only(Origin::root(), true));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::WhitelistOnlyChanged { enabled: true }));
+
+        assert_noop!(Cfmm::whitelist_asset(Origin::signed(1), 0), BadOrigin);
+        assert_ok!(Cfmm::whitelist_asset(Origin::root(), 0));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::AssetWhitelisted { asset: 0 }));
+        assert_noop!(
+            Cfmm::whitelist_asset(Origin::root(), 0),
+            Error::<Test>::AssetAlreadyWhitelisted
+        );
+
+        // Only one of the assets is whitelisted
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 1_000, 0, false),
+            Error::<Test>::AssetNotWhitelisted
+        );
+        assert_ok!(Cfmm::whitelist_asset(Origin::root(), 2));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 1_000, 0, false));
+
+        // Existing pools keep working, even if an asset is delisted
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+        assert_ok!(Cfmm::unwhitelist_asset(Origin::root(), 2));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::AssetUnwhitelisted { asset: 2 }));
+        assert_noop!(
+            Cfmm::unwhitelist_asset(Origin::root(), 2),
+            Error::<Test>::AssetNotWhitelisted
+        );
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 2, 0));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 2, 0, 1_000, 0, false));
+
+        // But once emptied, the pool cannot be re-created
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 2, 10_000));
+        assert_ok!(Cfmm::remove_liquidity(
+            Origin::signed(2),
+            0,
+            2,
+            Liquidity::<Test>::get(2u128, (0u32, 2u32)).amount
+        ));
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 1_000, 0, false),
+            Error::<Test>::AssetNotWhitelisted
+        );
+
+        // Disabling the mode allows any pool to be created
+        assert_ok!(Cfmm::set_whitelist_only(Origin::root(), false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 1_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 1, 0, 1_000, 2, 0, 1_000, 0, false));
+    });
+}
+
+#[test]
+fn bootstrap_period() {
+    ExtBuilder::default().build().execute_with(|| {
+        System::set_block_number(5);
+        let bootstrap = |until, restrict_exchanges| {
+            Some(Bootstrap { until, providers: vec![1].try_into().unwrap(), restrict_exchanges })
+        };
+        assert_noop!(
+            Cfmm::register_pool(Origin::signed(1), 0, 1, bootstrap(5, false)),
+            Error::<Test>::InvalidBootstrap
+        );
+        assert_ok!(Cfmm::register_pool(Origin::signed(1), 0, 1, bootstrap(10, false)));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::PoolRegistered {
+            asset_a: 0,
+            asset_b: 1,
+            bootstrap_until: Some(10),
+        }));
+        assert_ok!(Cfmm::register_pool(Origin::signed(1), 1, 2, bootstrap(10, true)));
+
+        // Only the provider may add liquidity, but anyone may trade unless exchanges are
+        // restricted too
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(2), 0, 0, 1_000, 1, 0, 2_000, 0, false),
+            Error::<Test>::BootstrapRestricted
+        );
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 1, 0, 1_000, 2, 0, 1_000, 0, false));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        assert_noop!(
+            Cfmm::exchange(Origin::signed(2), 1, 100, 2, 0),
+            Error::<Test>::BootstrapRestricted
+        );
+        assert_ok!(Cfmm::exchange(Origin::signed(1), 1, 100, 2, 0));
+
+        // The restrictions apply up to, but not including, the end block
+        System::set_block_number(9);
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false),
+            Error::<Test>::BootstrapRestricted
+        );
+        assert_noop!(
+            Cfmm::exchange(Origin::signed(2), 2, 100, 1, 0),
+            Error::<Test>::BootstrapRestricted
+        );
+        System::set_block_number(10);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+        System::assert_has_event(Event::Cfmm(CfmmEvent::BootstrapEnded { asset_a: 0, asset_b: 1 }));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 2, 100, 1, 0));
+        assert!(!Bootstraps::<Test>::contains_key((0, 1)));
+        assert!(!Bootstraps::<Test>::contains_key((1, 2)));
+
+        // Ended bootstrap periods of unused pools can be removed by anyone
+        assert_ok!(Cfmm::register_pool(Origin::signed(1), 0, 2, bootstrap(20, false)));
+        assert_noop!(Cfmm::end_bootstrap(Origin::signed(2), 0, 2), Error::<Test>::BootstrapActive);
+        assert_noop!(Cfmm::end_bootstrap(Origin::signed(2), 0, 1), Error::<Test>::NoBootstrap);
+        System::set_block_number(20);
+        assert_ok!(Cfmm::end_bootstrap(Origin::signed(2), 2, 0));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::BootstrapEnded {
+            asset_a: 2,
+            asset_b: 0,
+        }));
+        assert!(!Bootstraps::<Test>::contains_key((0, 2)));
+    });
+}
+
+#[test]
+fn pool_creation_restricted() {
+    ExtBuilder::default().build().execute_with(|| {
+        // Unrestricted by default
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+
+        CfmmRestrictPoolCreation::set(true);
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(2), 1, 0, 1_000, 2, 0, 1_000, 0, false),
+            Error::<Test>::PoolNotRegistered
+        );
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 1, 0, 1_000, 2, 0, 1_000, 0, false));
+
+        assert_noop!(Cfmm::register_pool(Origin::signed(2), 2, 0, None), BadOrigin);
+        assert_ok!(Cfmm::register_pool(Origin::signed(1), 2, 0, None));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::PoolRegistered {
+            asset_a: 2,
+            asset_b: 0,
+            bootstrap_until: None,
+        }));
+        assert_noop!(
+            Cfmm::register_pool(Origin::signed(1), 0, 2, None),
+            Error::<Test>::PoolAlreadyRegistered
+        );
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 1_000, 2, 0, 3_000, 0, false));
+
+        // Existing pools are unaffected
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+
+        // Weighted pools cannot be registered, so only PoolCreationOrigin may create them
+        let assets =
+            vec![(0, Perbill::from_percent(80), 1_000), (1, Perbill::from_percent(20), 2_000)];
+        let lbp_assets = vec![
+            (0, Perbill::from_percent(90), Perbill::from_percent(10), 1_000),
+            (1, Perbill::from_percent(10), Perbill::from_percent(90), 2_000),
+        ];
+        assert_noop!(
+            Cfmm::create_weighted_pool(Origin::signed(2), assets.clone()),
+            Error::<Test>::PoolNotRegistered
+        );
+        assert_noop!(
+            Cfmm::create_lbp(Origin::signed(2), lbp_assets.clone(), 10, 20),
+            Error::<Test>::PoolNotRegistered
+        );
+        assert_ok!(Cfmm::create_weighted_pool(Origin::signed(1), assets));
+        assert_ok!(Cfmm::create_lbp(Origin::signed(1), lbp_assets, 10, 20));
+    });
+}
+
+#[test]
+fn min_add_amount() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(Cfmm::set_min_add_amount(Origin::signed(2), 0, 1, None), BadOrigin);
+        assert_ok!(Cfmm::set_min_add_amount(
+            Origin::signed(1),
+            0,
+            1,
+            Some(MinAddAmount::Absolute(2_000, 4_000))
+        ));
+
+        // Creating the pool is exempt
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(2), 0, 0, 1_000, 1, 0, 2_000, 0, false),
+            Error::<Test>::AddTooSmall
+        );
+
+        assert_ok!(Cfmm::set_min_add_amount(
+            Origin::signed(1),
+            0,
+            1,
+            Some(MinAddAmount::Fraction(Permill::from_percent(1)))
+        ));
+        // Subsequent additions must be at least 1% of the reserves
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(2), 0, 0, 5, 1, 0, 10, 0, false),
+            Error::<Test>::AddTooSmall
+        );
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 10, 1, 0, 20, 0, false));
+
+        // Absolute amounts are given in the order of the assets passed
+        assert_ok!(Cfmm::set_min_add_amount(
+            Origin::signed(1),
+            1,
+            0,
+            Some(MinAddAmount::Absolute(100, 50))
+        ));
+        assert_eq!(MinAddAmounts::<Test>::get((0u32, 1u32)), Some(MinAddAmount::Absolute(50, 100)));
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(2), 1, 0, 80, 0, 0, 40, 0, false),
+            Error::<Test>::AddTooSmall
+        );
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 1, 0, 100, 0, 0, 50, 0, false));
+
+        assert_ok!(Cfmm::set_min_add_amount(Origin::signed(1), 0, 1, None));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::MinAddAmountChanged {
+            asset_a: 0,
+            asset_b: 1,
+            min_add_amount: None,
+        }));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 5, 1, 0, 10, 0, false));
+    });
+}
+
+#[test]
+fn initial_liquidity_per_asset_unit_override() {
+    ExtBuilder::default().build().execute_with(|| {
+        // Without an override, InitialLiquidityPerAssetUnit (10) is used
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_eq!(Liquidity::<Test>::get(1, (0u32, 1u32)).amount, 20_000);
+
+        assert_noop!(
+            Cfmm::set_initial_liquidity_per_asset_unit(Origin::signed(2), 0, 2, Some(100)),
+            BadOrigin
+        );
+        assert_noop!(
+            Cfmm::set_initial_liquidity_per_asset_unit(Origin::signed(1), 0, 2, Some(0)),
+            Error::<Test>::InvalidInitialLiquidityPerAssetUnit
+        );
+        assert_ok!(Cfmm::set_initial_liquidity_per_asset_unit(Origin::signed(1), 0, 2, Some(100)));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::InitialLiquidityPerAssetUnitChanged {
+            asset_a: 0,
+            asset_b: 2,
+            per_asset_unit: Some(100),
+        }));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 2_000, 0, false));
+        assert_eq!(Liquidity::<Test>::get(1, (0u32, 2u32)).amount, 200_000);
+
+        // The creator of a pool may not set the override, as it would have no effect
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 1, 0, 1_000, 2, 0, 2_000, 0, false));
+        assert_noop!(
+            Cfmm::set_initial_liquidity_per_asset_unit(Origin::signed(2), 2, 1, Some(1)),
+            BadOrigin
+        );
+        assert_ok!(Cfmm::set_initial_liquidity_per_asset_unit(Origin::signed(1), 2, 1, Some(1)));
+        assert_eq!(InitialLiquidityPerAssetUnitOverrides::<Test>::get((1u32, 2u32)), Some(1));
+    });
+}
+
+#[test]
+fn offchain_price_reporting() {
+    let builder = ExtBuilder::default().with_pool(1, 0, 1_000, 1, 2_000);
+    let builder = builder.with_pool(1, 0, 1_000, 2, 3_000).with_pool(1, 1, 2_000, 2, 3_000);
+    let mut ext = builder.build();
+    let (pool, pool_state) = TestTransactionPoolExt::new();
+    ext.register_extension(TransactionPoolExt::new(pool));
+    ext.execute_with(|| {
+        let take_report = || {
+            let tx = pool_state.write().transactions.pop()?;
+            let tx =
+                frame_system::mocking::MockUncheckedExtrinsic::<Test>::decode(&mut &*tx).unwrap();
+            assert!(tx.signature.is_none());
+            match tx.function {
+                Call::Cfmm(call @ crate::Call::report_prices { .. }) => Some(call),
+                _ => panic!("Unexpected call"),
+            }
+        };
+        let report = |call: crate::Call<Test>| match call {
+            crate::Call::report_prices { block_number, prices } =>
+                Cfmm::report_prices(Origin::none(), block_number, prices),
+            _ => unreachable!(),
+        };
+
+        // Reporting is opt-in
+        Cfmm::offchain_worker(1);
+        assert!(take_report().is_none());
+
+        CfmmOffchainPriceReporting::set(true);
+        Cfmm::offchain_worker(1);
+        let call = take_report().unwrap();
+        assert_ok!(Cfmm::validate_unsigned(TransactionSource::Local, &call));
+        assert_noop!(Cfmm::report_prices(Origin::signed(1), 1, Default::default()), BadOrigin);
+        assert_ok!(report(call.clone()));
+        assert_eq!(OraclePrices::<Test>::iter().count(), 2);
+        assert_eq!(
+            Cfmm::validate_unsigned(TransactionSource::Local, &call),
+            Err(InvalidTransaction::Stale.into())
+        );
+
+        // The remaining pool is reported in the next block
+        System::set_block_number(2);
+        Cfmm::offchain_worker(2);
+        assert_ok!(report(take_report().unwrap()));
+        assert_eq!(OraclePrices::<Test>::iter().count(), 3);
+        let price = OraclePrices::<Test>::get((1u32, 2u32)).unwrap();
+        assert_eq!(price.price_a, FixedU128::saturating_from_rational(3, 2));
+        assert_eq!(price.price_b, FixedU128::saturating_from_rational(2, 3));
+
+        // Reports must cover the pools following the cursor, match the current spot prices, and
+        // not be for future blocks
+        System::set_block_number(3);
+        Cfmm::offchain_worker(3);
+        let prices = match take_report().unwrap() {
+            crate::Call::report_prices { prices, .. } => prices.into_inner(),
+            _ => unreachable!(),
+        };
+        assert_eq!(prices.len(), 2);
+        let call = |block_number, prices: Vec<_>| crate::Call::<Test>::report_prices {
+            block_number,
+            prices: prices.try_into().unwrap(),
+        };
+        let mut wrong_price = prices.clone();
+        wrong_price[0].1 = FixedU128::one();
+        assert_eq!(
+            Cfmm::validate_unsigned(TransactionSource::External, &call(3, wrong_price)),
+            Err(InvalidTransaction::Stale.into())
+        );
+        // Skipping a pool would let anyone steer the cursor
+        assert_eq!(
+            Cfmm::validate_unsigned(TransactionSource::External, &call(3, vec![prices[1]])),
+            Err(InvalidTransaction::Stale.into())
+        );
+        assert_eq!(
+            Cfmm::validate_unsigned(TransactionSource::External, &call(3, vec![prices[0]])),
+            Err(InvalidTransaction::Stale.into())
+        );
+        assert_eq!(
+            Cfmm::validate_unsigned(TransactionSource::External, &call(4, prices.clone())),
+            Err(InvalidTransaction::Future.into())
+        );
+        assert_ok!(Cfmm::validate_unsigned(TransactionSource::External, &call(3, prices.clone())));
+        let (asset_a, asset_b) = prices[0].0;
+        assert_ok!(Cfmm::exchange(Origin::signed(2), asset_a, 100, asset_b, 0));
+        assert_eq!(
+            Cfmm::validate_unsigned(TransactionSource::External, &call(3, prices)),
+            Err(InvalidTransaction::Stale.into())
+        );
+    });
+}
+
+#[test]
+fn oracle_advances_every_block() {
+    ExtBuilder::default().build().execute_with(|| {
+        System::set_block_number(1);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        let expected = |blocks: u128, last_updated: u64| CumulativePrices {
+            price_a: FixedU128::saturating_from_integer(2 * blocks),
+            price_b: FixedU128::saturating_from_rational(blocks, 2u128),
+            last_updated,
+        };
+        assert_eq!(PriceCumulative::<Test>::get((0u32, 1u32)), expected(0, 1));
+
+        // The cumulative prices advance with no exchanges
+        for n in 2..=4 {
+            System::set_block_number(n);
+            Cfmm::on_initialize(n);
+            assert_eq!(PriceCumulative::<Test>::get((0u32, 1u32)), expected((n - 1).into(), n));
+        }
+
+        // Only MaxOracleUpdatesPerBlock pools are updated each block, in turn
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 2, 0, 1_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 1, 0, 1_000, 2, 0, 1_000, 0, false));
+        let updated_at = |n| {
+            PriceCumulative::<Test>::iter_values()
+                .filter(|prices| prices.last_updated == n)
+                .count()
+        };
+        System::set_block_number(5);
+        Cfmm::on_initialize(5);
+        assert_eq!(updated_at(5), 2);
+        System::set_block_number(6);
+        Cfmm::on_initialize(6);
+        assert_eq!(updated_at(6), 1);
+        assert_eq!(updated_at(4), 0);
+        let prices = PriceCumulative::<Test>::get((0u32, 1u32));
+        assert_eq!(prices, expected((prices.last_updated - 1).into(), prices.last_updated));
+    });
+}
+
+#[test]
+fn consult() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(Cfmm::consult(0, 1, 100, 0), Error::<Test>::NoLiquidity);
+        System::set_block_number(1);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        System::set_block_number(11);
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_100, 1_835)));
+        System::set_block_number(21);
+
+        // The spot price has dropped to 1.668, but the average over the last 20 blocks is only
+        // 1.834
+        assert_eq!(Cfmm::consult(0, 1, 100, 0), Ok(166));
+        assert_eq!(Cfmm::consult(0, 1, 100, 10), Ok(166));
+        assert_eq!(Cfmm::consult(0, 1, 100, 15), Ok(177));
+        assert_eq!(Cfmm::consult(0, 1, 100, 20), Ok(183));
+        assert_eq!(Cfmm::consult(1, 0, 100, 20), Ok(54));
+        assert_noop!(Cfmm::consult(0, 1, 100, 21), Error::<Test>::InsufficientHistory);
+        assert_noop!(Cfmm::consult(0, 1, 100, 100), Error::<Test>::InsufficientHistory);
+
+        // Updates in on_initialize do not affect the result
+        Cfmm::on_initialize(21);
+        assert_eq!(Cfmm::consult(0, 1, 100, 20), Ok(183));
+    });
+}
+
+#[test]
+fn twap() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        let snapshot = Cfmm::price_snapshot(0, 1).unwrap();
+        let reversed_snapshot = Cfmm::price_snapshot(1, 0).unwrap();
+        assert_eq!(snapshot.last_updated, 1);
+        assert_noop!(Cfmm::twap(0, 1, snapshot), Error::<Test>::InsufficientHistory);
+
+        // The pool is untouched for 100 blocks, so the cumulative prices are extrapolated
+        System::set_block_number(101);
+        assert_eq!(Cfmm::twap(0, 1, snapshot), Ok(FixedU128::saturating_from_integer(2)));
+        assert_eq!(
+            Cfmm::twap(1, 0, reversed_snapshot),
+            Ok(FixedU128::saturating_from_rational(1, 2))
+        );
+
+        // The price drops to 1835/1100 for the next 50 blocks, so the average over all 150 blocks
+        // is (2 * 100 + 1.668181818181818181 * 50) / 150
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_100, 1_835)));
+        System::set_block_number(151);
+        assert_eq!(
+            Cfmm::twap(0, 1, snapshot),
+            Ok(FixedU128::from_inner(1_889_393_939_393_939_393))
+        );
+
+        // Snapshots can be taken at any time, independent of the retained observations
+        let snapshot = Cfmm::price_snapshot(0, 1).unwrap();
+        System::set_block_number(1_151);
+        assert_eq!(
+            Cfmm::twap(0, 1, snapshot),
+            Ok(FixedU128::from_inner(1_668_181_818_181_818_181))
+        );
+    });
+}
+
+#[test]
+fn get_exchange_rate_errors() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_eq!(Cfmm::get_exchange_rate(0, 0), Err(Error::<Test>::AssetsIdentical.into()));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Err(Error::<Test>::PoolNotFound.into()));
+
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 20_000));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Err(Error::<Test>::NoLiquidity.into()));
+        #[allow(deprecated)]
+        let reserves = Cfmm::get_exchange_rate_or_zero(0, 1);
+        assert_eq!(reserves, (0, 0));
+
+        // Once cleaned up, the pool is as if it never existed
+        assert_ok!(Cfmm::cleanup_pool(Origin::signed(2), 0, 1));
+        assert_eq!(Cfmm::get_exchange_rate(1, 0), Err(Error::<Test>::PoolNotFound.into()));
+    });
+}
+
+#[test]
+fn opening_exchange_rate() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+
+        // Untouched in this block, so the live amounts are returned
+        System::set_block_number(2);
+        assert_eq!(Cfmm::opening_exchange_rate(0, 1), (1_000, 2_000));
+
+        // A giant exchange does not move the opening rate
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 9_000, 1, 0));
+        assert_ne!(Cfmm::get_exchange_rate(0, 1), Ok((1_000, 2_000)));
+        assert_eq!(Cfmm::opening_exchange_rate(0, 1), (1_000, 2_000));
+        assert_eq!(Cfmm::opening_exchange_rate(1, 0), (2_000, 1_000));
+
+        System::set_block_number(3);
+        assert_eq!(Ok(Cfmm::opening_exchange_rate(0, 1)), Cfmm::get_exchange_rate(0, 1));
+    });
+}
+
+#[test]
+fn cumulative_volume() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 5_000, 1, 0, 10_000, 0, false));
+        assert_eq!(Cfmm::get_volume(0, 1), (0, 0));
+
+        let balances = || (Assets::balance(0, 2), Assets::balance(1, 2));
+        let mut expected = (0, 0);
+        for (source, amount, dest) in [(0, 100, 1), (1, 200, 0), (0, 50, 1)] {
+            let before = balances();
+            assert_ok!(Cfmm::exchange(Origin::signed(2), source, amount, dest, 0));
+            let after = balances();
+            // Both the amount paid in and the amount paid out count
+            let diff = |before: AssetBalance, after| max(before, after) - min(before, after);
+            expected.0 += diff(before.0, after.0);
+            expected.1 += diff(before.1, after.1);
+        }
+        assert_eq!(Cfmm::get_volume(0, 1), expected);
+        assert_eq!(Cfmm::get_volume(1, 0), (expected.1, expected.0));
+        assert_eq!(CumulativeVolume::<Test>::get((0u32, 1u32)), expected);
+    });
+}
+
+#[test]
+fn estimate_apr() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 5_000, 1, 0, 10_000, 0, false));
+        // Before the window
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 1_000, 1, 0));
+        System::set_block_number(10);
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((6_100, 8_350)));
+
+        // 100 of asset 0 in and 125 of asset 1 out over 1,000,000 blocks of 6 seconds, with a fee
+        // of 10%: (100 / 6,100 + 125 / 8,350) * 10% / 4 * 5.256 ~= 0.412%
+        System::set_block_number(1_000_001);
+        let apr = Cfmm::estimate_apr(0, 1, 1_000_000).unwrap();
+        assert!(apr > Permill::from_parts(4_000) && apr < Permill::from_parts(4_250), "{:?}", apr);
+        assert_eq!(Cfmm::estimate_apr(1, 0, 1_000_000), Some(apr));
+        // No exchanges in the window
+        assert_eq!(Cfmm::estimate_apr(0, 1, 100), Some(Permill::zero()));
+        // Saturates over a short window
+        System::set_block_number(11);
+        assert_eq!(Cfmm::estimate_apr(0, 1, 2), Some(Permill::one()));
+
+        assert_eq!(Cfmm::estimate_apr(0, 1, 0), None);
+        assert_eq!(Cfmm::estimate_apr(0, 1, 12), None);
+        assert_eq!(Cfmm::estimate_apr(0, 2, 2), None);
+
+        // Once entries have been dropped from the history, windows starting before the oldest
+        // cannot be estimated
+        for block in 12..14 {
+            System::set_block_number(block);
+            assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        }
+        assert!(Cfmm::estimate_apr(0, 1, 3).is_some());
+        assert_eq!(Cfmm::estimate_apr(0, 1, 4), None);
+
+        // The volume is recorded once per block, before the first exchange in it, overwriting
+        // the oldest entry
+        System::set_block_number(14);
+        let (volume_a, volume_b) = CumulativeVolume::<Test>::get((0u32, 1u32));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        let history = VolumeHistory::<Test>::get((0u32, 1u32));
+        assert_eq!(history.iter().map(|point| point.block).collect::<Vec<_>>(), vec![13, 14, 12]);
+        assert_eq!(history[1], VolumePoint { block: 14, volume_a, volume_b });
+        assert_eq!(VolumeHistoryHead::<Test>::get((0u32, 1u32)), 2);
+    });
+}
+
+#[test]
+fn price_history() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 5_000, 1, 0, 10_000, 0, false));
+        assert_eq!(Cfmm::price_history(0, 1), vec![]);
+
+        // Only the amounts before the first exchange in a block are recorded
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        assert_eq!(Cfmm::price_history(0, 1), vec![(1, 5_000, 10_000)]);
+        assert_eq!(Cfmm::price_history(1, 0), vec![(1, 10_000, 5_000)]);
+
+        // The oldest entry is overwritten once MaxHistoryLen (3) is reached
+        let mut expected = vec![(1, 5_000, 10_000)];
+        for block in 2..6 {
+            System::set_block_number(block);
+            let (amount_0, amount_1) = Cfmm::get_exchange_rate(0, 1).unwrap();
+            expected.push((block, amount_0, amount_1));
+            assert_ok!(Cfmm::exchange(Origin::signed(2), 1, 100, 0, 0));
+        }
+        expected.drain(..2);
+        assert_eq!(Cfmm::price_history(0, 1), expected);
+        assert_eq!(PriceHistory::<Test>::get((0u32, 1u32))[0], expected[1]);
+        assert_eq!(PriceHistoryHead::<Test>::get((0u32, 1u32)), 2);
+
+        // Raising MaxHistoryLen keeps the entries in order
+        CfmmMaxHistoryLen::set(4);
+        System::set_block_number(6);
+        let (amount_0, amount_1) = Cfmm::get_exchange_rate(0, 1).unwrap();
+        expected.push((6, amount_0, amount_1));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 1, 100, 0, 0));
+        assert_eq!(Cfmm::price_history(0, 1), expected);
+        assert_eq!(PriceHistoryHead::<Test>::get((0u32, 1u32)), 0);
+    });
+}
+
+#[test]
+fn price_history_disabled() {
+    ExtBuilder::default().build().execute_with(|| {
+        CfmmMaxHistoryLen::set(0);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 5_000, 1, 0, 10_000, 0, false));
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        assert!(!PriceHistory::<Test>::contains_key((0u32, 1u32)));
+        assert_eq!(Cfmm::price_history(0, 1), vec![]);
+    });
+}
+
+#[test]
+fn observe() {
+    ExtBuilder::default().build().execute_with(|| {
+        System::set_block_number(1);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        for n in [3, 6, 10, 12] {
+            System::set_block_number(n);
+            assert_ok!(Cfmm::exchange(Origin::signed(2), 0, 100, 1, 0));
+        }
+
+        // Only the most recent ObservationCardinality observations are kept
+        let observations = Observations::<Test>::get((0u32, 1u32));
+        assert_eq!(
+            observations
+                .iter()
+                .map(|observation| observation.last_updated)
+                .collect::<Vec<_>>(),
+            vec![3, 6, 10, 12]
+        );
+
+        System::set_block_number(15);
+        let prices = Cfmm::observe(0, 1, &[0, 3, 7, 9]).unwrap();
+        assert_eq!(prices[1], observations[3]);
+        assert_eq!(prices[3], observations[1]);
+        // Halfway between two observations
+        assert_eq!(prices[2].last_updated, 8);
+        assert_eq!(
+            prices[2].price_a,
+            FixedU128::from_inner(
+                (observations[1].price_a.into_inner() + observations[2].price_a.into_inner()) / 2
+            )
+        );
+        // The price has not changed since the last exchange
+        let (pool_amount_0, pool_amount_1) = Cfmm::get_exchange_rate(0, 1).unwrap();
+        assert_eq!(
+            prices[0].price_a - prices[1].price_a,
+            FixedU128::saturating_from_rational(pool_amount_1, pool_amount_0) *
+                FixedU128::saturating_from_integer(3)
+        );
+
+        // Reversing the asset pair swaps the prices
+        let reversed = Cfmm::observe(1, 0, &[3]).unwrap();
+        assert_eq!(
+            (reversed[0].price_a, reversed[0].price_b),
+            (prices[1].price_b, prices[1].price_a)
+        );
+
+        assert_noop!(Cfmm::observe(0, 1, &[0, 13]), Error::<Test>::InsufficientHistory);
+        assert_noop!(Cfmm::observe(0, 1, &[16]), Error::<Test>::InsufficientHistory);
+    });
+}
+
+#[test]
+fn rescue_pool_funds() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        let pool_account = crate::pallet::get_pool_account::<Test>((0, 1));
+        assert_ok!(Assets::transfer(Origin::signed(2), 2, pool_account, 500));
+
+        assert_noop!(Cfmm::rescue_pool_funds(Origin::signed(2), 0, 1, 2, 2), BadOrigin);
+        // The pair assets cannot be drained, even by root
+        assert_noop!(
+            Cfmm::rescue_pool_funds(Origin::root(), 0, 1, 0, 2),
+            Error::<Test>::CannotRescuePairAsset
+        );
+        assert_noop!(
+            Cfmm::rescue_pool_funds(Origin::root(), 1, 0, 1, 2),
+            Error::<Test>::CannotRescuePairAsset
+        );
+
+        assert_ok!(Cfmm::rescue_pool_funds(Origin::root(), 1, 0, 2, 2));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::PoolFundsRescued {
+            asset_a: 1,
+            asset_b: 0,
+            stray_asset: 2,
+            amount: 500,
+            beneficiary: 2,
+        }));
+        assert_eq!(Assets::balance(2, 2), 10_000);
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_000, 2_000)));
+        assert_noop!(
+            Cfmm::rescue_pool_funds(Origin::root(), 0, 1, 2, 2),
+            Error::<Test>::NothingToRescue
+        );
+    });
+}
+
+#[test]
+fn admin_drain_pool() {
+    ExtBuilder::default().build().execute_with(|| {
+        CfmmLiquidityCooldown::set(5);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 100, 1, 0, 200, 0, false));
+        // Locks and donations don't get in the way
+        assert_ok!(Cfmm::lock_liquidity(Origin::signed(1), 0, 1, 10_000, 10));
+        let pool_account = crate::pallet::get_pool_account::<Test>((0, 1));
+        assert_ok!(Assets::transfer(Origin::signed(2), 0, pool_account, 50));
+        // Nor does anything else stored for the pool
+        assert_ok!(Cfmm::schedule_remove_liquidity(Origin::signed(2), 0, 1, 1_000, 10));
+        assert_ok!(Cfmm::set_min_add_amount(
+            Origin::signed(1),
+            0,
+            1,
+            Some(MinAddAmount::Absolute(100, 200))
+        ));
+        assert_ok!(Cfmm::set_initial_liquidity_per_asset_unit(Origin::signed(1), 0, 1, Some(1)));
+        let oracle_price =
+            OraclePrice { price_a: FixedU128::one(), price_b: FixedU128::one(), reported_at: 1 };
+        OraclePrices::<Test>::insert((0u32, 1u32), oracle_price);
+        // 110 of asset 2 per block for blocks 1 to 11
+        assert_ok!(Cfmm::fund_rewards(Origin::root(), 0, 1, 1, 2, 1_100, 1, 11));
+        System::set_block_number(6);
+
+        assert_noop!(Cfmm::admin_drain_pool(Origin::signed(1), 0, 1, 3, 10), BadOrigin);
+        assert_ok!(Cfmm::admin_drain_pool(Origin::root(), 1, 0, 3, 10));
+        System::assert_has_event(Event::Cfmm(CfmmEvent::PoolDrainedByAdmin {
+            asset_a: 1,
+            amount_a: 2_200,
+            asset_b: 0,
+            amount_b: 1_150,
+            recipient: 3,
+        }));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::PoolRemoved { asset_a: 1, asset_b: 0 }));
+
+        assert_eq!((Assets::balance(0, 3), Assets::balance(1, 3)), (1_150, 2_200));
+        assert_eq!((Assets::balance(0, pool_account), Assets::balance(1, pool_account)), (0, 0));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Err(Error::<Test>::PoolNotFound.into()));
+        assert_eq!(TotalLiquidity::<Test>::get((0u32, 1u32)), 0);
+        assert_eq!(Liquidity::<Test>::get(1, (0u32, 1u32)).amount, 0);
+        assert_eq!(Liquidity::<Test>::get(2, (0u32, 1u32)).amount, 0);
+        assert!(LiquidityLocks::<Test>::get(1, (0u32, 1u32)).is_empty());
+        assert!(!Pools::<Test>::contains_key((0u32, 1u32)));
+        assert_eq!(PoolCount::<Test>::get(), 0);
+        assert!(!LastLiquidityChange::<Test>::contains_key(2, (0u32, 1u32)));
+        assert!(!ScheduledRemovals::<Test>::contains_key(10));
+        assert!(!MinAddAmounts::<Test>::contains_key((0u32, 1u32)));
+        assert!(!InitialLiquidityPerAssetUnitOverrides::<Test>::contains_key((0u32, 1u32)));
+        assert!(!OraclePrices::<Test>::contains_key((0u32, 1u32)));
+        assert_noop!(
+            Cfmm::admin_drain_pool(Origin::root(), 0, 1, 3, 10),
+            Error::<Test>::NoLiquidity
+        );
+
+        // The 550 of asset 2 accrued over blocks 1 to 6 are paid out in proportion to liquidity,
+        // and the rest go to the recipient
+        System::assert_has_event(Event::Cfmm(CfmmEvent::RewardsClaimed {
+            who: 2,
+            asset_a: 0,
+            asset_b: 1,
+            reward_asset: 2,
+            amount: 50,
+        }));
+        System::assert_has_event(Event::Cfmm(CfmmEvent::RewardsReturned {
+            asset_a: 0,
+            asset_b: 1,
+            reward_asset: 2,
+            amount: 550,
+            recipient: 3,
+        }));
+        assert_eq!(
+            (Assets::balance(2, 1), Assets::balance(2, 2), Assets::balance(2, 3)),
+            (9_400, 10_050, 550)
+        );
+        assert!(!RewardPots::<Test>::contains_key((0u32, 1u32)));
+        assert!(!RewardPositions::<Test>::contains_key(1, (0u32, 1u32)));
+
+        // The pool can be recreated as usual
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_000, 2_000)));
+    });
+}
+
+#[test]
+fn admin_drain_pool_without_reward_pot() {
+    ExtBuilder::default()
+        .with_pool(1, 0, 1_000, 1, 2_000)
+        .with_pool(1, 0, 1_000, 2, 3_000)
+        .build()
+        .execute_with(|| {
+            assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 100, 1, 0, 200, 0, false));
+
+            // All three liquidity positions are visited, not just those in the pool. Without a
+            // reward pot, reward positions are not.
+            assert_noop!(
+                Cfmm::admin_drain_pool(Origin::root(), 0, 1, 3, 2),
+                Error::<Test>::MigrationTooLarge
+            );
+            assert_ok!(Cfmm::admin_drain_pool(Origin::root(), 0, 1, 3, 3));
+            System::assert_has_event(Event::Cfmm(CfmmEvent::PoolDrainedByAdmin {
+                asset_a: 0,
+                amount_a: 1_100,
+                asset_b: 1,
+                amount_b: 2_200,
+                recipient: 3,
+            }));
+            assert!(!System::events().iter().any(|record| matches!(
+                record.event,
+                Event::Cfmm(CfmmEvent::RewardsClaimed { .. } | CfmmEvent::RewardsReturned { .. })
+            )));
+            assert_eq!(Liquidity::<Test>::get(1, (0u32, 1u32)).amount, 0);
+            assert_eq!(Liquidity::<Test>::get(2, (0u32, 1u32)).amount, 0);
+            assert_eq!(PoolCount::<Test>::get(), 1);
+
+            // The other pool is untouched
+            assert_eq!(Cfmm::get_exchange_rate(0, 2), Ok((1_000, 3_000)));
+            assert_eq!(Liquidity::<Test>::get(1, (0u32, 2u32)).amount, 30_000);
+        });
+}
+
+#[test]
+fn admin_drain_pool_below_minimum_balance() {
+    ExtBuilder::default().with_pool(1, 0, 1_000, 1, 2_000).build().execute_with(|| {
+        // Account 1 is the asset admin, so can burn directly from the pool account. Burning all
+        // but 5 of asset 0 leaves less than the minimum balance of 10, so the rest is burned as
+        // dust, and syncing leaves the pool with none of asset 0.
+        let pool_account = crate::pallet::get_pool_account::<Test>((0, 1));
+        assert_ok!(Assets::burn(Origin::signed(1), 0, pool_account, 995));
+        assert_eq!(Assets::balance(0, pool_account), 0);
+        assert_ok!(Cfmm::sync(Origin::signed(3), 0, 1));
+        assert_eq!(Reserves::<Test>::get((0u32, 1u32)), (0, 2_000));
+
+        // What is left can still be recovered
+        assert_ok!(Cfmm::admin_drain_pool(Origin::root(), 0, 1, 3, 1));
+        System::assert_has_event(Event::Cfmm(CfmmEvent::PoolDrainedByAdmin {
+            asset_a: 0,
+            amount_a: 0,
+            asset_b: 1,
+            amount_b: 2_000,
+            recipient: 3,
+        }));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::PoolRemoved { asset_a: 0, asset_b: 1 }));
+        assert_eq!((Assets::balance(0, 3), Assets::balance(1, 3)), (0, 2_000));
+        assert_eq!(Assets::balance(1, pool_account), 0);
+        assert_eq!(Liquidity::<Test>::get(1, (0u32, 1u32)).amount, 0);
+        assert_eq!(TotalLiquidity::<Test>::get((0u32, 1u32)), 0);
+        assert_eq!(PoolCount::<Test>::get(), 0);
+    });
+}
+
+/// A minimal consumer of `PriceProvider`, standing in for eg a lending pallet, which values
+/// collateral in units of asset 1.
+struct MockLoans<Oracle>(sp_std::marker::PhantomData<Oracle>);
+
+impl<Oracle: PriceProvider<u32, FixedU128>> MockLoans<Oracle> {
+    fn collateral_value(asset: u32, amount: AssetBalance) -> Option<AssetBalance> {
+        Oracle::price(asset, 1).map(|price| price.saturating_mul_int(amount))
+    }
+}
+
+#[test]
+fn price_provider() {
+    ExtBuilder::default().build().execute_with(|| {
+        // No pool
+        assert_eq!(MockLoans::<Cfmm>::collateral_value(0, 100), None);
+        assert_eq!(<Cfmm as PriceProvider<_, _>>::price(0, 0), None);
+
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        System::set_block_number(2);
+        assert_eq!(<Cfmm as PriceProvider<_, _>>::price(1, 0), Some(FixedU128::from_float(0.5)));
+        assert_eq!(MockLoans::<Cfmm>::collateral_value(0, 100), Some(200));
+
+        // Manipulation within the block is ignored
+        assert_ok!(Cfmm::exchange(Origin::signed(2), 1, 8_000, 0, 0));
+        assert_eq!(MockLoans::<Cfmm>::collateral_value(0, 100), Some(200));
+
+        // Removing all liquidity leaves the pool empty; there is no price rather than a division
+        // by zero
+        System::set_block_number(3);
+        let liquidity = Liquidity::<Test>::get(1, (0u32, 1u32)).amount;
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, liquidity));
+        System::set_block_number(4);
+        assert_eq!(MockLoans::<Cfmm>::collateral_value(0, 100), None);
+    });
+}
+
+#[test]
+fn liquidity_mining_rewards() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_noop!(Cfmm::claim_rewards(Origin::signed(1), 0, 1), Error::<Test>::NoRewards);
+
+        assert_noop!(Cfmm::fund_rewards(Origin::signed(1), 0, 1, 1, 2, 1_000, 1, 11), BadOrigin);
+        assert_noop!(
+            Cfmm::fund_rewards(Origin::root(), 0, 1, 1, 2, 1_000, 11, 11),
+            Error::<Test>::InvalidRewardPeriod
+        );
+        // 100 of asset 2 per block for blocks 1 to 11
+        assert_ok!(Cfmm::fund_rewards(Origin::root(), 1, 0, 1, 2, 1_000, 1, 11));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::RewardsFunded {
+            asset_a: 1,
+            asset_b: 0,
+            reward_asset: 2,
+            amount: 1_000,
+            start: 1,
+            end: 11,
+        }));
+        assert_eq!(Assets::balance(2, 1), 9_000);
+        assert_noop!(
+            Cfmm::fund_rewards(Origin::root(), 0, 1, 1, 2, 1_000, 5, 15),
+            Error::<Test>::RewardsActive
+        );
+
+        // The two providers have equal shares
+        System::set_block_number(6);
+        assert_ok!(Cfmm::claim_rewards(Origin::signed(1), 0, 1));
+        assert_ok!(Cfmm::claim_rewards(Origin::signed(2), 1, 0));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::RewardsClaimed {
+            who: 2,
+            asset_a: 1,
+            asset_b: 0,
+            reward_asset: 2,
+            amount: 250,
+        }));
+        assert_eq!((Assets::balance(2, 1), Assets::balance(2, 2)), (9_250, 10_250));
+        assert_noop!(Cfmm::claim_rewards(Origin::signed(2), 0, 1), Error::<Test>::NoRewards);
+
+        // Once account 2 withdraws, account 1 gets all of the remaining rewards. Rewards stop
+        // accruing at the end of the period.
+        let liquidity = Liquidity::<Test>::get(2, (0u32, 1u32)).amount;
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(2), 0, 1, liquidity));
+        System::set_block_number(20);
+        assert_noop!(Cfmm::claim_rewards(Origin::signed(2), 0, 1), Error::<Test>::NoRewards);
+        assert_ok!(Cfmm::claim_rewards(Origin::signed(1), 0, 1));
+        assert_eq!((Assets::balance(2, 1), Assets::balance(2, 2)), (9_750, 10_250));
+        assert!(!RewardPositions::<Test>::contains_key(2, (0u32, 1u32)));
+
+        // The pot can be refilled once the period is over, but only with the same asset
+        assert_noop!(
+            Cfmm::fund_rewards(Origin::root(), 0, 1, 1, 0, 1_000, 20, 30),
+            Error::<Test>::RewardAssetMismatch
+        );
+        assert_ok!(Cfmm::fund_rewards(Origin::root(), 0, 1, 1, 2, 1_000, 20, 30));
+    });
+}
+
+#[test]
+fn liquidity_mining_rewards_follow_transfers() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_ok!(Cfmm::fund_rewards(Origin::root(), 0, 1, 1, 2, 1_000, 1, 11));
+
+        // Rewards accrued before a transfer stay with the sender
+        System::set_block_number(3);
+        let liquidity = Liquidity::<Test>::get(1, (0u32, 1u32)).amount;
+        assert_ok!(Cfmm::transfer_liquidity(Origin::signed(1), 0, 1, 2, liquidity / 2));
+        System::set_block_number(11);
+        assert_ok!(Cfmm::claim_rewards(Origin::signed(1), 0, 1));
+        assert_ok!(Cfmm::claim_rewards(Origin::signed(2), 0, 1));
+        assert_eq!((Assets::balance(2, 1), Assets::balance(2, 2)), (9_000 + 600, 10_000 + 400));
+    });
+}
+
+#[test]
+fn undistributed_rewards() {
+    ExtBuilder::default().build().execute_with(|| {
+        // 100 of asset 2 per block for blocks 1 to 11, with the pool empty until block 4
+        assert_ok!(Cfmm::fund_rewards(Origin::root(), 0, 1, 2, 2, 1_000, 1, 11));
+        assert_noop!(Cfmm::sweep_rewards(Origin::root(), 0, 1, 2), Error::<Test>::NoRewards);
+        System::set_block_number(4);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 3_000, 0, false));
+        assert_eq!(TotalLiquidity::<Test>::get((0u32, 1u32)), 30_000);
+        assert_eq!(UndistributedRewards::<Test>::get((0u32, 1u32)), 300);
+
+        // 100 / 30_000 per liquidity token rounds down, leaving 1 undistributed
+        System::set_block_number(5);
+        assert_ok!(Cfmm::claim_rewards(Origin::signed(1), 0, 1));
+        assert_eq!(Assets::balance(2, 1), 10_099);
+        assert_eq!(UndistributedRewards::<Test>::get((0u32, 1u32)), 301);
+
+        assert_noop!(Cfmm::sweep_rewards(Origin::signed(2), 0, 1, 2), BadOrigin);
+        assert_ok!(Cfmm::sweep_rewards(Origin::root(), 1, 0, 2));
+        System::assert_last_event(Event::Cfmm(CfmmEvent::RewardsReturned {
+            asset_a: 1,
+            asset_b: 0,
+            reward_asset: 2,
+            amount: 301,
+            recipient: 2,
+        }));
+        assert_eq!(Assets::balance(2, 2), 9_301);
+        assert_noop!(Cfmm::sweep_rewards(Origin::root(), 0, 1, 2), Error::<Test>::NoRewards);
+
+        // The rest of the pot is distributed, so exactly the pot is paid out in total
+        System::set_block_number(11);
+        assert_ok!(Cfmm::claim_rewards(Origin::signed(1), 0, 1));
+        assert_eq!(Assets::balance(2, 1), 10_699);
+        assert_noop!(Cfmm::sweep_rewards(Origin::root(), 0, 1, 2), Error::<Test>::NoRewards);
+        assert_eq!(Assets::balance(2, crate::pallet::get_rewards_account::<Test>()), 0);
+    });
+}
+
+#[test]
+fn chain_extension() {
+    use chain_extension::{dispatch, weight, EXCHANGE, GET_AMOUNT_OUT, GET_RESERVES};
+
+    ExtBuilder::default().with_pool(1, 0, 1_000, 1, 2_000).build().execute_with(|| {
+        assert_eq!(
+            dispatch::<Test>(GET_RESERVES, &2, &(1u32, 0u32).encode()),
+            Ok((2_000u32, 1_000u32).encode())
+        );
+        assert_eq!(
+            dispatch::<Test>(GET_AMOUNT_OUT, &2, &(0u32, 100u32, 1u32).encode()),
+            Ok(165u32.encode())
+        );
+        assert_noop!(
+            dispatch::<Test>(EXCHANGE, &2, &(0u32, 100u32, 1u32, 166u32).encode()),
+            Error::<Test>::UnexpectedExchangeRate
+        );
+        assert_eq!(
+            dispatch::<Test>(EXCHANGE, &2, &(0u32, 100u32, 1u32, 165u32).encode()),
+            Ok(165u32.encode())
+        );
+        assert_eq!(Assets::balance(1, 2), 10_165);
+        assert_eq!(Cfmm::get_exchange_rate(0, 1), Ok((1_100, 1_835)));
+
+        assert_eq!(
+            dispatch::<Test>(GET_RESERVES, &2, &0u32.encode()),
+            Err(DispatchError::Other("Invalid CFMM chain extension input"))
+        );
+        assert!(dispatch::<Test>(4, &2, &[]).is_err());
+        assert_eq!(weight::<Test>(EXCHANGE), Ok(Cfmm::exchange_weight()));
+        assert!(weight::<Test>(4).is_err());
+    });
+}
+
+#[test]
+fn pool_account_provider() {
+    ExtBuilder::default().build().execute_with(|| {
+        // Asset 3 is not sufficient, so holding it does not keep an account alive
+        assert_ok!(Assets::force_create(Origin::root(), 3, 1, false, 10));
+        assert_ok!(Balances::set_balance(Origin::root(), 1, 100, 0));
+        assert_ok!(Assets::mint(Origin::signed(1), 3, 1, 10_000));
+
+        let pool_account = crate::pallet::get_pool_account::<Test>((0, 3));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 3, 0, 1_000, 0, false));
+        assert_eq!(System::providers(&pool_account), 1);
+
+        // Reduce the reserves to the minimum pool amounts; the account survives
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 3, 9_000));
+        assert_eq!(Cfmm::get_exchange_rate(0, 3), Ok((100, 100)));
+        assert!(System::account_exists(&pool_account));
+        assert_eq!(System::providers(&pool_account), 1);
+
+        // Draining the pool releases the account
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 3, 1_000));
+        assert_eq!(Assets::balance(3, 1), 10_000);
+        assert!(!System::account_exists(&pool_account));
+        assert_eq!(System::providers(&pool_account), 0);
+    });
+}
+
+#[test]
+fn migrate_pool_account_providers() {
+    ExtBuilder::default().with_pool(1, 0, 1_000, 1, 2_000).build().execute_with(|| {
+        // Pools created before version 9 did not hold a provider reference
+        let pool_account = crate::pallet::get_pool_account::<Test>((0, 1));
+        assert_ok!(System::dec_providers(&pool_account));
+        assert_eq!(System::providers(&pool_account), 0);
+        StorageVersion::new(8).put::<Cfmm>();
+
+        migrations::v9::migrate::<Test>();
+        assert_eq!(StorageVersion::get::<Cfmm>(), StorageVersion::new(9));
+        assert_eq!(System::providers(&pool_account), 1);
+
+        // Running it again does not add another reference
+        migrations::migrate::<Test>();
+        assert_eq!(System::providers(&pool_account), 1);
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 20_000));
+        assert_eq!(System::providers(&pool_account), 0);
+    });
+}
+
+#[test]
+fn migrate_weighted_position_counts() {
+    ExtBuilder::default().build().execute_with(|| {
+        let assets =
+            vec![(0, Perbill::from_percent(80), 1_000), (1, Perbill::from_percent(20), 2_000)];
+        assert_ok!(Cfmm::create_weighted_pool(Origin::signed(1), assets));
+        assert_ok!(Cfmm::join_weighted_pool(Origin::signed(2), 0, 10_000, vec![500, 1_000]));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+
+        // Weighted pool positions were not counted before version 10
+        PositionCount::<Test>::remove(1u128);
+        PositionCount::<Test>::insert(2u128, 1);
+        StorageVersion::new(9).put::<Cfmm>();
+
+        migrations::v10::migrate::<Test>();
+        assert_eq!(StorageVersion::get::<Cfmm>(), StorageVersion::new(10));
+        assert_eq!(PositionCount::<Test>::get(1u128), 1);
+        assert_eq!(PositionCount::<Test>::get(2u128), 2);
+    });
+}
+
+#[test]
+fn position_deposit() {
+    ExtBuilder::default().build().execute_with(|| {
+        CfmmPositionDeposit::set(10);
+        assert_ok!(Balances::set_balance(Origin::root(), 1, 1_000, 0));
+        assert_ok!(Balances::set_balance(Origin::root(), 2, 1_000, 0));
+
+        // One deposit per position, however much liquidity is added
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_eq!(Balances::reserved_balance(1), 10);
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 250, 1, 0, 500, 0, false));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 250, 1, 0, 500, 0, false));
+        assert_eq!(Balances::reserved_balance(2), 10);
+
+        // The deposit is held until the position is closed, and the amount held is released even
+        // if the deposit has changed
+        CfmmPositionDeposit::set(50);
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(2), 0, 1, 5_000));
+        assert_eq!(Balances::reserved_balance(2), 10);
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(2), 0, 1, 5_000));
+        assert_eq!(Balances::reserved_balance(2), 0);
+
+        // The sender of a transfer pays the deposit for the recipient's new position, and has
+        // their own released if they transfer all of their liquidity
+        assert_ok!(Cfmm::transfer_liquidity(Origin::signed(1), 0, 1, 2, 5_000));
+        assert_eq!((Balances::free_balance(1), Balances::reserved_balance(1)), (940, 10));
+        assert_eq!((Balances::free_balance(2), Balances::reserved_balance(2)), (1_000, 50));
+        assert_ok!(Cfmm::transfer_liquidity(Origin::signed(1), 0, 1, 2, 15_000));
+        assert_eq!(Balances::reserved_balance(1), 0);
+        assert_eq!(Balances::reserved_balance(2), 50);
+    });
+}
+
+#[test]
+fn pool_deposit() {
+    ExtBuilder::default().build().execute_with(|| {
+        CfmmPoolDeposit::set(100);
+        assert_ok!(Balances::set_balance(Origin::root(), 1, 1_000, 0));
+        assert_ok!(Balances::set_balance(Origin::root(), 2, 1_000, 0));
+
+        // The deposit is taken from the creator of the pool only
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(1), 0, 0, 1_000, 1, 0, 2_000, 0, false));
+        assert_eq!(Balances::reserved_balance(1), 100);
+        assert_eq!(Pools::<Test>::get((0u32, 1u32)).deposit, Some((1, 100)));
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+        assert_eq!(Balances::reserved_balance(2), 0);
+        assert_noop!(
+            Cfmm::add_liquidity(Origin::signed(3), 0, 0, 1_000, 2, 0, 1_000, 0, false),
+            pallet_balances::Error::<Test>::InsufficientBalance
+        );
+
+        // Draining the pool does not release the deposit...
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(1), 0, 1, 20_000));
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(2), 0, 1, 10_000));
+        assert_eq!(Balances::reserved_balance(1), 100);
+
+        // ...but recreating it does, and takes a new deposit from the new creator
+        assert_ok!(Cfmm::add_liquidity(Origin::signed(2), 0, 0, 500, 1, 0, 1_000, 0, false));
+        System::assert_has_event(Event::Cfmm(CfmmEvent::PoolDepositReleased {
+            who: 1,
+            asset_a: 0,
+            asset_b: 1,
+            amount: 100,
+        }));
+        assert_eq!(Balances::reserved_balance(1), 0);
+        assert_eq!(Balances::reserved_balance(2), 100);
+        assert_eq!(Pools::<Test>::get((0u32, 1u32)).deposit, Some((2, 100)));
+
+        // As does cleaning up the drained pool
+        assert_ok!(Cfmm::remove_liquidity(Origin::signed(2), 0, 1, 10_000));
+        assert_ok!(Cfmm::cleanup_pool(Origin::signed(3), 1, 0));
+        System::assert_has_event(Event::Cfmm(CfmmEvent::PoolDepositReleased {
+            who: 2,
+            asset_a: 1,
+            asset_b: 0,
+            amount: 100,
+        }));
+        assert_eq!(Balances::reserved_balance(2), 0);
+        assert_eq!(Pools::<Test>::get((0u32, 1u32)).deposit, None);
+        assert_noop!(Cfmm::cleanup_pool(Origin::signed(3), 0, 1), Error::<Test>::NothingToCleanUp);
     });
 }
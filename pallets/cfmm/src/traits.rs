@@ -0,0 +1,84 @@
+//! Extension points allowing other pallets to react to activity in the CFMM pallet, and to provide
+//! information about assets.
+
+use frame_support::traits::tokens::fungibles::metadata;
+use sp_std::marker::PhantomData;
+
+/// Called after every exchange, including those performed as part of other calls (eg
+/// `swap_and_add_liquidity`).
+pub trait OnSwap<AccountId, AssetId, Balance> {
+    fn on_swap(
+        who: &AccountId,
+        source_asset: AssetId,
+        source_amount: Balance,
+        dest_asset: AssetId,
+        dest_amount: Balance,
+    );
+}
+
+impl<AccountId, AssetId, Balance> OnSwap<AccountId, AssetId, Balance> for () {
+    fn on_swap(_: &AccountId, _: AssetId, _: Balance, _: AssetId, _: Balance) {}
+}
+
+/// Called after liquidity is added to or removed from a liquidity pool. The amounts are those
+/// actually transferred to or from `who`, and `liquidity` is the number of liquidity tokens minted
+/// or redeemed.
+pub trait OnLiquidityChanged<AccountId, AssetId, Balance> {
+    fn on_liquidity_added(
+        who: &AccountId,
+        asset_a: AssetId,
+        amount_a: Balance,
+        asset_b: AssetId,
+        amount_b: Balance,
+        liquidity: Balance,
+    );
+
+    fn on_liquidity_removed(
+        who: &AccountId,
+        asset_a: AssetId,
+        amount_a: Balance,
+        asset_b: AssetId,
+        amount_b: Balance,
+        liquidity: Balance,
+    );
+}
+
+impl<AccountId, AssetId, Balance> OnLiquidityChanged<AccountId, AssetId, Balance> for () {
+    fn on_liquidity_added(
+        _: &AccountId,
+        _: AssetId,
+        _: Balance,
+        _: AssetId,
+        _: Balance,
+        _: Balance,
+    ) {
+    }
+
+    fn on_liquidity_removed(
+        _: &AccountId,
+        _: AssetId,
+        _: Balance,
+        _: AssetId,
+        _: Balance,
+        _: Balance,
+    ) {
+    }
+}
+
+/// Provides the number of decimal places used to display each asset.
+pub trait AssetDecimals<AssetId> {
+    fn decimals(asset: &AssetId) -> u8;
+}
+
+/// Implements `AssetDecimals` using the metadata of a `fungibles` implementation (typically
+/// `pallet_assets`). Assets without metadata have zero decimals.
+pub struct MetadataDecimals<AccountId, Metadata>(PhantomData<(AccountId, Metadata)>);
+
+impl<AccountId, Metadata> AssetDecimals<Metadata::AssetId> for MetadataDecimals<AccountId, Metadata>
+where
+    Metadata: metadata::Inspect<AccountId>,
+{
+    fn decimals(asset: &Metadata::AssetId) -> u8 {
+        Metadata::decimals(asset)
+    }
+}
@@ -0,0 +1,41 @@
+//! Support for conferring governance voting power on liquidity providers.
+
+use crate::{Config, Pallet};
+use frame_support::traits::Get;
+use sp_runtime::traits::Saturating;
+use sp_std::marker::PhantomData;
+
+/// Provides the voting power of an account, for use by a governance pallet.
+pub trait VotingPower<AccountId, Balance> {
+    fn voting_power(who: &AccountId) -> Balance;
+}
+
+/// Implements `VotingPower` by summing the amount of the governance token (the asset with ID
+/// `GovernanceAssetId`) that an account's locked liquidity is worth (see
+/// `Pallet::locked_amount_of`). This allows liquidity providers to vote with their governance
+/// tokens without first withdrawing them from the pools.
+///
+/// Only liquidity locked for at least `LockPeriod` more blocks counts; this should be at least the
+/// voting period, so that liquidity counted in an active vote can't be removed or transferred and
+/// counted again. Pool amounts are valued at the average price over the last `Window` blocks.
+///
+/// Governance tokens held directly by the account are not included; combine this with the
+/// account's free balance as required.
+pub struct LiquidityVotingPower<T, GovernanceAssetId, Window, LockPeriod>(
+    PhantomData<(T, GovernanceAssetId, Window, LockPeriod)>,
+);
+
+impl<T, GovernanceAssetId, Window, LockPeriod> VotingPower<T::AccountId, T::AssetBalance>
+    for LiquidityVotingPower<T, GovernanceAssetId, Window, LockPeriod>
+where
+    T: Config,
+    GovernanceAssetId: Get<T::AssetId>,
+    Window: Get<T::BlockNumber>,
+    LockPeriod: Get<T::BlockNumber>,
+{
+    fn voting_power(who: &T::AccountId) -> T::AssetBalance {
+        let locked_until =
+            frame_system::Pallet::<T>::block_number().saturating_add(LockPeriod::get());
+        Pallet::<T>::locked_amount_of(who, GovernanceAssetId::get(), Window::get(), locked_until)
+    }
+}
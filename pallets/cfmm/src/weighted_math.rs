@@ -0,0 +1,199 @@
+//! Fixed-point arithmetic for weighted liquidity pools.
+//!
+//! The invariant of a weighted pool is `prod(balance_i ^ weight_i) = k`. Exchanging between two
+//! of its assets requires raising a ratio of balances to the (generally non-integer) ratio of
+//! their weights, which is done here via `exp(-exp * ln(1 / base))` using series expansions.
+
+use sp_core::U256;
+use sp_runtime::{
+    traits::{One, Zero},
+    ArithmeticError, FixedPointNumber, FixedU128, PerThing, Perbill,
+};
+use sp_std::cmp::min;
+
+/// The minimum weight of an asset in a weighted pool. Together with `MAX_WEIGHT`, this bounds the
+/// exponent passed to `pow` to `[1/99, 99]`, and thus the error of its result.
+pub(crate) const MIN_WEIGHT: Perbill = Perbill::from_percent(1);
+
+/// The maximum weight of an asset in a weighted pool.
+pub(crate) const MAX_WEIGHT: Perbill = Perbill::from_percent(99);
+
+/// `ln(2)`.
+const LN_2: FixedU128 = FixedU128::from_inner(693_147_180_559_945_309);
+
+/// An upper bound on the error of `pow`. This is added to its result when computing the amounts
+/// left in a pool, so that rounding favours the pool.
+const POW_ERROR_MARGIN: FixedU128 = FixedU128::from_inner(1_000);
+
+/// Returns `ln(x)` for `x >= 1`.
+fn ln(x: FixedU128) -> Result<FixedU128, ArithmeticError> {
+    // Reduce to x = 2^k * m, with m in [1, 2)
+    let one = FixedU128::one();
+    let two = FixedU128::saturating_from_integer(2u32);
+    let mut m = x;
+    let mut k = 0u32;
+    while m >= two {
+        m = FixedU128::from_inner(m.into_inner() / 2);
+        k += 1;
+    }
+
+    // ln(m) = 2 * (z + z^3/3 + z^5/5 + ...), where z = (m - 1) / (m + 1) < 1/3
+    let z = m
+        .saturating_sub(one)
+        .checked_div(&m.saturating_add(one))
+        .ok_or(ArithmeticError::DivisionByZero)?;
+    let z_squared = z.saturating_mul(z);
+    let mut power = z;
+    let mut sum = FixedU128::zero();
+    let mut n = 1u128;
+    while !power.is_zero() {
+        sum = sum.saturating_add(FixedU128::from_inner(power.into_inner() / n));
+        power = power.saturating_mul(z_squared);
+        n += 2;
+    }
+
+    FixedU128::saturating_from_integer(k)
+        .checked_mul(&LN_2)
+        .and_then(|k_ln_2| k_ln_2.checked_add(&sum.saturating_mul(two)))
+        .ok_or(ArithmeticError::Overflow)
+}
+
+/// Returns `exp(-y)` for `y >= 0`.
+fn exp_neg(y: FixedU128) -> FixedU128 {
+    // Reduce to y = k * ln(2) + r, with r in [0, ln(2))
+    let k = y.into_inner() / LN_2.into_inner();
+    if k >= 128 {
+        // Far below the precision of FixedU128
+        return FixedU128::zero()
+    }
+    let r = FixedU128::from_inner(y.into_inner() - k * LN_2.into_inner());
+
+    // exp(r) = 1 + r + r^2/2! + r^3/3! + ...
+    let mut term = FixedU128::one();
+    let mut sum = FixedU128::one();
+    let mut n = 1u128;
+    while !term.is_zero() {
+        term = FixedU128::from_inner(term.saturating_mul(r).into_inner() / n);
+        sum = sum.saturating_add(term);
+        n += 1;
+    }
+
+    // exp(-y) = 2^-k / exp(r). exp(r) is in [1, 2), so the reciprocal always exists.
+    let reciprocal = sum.reciprocal().unwrap_or_default();
+    FixedU128::from_inner(reciprocal.into_inner() >> k)
+}
+
+/// Returns `base ^ exp` for `base` in `[0, 1]`, accurate to within `POW_ERROR_MARGIN`.
+pub(crate) fn pow(base: FixedU128, exp: FixedU128) -> Result<FixedU128, ArithmeticError> {
+    if base.is_zero() {
+        return Ok(FixedU128::zero())
+    }
+    let inverse = base.reciprocal().ok_or(ArithmeticError::DivisionByZero)?;
+    let y = exp.checked_mul(&ln(inverse)?).ok_or(ArithmeticError::Overflow)?;
+    Ok(exp_neg(y))
+}
+
+/// Returns the amount of the destination asset that must remain in a weighted pool when
+/// `amount_in` (excluding any fee) of the source asset is added, to preserve the invariant:
+/// `balance_out * (balance_in / (balance_in + amount_in)) ^ (weight_in / weight_out)`, rounded up.
+pub(crate) fn get_new_balance_out(
+    balance_in: u128,
+    weight_in: Perbill,
+    balance_out: u128,
+    weight_out: Perbill,
+    amount_in: u128,
+) -> Result<u128, ArithmeticError> {
+    let new_balance_in = balance_in.checked_add(amount_in).ok_or(ArithmeticError::Overflow)?;
+    if new_balance_in.is_zero() {
+        return Err(ArithmeticError::DivisionByZero)
+    }
+
+    let (numerator, denominator) = if weight_in == weight_out {
+        // Equal weights reduce to a plain constant product, which can be computed exactly (and
+        // matches a two-asset liquidity pool)
+        (U256::from(balance_in), U256::from(new_balance_in))
+    } else {
+        let base = FixedU128::checked_from_rational(balance_in, new_balance_in)
+            .ok_or(ArithmeticError::Overflow)?;
+        let exp =
+            FixedU128::checked_from_rational(weight_in.deconstruct(), weight_out.deconstruct())
+                .ok_or(ArithmeticError::DivisionByZero)?;
+        let ratio = min(pow(base, exp)?.saturating_add(POW_ERROR_MARGIN), FixedU128::one());
+        (U256::from(ratio.into_inner()), U256::from(FixedU128::DIV))
+    };
+
+    // ceil(balance_out * numerator / denominator)
+    let product = U256::from(balance_out).saturating_mul(numerator);
+    let res = product.saturating_add(denominator.saturating_sub(U256::one())) / denominator;
+    u128::try_from(res).map_err(|_| ArithmeticError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: FixedU128, expected: FixedU128) {
+        let error = if actual > expected { actual - expected } else { expected - actual };
+        assert!(error <= POW_ERROR_MARGIN, "{:?} is not within margin of {:?}", actual, expected);
+    }
+
+    #[test]
+    fn ln_edges() {
+        assert_eq!(ln(FixedU128::one()), Ok(FixedU128::zero()));
+        assert_eq!(ln(FixedU128::saturating_from_integer(2u32)), Ok(LN_2));
+        assert_close(
+            ln(FixedU128::saturating_from_integer(10u32)).unwrap(),
+            FixedU128::from_inner(2_302_585_092_994_045_684),
+        );
+        // The largest representable value
+        assert_close(
+            ln(FixedU128::from_inner(u128::MAX)).unwrap(),
+            FixedU128::from_inner(47_276_307_437_780_177_293),
+        );
+    }
+
+    #[test]
+    fn exp_neg_edges() {
+        assert_eq!(exp_neg(FixedU128::zero()), FixedU128::one());
+        assert_eq!(exp_neg(LN_2), FixedU128::saturating_from_rational(1u32, 2u32));
+        assert_eq!(exp_neg(FixedU128::saturating_from_integer(200u32)), FixedU128::zero());
+        assert_eq!(exp_neg(FixedU128::from_inner(u128::MAX)), FixedU128::zero());
+    }
+
+    #[test]
+    fn pow_edges() {
+        let one = FixedU128::one();
+        let half = FixedU128::saturating_from_rational(1u32, 2u32);
+        assert_eq!(pow(FixedU128::zero(), one), Ok(FixedU128::zero()));
+        assert_eq!(pow(one, one), Ok(one));
+        assert_eq!(pow(half, FixedU128::zero()), Ok(one));
+        assert_eq!(pow(half, FixedU128::saturating_from_integer(2u32)), Ok(half * half));
+        let three_tenths = FixedU128::saturating_from_rational(3u32, 10u32);
+        assert_close(pow(three_tenths, one).unwrap(), three_tenths);
+        assert_close(
+            pow(FixedU128::saturating_from_rational(1u32, 100u32), half).unwrap(),
+            FixedU128::saturating_from_rational(1u32, 10u32),
+        );
+    }
+
+    #[test]
+    fn pow_weight_bounds() {
+        // The exponents at MIN_WEIGHT and MAX_WEIGHT
+        let max_exp = FixedU128::saturating_from_integer(99u32);
+        let min_exp = FixedU128::saturating_from_rational(1u32, 99u32);
+        let smallest = FixedU128::from_inner(1);
+        let largest = FixedU128::from_inner(FixedU128::DIV - 1);
+
+        assert_eq!(pow(smallest, max_exp), Ok(FixedU128::zero()));
+        assert_close(
+            pow(smallest, min_exp).unwrap(),
+            FixedU128::from_inner(657_933_224_657_567_992),
+        );
+        assert_close(pow(largest, max_exp).unwrap(), FixedU128::one());
+        assert_close(pow(largest, min_exp).unwrap(), FixedU128::one());
+        assert_close(
+            pow(FixedU128::saturating_from_rational(99u32, 100u32), max_exp).unwrap(),
+            FixedU128::from_inner(369_729_637_649_726_773),
+        );
+    }
+}
@@ -6,7 +6,7 @@
 #[cfg(feature = "std")]
 include!(concat!(env!("OUT_DIR"), "/wasm_binary.rs"));
 
-use frame_system::EnsureRoot;
+use frame_system::{EnsureRoot, EnsureSigned};
 use pallet_grandpa::{
     fg_primitives, AuthorityId as GrandpaId, AuthorityList as GrandpaAuthorityList,
 };
@@ -15,8 +15,11 @@ use sp_consensus_aura::sr25519::AuthorityId as AuraId;
 use sp_core::{crypto::KeyTypeId, OpaqueMetadata};
 use sp_runtime::{
     create_runtime_str, generic, impl_opaque_keys,
-    traits::{AccountIdLookup, BlakeTwo256, Block as BlockT, IdentifyAccount, NumberFor, Verify},
-    transaction_validity::{TransactionSource, TransactionValidity},
+    traits::{
+        AccountIdConversion, AccountIdLookup, BlakeTwo256, Block as BlockT, IdentifyAccount,
+        NumberFor, Verify,
+    },
+    transaction_validity::{TransactionPriority, TransactionSource, TransactionValidity},
     ApplyExtrinsicResult, MultiSignature,
 };
 use sp_std::prelude::*;
@@ -287,9 +290,38 @@ impl pallet_assets::Config for Runtime {
 
 parameter_types!(
     pub const CfmmPalletId: PalletId = PalletId(*b"cfmm____");
+    pub const CfmmPoolDeposit: Balance = 100_000;
+    pub const CfmmPositionDeposit: Balance = 10_000;
+    pub const CfmmMaxPools: u32 = 1_000;
+    pub const CfmmMaxWeightedPoolAssets: u32 = 8;
+    pub const CfmmRestrictPoolCreation: bool = false;
+    pub const CfmmMaxBootstrapProviders: u32 = 16;
     pub const CfmmPoolMinAmountMultiple: AssetBalance = 1_000;
     pub const CfmmInitialLiquidityPerAssetUnit: AssetBalance = 1_000;
+    pub const CfmmInitialLiquidityFormula: pallet_cfmm::InitialLiquidityFormula =
+        pallet_cfmm::InitialLiquidityFormula::GeometricMean;
+    pub const CfmmRoundingPolicy: pallet_cfmm::RoundingPolicy =
+        pallet_cfmm::RoundingPolicy::FavorPool;
+    pub const CfmmMinimumLiquidity: AssetBalance = 1_000_000;
     pub const CfmmExchangeFee: Permill = Permill::from_perthousand(3);
+    pub const CfmmReferralShare: Permill = Permill::from_percent(20);
+    pub const CfmmMinLockPeriod: BlockNumber = 0;
+    pub const CfmmLiquidityCooldown: BlockNumber = 0;
+    pub const CfmmMaxPriceImpact: Option<Permill> = None;
+    pub const CfmmMaxTradeFraction: Option<Permill> = None;
+    pub const CfmmCircuitBreakerThreshold: Option<Permill> = None;
+    pub const CfmmMaxScheduledRemovalsPerBlock: u32 = 16;
+    pub const CfmmMaxLiquidityLocks: u32 = 8;
+    pub const CfmmMaxPositionsPerAccount: u32 = 64;
+    pub const CfmmMaxOracleUpdatesPerBlock: u32 = 8;
+    pub const CfmmObservationCardinality: u32 = 64;
+    pub const CfmmMaxHistoryLen: u32 = 128;
+    pub const CfmmExpectedBlockTime: u64 = MILLISECS_PER_BLOCK;
+    pub const CfmmOffchainPriceReporting: bool = false;
+    pub const CfmmMaxPriceReports: u32 = 16;
+    pub const CfmmUnsignedPriority: TransactionPriority = TransactionPriority::max_value() / 2;
+    pub const CfmmMaxRouteLength: u32 = 4;
+    pub CfmmDustSink: AccountId = PalletId(*b"cfmmdust").into_account_truncating();
 );
 
 impl pallet_cfmm::Config for Runtime {
@@ -298,9 +330,60 @@ impl pallet_cfmm::Config for Runtime {
     type AssetId = AssetId;
     type AssetBalance = AssetBalance;
     type Fungibles = Assets;
+    type Currency = Balances;
+    type PoolDeposit = CfmmPoolDeposit;
+    type PositionDeposit = CfmmPositionDeposit;
+    type MaxPools = CfmmMaxPools;
+    type MaxWeightedPoolAssets = CfmmMaxWeightedPoolAssets;
+    type RestrictPoolCreation = CfmmRestrictPoolCreation;
+    type MaxBootstrapProviders = CfmmMaxBootstrapProviders;
     type PoolMinAmountMultiple = CfmmPoolMinAmountMultiple;
     type InitialLiquidityPerAssetUnit = CfmmInitialLiquidityPerAssetUnit;
+    type InitialLiquidityFormula = CfmmInitialLiquidityFormula;
+    type RoundingPolicy = CfmmRoundingPolicy;
+    type MinimumLiquidity = CfmmMinimumLiquidity;
     type ExchangeFee = CfmmExchangeFee;
+    type ReferralShare = CfmmReferralShare;
+    type MinLockPeriod = CfmmMinLockPeriod;
+    type LiquidityCooldown = CfmmLiquidityCooldown;
+    type MaxPriceImpact = CfmmMaxPriceImpact;
+    type MaxTradeFraction = CfmmMaxTradeFraction;
+    type CircuitBreakerThreshold = CfmmCircuitBreakerThreshold;
+    type MaxScheduledRemovalsPerBlock = CfmmMaxScheduledRemovalsPerBlock;
+    type MaxLiquidityLocks = CfmmMaxLiquidityLocks;
+    type MaxPositionsPerAccount = CfmmMaxPositionsPerAccount;
+    type MaxOracleUpdatesPerBlock = CfmmMaxOracleUpdatesPerBlock;
+    type ObservationCardinality = CfmmObservationCardinality;
+    type MaxHistoryLen = CfmmMaxHistoryLen;
+    type ExpectedBlockTime = CfmmExpectedBlockTime;
+    type OffchainPriceReporting = CfmmOffchainPriceReporting;
+    type MaxPriceReports = CfmmMaxPriceReports;
+    type UnsignedPriority = CfmmUnsignedPriority;
+    type MaxRouteLength = CfmmMaxRouteLength;
+    type SwapOrigin = EnsureSigned<AccountId>;
+    type PauseOrigin = EnsureRoot<AccountId>;
+    type EmergencyOrigin = EnsureRoot<AccountId>;
+    type FeeAdminOrigin = EnsureRoot<AccountId>;
+    type PriceBandOrigin = EnsureRoot<AccountId>;
+    type BlacklistOrigin = EnsureRoot<AccountId>;
+    type WhitelistOrigin = EnsureRoot<AccountId>;
+    type PoolCreationOrigin = EnsureRoot<AccountId>;
+    type LiquidityProvisionOrigin = EnsureRoot<AccountId>;
+    type RewardOrigin = EnsureRoot<AccountId>;
+    type RescueOrigin = EnsureRoot<AccountId>;
+    type AdminOrigin = EnsureRoot<AccountId>;
+    type DustSink = CfmmDustSink;
+    type AssetDecimals = pallet_cfmm::traits::MetadataDecimals<AccountId, Assets>;
+    type OnSwap = ();
+    type OnLiquidityChanged = ();
+}
+
+impl<C> frame_system::offchain::SendTransactionTypes<C> for Runtime
+where
+    Call: From<C>,
+{
+    type OverarchingCall = Call;
+    type Extrinsic = UncheckedExtrinsic;
 }
 
 // Create the runtime by composing the FRAME pallets that were previously configured.
@@ -339,6 +422,7 @@ pub type SignedExtra = (
     frame_system::CheckNonce<Runtime>,
     frame_system::CheckWeight<Runtime>,
     pallet_transaction_payment::ChargeTransactionPayment<Runtime>,
+    pallet_cfmm::check_deadline::CheckSwapDeadline<Runtime>,
 );
 /// Unchecked extrinsic type as expected by this runtime.
 pub type UncheckedExtrinsic = generic::UncheckedExtrinsic<Address, Call, Signature, SignedExtra>;
@@ -499,6 +583,48 @@ impl_runtime_apis! {
         }
     }
 
+    impl pallet_cfmm_runtime_api::CfmmApi<Block, AccountId, AssetId, AssetBalance, Balance, BlockNumber>
+        for Runtime
+    {
+        fn max_removable_liquidity(
+            who: AccountId,
+            asset_a: AssetId,
+            asset_b: AssetId,
+        ) -> AssetBalance {
+            Cfmm::max_removable_liquidity(&who, asset_a, asset_b)
+        }
+
+        fn account_liquidity_paged(
+            who: AccountId,
+            start_key: Option<Vec<u8>>,
+            limit: u32,
+        ) -> (Vec<((AssetId, AssetId), AssetBalance)>, Option<Vec<u8>>) {
+            Cfmm::account_liquidity_paged(&who, start_key, limit)
+        }
+
+        fn simulate_exchange(
+            source_asset: AssetId,
+            source_amount: AssetBalance,
+            dest_asset: AssetId,
+        ) -> Result<pallet_cfmm::ExchangeSimulation<AssetBalance>, sp_runtime::DispatchError> {
+            Cfmm::simulate_exchange(source_asset, source_amount, dest_asset)
+        }
+
+        fn pool_info(
+            asset_a: AssetId,
+            asset_b: AssetId,
+        ) -> Option<pallet_cfmm::PoolInfo<AccountId, Balance, BlockNumber>> {
+            Cfmm::pool_info(asset_a, asset_b)
+        }
+
+        fn price_history(
+            asset_a: AssetId,
+            asset_b: AssetId,
+        ) -> Vec<(BlockNumber, AssetBalance, AssetBalance)> {
+            Cfmm::price_history(asset_a, asset_b)
+        }
+    }
+
     #[cfg(feature = "runtime-benchmarks")]
     impl frame_benchmarking::Benchmark<Block> for Runtime {
         fn benchmark_metadata(extra: bool) -> (